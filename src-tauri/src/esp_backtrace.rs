@@ -0,0 +1,242 @@
+use addr2line::Loader;
+use regex::Regex;
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct BacktraceFrame {
+    pub address: u32,
+    pub function: Option<String>,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    /// Set for frames the compiler inlined into the next frame.
+    pub inlined: bool,
+}
+
+impl BacktraceFrame {
+    pub fn describe(&self) -> String {
+        let function = self.function.as_deref().unwrap_or("??");
+        match (&self.file, self.line) {
+            (Some(file), Some(line)) => {
+                format!("0x{:08x}: {function} at {file}:{line}", self.address)
+            }
+            (Some(file), None) => format!("0x{:08x}: {function} at {file}", self.address),
+            _ => format!("0x{:08x}: {function}", self.address),
+        }
+    }
+}
+
+/// Resolves code addresses against the DWARF and symbol table of an app ELF.
+pub struct Symbolizer {
+    loader: Loader,
+}
+
+impl Symbolizer {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let loader =
+            Loader::new(path).map_err(|e| format!("failed to load ELF {}: {e}", path.display()))?;
+        Ok(Self { loader })
+    }
+
+    /// Returns the inlined call chain at `address`, innermost first, falling
+    /// back to the ELF symbol table when the address has no line info.
+    pub fn symbolize(&self, address: u32) -> Vec<BacktraceFrame> {
+        let probe = u64::from(address);
+        let mut frames = Vec::new();
+        if let Ok(mut iter) = self.loader.find_frames(probe) {
+            while let Ok(Some(frame)) = iter.next() {
+                let function = frame
+                    .function
+                    .as_ref()
+                    .and_then(|name| name.demangle().ok())
+                    .map(|name| name.to_string());
+                let location = frame.location.as_ref();
+                frames.push(BacktraceFrame {
+                    address,
+                    function,
+                    file: location.and_then(|l| l.file).map(str::to_string),
+                    line: location.and_then(|l| l.line),
+                    inlined: true,
+                });
+            }
+        }
+
+        if let Some(last) = frames.last_mut() {
+            last.inlined = false;
+        } else {
+            frames.push(BacktraceFrame {
+                address,
+                function: self.loader.find_symbol(probe).map(str::to_string),
+                file: None,
+                line: None,
+                inlined: false,
+            });
+        }
+        frames
+    }
+}
+
+fn backtrace_regex() -> &'static Regex {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    REGEX.get_or_init(|| {
+        Regex::new(r"0x([0-9a-fA-F]{8}):0x[0-9a-fA-F]{8}").expect("valid backtrace regex")
+    })
+}
+
+fn register_regex() -> &'static Regex {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    REGEX.get_or_init(|| {
+        Regex::new(r"\b(?:PC|MEPC|RA)\s*:\s*0x([0-9a-fA-F]{8})").expect("valid register regex")
+    })
+}
+
+/// Pulls code addresses out of a panic handler line: the PC half of each
+/// `pc:sp` pair on a Xtensa `Backtrace:` line, or the PC/MEPC/RA entries of
+/// a register dump.
+pub fn find_code_addresses(line: &str) -> Vec<u32> {
+    let regex = if line.contains("Backtrace:") {
+        backtrace_regex()
+    } else {
+        register_regex()
+    };
+    regex
+        .captures_iter(line)
+        .filter_map(|captures| u32::from_str_radix(&captures[1], 16).ok())
+        .filter(|address| *address != 0)
+        .collect()
+}
+
+/// Locates the app ELF of the ESP-IDF build directory holding
+/// `flasher_args.json`: `project_description.json` names it, otherwise it is
+/// the app binary with an `.elf` extension.
+pub fn find_project_elf(flasher_args: &Path) -> Result<PathBuf, String> {
+    let build_dir = flasher_args
+        .parent()
+        .ok_or_else(|| format!("invalid flasher_args path: {}", flasher_args.display()))?;
+
+    let read_json = |path: PathBuf| -> Option<Value> {
+        serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+    };
+
+    let mut candidates = Vec::new();
+    if let Some(description) = read_json(build_dir.join("project_description.json")) {
+        if let Some(elf) = description.get("app_elf").and_then(Value::as_str) {
+            candidates.push(build_dir.join(elf));
+        }
+    }
+    if let Some(args) = read_json(flasher_args.to_path_buf()) {
+        if let Some(bin) = args.pointer("/app/file").and_then(Value::as_str) {
+            candidates.push(build_dir.join(bin).with_extension("elf"));
+        }
+    }
+
+    candidates
+        .into_iter()
+        .find(|path| path.is_file())
+        .ok_or_else(|| format!("no app ELF found next to {}", flasher_args.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Abort on an ESP32 (Xtensa) running ESP-IDF v5.1.
+    const XTENSA_PANIC: &str = "\
+abort() was called at PC 0x400d2c3f on core 0\r
+\r
+\r
+Backtrace: 0x40081a8e:0x3ffb4c70 0x400870bd:0x3ffb4c90 0x4008c8a6:0x3ffb4cb0 0x400d2c3f:0x3ffb4d20 0x400d6b2e:0x3ffb4d40 0x40088a35:0x3ffb4d60\r
+\r
+\r
+\r
+\r
+ELF file SHA256: 5b6b3f4a2c4e1d90\r
+\r
+Rebooting...\r
+";
+
+    /// Load access fault on an ESP32-C3 (RISC-V) register dump.
+    const RISCV_PANIC: &str = "\
+Guru Meditation Error: Core  0 panic'ed (Load access fault). Exception was unhandled.\r
+\r
+Core  0 register dump:\r
+MEPC    : 0x42007a5e  RA      : 0x42007a56  SP      : 0x3fc98ef0  GP      : 0x3fc8e400  \r
+TP      : 0x3fc98fa0  T0      : 0x40057fa6  T1      : 0x0000000f  T2      : 0x00000000  \r
+MSTATUS : 0x00001881  MTVEC   : 0x40380001  MCAUSE  : 0x00000005  MTVAL   : 0x00000000  \r
+";
+
+    fn addresses(log: &str) -> Vec<u32> {
+        log.lines().flat_map(find_code_addresses).collect()
+    }
+
+    #[test]
+    fn extracts_xtensa_backtrace_pcs() {
+        assert_eq!(
+            addresses(XTENSA_PANIC),
+            [0x40081a8e, 0x400870bd, 0x4008c8a6, 0x400d2c3f, 0x400d6b2e, 0x40088a35]
+        );
+        let corrupted =
+            "\x1b[0;31mBacktrace: 0x400d1234:0x3ffb1230 0x00000000:0x00000000 |<-CORRUPTED";
+        assert_eq!(find_code_addresses(corrupted), [0x400d1234]);
+    }
+
+    #[test]
+    fn extracts_riscv_register_pcs() {
+        assert_eq!(addresses(RISCV_PANIC), [0x42007a5e, 0x42007a56]);
+        let xtensa_dump = "PC      : 0x400d1234  PS      : 0x00060930  A0      : 0x800d5678";
+        assert_eq!(find_code_addresses(xtensa_dump), [0x400d1234]);
+        assert!(find_code_addresses("I (12) wifi: rssi 0x12345678").is_empty());
+    }
+
+    #[test]
+    fn describes_frames() {
+        let mut frame = BacktraceFrame {
+            address: 0x400d2c3f,
+            function: Some("app_main".to_string()),
+            file: Some("main/main.c".to_string()),
+            line: Some(42),
+            inlined: false,
+        };
+        assert_eq!(frame.describe(), "0x400d2c3f: app_main at main/main.c:42");
+        frame.line = None;
+        assert_eq!(frame.describe(), "0x400d2c3f: app_main at main/main.c");
+        frame.function = None;
+        frame.file = None;
+        assert_eq!(frame.describe(), "0x400d2c3f: ??");
+    }
+
+    #[test]
+    fn finds_the_project_elf() {
+        let dir = std::env::temp_dir().join(format!("esp-backtrace-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let flasher_args = dir.join("flasher_args.json");
+        fs::write(
+            &flasher_args,
+            r#"{"app":{"offset":"0x10000","file":"hello.bin"}}"#,
+        )
+        .unwrap();
+        assert!(find_project_elf(&flasher_args).is_err());
+
+        fs::write(dir.join("hello.elf"), b"x").unwrap();
+        assert_eq!(
+            find_project_elf(&flasher_args).unwrap(),
+            dir.join("hello.elf")
+        );
+
+        fs::write(dir.join("named.elf"), b"x").unwrap();
+        fs::write(
+            dir.join("project_description.json"),
+            r#"{"app_elf":"named.elf"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            find_project_elf(&flasher_args).unwrap(),
+            dir.join("named.elf")
+        );
+        assert!(Symbolizer::load(&dir.join("named.elf")).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
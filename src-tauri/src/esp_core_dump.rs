@@ -0,0 +1,658 @@
+use crate::esp_backtrace::{BacktraceFrame, Symbolizer};
+use crate::esp_rom::Chip;
+use crate::serial_capture;
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+pub const CORE_DUMP_DIR: &str = "coredumps";
+
+const START_MARKER: &str = "CORE DUMP START";
+const END_MARKER: &str = "CORE DUMP END";
+/// Base64 text collected before a block without an end marker is dropped.
+const MAX_BLOCK_LEN: usize = 8 * 1024 * 1024;
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const EM_XTENSA: u16 = 94;
+const EM_RISCV: u16 = 243;
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const NT_PRSTATUS: u32 = 1;
+/// Size of the `elf_prstatus` fields ESP-IDF writes ahead of the registers.
+const PRSTATUS_LEN: usize = 72;
+/// Offset of `pcTaskName` in the FreeRTOS TCB without MPU support.
+const TCB_NAME_OFFSET: u32 = 52;
+const TASK_NAME_LEN: usize = 16;
+const MAX_FRAMES: usize = 64;
+
+const RISCV_REGISTER_NAMES: [&str; 32] = [
+    "pc", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4", "a5",
+    "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4", "t5",
+    "t6",
+];
+
+/// Reassembles the base64 block ESP-IDF prints to the console between the
+/// `CORE DUMP START` and `CORE DUMP END` banners.
+#[derive(Default)]
+pub struct CoreDumpCollector {
+    block: Option<String>,
+}
+
+impl CoreDumpCollector {
+    /// Feeds one console line. Returns the decoded dump once the end banner
+    /// is seen.
+    pub fn push_line(&mut self, line: &str) -> Option<Result<Vec<u8>, String>> {
+        if line.contains(START_MARKER) {
+            self.block = Some(String::new());
+            return None;
+        }
+        let block = self.block.as_mut()?;
+        if line.contains(END_MARKER) {
+            let block = self.block.take().unwrap_or_default();
+            return Some(
+                base64::engine::general_purpose::STANDARD
+                    .decode(block)
+                    .map_err(|e| format!("failed to decode core dump: {e}")),
+            );
+        }
+
+        let chunk = line.trim();
+        if chunk
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'+' || b == b'/' || b == b'=')
+        {
+            block.push_str(chunk);
+        }
+        if block.len() > MAX_BLOCK_LEN {
+            self.block = None;
+            return Some(Err("core dump exceeds the maximum size".to_string()));
+        }
+        None
+    }
+}
+
+/// Writes a received dump to `dir` as `<port>-<timestamp>.core`.
+pub fn save(dir: &Path, port: &str, data: &[u8]) -> Result<PathBuf, String> {
+    fs::create_dir_all(dir).map_err(|e| format!("failed to create core dump directory: {e}"))?;
+    let base = format!(
+        "{}-{}",
+        serial_capture::sanitize_port_name(port),
+        serial_capture::format_file_stamp(SystemTime::now())
+    );
+    let mut path = dir.join(format!("{base}.core"));
+    let mut suffix = 1;
+    while path.exists() {
+        suffix += 1;
+        path = dir.join(format!("{base}-{suffix}.core"));
+    }
+    fs::write(&path, data).map_err(|e| format!("failed to write {}: {e}", path.display()))?;
+    Ok(path)
+}
+
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct CoreRegister {
+    pub name: &'static str,
+    pub value: u32,
+}
+
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct CoreDumpTask {
+    /// TCB address, which ESP-IDF also uses as the task's `pr_pid`.
+    pub tcb: u32,
+    pub name: Option<String>,
+    pub crashed: bool,
+    pub registers: Vec<CoreRegister>,
+    pub stack_start: Option<u32>,
+    pub stack_size: Option<u32>,
+    pub backtrace: Vec<BacktraceFrame>,
+}
+
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct CoreDumpReport {
+    pub version: String,
+    pub chip: Option<String>,
+    pub arch: String,
+    pub checksum_valid: bool,
+    pub app_elf_sha256: Option<String>,
+    pub panic_reason: Option<String>,
+    pub crashed_task: Option<u32>,
+    pub symbolized: bool,
+    pub tasks: Vec<CoreDumpTask>,
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset + 2)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn c_string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).trim().to_string()
+}
+
+struct Note<'a> {
+    name: String,
+    kind: u32,
+    desc: &'a [u8],
+}
+
+/// The loadable segments of the core, i.e. the dumped TCBs and task stacks.
+struct Memory<'a> {
+    segments: Vec<(u32, &'a [u8])>,
+}
+
+impl<'a> Memory<'a> {
+    fn segment(&self, address: u32) -> Option<(u32, &'a [u8])> {
+        self.segments.iter().copied().find(|(start, data)| {
+            address >= *start && u64::from(address) < u64::from(*start) + data.len() as u64
+        })
+    }
+
+    fn read(&self, address: u32, len: usize) -> Option<&'a [u8]> {
+        let (start, data) = self.segment(address)?;
+        let offset = (address - start) as usize;
+        data.get(offset..offset + len)
+    }
+
+    fn read_u32(&self, address: u32) -> Option<u32> {
+        read_u32(self.read(address, 4)?, 0)
+    }
+}
+
+struct ElfCore<'a> {
+    machine: u16,
+    notes: Vec<Note<'a>>,
+    memory: Memory<'a>,
+}
+
+fn parse_elf_core(elf: &[u8]) -> Result<ElfCore<'_>, String> {
+    if elf.get(0..4) != Some(ELF_MAGIC) {
+        return Err("core dump does not contain an ELF image".to_string());
+    }
+    if elf.get(4) != Some(&1) || elf.get(5) != Some(&1) {
+        return Err("core dump ELF is not 32-bit little endian".to_string());
+    }
+    let truncated = || "core dump ELF is truncated".to_string();
+    let machine = read_u16(elf, 18).ok_or_else(truncated)?;
+    let ph_offset = read_u32(elf, 28).ok_or_else(truncated)? as usize;
+    let ph_size = read_u16(elf, 42).ok_or_else(truncated)? as usize;
+    let ph_count = read_u16(elf, 44).ok_or_else(truncated)? as usize;
+
+    let mut notes = Vec::new();
+    let mut segments = Vec::new();
+    for index in 0..ph_count {
+        let header = ph_offset + index * ph_size;
+        let kind = read_u32(elf, header).ok_or_else(truncated)?;
+        let offset = read_u32(elf, header + 4).ok_or_else(truncated)? as usize;
+        let vaddr = read_u32(elf, header + 8).ok_or_else(truncated)?;
+        let size = read_u32(elf, header + 16).ok_or_else(truncated)? as usize;
+        let data = elf.get(offset..offset + size).ok_or_else(truncated)?;
+        match kind {
+            PT_LOAD => segments.push((vaddr, data)),
+            PT_NOTE => notes.extend(parse_notes(data)),
+            _ => {}
+        }
+    }
+
+    Ok(ElfCore {
+        machine,
+        notes,
+        memory: Memory { segments },
+    })
+}
+
+fn parse_notes(mut data: &[u8]) -> Vec<Note<'_>> {
+    let align = |len: usize| (len + 3) & !3;
+    let mut notes = Vec::new();
+    while data.len() >= 12 {
+        let name_len = read_u32(data, 0).unwrap_or(0) as usize;
+        let desc_len = read_u32(data, 4).unwrap_or(0) as usize;
+        let kind = read_u32(data, 8).unwrap_or(0);
+        let desc_start = 12 + align(name_len);
+        let Some(name) = data.get(12..12 + name_len) else {
+            break;
+        };
+        let Some(desc) = data.get(desc_start..desc_start + desc_len) else {
+            break;
+        };
+        notes.push(Note {
+            name: c_string(name),
+            kind,
+            desc,
+        });
+        data = data.get(desc_start + align(desc_len)..).unwrap_or_default();
+    }
+    notes
+}
+
+fn task_name(memory: &Memory, tcb: u32) -> Option<String> {
+    let name = c_string(memory.read(tcb.checked_add(TCB_NAME_OFFSET)?, TASK_NAME_LEN)?);
+    if name.is_empty() || !name.bytes().all(|b| b.is_ascii_graphic() || b == b' ') {
+        return None;
+    }
+    Some(name)
+}
+
+/// Return addresses on Xtensa carry the window increment in their top bits
+/// and point past the `callN`, so they are mapped back into the call.
+fn xtensa_call_address(return_address: u32) -> u32 {
+    let address = if return_address & 0x8000_0000 != 0 {
+        (return_address & 0x3FFF_FFFF) | 0x4000_0000
+    } else {
+        return_address
+    };
+    address.wrapping_sub(3)
+}
+
+/// Walks the windowed-ABI base save areas: the caller's return address and
+/// stack pointer sit 16 and 12 bytes below each frame's stack pointer.
+fn xtensa_backtrace(memory: &Memory, pc: u32, a0: u32, a1: u32) -> Vec<u32> {
+    let mut addresses = vec![pc];
+    let (mut next_pc, mut sp) = (a0, a1);
+    while next_pc != 0 && addresses.len() < MAX_FRAMES {
+        addresses.push(xtensa_call_address(next_pc));
+        let (Some(saved_pc), Some(saved_sp)) = (
+            memory.read_u32(sp.wrapping_sub(16)),
+            memory.read_u32(sp.wrapping_sub(12)),
+        ) else {
+            break;
+        };
+        if saved_sp <= sp {
+            break;
+        }
+        next_pc = saved_pc;
+        sp = saved_sp;
+    }
+    addresses
+}
+
+fn decode_task(
+    core: &ElfCore,
+    desc: &[u8],
+    symbolizer: Option<&Symbolizer>,
+) -> Option<CoreDumpTask> {
+    let tcb = read_u32(desc, 24)?;
+    let regs = desc.get(PRSTATUS_LEN..)?;
+    let reg = |index: usize| read_u32(regs, index * 4);
+
+    let (registers, sp, addresses) = match core.machine {
+        EM_XTENSA => {
+            // pc, ps, lbeg, lend, lcount, sar, windowstart, windowbase,
+            // 56 reserved words, then the address registers.
+            let mut registers = vec![
+                CoreRegister {
+                    name: "pc",
+                    value: reg(0)?,
+                },
+                CoreRegister {
+                    name: "ps",
+                    value: reg(1)?,
+                },
+                CoreRegister {
+                    name: "sar",
+                    value: reg(5)?,
+                },
+            ];
+            const AR_NAMES: [&str; 16] = [
+                "a0", "a1", "a2", "a3", "a4", "a5", "a6", "a7", "a8", "a9", "a10", "a11", "a12",
+                "a13", "a14", "a15",
+            ];
+            for (index, name) in AR_NAMES.iter().enumerate() {
+                registers.push(CoreRegister {
+                    name,
+                    value: reg(64 + index)?,
+                });
+            }
+            let (pc, a0, a1) = (reg(0)?, reg(64)?, reg(65)?);
+            (registers, a1, xtensa_backtrace(&core.memory, pc, a0, a1))
+        }
+        EM_RISCV => {
+            let mut registers = Vec::with_capacity(RISCV_REGISTER_NAMES.len());
+            for (index, name) in RISCV_REGISTER_NAMES.iter().enumerate() {
+                registers.push(CoreRegister {
+                    name,
+                    value: reg(index)?,
+                });
+            }
+            // Without frame pointers or unwind tables only pc and ra are
+            // known, as in the panic handler's own output.
+            let (pc, ra) = (reg(0)?, reg(1)?);
+            let mut addresses = vec![pc];
+            if ra != 0 && ra != pc {
+                addresses.push(ra);
+            }
+            (registers, reg(2)?, addresses)
+        }
+        _ => return None,
+    };
+
+    let backtrace = addresses
+        .into_iter()
+        .flat_map(|address| match symbolizer {
+            Some(symbolizer) => symbolizer.symbolize(address),
+            None => vec![BacktraceFrame {
+                address,
+                function: None,
+                file: None,
+                line: None,
+                inlined: false,
+            }],
+        })
+        .collect();
+    let stack = core.memory.segment(sp);
+
+    Some(CoreDumpTask {
+        tcb,
+        name: task_name(&core.memory, tcb),
+        crashed: false,
+        registers,
+        stack_start: stack.map(|(start, _)| start),
+        stack_size: stack.map(|(_, data)| data.len() as u32),
+        backtrace,
+    })
+}
+
+/// Decodes an ESP-IDF ELF core dump as received over UART (header, ELF core,
+/// checksum) into a per-task report.
+pub fn decode(data: &[u8], symbolizer: Option<&Symbolizer>) -> Result<CoreDumpReport, String> {
+    let truncated = || "core dump is truncated".to_string();
+    let data_len = read_u32(data, 0).ok_or_else(truncated)? as usize;
+    let version = read_u32(data, 4).ok_or_else(truncated)?;
+    let data = data.get(..data_len).ok_or_else(truncated)?;
+
+    let (major, minor) = ((version >> 8) & 0xFF, version & 0xFF);
+    if major != 1 {
+        return Err(format!(
+            "core dump format {major}.{minor} is not supported, only ELF core dumps are"
+        ));
+    }
+    // Format 1.2 and later add the chip revision to the header; odd minor
+    // versions are SHA-256 protected instead of CRC32.
+    let header_len = if minor >= 2 { 24 } else { 20 };
+    let checksum_len = if minor % 2 == 1 { 32 } else { 4 };
+    let body_end = data_len.checked_sub(checksum_len).ok_or_else(truncated)?;
+    let stored = data.get(body_end..).ok_or_else(truncated)?;
+    let checksum_valid = if checksum_len == 4 {
+        read_u32(stored, 0) == Some(crc32(&data[..body_end]))
+    } else {
+        Sha256::digest(&data[..body_end]).as_slice() == stored
+    };
+
+    let core = parse_elf_core(data.get(header_len..body_end).ok_or_else(truncated)?)?;
+    let arch = match core.machine {
+        EM_XTENSA => "xtensa",
+        EM_RISCV => "riscv",
+        machine => return Err(format!("unsupported core dump machine type: {machine}")),
+    };
+
+    let mut app_elf_sha256 = None;
+    let mut panic_reason = None;
+    let mut crashed_task = None;
+    let mut tasks = Vec::new();
+    for note in &core.notes {
+        match (note.name.as_str(), note.kind) {
+            ("CORE", NT_PRSTATUS) => tasks.extend(decode_task(&core, note.desc, symbolizer)),
+            ("ESP_CORE_DUMP_INFO", _) => {
+                app_elf_sha256 = note
+                    .desc
+                    .get(4..)
+                    .map(c_string)
+                    .filter(|sha| !sha.is_empty());
+            }
+            ("EXTRA_INFO", _) => crashed_task = read_u32(note.desc, 0),
+            ("ESP_PANIC_DETAILS", _) => {
+                panic_reason = Some(c_string(note.desc)).filter(|reason| !reason.is_empty());
+            }
+            _ => {}
+        }
+    }
+    for task in &mut tasks {
+        task.crashed = Some(task.tcb) == crashed_task;
+    }
+
+    Ok(CoreDumpReport {
+        version: format!("{major}.{minor}"),
+        chip: Chip::from_image_chip_id((version >> 16) as u16).map(|chip| chip.name().to_string()),
+        arch: arch.to_string(),
+        checksum_valid,
+        app_elf_sha256,
+        panic_reason,
+        crashed_task,
+        symbolized: symbolizer.is_some(),
+        tasks,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TCB: u32 = 0x3FFB_0000;
+    const STACK: u32 = 0x3FFC_0000;
+    const APP_SHA: &str = "5b6b3f4a2c4e1d90";
+
+    fn note(name: &str, kind: u32, desc: &[u8]) -> Vec<u8> {
+        let pad = |data: &mut Vec<u8>| data.resize(data.len().next_multiple_of(4), 0);
+        let name = [name.as_bytes(), b"\0"].concat();
+        let mut note = Vec::new();
+        note.extend((name.len() as u32).to_le_bytes());
+        note.extend((desc.len() as u32).to_le_bytes());
+        note.extend(kind.to_le_bytes());
+        note.extend(&name);
+        pad(&mut note);
+        note.extend(desc);
+        pad(&mut note);
+        note
+    }
+
+    /// `elf_prstatus` for the task at `TCB` followed by `registers`.
+    fn prstatus(registers: &[(usize, u32)], count: usize) -> Vec<u8> {
+        let mut desc = vec![0u8; PRSTATUS_LEN];
+        desc[24..28].copy_from_slice(&TCB.to_le_bytes());
+        let mut words = vec![0u32; count];
+        for &(index, value) in registers {
+            words[index] = value;
+        }
+        desc.extend(words.iter().flat_map(|word| word.to_le_bytes()));
+        desc
+    }
+
+    /// A 32-bit little-endian ELF core with one PT_NOTE and PT_LOAD segments.
+    fn elf(machine: u16, notes: Vec<u8>, loads: Vec<(u32, Vec<u8>)>) -> Vec<u8> {
+        let segments: Vec<_> = [(PT_NOTE, 0, notes)]
+            .into_iter()
+            .chain(
+                loads
+                    .into_iter()
+                    .map(|(vaddr, data)| (PT_LOAD, vaddr, data)),
+            )
+            .collect();
+        let mut elf = vec![0u8; 52];
+        elf[..4].copy_from_slice(ELF_MAGIC);
+        elf[4] = 1;
+        elf[5] = 1;
+        elf[18..20].copy_from_slice(&machine.to_le_bytes());
+        elf[28..32].copy_from_slice(&52u32.to_le_bytes());
+        elf[42..44].copy_from_slice(&32u16.to_le_bytes());
+        elf[44..46].copy_from_slice(&(segments.len() as u16).to_le_bytes());
+
+        let mut offset = 52 + 32 * segments.len();
+        let mut body: Vec<u8> = Vec::new();
+        for (kind, vaddr, data) in &segments {
+            let mut header = [0u8; 32];
+            header[0..4].copy_from_slice(&kind.to_le_bytes());
+            header[4..8].copy_from_slice(&(offset as u32).to_le_bytes());
+            header[8..12].copy_from_slice(&vaddr.to_le_bytes());
+            header[16..20].copy_from_slice(&(data.len() as u32).to_le_bytes());
+            elf.extend(header);
+            body.extend(data);
+            offset += data.len();
+        }
+        elf.extend(body);
+        elf
+    }
+
+    /// Wraps an ELF core the way `esp_core_dump_uart` sends it: a 20 byte
+    /// header for format 1.0/1.1, CRC32 or SHA-256 at the end.
+    fn dump(chip_id: u32, minor: u32, elf: Vec<u8>) -> Vec<u8> {
+        let checksum_len = if minor % 2 == 1 { 32 } else { 4 };
+        let mut dump = Vec::new();
+        dump.extend(((20 + elf.len() + checksum_len) as u32).to_le_bytes());
+        dump.extend(((chip_id << 16) | 0x0100 | minor).to_le_bytes());
+        dump.extend([0u8; 12]);
+        dump.extend(elf);
+        if checksum_len == 4 {
+            let crc = crc32(&dump);
+            dump.extend(crc.to_le_bytes());
+        } else {
+            let sha = Sha256::digest(&dump);
+            dump.extend(sha);
+        }
+        dump
+    }
+
+    /// An ESP32-S3 task crashed in `pc` with two callers on its stack.
+    fn xtensa_dump(minor: u32) -> Vec<u8> {
+        // pc, then a0/a1 after the 64 special register slots.
+        let registers = prstatus(
+            &[(0, 0x400D_1000), (64, 0x800D_2010), (65, STACK + 0x20)],
+            128,
+        );
+        let mut info = 0u32.to_le_bytes().to_vec();
+        info.extend(APP_SHA.as_bytes());
+        info.push(0);
+        let mut notes = note("CORE", NT_PRSTATUS, &registers);
+        notes.extend(note("ESP_CORE_DUMP_INFO", 8194, &info));
+        notes.extend(note("EXTRA_INFO", 677, &TCB.to_le_bytes()));
+        notes.extend(note("ESP_PANIC_DETAILS", 1, b"LoadProhibited\0"));
+
+        let mut tcb = vec![0u8; 96];
+        tcb[52..56].copy_from_slice(b"main");
+        // Base save area of the frame at STACK + 0x20: caller's return
+        // address and stack pointer. The next frame's area is zeroed.
+        let mut stack = vec![0u8; 0x80];
+        stack[0x10..0x14].copy_from_slice(&0x800D_3020u32.to_le_bytes());
+        stack[0x14..0x18].copy_from_slice(&(STACK + 0x60).to_le_bytes());
+        dump(
+            9,
+            minor,
+            elf(EM_XTENSA, notes, vec![(TCB, tcb), (STACK, stack)]),
+        )
+    }
+
+    fn addresses(task: &CoreDumpTask) -> Vec<u32> {
+        task.backtrace.iter().map(|frame| frame.address).collect()
+    }
+
+    #[test]
+    fn decodes_an_xtensa_core() {
+        let report = decode(&xtensa_dump(0), None).unwrap();
+        assert_eq!(report.version, "1.0");
+        assert_eq!(report.chip.as_deref(), Some("ESP32-S3"));
+        assert_eq!(report.arch, "xtensa");
+        assert!(report.checksum_valid);
+        assert_eq!(report.app_elf_sha256.as_deref(), Some(APP_SHA));
+        assert_eq!(report.panic_reason.as_deref(), Some("LoadProhibited"));
+        assert_eq!(report.crashed_task, Some(TCB));
+
+        let task = &report.tasks[0];
+        assert!(task.crashed);
+        assert_eq!(task.name.as_deref(), Some("main"));
+        assert_eq!(addresses(task), [0x400D_1000, 0x400D_200D, 0x400D_301D]);
+        assert_eq!(
+            (task.stack_start, task.stack_size),
+            (Some(STACK), Some(0x80))
+        );
+        let a1 = task.registers.iter().find(|reg| reg.name == "a1").unwrap();
+        assert_eq!(a1.value, STACK + 0x20);
+    }
+
+    #[test]
+    fn decodes_a_riscv_core() {
+        let registers = prstatus(&[(0, 0x4200_7A5E), (1, 0x4200_7A56), (2, STACK + 0x40)], 32);
+        let notes = note("CORE", NT_PRSTATUS, &registers);
+        let data = dump(5, 0, elf(EM_RISCV, notes, vec![(STACK, vec![0; 0x100])]));
+        let report = decode(&data, None).unwrap();
+        assert_eq!(report.chip.as_deref(), Some("ESP32-C3"));
+        assert_eq!(report.arch, "riscv");
+        let task = &report.tasks[0];
+        assert_eq!(addresses(task), [0x4200_7A5E, 0x4200_7A56]);
+        assert_eq!(task.registers.len(), 32);
+        assert_eq!(task.registers[2].name, "sp");
+        assert_eq!(task.name, None);
+        assert!(!task.crashed);
+    }
+
+    #[test]
+    fn checks_crc_and_sha_protected_dumps() {
+        let sha = decode(&xtensa_dump(1), None).unwrap();
+        assert_eq!(sha.version, "1.1");
+        assert!(sha.checksum_valid);
+
+        let mut corrupted = xtensa_dump(0);
+        let flip = corrupted.len() - 8;
+        corrupted[flip] ^= 0xFF;
+        assert!(!decode(&corrupted, None).unwrap().checksum_valid);
+
+        let mut binary = xtensa_dump(0);
+        binary[5] = 0x00;
+        let err = decode(&binary, None).unwrap_err();
+        assert!(err.contains("not supported"), "{err}");
+        let data = xtensa_dump(0);
+        assert_eq!(
+            decode(&data[..40], None).unwrap_err(),
+            "core dump is truncated"
+        );
+    }
+
+    #[test]
+    fn collects_the_console_block() {
+        let data = xtensa_dump(0);
+        let encoded = base64::engine::general_purpose::STANDARD.encode(&data);
+        let mut collector = CoreDumpCollector::default();
+        assert!(collector
+            .push_line("I (5) esp_core_dump_uart: Press Enter to print core dump to UART...")
+            .is_none());
+        assert!(collector
+            .push_line("================= CORE DUMP START =================\r")
+            .is_none());
+        for chunk in encoded.as_bytes().chunks(76) {
+            let line = format!("{}\r\n", std::str::from_utf8(chunk).unwrap());
+            assert!(collector.push_line(&line).is_none());
+        }
+        let collected = collector
+            .push_line("================= CORE DUMP END =================")
+            .unwrap();
+        assert_eq!(collected.unwrap(), data);
+        assert!(collector.push_line("abc").is_none());
+
+        // Lines that are not base64 are skipped; a broken block still ends
+        // at the banner, with an error.
+        assert!(collector.push_line("CORE DUMP START").is_none());
+        assert!(collector.push_line("I (9) wifi: @@@").is_none());
+        assert!(collector.push_line("abc").is_none());
+        assert!(collector.push_line("CORE DUMP END").unwrap().is_err());
+    }
+}
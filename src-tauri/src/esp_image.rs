@@ -0,0 +1,571 @@
+use sha2::{Digest, Sha256};
+
+use crate::esp_rom::Chip;
+
+pub const ESP_IMAGE_MAGIC: u8 = 0xE9;
+
+const COMMON_HEADER_LEN: usize = 8;
+const EXTENDED_HEADER_LEN: usize = 16;
+const SEGMENT_HEADER_LEN: usize = 8;
+const DIGEST_LEN: usize = 32;
+const MAX_SEGMENTS: u8 = 16;
+const APP_DESC_MAGIC: u32 = 0xABCD_5432;
+const APP_DESC_LEN: usize = 256;
+
+const FLASH_FREQ_NAMES: [&str; 11] = [
+    "80m", "60m", "48m", "40m", "30m", "26m", "24m", "20m", "16m", "15m", "12m",
+];
+const FLASH_SIZE_NAMES: [&str; 11] = [
+    "256KB", "512KB", "1MB", "2MB", "2MB-C1", "4MB", "4MB-C1", "8MB", "16MB", "32MB", "64MB",
+];
+
+pub struct ExtendedHeader {
+    pub wp_pin: u8,
+    pub chip_id: u16,
+    pub min_chip_rev_full: u16,
+    pub max_chip_rev_full: u16,
+}
+
+pub struct ImageHeader {
+    pub segment_count: u8,
+    pub flash_mode: u8,
+    pub flash_size_freq: u8,
+    pub entry: u32,
+    pub extended: Option<ExtendedHeader>,
+    pub hash_appended: bool,
+}
+
+pub struct ImageSegment {
+    pub address: u32,
+    pub file_offset: usize,
+    pub data: Vec<u8>,
+}
+
+pub struct EspImage {
+    pub header: ImageHeader,
+    pub segments: Vec<ImageSegment>,
+    /// Offset of the trailing checksum byte; everything before it plus the
+    /// checksum itself is what an appended SHA-256 digest covers.
+    pub checksum_offset: usize,
+}
+
+impl EspImage {
+    pub fn digest_offset(&self) -> Option<usize> {
+        self.header
+            .hash_appended
+            .then_some(self.checksum_offset + 1)
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+pub fn parse(data: &[u8], extended_header: bool) -> Result<EspImage, String> {
+    let header_len = if extended_header {
+        COMMON_HEADER_LEN + EXTENDED_HEADER_LEN
+    } else {
+        COMMON_HEADER_LEN
+    };
+    if data.len() < header_len {
+        return Err("image is too small to contain an ESP image header".to_string());
+    }
+    if data[0] != ESP_IMAGE_MAGIC {
+        return Err(format!(
+            "invalid image magic 0x{:02X}, expected 0x{ESP_IMAGE_MAGIC:02X}",
+            data[0]
+        ));
+    }
+
+    let header = ImageHeader {
+        segment_count: data[1],
+        flash_mode: data[2],
+        flash_size_freq: data[3],
+        entry: read_u32(data, 4),
+        extended: extended_header.then(|| ExtendedHeader {
+            wp_pin: data[8],
+            chip_id: read_u16(data, 12),
+            min_chip_rev_full: read_u16(data, 15),
+            max_chip_rev_full: read_u16(data, 17),
+        }),
+        hash_appended: extended_header && data[COMMON_HEADER_LEN + EXTENDED_HEADER_LEN - 1] == 1,
+    };
+    if header.segment_count == 0 || header.segment_count > MAX_SEGMENTS {
+        return Err(format!(
+            "invalid segment count {}",
+            header.segment_count
+        ));
+    }
+
+    let mut offset = header_len;
+    let mut segments = Vec::with_capacity(usize::from(header.segment_count));
+    for index in 0..header.segment_count {
+        if offset + SEGMENT_HEADER_LEN > data.len() {
+            return Err(format!("segment {index} header is truncated"));
+        }
+        let address = read_u32(data, offset);
+        let len = read_u32(data, offset + 4) as usize;
+        let file_offset = offset + SEGMENT_HEADER_LEN;
+        if file_offset + len > data.len() {
+            return Err(format!(
+                "segment {index} at 0x{address:08X} claims {len} bytes beyond end of file"
+            ));
+        }
+        segments.push(ImageSegment {
+            address,
+            file_offset,
+            data: data[file_offset..file_offset + len].to_vec(),
+        });
+        offset = file_offset + len;
+    }
+
+    // The checksum byte sits at the last position of the 16-byte aligned block.
+    let checksum_offset = offset + (15 - offset % 16);
+    if checksum_offset >= data.len() {
+        return Err("image checksum is missing".to_string());
+    }
+
+    Ok(EspImage {
+        header,
+        segments,
+        checksum_offset,
+    })
+}
+
+/// Maps the SPI mode names used by esptool (and the SPI mode selector) to the
+/// header byte. `keep` leaves the image untouched.
+pub fn parse_flash_mode(mode: &str) -> Result<Option<u8>, String> {
+    match mode.to_ascii_lowercase().as_str() {
+        "" | "keep" => Ok(None),
+        "qio" => Ok(Some(0)),
+        "qout" => Ok(Some(1)),
+        "dio" => Ok(Some(2)),
+        "dout" => Ok(Some(3)),
+        _ => Err("flash_mode must be one of: keep, qio, qout, dio, dout".to_string()),
+    }
+}
+
+/// Header nibble for the SPI clock; the encoding differs between chip families.
+pub fn parse_flash_freq(chip: Chip, freq: &str) -> Result<Option<u8>, String> {
+    let freq = freq.to_ascii_lowercase();
+    if freq.is_empty() || freq == "keep" {
+        return Ok(None);
+    }
+
+    let value = match chip {
+        Chip::Esp32c2 => match freq.as_str() {
+            "60m" => Some(0xF),
+            "30m" => Some(0x0),
+            "20m" => Some(0x1),
+            "15m" => Some(0x2),
+            _ => None,
+        },
+        Chip::Esp32c6 => match freq.as_str() {
+            "80m" | "40m" => Some(0x0),
+            "20m" => Some(0x2),
+            _ => None,
+        },
+        Chip::Esp32h2 => match freq.as_str() {
+            "48m" => Some(0xF),
+            "24m" => Some(0x0),
+            "16m" => Some(0x1),
+            "12m" => Some(0x2),
+            _ => None,
+        },
+        _ => match freq.as_str() {
+            "80m" => Some(0xF),
+            "40m" => Some(0x0),
+            "26m" => Some(0x1),
+            "20m" => Some(0x2),
+            _ => None,
+        },
+    };
+    value
+        .map(Some)
+        .ok_or_else(|| format!("flash_freq {freq} is not supported by {}", chip.name()))
+}
+
+/// Header nibble for the flash chip size, e.g. `4MB`.
+pub fn parse_flash_size(chip: Chip, size: &str) -> Result<Option<u8>, String> {
+    let size = size.to_ascii_uppercase();
+    if size.is_empty() || size == "KEEP" {
+        return Ok(None);
+    }
+
+    let value = if chip == Chip::Esp8266 {
+        match size.as_str() {
+            "512KB" => Some(0x0),
+            "256KB" => Some(0x1),
+            "1MB" => Some(0x2),
+            "2MB" => Some(0x3),
+            "4MB" => Some(0x4),
+            "2MB-C1" => Some(0x5),
+            "4MB-C1" => Some(0x6),
+            "8MB" => Some(0x8),
+            "16MB" => Some(0x9),
+            _ => None,
+        }
+    } else {
+        match size.as_str() {
+            "1MB" => Some(0x0),
+            "2MB" => Some(0x1),
+            "4MB" => Some(0x2),
+            "8MB" => Some(0x3),
+            "16MB" => Some(0x4),
+            "32MB" => Some(0x5),
+            "64MB" => Some(0x6),
+            "128MB" => Some(0x7),
+            _ => None,
+        }
+    };
+    value
+        .map(Some)
+        .ok_or_else(|| format!("flash_size {size} is not supported by {}", chip.name()))
+}
+
+/// Capacity in bytes of a flash size name such as `4MB` or `2MB-C1`.
+pub fn flash_size_bytes(size: &str) -> Option<u32> {
+    let size = size.to_ascii_uppercase();
+    let size = size.split('-').next()?;
+    let (value, unit) = if let Some(value) = size.strip_suffix("MB") {
+        (value, 1024 * 1024)
+    } else {
+        (size.strip_suffix("KB")?, 1024)
+    };
+    value.parse::<u32>().ok()?.checked_mul(unit)
+}
+
+fn flash_mode_name(mode: u8) -> String {
+    match mode {
+        0 => "qio".to_string(),
+        1 => "qout".to_string(),
+        2 => "dio".to_string(),
+        3 => "dout".to_string(),
+        other => format!("unknown (0x{other:02X})"),
+    }
+}
+
+fn flash_freq_name(chip: Option<Chip>, nibble: u8) -> String {
+    chip.and_then(|chip| {
+        FLASH_FREQ_NAMES
+            .iter()
+            .find(|name| parse_flash_freq(chip, name) == Ok(Some(nibble)))
+    })
+    .map(|name| name.to_string())
+    .unwrap_or_else(|| format!("unknown (0x{nibble:X})"))
+}
+
+fn flash_size_name(chip: Option<Chip>, nibble: u8) -> String {
+    chip.and_then(|chip| {
+        FLASH_SIZE_NAMES
+            .iter()
+            .find(|name| parse_flash_size(chip, name) == Ok(Some(nibble)))
+    })
+    .map(|name| name.to_string())
+    .unwrap_or_else(|| format!("unknown (0x{nibble:X})"))
+}
+
+fn hex_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn c_string(bytes: &[u8]) -> String {
+    let len = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..len]).to_string()
+}
+
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct SegmentInfo {
+    pub address: u32,
+    pub length: u32,
+    pub file_offset: u32,
+}
+
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct AppDescription {
+    pub secure_version: u32,
+    pub version: String,
+    pub project_name: String,
+    pub compile_time: String,
+    pub compile_date: String,
+    pub idf_version: String,
+    pub elf_sha256: String,
+}
+
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct FirmwareImageInfo {
+    pub size: u64,
+    pub chip: String,
+    pub chip_id: Option<u16>,
+    pub segment_count: u8,
+    pub flash_mode: String,
+    pub flash_freq: String,
+    pub flash_size: String,
+    pub entry: u32,
+    pub wp_pin: Option<u8>,
+    pub min_chip_rev: Option<String>,
+    pub max_chip_rev: Option<String>,
+    pub segments: Vec<SegmentInfo>,
+    pub checksum_stored: u8,
+    pub checksum_calculated: u8,
+    pub checksum_valid: bool,
+    pub sha256_stored: Option<String>,
+    pub sha256_calculated: Option<String>,
+    pub sha256_valid: Option<bool>,
+    pub app_desc: Option<AppDescription>,
+}
+
+fn chip_revision(full: u16) -> String {
+    format!("v{}.{}", full / 100, full % 100)
+}
+
+/// Images built for ESP8266 have no extended header. For the others the
+/// extended block carries a chip id and a 0/1 hash flag, which an ESP8266
+/// segment header is very unlikely to match.
+fn guess_extended_header(data: &[u8]) -> bool {
+    if data.len() < COMMON_HEADER_LEN + EXTENDED_HEADER_LEN {
+        return false;
+    }
+    Chip::from_image_chip_id(read_u16(data, 12)).is_some()
+        && data[COMMON_HEADER_LEN + EXTENDED_HEADER_LEN - 1] <= 1
+}
+
+/// `esp_app_desc_t` sits at the start of the first (DROM) segment of an
+/// application image.
+fn parse_app_desc(image: &EspImage) -> Option<AppDescription> {
+    let data = &image.segments.first()?.data;
+    if data.len() < APP_DESC_LEN || read_u32(data, 0) != APP_DESC_MAGIC {
+        return None;
+    }
+    Some(AppDescription {
+        secure_version: read_u32(data, 4),
+        version: c_string(&data[16..48]),
+        project_name: c_string(&data[48..80]),
+        compile_time: c_string(&data[80..96]),
+        compile_date: c_string(&data[96..112]),
+        idf_version: c_string(&data[112..144]),
+        elf_sha256: hex_string(&data[144..176]),
+    })
+}
+
+pub fn inspect(data: &[u8]) -> Result<FirmwareImageInfo, String> {
+    let extended_header = guess_extended_header(data);
+    let image = parse(data, extended_header)?;
+    let chip = match &image.header.extended {
+        Some(extended) => Chip::from_image_chip_id(extended.chip_id),
+        None => Some(Chip::Esp8266),
+    };
+
+    let checksum_calculated = image
+        .segments
+        .iter()
+        .flat_map(|segment| segment.data.iter())
+        .fold(0xEF, |acc, byte| acc ^ byte);
+    let checksum_stored = data[image.checksum_offset];
+
+    let (sha256_stored, sha256_calculated, sha256_valid) = match image.digest_offset() {
+        Some(offset) if offset + DIGEST_LEN <= data.len() => {
+            let calculated = Sha256::digest(&data[..offset]);
+            let stored = &data[offset..offset + DIGEST_LEN];
+            (
+                Some(hex_string(stored)),
+                Some(hex_string(&calculated)),
+                Some(stored == &calculated[..]),
+            )
+        }
+        Some(_) => (None, None, Some(false)),
+        None => (None, None, None),
+    };
+
+    let header = &image.header;
+    Ok(FirmwareImageInfo {
+        size: data.len() as u64,
+        chip: chip
+            .map(|chip| chip.name().to_string())
+            .unwrap_or_else(|| "unknown".to_string()),
+        chip_id: header.extended.as_ref().map(|extended| extended.chip_id),
+        segment_count: header.segment_count,
+        flash_mode: flash_mode_name(header.flash_mode),
+        flash_freq: flash_freq_name(chip, header.flash_size_freq & 0x0F),
+        flash_size: flash_size_name(chip, header.flash_size_freq >> 4),
+        entry: header.entry,
+        wp_pin: header.extended.as_ref().map(|extended| extended.wp_pin),
+        min_chip_rev: header
+            .extended
+            .as_ref()
+            .map(|extended| chip_revision(extended.min_chip_rev_full)),
+        max_chip_rev: header
+            .extended
+            .as_ref()
+            .map(|extended| chip_revision(extended.max_chip_rev_full)),
+        segments: image
+            .segments
+            .iter()
+            .map(|segment| SegmentInfo {
+                address: segment.address,
+                length: segment.data.len() as u32,
+                file_offset: segment.file_offset as u32,
+            })
+            .collect(),
+        checksum_stored,
+        checksum_calculated,
+        checksum_valid: checksum_stored == checksum_calculated,
+        sha256_stored,
+        sha256_calculated,
+        sha256_valid,
+        app_desc: parse_app_desc(&image),
+    })
+}
+
+/// Rewrites the SPI flash mode, frequency and size fields of an image header in
+/// place and refreshes the appended SHA-256 digest so the ROM still accepts it.
+/// Returns `false` when the data is not an ESP image or nothing changed.
+pub fn patch_flash_params(
+    data: &mut [u8],
+    extended_header: bool,
+    flash_mode: Option<u8>,
+    flash_freq: Option<u8>,
+    flash_size: Option<u8>,
+) -> Result<bool, String> {
+    if data.first() != Some(&ESP_IMAGE_MAGIC) {
+        return Ok(false);
+    }
+
+    let image = parse(data, extended_header)?;
+    let original = (data[2], data[3]);
+    if let Some(mode) = flash_mode {
+        data[2] = mode;
+    }
+    if let Some(freq) = flash_freq {
+        data[3] = (data[3] & 0xF0) | (freq & 0x0F);
+    }
+    if let Some(size) = flash_size {
+        data[3] = (data[3] & 0x0F) | (size << 4);
+    }
+    if (data[2], data[3]) == original {
+        return Ok(false);
+    }
+
+    if let Some(digest_offset) = image.digest_offset() {
+        if digest_offset + DIGEST_LEN <= data.len() {
+            let digest = Sha256::digest(&data[..digest_offset]);
+            data[digest_offset..digest_offset + DIGEST_LEN].copy_from_slice(&digest);
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ESP32C3_CHIP_ID: u8 = 5;
+
+    /// Builds an ESP32-C3 app image whose first segment carries an
+    /// `esp_app_desc_t`, with a valid checksum and appended SHA-256.
+    fn build_image() -> Vec<u8> {
+        let mut app_desc = vec![0u8; APP_DESC_LEN];
+        app_desc[0..4].copy_from_slice(&APP_DESC_MAGIC.to_le_bytes());
+        app_desc[4..8].copy_from_slice(&2u32.to_le_bytes());
+        app_desc[16..21].copy_from_slice(b"1.2.3");
+        app_desc[48..52].copy_from_slice(b"demo");
+        app_desc[112..118].copy_from_slice(b"v5.1.2");
+        let text = vec![0x13u8; 36];
+
+        // DIO, 4MB at 80MHz, entry 0x40380080.
+        let mut image = vec![ESP_IMAGE_MAGIC, 2, 0x02, 0x2F];
+        image.extend_from_slice(&0x4038_0080u32.to_le_bytes());
+        let mut extended = [0u8; EXTENDED_HEADER_LEN];
+        extended[0] = 0xEE;
+        extended[4] = ESP32C3_CHIP_ID;
+        extended[7..9].copy_from_slice(&3u16.to_le_bytes());
+        extended[9..11].copy_from_slice(&199u16.to_le_bytes());
+        extended[15] = 1;
+        image.extend_from_slice(&extended);
+        for (address, data) in [(0x3C00_0020u32, &app_desc), (0x4038_0000, &text)] {
+            image.extend_from_slice(&address.to_le_bytes());
+            image.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            image.extend_from_slice(data);
+        }
+        image.resize(image.len() + 15 - image.len() % 16, 0);
+        let checksum = app_desc
+            .iter()
+            .chain(&text)
+            .fold(0xEF, |acc, byte| acc ^ byte);
+        image.push(checksum);
+        let digest = Sha256::digest(&image);
+        image.extend_from_slice(&digest);
+        image
+    }
+
+    #[test]
+    fn inspects_a_valid_app_image() {
+        let image = build_image();
+        let info = inspect(&image).unwrap();
+        assert_eq!(info.chip, "ESP32-C3");
+        assert_eq!(info.chip_id, Some(5));
+        assert_eq!(info.flash_mode, "dio");
+        assert_eq!(info.flash_size, "4MB");
+        assert_eq!(info.entry, 0x4038_0080);
+        assert_eq!(info.wp_pin, Some(0xEE));
+        assert_eq!(info.min_chip_rev.as_deref(), Some("v0.3"));
+        assert_eq!(info.max_chip_rev.as_deref(), Some("v1.99"));
+        let segments: Vec<_> = info
+            .segments
+            .iter()
+            .map(|segment| (segment.address, segment.length, segment.file_offset))
+            .collect();
+        assert_eq!(segments, [(0x3C00_0020, 256, 32), (0x4038_0000, 36, 296)]);
+
+        assert!(info.checksum_valid);
+        assert_eq!(info.checksum_stored, image[image.len() - DIGEST_LEN - 1]);
+        let digest = hex_string(&image[image.len() - DIGEST_LEN..]);
+        assert_eq!(info.sha256_stored.as_deref(), Some(digest.as_str()));
+        assert_eq!(info.sha256_valid, Some(true));
+
+        let app_desc = info.app_desc.unwrap();
+        assert_eq!(app_desc.project_name, "demo");
+        assert_eq!(app_desc.version, "1.2.3");
+        assert_eq!(app_desc.idf_version, "v5.1.2");
+        assert_eq!(app_desc.secure_version, 2);
+    }
+
+    #[test]
+    fn reports_bad_checksum_and_digest() {
+        let mut image = build_image();
+        // Flip a byte inside the text segment.
+        image[300] ^= 0x01;
+        let info = inspect(&image).unwrap();
+        assert!(!info.checksum_valid);
+        assert_eq!(info.checksum_calculated, info.checksum_stored ^ 0x01);
+        assert_eq!(info.sha256_valid, Some(false));
+        assert_ne!(info.sha256_stored, info.sha256_calculated);
+    }
+
+    #[test]
+    fn rejects_truncated_images() {
+        let image = build_image();
+        // Cut inside the digest: the image parses but the hash can't be checked.
+        let info = inspect(&image[..image.len() - 8]).unwrap();
+        assert_eq!(info.sha256_valid, Some(false));
+        assert_eq!(info.sha256_stored, None);
+
+        for len in [0, 4, 20, 30, 100, 331] {
+            assert!(inspect(&image[..len]).is_err(), "{len} bytes");
+        }
+        let err = inspect(&image[..100]).unwrap_err();
+        assert!(err.contains("beyond end of file"), "{err}");
+        let mut bad_magic = image.clone();
+        bad_magic[0] = 0xE8;
+        assert!(inspect(&bad_magic).unwrap_err().contains("magic"));
+    }
+}
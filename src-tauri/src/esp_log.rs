@@ -0,0 +1,181 @@
+use regex::Regex;
+use std::collections::HashSet;
+use std::sync::OnceLock;
+
+#[derive(serde::Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Verbose,
+}
+
+impl LogLevel {
+    fn from_letter(letter: &str) -> Option<Self> {
+        match letter {
+            "E" => Some(LogLevel::Error),
+            "W" => Some(LogLevel::Warn),
+            "I" => Some(LogLevel::Info),
+            "D" => Some(LogLevel::Debug),
+            "V" => Some(LogLevel::Verbose),
+            _ => None,
+        }
+    }
+
+    pub fn parse(level: &str) -> Result<Self, String> {
+        match level.to_ascii_lowercase().as_str() {
+            "e" | "error" => Ok(LogLevel::Error),
+            "w" | "warn" | "warning" => Ok(LogLevel::Warn),
+            "i" | "info" => Ok(LogLevel::Info),
+            "d" | "debug" => Ok(LogLevel::Debug),
+            "v" | "verbose" => Ok(LogLevel::Verbose),
+            _ => Err("log level must be one of: error, warn, info, debug, verbose".to_string()),
+        }
+    }
+}
+
+/// One `esp_log` line, e.g. `I (1234) wifi: connected`. `time` is the raw
+/// timestamp field, which is either the tick count in milliseconds or the
+/// system time when `CONFIG_LOG_TIMESTAMP_SOURCE_SYSTEM` is set.
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct LogRecord {
+    pub level: LogLevel,
+    pub time: String,
+    pub tick: Option<u64>,
+    pub tag: String,
+    pub message: String,
+}
+
+fn ansi_regex() -> &'static Regex {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    REGEX.get_or_init(|| Regex::new(r"\x1b\[[0-9;]*m").expect("valid ANSI regex"))
+}
+
+fn header_regex() -> &'static Regex {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    REGEX.get_or_init(|| {
+        Regex::new(r"^([EWIDV]) \(([0-9:.]+)\)\s+([^:]+):\s?(.*)$").expect("valid log regex")
+    })
+}
+
+pub fn strip_ansi(text: &str) -> String {
+    ansi_regex().replace_all(text, "").to_string()
+}
+
+pub fn parse_line(line: &str) -> Option<LogRecord> {
+    let clean = strip_ansi(line);
+    let clean = clean.trim_end_matches(['\r', '\n']);
+    let captures = header_regex().captures(clean)?;
+    let time = captures[2].to_string();
+    Some(LogRecord {
+        level: LogLevel::from_letter(&captures[1])?,
+        tick: time.parse().ok(),
+        time,
+        tag: captures[3].trim().to_string(),
+        message: captures[4].to_string(),
+    })
+}
+
+/// Drops log lines before they reach the frontend. An empty allow list
+/// admits every tag; the deny list always wins.
+#[derive(Clone, Debug)]
+pub struct LogFilter {
+    pub min_level: LogLevel,
+    pub allow_tags: HashSet<String>,
+    pub deny_tags: HashSet<String>,
+}
+
+impl LogFilter {
+    pub fn accepts(&self, record: &LogRecord) -> bool {
+        record.level <= self.min_level
+            && !self.deny_tags.contains(&record.tag)
+            && (self.allow_tags.is_empty() || self.allow_tags.contains(&record.tag))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Start of an ESP32-S3 boot with `CONFIG_LOG_COLORS` on, as the UART
+    /// delivers it.
+    const BOOT_LOG: &str = "\
+ESP-ROM:esp32s3-20210327\r
+Build:Mar 27 2021\r
+rst:0x1 (POWERON),boot:0x8 (SPI_FAST_FLASH_BOOT)\r
+\x1b[0;32mI (27) boot: ESP-IDF v5.1.2 2nd stage bootloader\x1b[0m\r
+\x1b[0;32mI (27) boot: compile time Jan 10 2024 10:12:44\x1b[0m\r
+\x1b[0;32mI (577) cpu_start: Pro cpu start user code\x1b[0m\r
+\x1b[0;33mW (612) spi_flash: Detected size(8192k) larger than the size in the binary image header(2048k). Using the size in the binary image header.\x1b[0m\r
+\x1b[0;31mE (1042) wifi:sta is connecting, return error\x1b[0m\r
+\x1b[0;32mI (1043) main_task: Calling app_main()\x1b[0m\r
+D (1050) nvs: nvs_open_from_partition misc 1\r
+";
+
+    fn filter(min_level: LogLevel, allow: &[&str], deny: &[&str]) -> LogFilter {
+        LogFilter {
+            min_level,
+            allow_tags: allow.iter().map(|tag| tag.to_string()).collect(),
+            deny_tags: deny.iter().map(|tag| tag.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn parses_a_captured_boot_log() {
+        let records: Vec<_> = BOOT_LOG
+            .split_inclusive('\n')
+            .filter_map(parse_line)
+            .collect();
+        let summary: Vec<_> = records
+            .iter()
+            .map(|r| (r.level, r.tick, r.tag.as_str()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (LogLevel::Info, Some(27), "boot"),
+                (LogLevel::Info, Some(27), "boot"),
+                (LogLevel::Info, Some(577), "cpu_start"),
+                (LogLevel::Warn, Some(612), "spi_flash"),
+                (LogLevel::Error, Some(1042), "wifi"),
+                (LogLevel::Info, Some(1043), "main_task"),
+                (LogLevel::Debug, Some(1050), "nvs"),
+            ]
+        );
+        assert_eq!(records[0].message, "ESP-IDF v5.1.2 2nd stage bootloader");
+        assert_eq!(records[4].message, "sta is connecting, return error");
+        assert!(!records.iter().any(|r| r.message.contains('\x1b')));
+    }
+
+    #[test]
+    fn keeps_system_time_stamps_as_text() {
+        let record = parse_line("W (12:34:56.789) app_main: low memory").unwrap();
+        assert_eq!(record.time, "12:34:56.789");
+        assert_eq!(record.tick, None);
+        assert!(parse_line("Guru Meditation Error: Core  0 panic'ed").is_none());
+        assert!(parse_line("X (12) tag: unknown level").is_none());
+        assert_eq!(strip_ansi("\x1b[1;31mred\x1b[0m"), "red");
+    }
+
+    #[test]
+    fn filters_by_level_and_tag() {
+        let warn = parse_line("W (1) spi_flash: slow").unwrap();
+        let info = parse_line("I (2) wifi: up").unwrap();
+        let error = parse_line("E (3) wifi: down").unwrap();
+
+        let only_warnings = filter(LogLevel::Warn, &[], &[]);
+        assert!(only_warnings.accepts(&warn) && only_warnings.accepts(&error));
+        assert!(!only_warnings.accepts(&info));
+
+        let wifi = filter(LogLevel::Verbose, &["wifi"], &[]);
+        assert!(wifi.accepts(&info) && !wifi.accepts(&warn));
+
+        let deny_wins = filter(LogLevel::Verbose, &["wifi"], &["wifi"]);
+        assert!(!deny_wins.accepts(&info));
+
+        assert_eq!(LogLevel::parse("Warning").unwrap(), LogLevel::Warn);
+        assert!(LogLevel::parse("trace").is_err());
+    }
+}
@@ -0,0 +1,216 @@
+use crate::esp_image;
+use crate::esp_rom::Chip;
+
+pub struct MergeInput {
+    pub address: u32,
+    pub path: String,
+    pub data: Vec<u8>,
+}
+
+#[derive(Default, Clone, Copy)]
+pub struct FlashParams {
+    pub mode: Option<u8>,
+    pub freq: Option<u8>,
+    pub size: Option<u8>,
+}
+
+impl FlashParams {
+    fn is_empty(&self) -> bool {
+        self.mode.is_none() && self.freq.is_none() && self.size.is_none()
+    }
+}
+
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct MergeRegion {
+    pub kind: String,
+    pub address: u32,
+    pub size: u32,
+    pub path: String,
+    pub patched: bool,
+}
+
+/// Fails when two `(address, len, name)` regions share any byte.
+fn check_overlaps(regions: &[(u32, usize, &str)]) -> Result<(), String> {
+    let mut sorted: Vec<_> = regions.iter().collect();
+    sorted.sort_by_key(|(address, _, _)| *address);
+
+    for pair in sorted.windows(2) {
+        let (prev_address, prev_len, prev_name) = pair[0];
+        let (address, _, name) = pair[1];
+        let prev_end = u64::from(*prev_address) + *prev_len as u64;
+        if prev_end > u64::from(*address) {
+            return Err(format!(
+                "{name} at 0x{address:08X} overlaps {prev_name} (0x{prev_address:08X}-0x{:08X})",
+                prev_end - 1
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Largest flash the merged image may cover: the chosen size, or the biggest
+/// one the chip's image header can describe when the size is kept.
+pub fn flash_limit(chip: Chip, size: &str) -> Result<u32, String> {
+    if esp_image::parse_flash_size(chip, size)?.is_none() {
+        let largest = if chip == Chip::Esp8266 {
+            "16MB"
+        } else {
+            "128MB"
+        };
+        return Ok(esp_image::flash_size_bytes(largest).unwrap_or(u32::MAX));
+    }
+    esp_image::flash_size_bytes(size).ok_or_else(|| format!("invalid flash_size: {size}"))
+}
+
+/// Lays the inputs out from offset 0, filling gaps with 0xFF like
+/// `esptool.py merge_bin`, and patches the bootloader header when asked to.
+/// Images must end within `flash_limit` bytes, so a mistyped address cannot
+/// pad the output out to gigabytes.
+pub fn merge_images(
+    chip: Chip,
+    mut inputs: Vec<MergeInput>,
+    params: FlashParams,
+    flash_limit: u32,
+) -> Result<(Vec<u8>, Vec<MergeRegion>), String> {
+    if inputs.is_empty() {
+        return Err("no firmware selected".to_string());
+    }
+    if let Some(input) = inputs
+        .iter()
+        .find(|input| u64::from(input.address) + input.data.len() as u64 > u64::from(flash_limit))
+    {
+        return Err(format!(
+            "{} at 0x{:08X} does not fit in a 0x{flash_limit:X} byte flash",
+            input.path, input.address
+        ));
+    }
+
+    let spans: Vec<_> = inputs
+        .iter()
+        .map(|input| (input.address, input.data.len(), input.path.as_str()))
+        .collect();
+    check_overlaps(&spans)?;
+    inputs.sort_by_key(|input| input.address);
+
+    let mut merged = Vec::new();
+    let mut regions = Vec::with_capacity(inputs.len() * 2);
+    for mut input in inputs {
+        let start = input.address as usize;
+        if start > merged.len() {
+            regions.push(MergeRegion {
+                kind: "padding".to_string(),
+                address: merged.len() as u32,
+                size: (start - merged.len()) as u32,
+                path: String::new(),
+                patched: false,
+            });
+            merged.resize(start, 0xFF);
+        }
+
+        let patched = input.address == chip.bootloader_offset()
+            && !params.is_empty()
+            && esp_image::patch_flash_params(
+                &mut input.data,
+                chip.has_extended_image_header(),
+                params.mode,
+                params.freq,
+                params.size,
+            )?;
+
+        regions.push(MergeRegion {
+            kind: "image".to_string(),
+            address: input.address,
+            size: input.data.len() as u32,
+            path: input.path,
+            patched,
+        });
+        merged.extend_from_slice(&input.data);
+    }
+
+    Ok((merged, regions))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(address: u32, path: &str, data: Vec<u8>) -> MergeInput {
+        MergeInput {
+            address,
+            path: path.to_string(),
+            data,
+        }
+    }
+
+    #[test]
+    fn pads_gaps_with_erased_flash() {
+        let (merged, regions) = merge_images(
+            Chip::Esp32c3,
+            vec![
+                input(0x8000, "partitions.bin", vec![1; 16]),
+                input(0x10, "bootloader.bin", vec![2; 4]),
+            ],
+            FlashParams::default(),
+            0x40_0000,
+        )
+        .unwrap();
+        assert_eq!(merged.len(), 0x8010);
+        let kinds: Vec<_> = regions.iter().map(|region| region.kind.as_str()).collect();
+        assert_eq!(kinds, ["padding", "image", "padding", "image"]);
+        assert_eq!(merged[0], 0xFF);
+        assert_eq!(merged[0x10], 2);
+        assert_eq!(merged[0x14], 0xFF);
+        assert_eq!(merged[0x8000], 1);
+    }
+
+    #[test]
+    fn rejects_overlaps_and_addresses_past_flash() {
+        let err = merge_images(
+            Chip::Esp32,
+            vec![
+                input(0x0, "a.bin", vec![1; 16]),
+                input(0x8, "b.bin", vec![2; 4]),
+            ],
+            FlashParams::default(),
+            0x40_0000,
+        )
+        .unwrap_err();
+        assert!(err.contains("overlaps"), "{err}");
+
+        let limit = flash_limit(Chip::Esp32, "4MB").unwrap();
+        assert_eq!(limit, 0x40_0000);
+        let err = merge_images(
+            Chip::Esp32,
+            vec![input(0x4000_0000, "app.bin", vec![0; 4])],
+            FlashParams::default(),
+            limit,
+        )
+        .unwrap_err();
+        assert!(err.contains("does not fit"), "{err}");
+        assert!(merge_images(
+            Chip::Esp32,
+            vec![input(0x3F_FFFC, "tail.bin", vec![0; 4])],
+            FlashParams::default(),
+            limit,
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn flash_limits() {
+        assert_eq!(
+            flash_limit(Chip::Esp32s3, "keep").unwrap(),
+            128 * 1024 * 1024
+        );
+        assert_eq!(
+            flash_limit(Chip::Esp8266, "keep").unwrap(),
+            16 * 1024 * 1024
+        );
+        assert_eq!(
+            flash_limit(Chip::Esp8266, "2MB-C1").unwrap(),
+            2 * 1024 * 1024
+        );
+        assert_eq!(flash_limit(Chip::Esp8266, "512KB").unwrap(), 512 * 1024);
+        assert!(flash_limit(Chip::Esp32, "3MB").is_err());
+    }
+}
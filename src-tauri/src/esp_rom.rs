@@ -0,0 +1,1106 @@
+use md5::{Digest, Md5};
+use serialport::SerialPort;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::esp_image;
+
+const SLIP_END: u8 = 0xC0;
+const SLIP_ESC: u8 = 0xDB;
+const SLIP_ESC_END: u8 = 0xDC;
+const SLIP_ESC_ESC: u8 = 0xDD;
+
+const ESP_FLASH_BEGIN: u8 = 0x02;
+const ESP_FLASH_DATA: u8 = 0x03;
+const ESP_FLASH_END: u8 = 0x04;
+const ESP_MEM_BEGIN: u8 = 0x05;
+const ESP_MEM_END: u8 = 0x06;
+const ESP_MEM_DATA: u8 = 0x07;
+const ESP_SYNC: u8 = 0x08;
+const ESP_READ_REG: u8 = 0x0A;
+const ESP_SPI_SET_PARAMS: u8 = 0x0B;
+const ESP_SPI_ATTACH: u8 = 0x0D;
+const ESP_CHANGE_BAUDRATE: u8 = 0x0F;
+const ESP_SPI_FLASH_MD5: u8 = 0x13;
+
+const ESP_CHECKSUM_MAGIC: u8 = 0xEF;
+const FLASH_WRITE_SIZE: usize = 0x400;
+const MEM_WRITE_SIZE: usize = 0x1800;
+const FLASH_SECTOR_SIZE: u32 = 0x1000;
+const FLASH_SECTORS_PER_BLOCK: u32 = 16;
+const SPI_PARAMS_FLASH_SIZE: u32 = 16 * 1024 * 1024;
+const CHIP_DETECT_MAGIC_REG_ADDR: u32 = 0x4000_1000;
+
+pub const ROM_BAUD_RATE: u32 = 115_200;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(3);
+const SYNC_TIMEOUT: Duration = Duration::from_millis(100);
+const MEM_END_TIMEOUT: Duration = Duration::from_millis(50);
+const ERASE_TIMEOUT_PER_MB: Duration = Duration::from_secs(30);
+const MD5_TIMEOUT_PER_MB: Duration = Duration::from_secs(8);
+const CONNECT_ATTEMPTS: usize = 7;
+const SYNC_ATTEMPTS: usize = 5;
+
+/// Serial transport used by the ROM loader client.
+///
+/// Implemented for real serial ports; a scripted fake that replays recorded
+/// ROM responses can implement it as well to exercise the protocol offline.
+pub trait RomPort: Read + Write {
+    fn set_dtr(&mut self, level: bool) -> io::Result<()>;
+    fn set_rts(&mut self, level: bool) -> io::Result<()>;
+    fn set_baud_rate(&mut self, baud_rate: u32) -> io::Result<()>;
+    fn clear_input(&mut self) -> io::Result<()>;
+}
+
+impl RomPort for Box<dyn SerialPort> {
+    fn set_dtr(&mut self, level: bool) -> io::Result<()> {
+        self.write_data_terminal_ready(level).map_err(io::Error::from)
+    }
+
+    fn set_rts(&mut self, level: bool) -> io::Result<()> {
+        self.write_request_to_send(level).map_err(io::Error::from)
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> io::Result<()> {
+        SerialPort::set_baud_rate(self.as_mut(), baud_rate).map_err(io::Error::from)
+    }
+
+    fn clear_input(&mut self) -> io::Result<()> {
+        self.clear(serialport::ClearBuffer::Input)
+            .map_err(io::Error::from)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Chip {
+    Esp8266,
+    Esp32,
+    Esp32s2,
+    Esp32s3,
+    Esp32c2,
+    Esp32c3,
+    Esp32c6,
+    Esp32h2,
+}
+
+impl Chip {
+    fn from_magic(magic: u32) -> Option<Self> {
+        match magic {
+            0xFFF0_C101 => Some(Chip::Esp8266),
+            0x00F0_1D83 => Some(Chip::Esp32),
+            0x0000_07C6 => Some(Chip::Esp32s2),
+            0x0000_0009 => Some(Chip::Esp32s3),
+            0x6F51_306F | 0x7C41_A06F => Some(Chip::Esp32c2),
+            0x6921_506F | 0x1B31_506F | 0x4881_606F | 0x4361_606F => Some(Chip::Esp32c3),
+            0x2CE0_806F => Some(Chip::Esp32c6),
+            0xD7B7_3E80 => Some(Chip::Esp32h2),
+            _ => None,
+        }
+    }
+
+    /// Accepts the names from `chip.list.json` (`ESP32C3`) as well as the
+    /// dashed form (`esp32-c3`).
+    pub fn from_name(name: &str) -> Option<Self> {
+        let normalized: String = name
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .map(|c| c.to_ascii_uppercase())
+            .collect();
+        match normalized.as_str() {
+            "ESP8266" => Some(Chip::Esp8266),
+            "ESP32" => Some(Chip::Esp32),
+            "ESP32S2" => Some(Chip::Esp32s2),
+            "ESP32S3" => Some(Chip::Esp32s3),
+            "ESP32C2" => Some(Chip::Esp32c2),
+            "ESP32C3" => Some(Chip::Esp32c3),
+            "ESP32C6" => Some(Chip::Esp32c6),
+            "ESP32H2" => Some(Chip::Esp32h2),
+            _ => None,
+        }
+    }
+
+    /// Chip id stored in the extended header of an application image.
+    pub fn from_image_chip_id(chip_id: u16) -> Option<Self> {
+        match chip_id {
+            0 => Some(Chip::Esp32),
+            2 => Some(Chip::Esp32s2),
+            5 => Some(Chip::Esp32c3),
+            9 => Some(Chip::Esp32s3),
+            12 => Some(Chip::Esp32c2),
+            13 => Some(Chip::Esp32c6),
+            16 => Some(Chip::Esp32h2),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Chip::Esp8266 => "ESP8266",
+            Chip::Esp32 => "ESP32",
+            Chip::Esp32s2 => "ESP32-S2",
+            Chip::Esp32s3 => "ESP32-S3",
+            Chip::Esp32c2 => "ESP32-C2",
+            Chip::Esp32c3 => "ESP32-C3",
+            Chip::Esp32c6 => "ESP32-C6",
+            Chip::Esp32h2 => "ESP32-H2",
+        }
+    }
+
+    pub fn bootloader_offset(self) -> u32 {
+        match self {
+            Chip::Esp32 | Chip::Esp32s2 => 0x1000,
+            _ => 0x0,
+        }
+    }
+
+    /// ESP8266 images use the legacy 8-byte header without the extended block.
+    pub fn has_extended_image_header(self) -> bool {
+        self != Chip::Esp8266
+    }
+
+    fn status_len(self) -> usize {
+        match self {
+            Chip::Esp8266 => 2,
+            _ => 4,
+        }
+    }
+
+    fn supports_rom_spi_attach(self) -> bool {
+        self != Chip::Esp8266
+    }
+
+    fn supports_rom_change_baud(self) -> bool {
+        self != Chip::Esp8266
+    }
+
+    fn flash_begin_has_encrypt_flag(self) -> bool {
+        !matches!(self, Chip::Esp8266 | Chip::Esp32)
+    }
+}
+
+/// Incremental SLIP frame decoder. Bytes outside of a frame (boot log noise)
+/// are discarded.
+#[derive(Default)]
+pub struct SlipDecoder {
+    frame: Vec<u8>,
+    in_frame: bool,
+    escaped: bool,
+}
+
+impl SlipDecoder {
+    pub fn push(&mut self, byte: u8) -> Option<Vec<u8>> {
+        if !self.in_frame {
+            if byte == SLIP_END {
+                self.in_frame = true;
+                self.frame.clear();
+            }
+            return None;
+        }
+
+        if self.escaped {
+            self.escaped = false;
+            match byte {
+                SLIP_ESC_END => self.frame.push(SLIP_END),
+                SLIP_ESC_ESC => self.frame.push(SLIP_ESC),
+                _ => {
+                    self.in_frame = false;
+                    self.frame.clear();
+                }
+            }
+            return None;
+        }
+
+        match byte {
+            SLIP_END if self.frame.is_empty() => {}
+            SLIP_END => {
+                self.in_frame = false;
+                return Some(std::mem::take(&mut self.frame));
+            }
+            SLIP_ESC => self.escaped = true,
+            _ => self.frame.push(byte),
+        }
+        None
+    }
+}
+
+pub fn slip_encode(payload: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(payload.len() + 2);
+    encoded.push(SLIP_END);
+    for &byte in payload {
+        match byte {
+            SLIP_END => encoded.extend_from_slice(&[SLIP_ESC, SLIP_ESC_END]),
+            SLIP_ESC => encoded.extend_from_slice(&[SLIP_ESC, SLIP_ESC_ESC]),
+            _ => encoded.push(byte),
+        }
+    }
+    encoded.push(SLIP_END);
+    encoded
+}
+
+fn checksum(data: &[u8]) -> u32 {
+    u32::from(data.iter().fold(ESP_CHECKSUM_MAGIC, |acc, byte| acc ^ byte))
+}
+
+fn rom_error_text(code: u8) -> &'static str {
+    match code {
+        0x05 => "received message is invalid",
+        0x06 => "failed to act on received message",
+        0x07 => "invalid CRC in message",
+        0x08 => "flash write error",
+        0x09 => "flash read error",
+        0x0A => "flash read length error",
+        0x0B => "deflate error",
+        _ => "unknown error",
+    }
+}
+
+fn command_name(op: u8) -> &'static str {
+    match op {
+        ESP_FLASH_BEGIN => "FLASH_BEGIN",
+        ESP_FLASH_DATA => "FLASH_DATA",
+        ESP_FLASH_END => "FLASH_END",
+        ESP_MEM_BEGIN => "MEM_BEGIN",
+        ESP_MEM_END => "MEM_END",
+        ESP_MEM_DATA => "MEM_DATA",
+        ESP_SYNC => "SYNC",
+        ESP_READ_REG => "READ_REG",
+        ESP_SPI_SET_PARAMS => "SPI_SET_PARAMS",
+        ESP_SPI_ATTACH => "SPI_ATTACH",
+        ESP_CHANGE_BAUDRATE => "CHANGE_BAUDRATE",
+        ESP_SPI_FLASH_MD5 => "SPI_FLASH_MD5",
+        _ => "UNKNOWN",
+    }
+}
+
+fn hex_digest(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn pack_u32(values: &[u32]) -> Vec<u8> {
+    values.iter().flat_map(|value| value.to_le_bytes()).collect()
+}
+
+fn timeout_per_mb(per_mb: Duration, size: u32) -> Duration {
+    let scaled = per_mb.mul_f64(f64::from(size) / (1024.0 * 1024.0));
+    scaled.max(DEFAULT_TIMEOUT)
+}
+
+/// The ESP8266 ROM erases more than requested when the region spans a 64K
+/// block boundary, so the size passed to FLASH_BEGIN has to be compensated.
+fn esp8266_erase_size(offset: u32, size: u32) -> u32 {
+    let num_sectors = size.div_ceil(FLASH_SECTOR_SIZE);
+    let start_sector = offset / FLASH_SECTOR_SIZE;
+    let head_sectors =
+        (FLASH_SECTORS_PER_BLOCK - start_sector % FLASH_SECTORS_PER_BLOCK).min(num_sectors);
+
+    if num_sectors < 2 * head_sectors {
+        num_sectors.div_ceil(2) * FLASH_SECTOR_SIZE
+    } else {
+        (num_sectors - head_sectors) * FLASH_SECTOR_SIZE
+    }
+}
+
+struct RomResponse {
+    value: u32,
+    data: Vec<u8>,
+}
+
+pub struct RomClient<P: RomPort> {
+    port: P,
+    decoder: SlipDecoder,
+    pending: VecDeque<u8>,
+    chip: Option<Chip>,
+}
+
+impl<P: RomPort> RomClient<P> {
+    pub fn new(port: P) -> Self {
+        Self {
+            port,
+            decoder: SlipDecoder::default(),
+            pending: VecDeque::new(),
+            chip: None,
+        }
+    }
+
+    fn io_error(action: &str, err: io::Error) -> String {
+        format!("failed to {action}: {err}")
+    }
+
+    fn read_frame(&mut self, timeout: Duration) -> Result<Vec<u8>, String> {
+        let deadline = Instant::now() + timeout;
+        let mut buffer = [0u8; 256];
+
+        loop {
+            while let Some(byte) = self.pending.pop_front() {
+                if let Some(frame) = self.decoder.push(byte) {
+                    return Ok(frame);
+                }
+            }
+
+            if Instant::now() >= deadline {
+                return Err("timed out waiting for response from ROM loader".to_string());
+            }
+
+            match self.port.read(&mut buffer) {
+                Ok(0) => thread::sleep(Duration::from_millis(1)),
+                Ok(size) => self.pending.extend(&buffer[..size]),
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+                    ) => {}
+                Err(err) => return Err(Self::io_error("read from serial port", err)),
+            }
+        }
+    }
+
+    fn command(
+        &mut self,
+        op: u8,
+        data: &[u8],
+        checksum: u32,
+        timeout: Duration,
+    ) -> Result<RomResponse, String> {
+        let mut packet = Vec::with_capacity(8 + data.len());
+        packet.push(0x00);
+        packet.push(op);
+        packet.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        packet.extend_from_slice(&checksum.to_le_bytes());
+        packet.extend_from_slice(data);
+
+        self.port
+            .write_all(&slip_encode(&packet))
+            .and_then(|_| self.port.flush())
+            .map_err(|e| Self::io_error("write to serial port", e))?;
+
+        // Stale responses (e.g. extra SYNC replies) can precede the one we
+        // are waiting for, so skip frames until the opcode matches.
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let frame = self.read_frame(remaining).map_err(|e| {
+                format!("{} failed: {e}", command_name(op))
+            })?;
+            if frame.len() < 8 || frame[0] != 0x01 || frame[1] != op {
+                continue;
+            }
+
+            let size = usize::from(u16::from_le_bytes([frame[2], frame[3]]));
+            let value = u32::from_le_bytes([frame[4], frame[5], frame[6], frame[7]]);
+            let data = frame[8..].iter().take(size).copied().collect();
+            return Ok(RomResponse { value, data });
+        }
+    }
+
+    /// Sends a command and fails on a non-zero status. The returned data has
+    /// the status bytes stripped.
+    fn check_command(
+        &mut self,
+        op: u8,
+        data: &[u8],
+        checksum: u32,
+        timeout: Duration,
+    ) -> Result<RomResponse, String> {
+        let mut response = self.command(op, data, checksum, timeout)?;
+        let status_len = self
+            .chip
+            .map(Chip::status_len)
+            .unwrap_or(if response.data.len() >= 4 { 4 } else { 2 });
+        if response.data.len() < status_len {
+            return Err(format!(
+                "{} failed: response is missing status bytes",
+                command_name(op)
+            ));
+        }
+
+        let status = &response.data[response.data.len() - status_len..];
+        if status[0] != 0 {
+            return Err(format!(
+                "{} failed: {} (0x{:02X})",
+                command_name(op),
+                rom_error_text(status[1]),
+                status[1]
+            ));
+        }
+        response.data.truncate(response.data.len() - status_len);
+        Ok(response)
+    }
+
+    /// Classic DTR/RTS auto-reset circuit: pulse EN low while holding IO0 low.
+    pub fn reset_into_bootloader(&mut self) -> Result<(), String> {
+        self.port
+            .set_dtr(false)
+            .and_then(|_| self.port.set_rts(true))
+            .map_err(|e| Self::io_error("set reset signals", e))?;
+        thread::sleep(Duration::from_millis(100));
+        self.port
+            .set_dtr(true)
+            .and_then(|_| self.port.set_rts(false))
+            .map_err(|e| Self::io_error("set boot signals", e))?;
+        thread::sleep(Duration::from_millis(50));
+        self.port
+            .set_dtr(false)
+            .map_err(|e| Self::io_error("release boot signal", e))
+    }
+
+    pub fn hard_reset(&mut self) -> Result<(), String> {
+        self.port
+            .set_rts(true)
+            .map_err(|e| Self::io_error("set reset signal", e))?;
+        thread::sleep(Duration::from_millis(100));
+        self.port
+            .set_rts(false)
+            .map_err(|e| Self::io_error("release reset signal", e))
+    }
+
+    pub fn sync(&mut self) -> Result<(), String> {
+        let mut payload = vec![0x07, 0x07, 0x12, 0x20];
+        payload.extend_from_slice(&[0x55; 32]);
+        self.check_command(ESP_SYNC, &payload, 0, SYNC_TIMEOUT)?;
+
+        // The ROM answers a single SYNC with several replies; drop the rest.
+        while self.read_frame(SYNC_TIMEOUT).is_ok() {}
+        Ok(())
+    }
+
+    pub fn connect(&mut self) -> Result<(), String> {
+        let mut last_error = String::from("no response");
+        for _ in 0..CONNECT_ATTEMPTS {
+            self.reset_into_bootloader()?;
+            let _ = self.port.clear_input();
+            self.pending.clear();
+            self.decoder = SlipDecoder::default();
+
+            for _ in 0..SYNC_ATTEMPTS {
+                match self.sync() {
+                    Ok(()) => return Ok(()),
+                    Err(err) => last_error = err,
+                }
+            }
+        }
+        Err(format!("failed to connect to ESP ROM loader: {last_error}"))
+    }
+
+    pub fn read_reg(&mut self, address: u32) -> Result<u32, String> {
+        self.check_command(ESP_READ_REG, &address.to_le_bytes(), 0, DEFAULT_TIMEOUT)
+            .map(|response| response.value)
+    }
+
+    pub fn detect_chip(&mut self) -> Result<Chip, String> {
+        let magic = self.read_reg(CHIP_DETECT_MAGIC_REG_ADDR)?;
+        let chip = Chip::from_magic(magic)
+            .ok_or_else(|| format!("unrecognised chip magic value 0x{magic:08X}"))?;
+        self.chip = Some(chip);
+        Ok(chip)
+    }
+
+    fn require_chip(&self) -> Result<Chip, String> {
+        self.chip
+            .ok_or_else(|| "chip type has not been detected".to_string())
+    }
+
+    pub fn change_baud(&mut self, baud_rate: u32) -> Result<(), String> {
+        if !self.require_chip()?.supports_rom_change_baud() {
+            return Err("the ESP8266 ROM loader cannot change baud rate".to_string());
+        }
+        self.check_command(
+            ESP_CHANGE_BAUDRATE,
+            &pack_u32(&[baud_rate, 0]),
+            0,
+            DEFAULT_TIMEOUT,
+        )?;
+        self.port
+            .set_baud_rate(baud_rate)
+            .map_err(|e| Self::io_error("change serial baud rate", e))?;
+        thread::sleep(Duration::from_millis(50));
+        let _ = self.port.clear_input();
+        self.pending.clear();
+        Ok(())
+    }
+
+    pub fn spi_attach(&mut self) -> Result<(), String> {
+        if !self.require_chip()?.supports_rom_spi_attach() {
+            return Ok(());
+        }
+        // ROM loaders expect an extra "is legacy" word after the pin config.
+        self.check_command(ESP_SPI_ATTACH, &pack_u32(&[0, 0]), 0, DEFAULT_TIMEOUT)?;
+        Ok(())
+    }
+
+    pub fn spi_set_params(&mut self, total_size: u32) -> Result<(), String> {
+        self.check_command(
+            ESP_SPI_SET_PARAMS,
+            &pack_u32(&[0, total_size, 64 * 1024, FLASH_SECTOR_SIZE, 256, 0xFFFF]),
+            0,
+            DEFAULT_TIMEOUT,
+        )?;
+        Ok(())
+    }
+
+    pub fn flash_begin(&mut self, size: u32, offset: u32) -> Result<(), String> {
+        let chip = self.require_chip()?;
+        let num_blocks = (size as usize).div_ceil(FLASH_WRITE_SIZE) as u32;
+        let erase_size = if chip == Chip::Esp8266 {
+            esp8266_erase_size(offset, size)
+        } else {
+            size
+        };
+
+        let mut params = vec![erase_size, num_blocks, FLASH_WRITE_SIZE as u32, offset];
+        if chip.flash_begin_has_encrypt_flag() {
+            params.push(0);
+        }
+        self.check_command(
+            ESP_FLASH_BEGIN,
+            &pack_u32(&params),
+            0,
+            timeout_per_mb(ERASE_TIMEOUT_PER_MB, size),
+        )?;
+        Ok(())
+    }
+
+    pub fn flash_block(&mut self, block: &[u8], sequence: u32) -> Result<(), String> {
+        let mut data = block.to_vec();
+        data.resize(FLASH_WRITE_SIZE, 0xFF);
+
+        let mut payload = pack_u32(&[data.len() as u32, sequence, 0, 0]);
+        payload.extend_from_slice(&data);
+        self.check_command(ESP_FLASH_DATA, &payload, checksum(&data), DEFAULT_TIMEOUT)?;
+        Ok(())
+    }
+
+    pub fn flash_finish(&mut self, reboot: bool) -> Result<(), String> {
+        self.check_command(
+            ESP_FLASH_END,
+            &pack_u32(&[u32::from(!reboot)]),
+            0,
+            DEFAULT_TIMEOUT,
+        )?;
+        Ok(())
+    }
+
+    /// MD5 of a flash region as computed by the chip. The ROM replies with
+    /// the digest in hex, a flasher stub with the raw bytes.
+    pub fn flash_md5(&mut self, offset: u32, size: u32) -> Result<[u8; 16], String> {
+        let response = self.check_command(
+            ESP_SPI_FLASH_MD5,
+            &pack_u32(&[offset, size, 0, 0]),
+            0,
+            timeout_per_mb(MD5_TIMEOUT_PER_MB, size),
+        )?;
+        let mut digest = [0u8; 16];
+        match response.data.len() {
+            16 => digest.copy_from_slice(&response.data),
+            32 => {
+                for (byte, pair) in digest.iter_mut().zip(response.data.chunks(2)) {
+                    let high = char::from(pair[0]).to_digit(16);
+                    let low = char::from(pair[1]).to_digit(16);
+                    *byte = match (high, low) {
+                        (Some(high), Some(low)) => (high << 4 | low) as u8,
+                        _ => return Err("SPI_FLASH_MD5 failed: digest is not hex".to_string()),
+                    };
+                }
+            }
+            len => {
+                return Err(format!(
+                    "SPI_FLASH_MD5 failed: unexpected digest length {len}"
+                ))
+            }
+        }
+        Ok(digest)
+    }
+
+    pub fn mem_begin(&mut self, size: u32, offset: u32) -> Result<(), String> {
+        let num_blocks = (size as usize).div_ceil(MEM_WRITE_SIZE) as u32;
+        self.check_command(
+            ESP_MEM_BEGIN,
+            &pack_u32(&[size, num_blocks, MEM_WRITE_SIZE as u32, offset]),
+            0,
+            DEFAULT_TIMEOUT,
+        )?;
+        Ok(())
+    }
+
+    pub fn mem_block(&mut self, data: &[u8], sequence: u32) -> Result<(), String> {
+        let mut payload = pack_u32(&[data.len() as u32, sequence, 0, 0]);
+        payload.extend_from_slice(data);
+        self.check_command(ESP_MEM_DATA, &payload, checksum(data), DEFAULT_TIMEOUT)?;
+        Ok(())
+    }
+
+    /// Jumps to `entry`. The ROM may start the loaded code before the reply
+    /// makes it out, so a missing response is not treated as a failure.
+    pub fn mem_finish(&mut self, entry: u32) -> Result<(), String> {
+        let payload = pack_u32(&[u32::from(entry == 0), entry]);
+        match self.check_command(ESP_MEM_END, &payload, 0, MEM_END_TIMEOUT) {
+            Ok(_) => Ok(()),
+            Err(err) if err.contains("timed out") => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+pub struct FlashImage {
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+pub enum FlashEvent {
+    Status(String),
+    Progress {
+        index: usize,
+        address: u32,
+        written: usize,
+        total: usize,
+    },
+}
+
+fn prepare_session<P: RomPort>(
+    client: &mut RomClient<P>,
+    baud_rate: u32,
+    on_event: &mut impl FnMut(FlashEvent),
+) -> Result<Chip, String> {
+    on_event(FlashEvent::Status("Connecting...".to_string()));
+    client.connect()?;
+    let chip = client.detect_chip()?;
+    on_event(FlashEvent::Status(format!("Detected chip: {}", chip.name())));
+
+    if baud_rate != ROM_BAUD_RATE {
+        if chip.supports_rom_change_baud() {
+            client.change_baud(baud_rate)?;
+            on_event(FlashEvent::Status(format!("Changed baud rate to {baud_rate}")));
+        } else {
+            on_event(FlashEvent::Status(format!(
+                "{} ROM keeps {ROM_BAUD_RATE} baud",
+                chip.name()
+            )));
+        }
+    }
+    Ok(chip)
+}
+
+/// Connects to the ROM loader, writes every image and hard-resets the chip.
+pub fn flash_images<P: RomPort>(
+    port: P,
+    baud_rate: u32,
+    flash_mode: Option<u8>,
+    mut images: Vec<FlashImage>,
+    mut on_event: impl FnMut(FlashEvent),
+) -> Result<Chip, String> {
+    let mut client = RomClient::new(port);
+    let chip = prepare_session(&mut client, baud_rate, &mut on_event)?;
+
+    client.spi_attach()?;
+    if chip != Chip::Esp8266 {
+        client.spi_set_params(SPI_PARAMS_FLASH_SIZE)?;
+    }
+
+    for (index, image) in images.iter_mut().enumerate() {
+        if image.address == chip.bootloader_offset() {
+            if let Some(mode) = flash_mode {
+                if esp_image::patch_flash_params(
+                    &mut image.data,
+                    chip.has_extended_image_header(),
+                    Some(mode),
+                    None,
+                    None,
+                )? {
+                    on_event(FlashEvent::Status(format!(
+                        "Patched bootloader header at 0x{:08X}",
+                        image.address
+                    )));
+                }
+            }
+        }
+
+        let padding = (4 - image.data.len() % 4) % 4;
+        image.data.extend(std::iter::repeat_n(0xFF, padding));
+
+        let total = image.data.len();
+        on_event(FlashEvent::Status(format!(
+            "Writing {total} bytes at 0x{:08X}...",
+            image.address
+        )));
+        client.flash_begin(total as u32, image.address)?;
+
+        let mut written = 0;
+        for (sequence, block) in image.data.chunks(FLASH_WRITE_SIZE).enumerate() {
+            client.flash_block(block, sequence as u32)?;
+            written += block.len();
+            on_event(FlashEvent::Progress {
+                index,
+                address: image.address,
+                written,
+                total,
+            });
+        }
+
+        // The ESP8266 ROM has no SPI_FLASH_MD5 command.
+        if chip != Chip::Esp8266 {
+            let expected: [u8; 16] = Md5::digest(&image.data).into();
+            let actual = client.flash_md5(image.address, total as u32)?;
+            if actual != expected {
+                return Err(format!(
+                    "flash verification failed at 0x{:08X}: MD5 {} != {}",
+                    image.address,
+                    hex_digest(&actual),
+                    hex_digest(&expected)
+                ));
+            }
+            on_event(FlashEvent::Status(format!(
+                "Verified 0x{:08X} (MD5 {})",
+                image.address,
+                hex_digest(&actual)
+            )));
+        }
+    }
+
+    client.flash_finish(true)?;
+    client.hard_reset()?;
+    on_event(FlashEvent::Status("Hard resetting via RTS pin...".to_string()));
+    Ok(chip)
+}
+
+/// Loads an ESP application image into RAM through MEM_* and jumps to its
+/// entry point without touching flash.
+pub fn load_ram_image<P: RomPort>(
+    port: P,
+    baud_rate: u32,
+    image: &[u8],
+    mut on_event: impl FnMut(FlashEvent),
+) -> Result<Chip, String> {
+    let mut client = RomClient::new(port);
+    let chip = prepare_session(&mut client, baud_rate, &mut on_event)?;
+    let parsed = esp_image::parse(image, chip.has_extended_image_header())?;
+    let total = parsed.segments.iter().map(|segment| segment.data.len()).sum();
+
+    let mut written = 0;
+    for (index, segment) in parsed.segments.iter().enumerate() {
+        on_event(FlashEvent::Status(format!(
+            "Loading {} bytes to 0x{:08X}...",
+            segment.data.len(),
+            segment.address
+        )));
+        client.mem_begin(segment.data.len() as u32, segment.address)?;
+        for (sequence, block) in segment.data.chunks(MEM_WRITE_SIZE).enumerate() {
+            client.mem_block(block, sequence as u32)?;
+            written += block.len();
+            on_event(FlashEvent::Progress {
+                index,
+                address: segment.address,
+                written,
+                total,
+            });
+        }
+    }
+
+    on_event(FlashEvent::Status(format!(
+        "Jumping to entry point 0x{:08X}",
+        parsed.header.entry
+    )));
+    client.mem_finish(parsed.header.entry)?;
+    Ok(chip)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ESP32C3_MAGIC: u32 = 0x6921_506F;
+    const OK: [u8; 4] = [0, 0, 0, 0];
+
+    /// Replays recorded ROM replies. Every command written must match the
+    /// next scripted opcode; its reply frames (if any) are then queued for
+    /// reading, after some boot log noise.
+    struct FakePort {
+        script: VecDeque<(u8, Vec<Vec<u8>>)>,
+        decoder: SlipDecoder,
+        output: VecDeque<u8>,
+        commands: Vec<(u8, Vec<u8>)>,
+        baud_rate: u32,
+    }
+
+    impl FakePort {
+        fn new(script: Vec<(u8, Vec<Vec<u8>>)>) -> Self {
+            Self {
+                script: script.into(),
+                decoder: SlipDecoder::default(),
+                output: VecDeque::new(),
+                commands: Vec::new(),
+                baud_rate: ROM_BAUD_RATE,
+            }
+        }
+    }
+
+    impl Read for FakePort {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.output.is_empty() {
+                thread::sleep(Duration::from_millis(1));
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "no data"));
+            }
+            let size = buf.len().min(self.output.len());
+            for (slot, byte) in buf.iter_mut().zip(self.output.drain(..size)) {
+                *slot = byte;
+            }
+            Ok(size)
+        }
+    }
+
+    impl Write for FakePort {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            for &byte in buf {
+                let Some(frame) = self.decoder.push(byte) else {
+                    continue;
+                };
+                let op = frame[1];
+                self.commands.push((op, frame[8..].to_vec()));
+                let (expected, replies) = self
+                    .script
+                    .pop_front()
+                    .unwrap_or_else(|| panic!("unscripted {}", command_name(op)));
+                assert_eq!(command_name(op), command_name(expected));
+                self.output.extend(b"ets Jun  8 2016 00:22:57\r\n");
+                for reply in replies {
+                    self.output.extend(slip_encode(&reply));
+                }
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl RomPort for FakePort {
+        fn set_dtr(&mut self, _level: bool) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn set_rts(&mut self, _level: bool) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn set_baud_rate(&mut self, baud_rate: u32) -> io::Result<()> {
+            self.baud_rate = baud_rate;
+            Ok(())
+        }
+
+        fn clear_input(&mut self) -> io::Result<()> {
+            self.output.clear();
+            Ok(())
+        }
+    }
+
+    fn reply(op: u8, value: u32, data: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0x01, op];
+        frame.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        frame.extend_from_slice(&value.to_le_bytes());
+        frame.extend_from_slice(data);
+        frame
+    }
+
+    fn ok(op: u8) -> (u8, Vec<Vec<u8>>) {
+        (op, vec![reply(op, 0, &OK)])
+    }
+
+    fn connect_script() -> Vec<(u8, Vec<Vec<u8>>)> {
+        let sync = reply(ESP_SYNC, 0, &OK);
+        vec![
+            (ESP_SYNC, vec![sync.clone(), sync.clone(), sync]),
+            (ESP_READ_REG, vec![reply(ESP_READ_REG, ESP32C3_MAGIC, &OK)]),
+        ]
+    }
+
+    fn md5_reply(digest: &[u8]) -> (u8, Vec<Vec<u8>>) {
+        let mut data = hex_digest(digest).into_bytes();
+        data.extend_from_slice(&OK);
+        (ESP_SPI_FLASH_MD5, vec![reply(ESP_SPI_FLASH_MD5, 0, &data)])
+    }
+
+    fn flash_script(image: &[u8], digest: &[u8]) -> Vec<(u8, Vec<Vec<u8>>)> {
+        let mut script = connect_script();
+        script.push(ok(ESP_CHANGE_BAUDRATE));
+        script.push(ok(ESP_SPI_ATTACH));
+        script.push(ok(ESP_SPI_SET_PARAMS));
+        script.push(ok(ESP_FLASH_BEGIN));
+        for _ in image.chunks(FLASH_WRITE_SIZE) {
+            script.push(ok(ESP_FLASH_DATA));
+        }
+        script.push(md5_reply(digest));
+        script.push(ok(ESP_FLASH_END));
+        script
+    }
+
+    fn flash(port: &mut FakePort, image: Vec<u8>) -> Result<Chip, String> {
+        flash_images(
+            port,
+            460_800,
+            None,
+            vec![FlashImage {
+                address: 0x10000,
+                data: image,
+            }],
+            |_| {},
+        )
+    }
+
+    impl RomPort for &mut FakePort {
+        fn set_dtr(&mut self, level: bool) -> io::Result<()> {
+            (**self).set_dtr(level)
+        }
+
+        fn set_rts(&mut self, level: bool) -> io::Result<()> {
+            (**self).set_rts(level)
+        }
+
+        fn set_baud_rate(&mut self, baud_rate: u32) -> io::Result<()> {
+            (**self).set_baud_rate(baud_rate)
+        }
+
+        fn clear_input(&mut self) -> io::Result<()> {
+            (**self).clear_input()
+        }
+    }
+
+    #[test]
+    fn slip_round_trip() {
+        let payload = [1, SLIP_END, SLIP_ESC, 2];
+        let encoded = slip_encode(&payload);
+        assert_eq!(
+            encoded,
+            [SLIP_END, 1, SLIP_ESC, SLIP_ESC_END, SLIP_ESC, SLIP_ESC_ESC, 2, SLIP_END]
+        );
+        let mut decoder = SlipDecoder::default();
+        let frames: Vec<_> = b"noise"
+            .iter()
+            .chain(&encoded)
+            .filter_map(|&byte| decoder.push(byte))
+            .collect();
+        assert_eq!(frames, [payload.to_vec()]);
+    }
+
+    #[test]
+    fn esp8266_erase_compensation() {
+        assert_eq!(esp8266_erase_size(0, 0x1000), 0x1000);
+        assert_eq!(esp8266_erase_size(0, 0x20000), 0x10000);
+        assert_eq!(esp8266_erase_size(0xF000, 0x3000), 0x2000);
+    }
+
+    #[test]
+    fn connects_after_a_missed_sync() {
+        let mut script = vec![(ESP_SYNC, Vec::new())];
+        script.extend(connect_script());
+        let mut client = RomClient::new(FakePort::new(script));
+        client.connect().unwrap();
+        assert_eq!(client.detect_chip().unwrap(), Chip::Esp32c3);
+        assert!(client.port.script.is_empty());
+        let (_, read_reg) = client.port.commands.last().unwrap();
+        assert_eq!(read_reg, &CHIP_DETECT_MAGIC_REG_ADDR.to_le_bytes());
+    }
+
+    #[test]
+    fn flashes_and_verifies() {
+        let image: Vec<u8> = (0..3000u32).map(|i| i as u8).collect();
+        let digest = Md5::digest(&image);
+        let mut port = FakePort::new(flash_script(&image, &digest));
+
+        assert_eq!(flash(&mut port, image.clone()).unwrap(), Chip::Esp32c3);
+        assert!(port.script.is_empty());
+        assert_eq!(port.baud_rate, 460_800);
+
+        let payload = |op| {
+            port.commands
+                .iter()
+                .filter(move |(command, _)| *command == op)
+                .map(|(_, data)| data.clone())
+        };
+        let begin = payload(ESP_FLASH_BEGIN).next().unwrap();
+        assert_eq!(begin, pack_u32(&[3000, 3, FLASH_WRITE_SIZE as u32, 0x10000, 0]));
+        let blocks: Vec<_> = payload(ESP_FLASH_DATA).collect();
+        assert_eq!(blocks.len(), 3);
+        assert_eq!(&blocks[2][..16], pack_u32(&[FLASH_WRITE_SIZE as u32, 2, 0, 0]));
+        assert_eq!(&blocks[2][16..16 + 952], &image[2048..]);
+        assert!(blocks[2][16 + 952..].iter().all(|&byte| byte == 0xFF));
+        let md5 = payload(ESP_SPI_FLASH_MD5).next().unwrap();
+        assert_eq!(md5, pack_u32(&[0x10000, 3000, 0, 0]));
+    }
+
+    #[test]
+    fn accepts_raw_stub_digest() {
+        let mut data = [0x5Au8; 16].to_vec();
+        data.extend_from_slice(&OK);
+        let mut script = connect_script();
+        script.push((ESP_SPI_FLASH_MD5, vec![reply(ESP_SPI_FLASH_MD5, 0, &data)]));
+        let mut client = RomClient::new(FakePort::new(script));
+        client.connect().unwrap();
+        client.detect_chip().unwrap();
+        assert_eq!(client.flash_md5(0, 16).unwrap(), [0x5A; 16]);
+    }
+
+    #[test]
+    fn decodes_rom_hex_digest_and_rejects_garbage() {
+        let mut hex_reply = b"00112233445566778899aabbccddeeff".to_vec();
+        hex_reply.extend_from_slice(&OK);
+        // Valid UTF-8, but "é" straddles a digit pair.
+        let mut garbled = "aé".as_bytes().to_vec();
+        garbled.resize(32, b'0');
+        garbled.extend_from_slice(&OK);
+        let mut script = connect_script();
+        script.push((ESP_SPI_FLASH_MD5, vec![reply(ESP_SPI_FLASH_MD5, 0, &hex_reply)]));
+        script.push((ESP_SPI_FLASH_MD5, vec![reply(ESP_SPI_FLASH_MD5, 0, &garbled)]));
+        let mut client = RomClient::new(FakePort::new(script));
+        client.connect().unwrap();
+        client.detect_chip().unwrap();
+        assert_eq!(
+            client.flash_md5(0, 16).unwrap(),
+            [
+                0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xAA, 0xBB, 0xCC,
+                0xDD, 0xEE, 0xFF
+            ]
+        );
+        let err = client.flash_md5(0, 16).unwrap_err();
+        assert!(err.contains("not hex"), "{err}");
+    }
+
+    #[test]
+    fn reports_rom_error_status() {
+        let image = vec![0u8; 16];
+        let mut script = flash_script(&image, &Md5::digest(&image));
+        let begin = script
+            .iter_mut()
+            .find(|(op, _)| *op == ESP_FLASH_BEGIN)
+            .unwrap();
+        begin.1 = vec![reply(ESP_FLASH_BEGIN, 0, &[1, 0x06, 0, 0])];
+        let mut port = FakePort::new(script);
+        let err = flash(&mut port, image).unwrap_err();
+        assert_eq!(err, "FLASH_BEGIN failed: failed to act on received message (0x06)");
+    }
+
+    #[test]
+    fn times_out_without_reply() {
+        let mut script = connect_script();
+        script[1].1.clear();
+        let mut client = RomClient::new(FakePort::new(script));
+        client.connect().unwrap();
+        let err = client.detect_chip().unwrap_err();
+        assert_eq!(err, "READ_REG failed: timed out waiting for response from ROM loader");
+    }
+
+    #[test]
+    fn rejects_md5_mismatch() {
+        let image = vec![0xA5u8; 1024];
+        let mut script = flash_script(&image, &[0u8; 16]);
+        // FLASH_END must not be sent after a failed verification.
+        script.pop();
+        let mut port = FakePort::new(script);
+        let err = flash(&mut port, image).unwrap_err();
+        assert!(
+            err.starts_with("flash verification failed at 0x00010000: MD5 00000000"),
+            "{err}"
+        );
+        assert!(port.commands.iter().all(|(op, _)| *op != ESP_FLASH_END));
+    }
+}
@@ -1,28 +1,49 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod esp_backtrace;
+mod esp_core_dump;
+mod esp_image;
+mod esp_log;
+mod esp_merge;
+mod esp_rom;
+mod partition_table;
+mod serial_assistant;
+mod serial_capture;
+mod serial_checksum;
+mod serial_framing;
+mod serial_modbus;
+mod serial_network;
+mod serial_periodic;
+mod serial_ports;
+mod serial_rfc2217;
+mod serial_script;
+mod serial_sequence;
+mod serial_stats;
+mod serial_transfer;
+mod serial_virtual;
+
 use btleplug::api::Peripheral;
 use btleplug::api::{Central, CentralEvent, Manager as _, ScanFilter};
 use btleplug::platform::{Adapter, Manager};
 use futures::stream::StreamExt;
-use serialport::{available_ports, DataBits, FlowControl, Parity, StopBits};
+use serial_assistant::{
+    LineSettings, ReconnectPolicy, SerialAssistantState, SerialCommand, SerialOpenOptions,
+    SerialSessionInfo,
+};
+use serial_virtual::{DeviceScript, VirtualDeviceState};
 use serde_json::Value;
 use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
 use std::env;
 use std::fs;
 use std::hash::{Hash, Hasher};
-use std::io::{Read, Write};
+use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::sync::mpsc::{self, Sender, SyncSender, TryRecvError};
-use std::sync::{Mutex, OnceLock};
-use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    Arc,
-};
-use std::thread;
-use std::time::{Duration, Instant, SystemTime};
+use std::sync::mpsc;
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
 use tauri::{Emitter, Listener, Manager as _};
 
 // Learn more about Tauri commands at https://tauri.app/v1/guides/features/command
@@ -77,6 +98,77 @@ struct AudioSourceResult {
     info: AudioInfo,
 }
 
+#[derive(serde::Deserialize, Debug, Clone)]
+struct FlashFileArg {
+    address: String,
+    path: String,
+}
+
+#[derive(serde::Serialize, Debug, Clone)]
+struct MergeFirmwareResult {
+    path: String,
+    size: u64,
+    regions: Vec<esp_merge::MergeRegion>,
+}
+
+#[derive(serde::Serialize, Debug, Clone)]
+struct PartitionTableResult {
+    csv: String,
+    entries: Vec<partition_table::PartitionEntry>,
+    used_size: u64,
+}
+
+#[derive(serde::Serialize, Clone)]
+struct FlashWriteEvent {
+    kind: String,
+    text: String,
+    index: usize,
+    address: u32,
+    written: usize,
+    total: usize,
+}
+
+impl FlashWriteEvent {
+    fn error(text: impl Into<String>) -> Self {
+        Self {
+            kind: "error".to_string(),
+            text: text.into(),
+            index: 0,
+            address: 0,
+            written: 0,
+            total: 0,
+        }
+    }
+}
+
+impl From<esp_rom::FlashEvent> for FlashWriteEvent {
+    fn from(event: esp_rom::FlashEvent) -> Self {
+        match event {
+            esp_rom::FlashEvent::Status(text) => Self {
+                kind: "status".to_string(),
+                text,
+                index: 0,
+                address: 0,
+                written: 0,
+                total: 0,
+            },
+            esp_rom::FlashEvent::Progress {
+                index,
+                address,
+                written,
+                total,
+            } => Self {
+                kind: "progress".to_string(),
+                text: String::new(),
+                index,
+                address,
+                written,
+                total,
+            },
+        }
+    }
+}
+
 #[derive(Default, Clone)]
 struct BinaryPathState {
     resource_dir: Option<PathBuf>,
@@ -147,175 +239,23 @@ fn sidecar_binary_name(binary_name: &str) -> String {
     }
 }
 
-struct SerialSession {
-    command_tx: SyncSender<SerialCommand>,
-    stop_tx: Sender<()>,
-    closing: Arc<AtomicBool>,
-    reader_handle: Option<thread::JoinHandle<()>>,
-    writer_handle: Option<thread::JoinHandle<()>>,
-}
-
-#[derive(Default)]
-struct SerialAssistantState {
-    sessions: Mutex<HashMap<String, SerialSession>>,
-}
-
-#[derive(serde::Serialize, Clone)]
-struct SerialAssistantEvent {
-    kind: String,
-    text: String,
-    hex: String,
-}
-
-enum SerialCommand {
-    Send {
-        data: Vec<u8>,
-        response_tx: Sender<Result<usize, String>>,
-    },
-    SetSignals {
-        rts: bool,
-        dtr: bool,
-        response_tx: Sender<Result<(), String>>,
-    },
-    Shutdown,
-}
-
-impl SerialAssistantEvent {
-    fn status(text: impl Into<String>) -> Self {
-        Self {
-            kind: "status".to_string(),
-            text: text.into(),
-            hex: String::new(),
-        }
-    }
-
-    fn error(text: impl Into<String>) -> Self {
-        Self {
-            kind: "error".to_string(),
-            text: text.into(),
-            hex: String::new(),
-        }
-    }
-
-    fn data(bytes: &[u8]) -> Self {
-        let hex = bytes
-            .iter()
-            .map(|b| format!("{b:02X}"))
-            .collect::<Vec<_>>()
-            .join(" ");
-        let text = String::from_utf8_lossy(bytes).to_string();
-        Self {
-            kind: "data".to_string(),
-            text,
-            hex,
-        }
-    }
-}
-
-fn parse_data_bits(bits: u8) -> Result<DataBits, String> {
-    match bits {
-        5 => Ok(DataBits::Five),
-        6 => Ok(DataBits::Six),
-        7 => Ok(DataBits::Seven),
-        8 => Ok(DataBits::Eight),
-        _ => Err("data_bits must be one of: 5, 6, 7, 8".to_string()),
-    }
-}
-
-fn parse_stop_bits(bits: u8) -> Result<StopBits, String> {
-    match bits {
-        1 => Ok(StopBits::One),
-        2 => Ok(StopBits::Two),
-        _ => Err("stop_bits must be one of: 1, 2".to_string()),
-    }
-}
-
-fn parse_parity(parity: &str) -> Result<Parity, String> {
-    match parity.to_ascii_lowercase().as_str() {
-        "none" => Ok(Parity::None),
-        "odd" => Ok(Parity::Odd),
-        "even" => Ok(Parity::Even),
-        _ => Err("parity must be one of: none, odd, even".to_string()),
-    }
-}
-
-fn parse_flow_control(flow_control: &str) -> Result<FlowControl, String> {
-    match flow_control.to_ascii_lowercase().as_str() {
-        "none" => Ok(FlowControl::None),
-        "software" => Ok(FlowControl::Software),
-        "hardware" => Ok(FlowControl::Hardware),
-        _ => Err("flow_control must be one of: none, software, hardware".to_string()),
-    }
-}
-
-fn close_serial_session(state: &SerialAssistantState, label: &str) -> Result<(), String> {
-    let session = {
-        let mut sessions = state
-            .sessions
-            .lock()
-            .map_err(|_| "failed to lock serial sessions".to_string())?;
-        sessions.remove(label)
+fn parse_flash_address(address: &str) -> Result<u32, String> {
+    let trimmed = address.trim();
+    let parsed = match trimmed
+        .strip_prefix("0x")
+        .or_else(|| trimmed.strip_prefix("0X"))
+    {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => trimmed.parse::<u32>(),
     };
-
-    if let Some(session) = session {
-        shutdown_serial_session(session, true);
-    }
-
-    Ok(())
-}
-
-fn shutdown_serial_session(mut session: SerialSession, wait_for_threads: bool) {
-    session.closing.store(true, Ordering::SeqCst);
-    let _ = session.command_tx.send(SerialCommand::Shutdown);
-    let _ = session.stop_tx.send(());
-
-    let writer_handle = session.writer_handle.take();
-    let reader_handle = session.reader_handle.take();
-
-    if wait_for_threads {
-        if let Some(handle) = writer_handle {
-            let _ = handle.join();
-        }
-        if let Some(handle) = reader_handle {
-            let _ = handle.join();
-        }
-        return;
-    }
-
-    thread::spawn(move || {
-        if let Some(handle) = writer_handle {
-            let _ = handle.join();
-        }
-        if let Some(handle) = reader_handle {
-            let _ = handle.join();
-        }
-    });
+    parsed.map_err(|_| format!("invalid flash address: {address}"))
 }
 
-fn wait_for_output_drain(
-    writer_port: &mut Box<dyn serialport::SerialPort>,
-    closing: &AtomicBool,
-) -> Result<(), String> {
-    let start = Instant::now();
-    let max_wait = Duration::from_millis(300);
-
-    loop {
-        if closing.load(Ordering::SeqCst) {
-            return Err("serial is closing".to_string());
-        }
-
-        match writer_port.bytes_to_write() {
-            Ok(0) => return Ok(()),
-            Ok(_) => {}
-            Err(_) => return Ok(()),
-        }
-
-        if start.elapsed() >= max_wait {
-            return Ok(());
-        }
-
-        thread::sleep(Duration::from_millis(2));
-    }
+fn open_rom_port(port: &str) -> Result<Box<dyn serialport::SerialPort>, String> {
+    serialport::new(port, esp_rom::ROM_BAUD_RATE)
+        .timeout(Duration::from_millis(20))
+        .open()
+        .map_err(|e| format!("failed to open serial port: {e}"))
 }
 
 fn resolve_binary_path(binary: &str) -> Result<String, String> {
@@ -751,6 +691,14 @@ fn build_pcm_audio_info(
     })
 }
 
+fn resolve_app_data_dir(app_handle: &tauri::AppHandle) -> PathBuf {
+    app_handle.path().app_data_dir().unwrap_or_else(|_| {
+        let mut fallback = env::temp_dir();
+        fallback.push("wheat-embedding-toolkit");
+        fallback
+    })
+}
+
 fn create_pcm_wav_preview(
     app_handle: &tauri::AppHandle,
     input_path: &str,
@@ -773,11 +721,7 @@ fn create_pcm_wav_preview(
         .hash(&mut hasher);
     let hash = hasher.finish();
 
-    let app_dir = app_handle.path().app_data_dir().unwrap_or_else(|_| {
-        let mut fallback = env::temp_dir();
-        fallback.push("wheat-embedding-toolkit");
-        fallback
-    });
+    let app_dir = resolve_app_data_dir(app_handle);
     let preview_dir = app_dir.join("audio").join("preview");
     if !preview_dir.exists() {
         fs::create_dir_all(&preview_dir)
@@ -979,235 +923,713 @@ fn greet(name: &str) -> String {
 }
 
 #[tauri::command]
-fn get_serial_port_list() -> Vec<String> {
-    match available_ports() {
-        Ok(port_info_list) => port_info_list
-            .iter()
-            .map(|x| x.port_name.to_string())
-            .collect(),
-        Err(_) => Vec::new(),
-    }
+fn get_serial_port_list() -> Vec<serial_ports::SerialPortEntry> {
+    serial_ports::list_ports().unwrap_or_default()
 }
 
+/// Connecting to a network port can take seconds, so the port is opened off
+/// the main thread.
 #[tauri::command]
-fn serial_assistant_open(
+async fn serial_assistant_open(
     window: tauri::Window,
-    state: tauri::State<SerialAssistantState>,
     port: String,
     baud_rate: u32,
     data_bits: u8,
     stop_bits: u8,
     parity: String,
     flow_control: String,
-) -> Result<(), String> {
-    if port.is_empty() {
-        return Err("port is required".to_string());
-    }
-
-    close_serial_session(&state, window.label())?;
-
-    let data_bits = parse_data_bits(data_bits)?;
-    let stop_bits = parse_stop_bits(stop_bits)?;
-    let parity = parse_parity(&parity)?;
-    let flow_control = parse_flow_control(&flow_control)?;
+) -> Result<String, String> {
+    tokio::task::spawn_blocking(move || {
+        let core_dump_dir =
+            resolve_app_data_dir(window.app_handle()).join(esp_core_dump::CORE_DUMP_DIR);
+        let state = window.state::<SerialAssistantState>();
+        serial_assistant::open_session(
+            &window,
+            &state,
+            SerialOpenOptions {
+                port,
+                baud_rate,
+                data_bits,
+                stop_bits,
+                parity,
+                flow_control,
+                core_dump_dir,
+            },
+        )
+    })
+    .await
+    .map_err(|e| format!("open task failed: {e}"))?
+}
 
-    let serial = serialport::new(&port, baud_rate)
-        .data_bits(data_bits)
-        .stop_bits(stop_bits)
-        .parity(parity)
-        .flow_control(flow_control)
-        .timeout(Duration::from_millis(100))
-        .open()
-        .map_err(|e| format!("failed to open serial port: {e}"))?;
-
-    let mut reader_port = serial
-        .try_clone()
-        .map_err(|e| format!("failed to clone serial port: {e}"))?;
-    let mut writer_port = serial;
-    let (command_tx, command_rx) = mpsc::sync_channel::<SerialCommand>(1);
-    let (stop_tx, stop_rx) = mpsc::channel();
-    let closing = Arc::new(AtomicBool::new(false));
-    let writer_closing = Arc::clone(&closing);
-    let cloned_window = window.clone();
-    let port_for_reader = port.clone();
-
-    let reader_handle = thread::spawn(move || {
-        let mut buffer = [0u8; 4096];
-        loop {
-            match stop_rx.try_recv() {
-                Ok(_) | Err(TryRecvError::Disconnected) => break,
-                Err(TryRecvError::Empty) => {}
-            }
+/// The writer sets the lines, and it may be busy with a sequence or a held
+/// BREAK for seconds.
+#[tauri::command]
+async fn serial_assistant_set_signals(
+    app_handle: tauri::AppHandle,
+    session_id: String,
+    rts: bool,
+    dtr: bool,
+) -> Result<(), String> {
+    tokio::task::spawn_blocking(move || {
+        let state = app_handle.state::<SerialAssistantState>();
+        state.request(&session_id, |response_tx| SerialCommand::SetSignals {
+            rts,
+            dtr,
+            response_tx,
+        })
+    })
+    .await
+    .map_err(|e| format!("signals task failed: {e}"))?
+}
 
-            match reader_port.read(&mut buffer) {
-                Ok(size) if size > 0 => {
-                    let payload = SerialAssistantEvent::data(&buffer[..size]);
-                    let _ = cloned_window.emit("serial_assistant_event", payload);
-                }
-                Ok(_) => {}
-                Err(err) if err.kind() == std::io::ErrorKind::TimedOut => {}
-                Err(err) => {
-                    let payload = SerialAssistantEvent::error(format!(
-                        "serial read failed ({port_for_reader}): {err}"
-                    ));
-                    let _ = cloned_window.emit("serial_assistant_event", payload);
-                    break;
-                }
-            }
-        }
-    });
+#[tauri::command]
+fn serial_assistant_reconfigure(
+    state: tauri::State<SerialAssistantState>,
+    session_id: String,
+    baud_rate: u32,
+    data_bits: u8,
+    stop_bits: u8,
+    parity: String,
+    flow_control: String,
+) -> Result<(), String> {
+    let settings =
+        LineSettings::parse(baud_rate, data_bits, stop_bits, &parity, &flow_control)?;
+    state.reconfigure(&session_id, settings)
+}
 
-    let writer_handle = thread::spawn(move || {
-        while let Ok(command) = command_rx.recv() {
-            match command {
-                SerialCommand::Send { data, response_tx } => {
-                    let result = writer_port
-                        .write_all(&data)
-                        .map_err(|e| format!("failed to write serial data: {e}"))
-                        .and_then(|_| wait_for_output_drain(&mut writer_port, writer_closing.as_ref()))
-                        .map(|_| data.len())
-                        .map_err(|e| e.to_string());
-                    let _ = response_tx.send(result);
-                }
-                SerialCommand::SetSignals {
-                    rts,
-                    dtr,
-                    response_tx,
-                } => {
-                    let result = writer_port
-                        .write_request_to_send(rts)
-                        .map_err(|e| format!("failed to set RTS: {e}"))
-                        .and_then(|_| {
-                            writer_port
-                                .write_data_terminal_ready(dtr)
-                                .map_err(|e| format!("failed to set DTR: {e}"))
-                        });
-                    let _ = response_tx.send(result);
-                }
-                SerialCommand::Shutdown => break,
-            }
-        }
+/// Turns automatic reconnect on or off for the session. `timeout_ms` bounds
+/// how long a lost port is waited for; without it the wait lasts until the
+/// session is closed.
+#[tauri::command]
+fn serial_assistant_set_reconnect(
+    state: tauri::State<SerialAssistantState>,
+    session_id: String,
+    enabled: bool,
+    timeout_ms: Option<u64>,
+) -> Result<(), String> {
+    let policy = enabled.then(|| ReconnectPolicy {
+        timeout: timeout_ms
+            .filter(|timeout| *timeout > 0)
+            .map(Duration::from_millis),
     });
+    state.set_reconnect(&session_id, policy)
+}
 
-    {
-        let mut sessions = state
-            .sessions
-            .lock()
-            .map_err(|_| "failed to lock serial sessions".to_string())?;
-        sessions.insert(
-            window.label().to_string(),
-            SerialSession {
-                command_tx,
-                stop_tx,
-                closing,
-                reader_handle: Some(reader_handle),
-                writer_handle: Some(writer_handle),
-            },
-        );
+#[tauri::command]
+async fn serial_assistant_send_break(
+    app_handle: tauri::AppHandle,
+    session_id: String,
+    duration_ms: u64,
+) -> Result<(), String> {
+    let duration = Duration::from_millis(duration_ms);
+    if duration.is_zero() || duration > serial_sequence::MAX_WAIT {
+        return Err(format!(
+            "break duration must be 1..={} ms",
+            serial_sequence::MAX_WAIT.as_millis()
+        ));
     }
+    tokio::task::spawn_blocking(move || {
+        let state = app_handle.state::<SerialAssistantState>();
+        state.request(&session_id, |response_tx| SerialCommand::Break {
+            duration,
+            response_tx,
+        })
+    })
+    .await
+    .map_err(|e| format!("break task failed: {e}"))?
+}
 
-    let _ = window.emit(
-        "serial_assistant_event",
-        SerialAssistantEvent::status(format!("serial connected: {port} @ {baud_rate}")),
-    );
-    Ok(())
+#[tauri::command]
+fn serial_assistant_start_periodic(
+    state: tauri::State<SerialAssistantState>,
+    session_id: String,
+    payloads: Vec<Vec<u8>>,
+    interval_ms: u64,
+    count: Option<u64>,
+    counter_offset: Option<usize>,
+) -> Result<(), String> {
+    let schedule = serial_periodic::PeriodicSchedule::new(
+        payloads,
+        Duration::from_millis(interval_ms),
+        count,
+        counter_offset,
+    )?;
+    state.start_periodic(&session_id, schedule)
 }
 
 #[tauri::command]
-fn serial_assistant_set_signals(
-    window: tauri::Window,
+fn serial_assistant_stop_periodic(
     state: tauri::State<SerialAssistantState>,
-    rts: bool,
-    dtr: bool,
+    session_id: String,
 ) -> Result<(), String> {
-    let command_tx = {
-        let sessions = state
-            .sessions
-            .lock()
-            .map_err(|_| "failed to lock serial sessions".to_string())?;
-        let session = sessions
-            .get(window.label())
-            .ok_or_else(|| "serial is not connected".to_string())?;
-        session.command_tx.clone()
-    };
+    state.stop_periodic(&session_id)
+}
 
-    let (response_tx, response_rx) = mpsc::channel();
-    command_tx
-        .send(SerialCommand::SetSignals {
-            rts,
-            dtr,
+/// Sequences can wait for seconds between steps, so the writer is awaited
+/// off the main thread.
+#[tauri::command]
+async fn serial_assistant_run_sequence(
+    app_handle: tauri::AppHandle,
+    session_id: String,
+    sequence: String,
+) -> Result<(), String> {
+    let steps = serial_sequence::resolve(&sequence)?;
+    tokio::task::spawn_blocking(move || {
+        let state = app_handle.state::<SerialAssistantState>();
+        state.request(&session_id, |response_tx| SerialCommand::RunSequence {
+            steps,
             response_tx,
         })
-        .map_err(|_| "serial writer is unavailable".to_string())?;
-
-    response_rx
-        .recv()
-        .map_err(|_| "serial writer did not respond".to_string())?
+    })
+    .await
+    .map_err(|e| format!("sequence task failed: {e}"))?
 }
 
+/// Data queues behind whatever the writer is doing, up to a 10 s sequence
+/// wait, so the send is awaited off the main thread.
 #[tauri::command]
-fn serial_assistant_send(
-    window: tauri::Window,
-    state: tauri::State<SerialAssistantState>,
-    data: Vec<u8>,
+async fn serial_assistant_send(
+    app_handle: tauri::AppHandle,
+    session_id: String,
+    mut data: Vec<u8>,
+    checksum: Option<String>,
+    checksum_endian: Option<String>,
 ) -> Result<usize, String> {
     if data.is_empty() {
         return Ok(0);
     }
+    if let Some(checksum) = checksum {
+        serial_checksum::ChecksumSpec::parse(&checksum, checksum_endian.as_deref())?
+            .append(&mut data);
+    }
 
-    let command_tx = {
-        let sessions = state
-            .sessions
-            .lock()
-            .map_err(|_| "failed to lock serial sessions".to_string())?;
-        let session = sessions
-            .get(window.label())
-            .ok_or_else(|| "serial is not connected".to_string())?;
-        session.command_tx.clone()
-    };
+    tokio::task::spawn_blocking(move || {
+        let state = app_handle.state::<SerialAssistantState>();
+        state.request(&session_id, |response_tx| SerialCommand::Send {
+            data,
+            response_tx,
+        })
+    })
+    .await
+    .map_err(|e| format!("send task failed: {e}"))?
+}
 
-    let (response_tx, response_rx) = mpsc::channel();
-    command_tx
-        .send(SerialCommand::Send { data, response_tx })
-        .map_err(|_| "serial writer is unavailable".to_string())?;
+#[tauri::command]
+fn serial_checksum(
+    data: Vec<u8>,
+    algorithm: String,
+    endian: Option<String>,
+) -> Result<Vec<u8>, String> {
+    Ok(serial_checksum::ChecksumSpec::parse(&algorithm, endian.as_deref())?.encode(&data))
+}
 
-    response_rx
-        .recv()
-        .map_err(|_| "serial writer did not respond".to_string())?
+#[tauri::command]
+fn serial_assistant_set_rx_checksum(
+    state: tauri::State<SerialAssistantState>,
+    session_id: String,
+    algorithm: Option<String>,
+    endian: Option<String>,
+) -> Result<(), String> {
+    let spec = algorithm
+        .map(|algorithm| serial_checksum::ChecksumSpec::parse(&algorithm, endian.as_deref()))
+        .transpose()?;
+    state.set_rx_checksum(&session_id, spec)
 }
 
 #[tauri::command]
-fn serial_assistant_close(
-    window: tauri::Window,
+fn serial_assistant_set_framing(
+    state: tauri::State<SerialAssistantState>,
+    session_id: String,
+    mode: String,
+    value: Option<u64>,
+) -> Result<(), String> {
+    let mode = serial_framing::FramingMode::parse(&mode, value)?;
+    state.set_framing(&session_id, mode)
+}
+
+#[tauri::command]
+fn serial_assistant_set_modbus_decode(
+    state: tauri::State<SerialAssistantState>,
+    session_id: String,
+    enabled: bool,
+) -> Result<(), String> {
+    state.set_modbus_decode(&session_id, enabled)
+}
+
+#[tauri::command]
+fn serial_assistant_start_modbus_poll(
+    state: tauri::State<SerialAssistantState>,
+    session_id: String,
+    unit: u8,
+    function: u8,
+    address: u16,
+    quantity: u16,
+    interval_ms: u64,
+) -> Result<(), String> {
+    let poll = serial_modbus::ModbusPoll::new(
+        unit,
+        function,
+        address,
+        quantity,
+        Duration::from_millis(interval_ms),
+    )?;
+    state.start_modbus_poll(&session_id, poll)
+}
+
+#[tauri::command]
+fn serial_assistant_stop_modbus_poll(
+    state: tauri::State<SerialAssistantState>,
+    session_id: String,
+) -> Result<(), String> {
+    state.stop_modbus_poll(&session_id)
+}
+
+/// Publishes an open session as an RFC 2217 server. `address` is a
+/// `host:port` to listen on, e.g. `0.0.0.0:2217` for the LAN; port 0 picks a
+/// free one. Returns the address actually bound.
+#[tauri::command]
+fn serial_assistant_start_rfc2217(
     state: tauri::State<SerialAssistantState>,
+    session_id: String,
+    address: String,
+) -> Result<String, String> {
+    state.start_rfc2217(&session_id, address.trim())
+}
+
+#[tauri::command]
+fn serial_assistant_stop_rfc2217(
+    state: tauri::State<SerialAssistantState>,
+    session_id: String,
 ) -> Result<(), String> {
-    let session = {
-        let mut sessions = state
-            .sessions
-            .lock()
-            .map_err(|_| "failed to lock serial sessions".to_string())?;
-        sessions.remove(window.label())
+    state.stop_rfc2217(&session_id)
+}
+
+#[tauri::command]
+fn serial_assistant_set_log_filter(
+    state: tauri::State<SerialAssistantState>,
+    session_id: String,
+    enabled: bool,
+    min_level: Option<String>,
+    allow_tags: Vec<String>,
+    deny_tags: Vec<String>,
+) -> Result<(), String> {
+    let filter = if enabled {
+        let tidy = |tags: Vec<String>| {
+            tags.into_iter()
+                .map(|tag| tag.trim().to_string())
+                .filter(|tag| !tag.is_empty())
+                .collect()
+        };
+        Some(esp_log::LogFilter {
+            min_level: esp_log::LogLevel::parse(min_level.as_deref().unwrap_or("verbose"))?,
+            allow_tags: tidy(allow_tags),
+            deny_tags: tidy(deny_tags),
+        })
+    } else {
+        None
     };
-    if let Some(session) = session {
-        shutdown_serial_session(session, false);
+    state.set_log_filter(&session_id, filter)
+}
+
+#[tauri::command]
+fn serial_assistant_set_elf(
+    state: tauri::State<SerialAssistantState>,
+    session_id: String,
+    path: Option<String>,
+) -> Result<(), String> {
+    let path = path.filter(|path| !path.trim().is_empty()).map(PathBuf::from);
+    if let Some(path) = &path {
+        if !path.is_file() {
+            return Err(format!("ELF file not found: {}", path.display()));
+        }
     }
-    let _ = window.emit(
-        "serial_assistant_event",
-        SerialAssistantEvent::status("serial disconnected"),
-    );
+    state.set_elf(&session_id, path)
+}
+
+#[tauri::command]
+async fn decode_core_dump(
+    path: String,
+    elf_path: Option<String>,
+) -> Result<esp_core_dump::CoreDumpReport, String> {
+    tokio::task::spawn_blocking(move || {
+        let data = fs::read(&path).map_err(|e| format!("failed to read core dump: {e}"))?;
+        let symbolizer = match elf_path.filter(|path| !path.trim().is_empty()) {
+            Some(elf_path) => Some(esp_backtrace::Symbolizer::load(Path::new(&elf_path))?),
+            None => None,
+        };
+        esp_core_dump::decode(&data, symbolizer.as_ref())
+    })
+    .await
+    .map_err(|e| format!("core dump task failed: {e}"))?
+}
+
+#[tauri::command]
+fn find_project_elf(flasher_args_path: &str) -> Result<String, String> {
+    esp_backtrace::find_project_elf(Path::new(flasher_args_path))
+        .map(|path| path.to_string_lossy().to_string())
+}
+
+#[tauri::command]
+fn serial_assistant_run_script(
+    state: tauri::State<SerialAssistantState>,
+    session_id: String,
+    script: String,
+) -> Result<(), String> {
+    let steps = serial_script::parse(&script)?;
+    state.run_script(&session_id, steps)
+}
+
+#[tauri::command]
+fn serial_assistant_cancel_script(
+    state: tauri::State<SerialAssistantState>,
+    session_id: String,
+) -> Result<(), String> {
+    state.cancel_script(&session_id)
+}
+
+#[tauri::command]
+fn serial_assistant_send_file(
+    state: tauri::State<SerialAssistantState>,
+    session_id: String,
+    protocol: String,
+    paths: Vec<String>,
+    chunk_size: Option<usize>,
+    chunk_delay_ms: Option<u64>,
+) -> Result<(), String> {
+    let protocol = serial_transfer::TransferProtocol::parse(
+        &protocol,
+        chunk_size,
+        chunk_delay_ms.map(Duration::from_millis),
+    )?;
+    let files = paths.into_iter().map(PathBuf::from).collect();
+    let job =
+        serial_transfer::TransferJob::new(protocol, serial_transfer::TransferDirection::Send(files))?;
+    state.start_transfer(&session_id, job)
+}
+
+#[tauri::command]
+fn serial_assistant_receive_file(
+    state: tauri::State<SerialAssistantState>,
+    session_id: String,
+    protocol: String,
+    destination: String,
+) -> Result<(), String> {
+    let protocol = serial_transfer::TransferProtocol::parse(&protocol, None, None)?;
+    let job = serial_transfer::TransferJob::new(
+        protocol,
+        serial_transfer::TransferDirection::Receive(PathBuf::from(destination)),
+    )?;
+    state.start_transfer(&session_id, job)
+}
+
+#[tauri::command]
+fn serial_assistant_cancel_transfer(
+    state: tauri::State<SerialAssistantState>,
+    session_id: String,
+) -> Result<(), String> {
+    state.cancel_transfer(&session_id)
+}
+
+#[tauri::command]
+fn serial_assistant_stats(
+    state: tauri::State<SerialAssistantState>,
+    session_id: String,
+) -> Result<serial_stats::StatsSnapshot, String> {
+    state.stats(&session_id)
+}
+
+#[tauri::command]
+fn serial_assistant_close(
+    state: tauri::State<SerialAssistantState>,
+    session_id: String,
+) -> Result<(), String> {
+    state.close(&session_id, false)?;
     Ok(())
 }
 
 #[tauri::command]
 fn serial_assistant_is_open(
-    window: tauri::Window,
     state: tauri::State<SerialAssistantState>,
+    session_id: String,
 ) -> Result<bool, String> {
-    let sessions = state
-        .sessions
-        .lock()
-        .map_err(|_| "failed to lock serial sessions".to_string())?;
-    Ok(sessions.contains_key(window.label()))
+    state.contains(&session_id)
+}
+
+#[tauri::command]
+fn serial_assistant_start_capture(
+    app_handle: tauri::AppHandle,
+    state: tauri::State<SerialAssistantState>,
+    session_id: String,
+    max_size_kb: Option<u64>,
+    max_minutes: Option<u64>,
+) -> Result<String, String> {
+    let policy = serial_capture::RotationPolicy {
+        max_bytes: max_size_kb.filter(|size| *size > 0).map(|size| size * 1024),
+        max_duration: max_minutes
+            .filter(|minutes| *minutes > 0)
+            .map(|minutes| Duration::from_secs(minutes * 60)),
+    };
+    let dir = resolve_app_data_dir(&app_handle).join(serial_capture::CAPTURE_DIR);
+    state.start_capture(&session_id, &dir, policy)
+}
+
+#[tauri::command]
+fn serial_assistant_stop_capture(
+    state: tauri::State<SerialAssistantState>,
+    session_id: String,
+) -> Result<(), String> {
+    state.stop_capture(&session_id)
+}
+
+/// Publishes a `sim://` device on a pseudo-terminal and returns its path.
+/// `baud_rate` paces raw capture replays; it defaults to 115200.
+#[tauri::command]
+fn serial_virtual_start_pty(
+    state: tauri::State<VirtualDeviceState>,
+    device: String,
+    baud_rate: Option<u32>,
+) -> Result<String, String> {
+    state.start_pty(DeviceScript::parse(device.trim(), baud_rate.unwrap_or(115_200))?)
+}
+
+#[tauri::command]
+fn serial_virtual_stop_pty(
+    state: tauri::State<VirtualDeviceState>,
+    path: String,
+) -> Result<(), String> {
+    state.stop_pty(&path)
+}
+
+#[tauri::command]
+fn serial_virtual_list_ptys(
+    state: tauri::State<VirtualDeviceState>,
+) -> Result<Vec<String>, String> {
+    state.list()
+}
+
+#[tauri::command]
+fn serial_capture_list(
+    app_handle: tauri::AppHandle,
+) -> Result<Vec<serial_capture::CaptureFileInfo>, String> {
+    serial_capture::list_captures(
+        &resolve_app_data_dir(&app_handle).join(serial_capture::CAPTURE_DIR),
+    )
+}
+
+/// Opens a capture in the system's default application instead of pulling a
+/// possibly huge, partly binary file through IPC.
+#[tauri::command]
+fn serial_capture_open(app_handle: tauri::AppHandle, name: String) -> Result<(), String> {
+    let dir = resolve_app_data_dir(&app_handle).join(serial_capture::CAPTURE_DIR);
+    let path = serial_capture::capture_path(&dir, &name)?;
+    #[cfg(target_os = "windows")]
+    let mut command = {
+        let mut command = Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        command
+    };
+    #[cfg(target_os = "macos")]
+    let mut command = Command::new("open");
+    #[cfg(target_os = "linux")]
+    let mut command = Command::new("xdg-open");
+    command
+        .arg(&path)
+        .spawn()
+        .map(|_| ())
+        .map_err(|e| format!("failed to open capture {name}: {e}"))
+}
+
+#[tauri::command]
+fn serial_assistant_sessions(
+    window: tauri::Window,
+    state: tauri::State<SerialAssistantState>,
+) -> Result<Vec<SerialSessionInfo>, String> {
+    state.sessions_for_window(window.label())
+}
+
+#[tauri::command]
+async fn flash_write(
+    window: tauri::Window,
+    port: String,
+    baud_rate: u32,
+    flash_mode: Option<String>,
+    files: Vec<FlashFileArg>,
+) -> Result<(), String> {
+    if port.is_empty() {
+        return Err("port is required".to_string());
+    }
+    if files.is_empty() {
+        return Err("no firmware selected".to_string());
+    }
+
+    let flash_mode = esp_image::parse_flash_mode(flash_mode.as_deref().unwrap_or("keep"))?;
+    let mut images = Vec::with_capacity(files.len());
+    for file in &files {
+        images.push(esp_rom::FlashImage {
+            address: parse_flash_address(&file.address)?,
+            data: fs::read(&file.path)
+                .map_err(|e| format!("failed to read firmware {}: {e}", file.path))?,
+        });
+    }
+
+    tokio::task::spawn_blocking(move || {
+        let serial = open_rom_port(&port)?;
+        esp_rom::flash_images(serial, baud_rate, flash_mode, images, |event| {
+            let _ = window.emit("flash_write_event", FlashWriteEvent::from(event));
+        })
+        .inspect_err(|err| {
+            let _ = window.emit("flash_write_event", FlashWriteEvent::error(err.as_str()));
+        })
+        .map(|_| ())
+    })
+    .await
+    .map_err(|e| format!("flash task failed: {e}"))?
+}
+
+#[tauri::command]
+async fn ram_load(
+    window: tauri::Window,
+    port: String,
+    baud_rate: u32,
+    path: String,
+) -> Result<(), String> {
+    if port.is_empty() {
+        return Err("port is required".to_string());
+    }
+    let image = fs::read(&path).map_err(|e| format!("failed to read image {path}: {e}"))?;
+
+    tokio::task::spawn_blocking(move || {
+        let serial = open_rom_port(&port)?;
+        esp_rom::load_ram_image(serial, baud_rate, &image, |event| {
+            let _ = window.emit("flash_write_event", FlashWriteEvent::from(event));
+        })
+        .inspect_err(|err| {
+            let _ = window.emit("flash_write_event", FlashWriteEvent::error(err.as_str()));
+        })
+        .map(|_| ())
+    })
+    .await
+    .map_err(|e| format!("ram load task failed: {e}"))?
+}
+
+#[tauri::command]
+fn merge_firmware(
+    app_handle: tauri::AppHandle,
+    chip: String,
+    files: Vec<FlashFileArg>,
+    output_name: String,
+    flash_mode: Option<String>,
+    flash_freq: Option<String>,
+    flash_size: Option<String>,
+) -> Result<MergeFirmwareResult, String> {
+    let chip = esp_rom::Chip::from_name(&chip).ok_or_else(|| format!("unsupported chip: {chip}"))?;
+    if output_name.trim().is_empty() || output_name.contains(['/', '\\']) {
+        return Err(format!("invalid output file name: {output_name}"));
+    }
+
+    let params = esp_merge::FlashParams {
+        mode: esp_image::parse_flash_mode(flash_mode.as_deref().unwrap_or("keep"))?,
+        freq: esp_image::parse_flash_freq(chip, flash_freq.as_deref().unwrap_or("keep"))?,
+        size: esp_image::parse_flash_size(chip, flash_size.as_deref().unwrap_or("keep"))?,
+    };
+    let flash_limit = esp_merge::flash_limit(chip, flash_size.as_deref().unwrap_or("keep"))?;
+    let mut inputs = Vec::with_capacity(files.len());
+    for file in files {
+        inputs.push(esp_merge::MergeInput {
+            address: parse_flash_address(&file.address)?,
+            data: fs::read(&file.path)
+                .map_err(|e| format!("failed to read firmware {}: {e}", file.path))?,
+            path: file.path,
+        });
+    }
+
+    let (merged, regions) = esp_merge::merge_images(chip, inputs, params, flash_limit)?;
+    let firmware_dir = resolve_app_data_dir(&app_handle).join("firmware");
+    if !firmware_dir.exists() {
+        fs::create_dir_all(&firmware_dir)
+            .map_err(|e| format!("failed to create firmware directory: {e}"))?;
+    }
+    let output_path = firmware_dir.join(&output_name);
+    fs::write(&output_path, &merged).map_err(|e| format!("failed to write merged firmware: {e}"))?;
+
+    Ok(MergeFirmwareResult {
+        path: output_path.display().to_string(),
+        size: merged.len() as u64,
+        regions,
+    })
+}
+
+fn parse_table_offset(table_offset: Option<&str>) -> Result<u32, String> {
+    match table_offset.map(str::trim).filter(|offset| !offset.is_empty()) {
+        Some(offset) => parse_flash_address(offset),
+        None => Ok(partition_table::DEFAULT_TABLE_OFFSET),
+    }
+}
+
+fn build_partition_table_result(
+    entries: Vec<partition_table::PartitionEntry>,
+    table_offset: u32,
+    flash_size: Option<&str>,
+) -> Result<PartitionTableResult, String> {
+    let flash_size = partition_table::parse_flash_size(flash_size.unwrap_or("NONE"))?;
+    partition_table::verify(&entries, table_offset, flash_size)?;
+    Ok(PartitionTableResult {
+        csv: partition_table::to_csv(&entries),
+        used_size: partition_table::table_size_used(&entries),
+        entries,
+    })
+}
+
+#[tauri::command]
+fn partition_table_from_csv(
+    csv: String,
+    flash_size: Option<String>,
+    table_offset: Option<String>,
+) -> Result<PartitionTableResult, String> {
+    let table_offset = parse_table_offset(table_offset.as_deref())?;
+    let entries = partition_table::parse_csv(&csv, table_offset)?;
+    build_partition_table_result(entries, table_offset, flash_size.as_deref())
+}
+
+#[tauri::command]
+fn partition_table_from_file(
+    path: String,
+    flash_size: Option<String>,
+    table_offset: Option<String>,
+) -> Result<PartitionTableResult, String> {
+    let table_offset = parse_table_offset(table_offset.as_deref())?;
+    let data = fs::read(&path).map_err(|e| format!("failed to read partition table {path}: {e}"))?;
+    let entries = if data.starts_with(&[0xAA, 0x50]) {
+        partition_table::from_binary(&data)?
+    } else {
+        let csv = String::from_utf8(data).map_err(|_| format!("{path} is neither a partition binary nor UTF-8 CSV"))?;
+        partition_table::parse_csv(&csv, table_offset)?
+    };
+    build_partition_table_result(entries, table_offset, flash_size.as_deref())
+}
+
+#[tauri::command]
+fn partition_table_to_binary(
+    csv: String,
+    output_path: String,
+    flash_size: Option<String>,
+    table_offset: Option<String>,
+) -> Result<PartitionTableResult, String> {
+    if output_path.trim().is_empty() {
+        return Err("output path is required".to_string());
+    }
+    let table_offset = parse_table_offset(table_offset.as_deref())?;
+    let entries = partition_table::parse_csv(&csv, table_offset)?;
+    let result = build_partition_table_result(entries, table_offset, flash_size.as_deref())?;
+    let binary = partition_table::to_binary(&result.entries)?;
+    fs::write(&output_path, binary).map_err(|e| format!("failed to write partition table: {e}"))?;
+    Ok(result)
+}
+
+#[tauri::command]
+fn inspect_firmware_image(path: &str) -> Result<esp_image::FirmwareImageInfo, String> {
+    let data = fs::read(path).map_err(|e| format!("failed to read firmware {path}: {e}"))?;
+    esp_image::inspect(&data)
 }
 
 #[tauri::command]
@@ -1351,12 +1773,7 @@ fn clip_audio_segment(
 
 #[tauri::command]
 fn get_current_dir(app_handle: tauri::AppHandle) -> String {
-    let path = app_handle.path().app_data_dir().unwrap_or_else(|_| {
-        let mut fallback = env::temp_dir();
-        fallback.push("wheat-embedding-toolkit");
-        fallback
-    });
-    path.display().to_string()
+    resolve_app_data_dir(&app_handle).display().to_string()
 }
 
 #[tauri::command]
@@ -1451,6 +1868,7 @@ fn get_file_info(path: &str) -> FileInfo {
 fn main() {
     tauri::Builder::default()
         .manage(SerialAssistantState::default())
+        .manage(VirtualDeviceState::default())
         .plugin(tauri_plugin_shell::init())
         .plugin(tauri_plugin_os::init())
         .plugin(tauri_plugin_dialog::init())
@@ -1481,7 +1899,7 @@ fn main() {
                 let _ = fs::create_dir_all(&app_dir);
             }
 
-            for item in ["firmware", "partitions", "audio"].iter() {
+            for item in ["firmware", "partitions", "audio", "captures", "coredumps"].iter() {
                 let dir = app_dir.join(item);
                 if !dir.exists() {
                     let _ = fs::create_dir_all(&dir);
@@ -1494,6 +1912,13 @@ fn main() {
                 let _ = fs::write(&chip_list, data);
             }
 
+            let app_handle = app.handle().clone();
+            serial_ports::spawn_watcher(app.handle().clone(), move |entry| {
+                app_handle
+                    .state::<SerialAssistantState>()
+                    .handle_port_removed(entry)
+            });
+
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
@@ -1504,12 +1929,51 @@ fn main() {
             open_file_in_explorer,
             open_directory_in_explorer,
             get_file_info,
+            inspect_firmware_image,
             start_ble_advertisement_scan,
             serial_assistant_open,
             serial_assistant_send,
             serial_assistant_close,
+            serial_assistant_stats,
+            serial_assistant_run_script,
+            serial_assistant_cancel_script,
+            serial_assistant_send_file,
+            serial_assistant_receive_file,
+            serial_assistant_cancel_transfer,
             serial_assistant_is_open,
             serial_assistant_set_signals,
+            serial_assistant_run_sequence,
+            serial_assistant_send_break,
+            serial_assistant_reconfigure,
+            serial_assistant_set_reconnect,
+            serial_assistant_start_periodic,
+            serial_assistant_stop_periodic,
+            serial_assistant_sessions,
+            serial_assistant_set_framing,
+            serial_assistant_set_rx_checksum,
+            serial_checksum,
+            serial_assistant_set_log_filter,
+            serial_assistant_set_modbus_decode,
+            serial_assistant_start_modbus_poll,
+            serial_assistant_stop_modbus_poll,
+            serial_assistant_start_rfc2217,
+            serial_assistant_stop_rfc2217,
+            serial_assistant_set_elf,
+            find_project_elf,
+            decode_core_dump,
+            serial_assistant_start_capture,
+            serial_assistant_stop_capture,
+            serial_capture_list,
+            serial_virtual_start_pty,
+            serial_virtual_stop_pty,
+            serial_virtual_list_ptys,
+            serial_capture_open,
+            flash_write,
+            ram_load,
+            merge_firmware,
+            partition_table_from_csv,
+            partition_table_from_file,
+            partition_table_to_binary,
             get_audio_info,
             prepare_audio_source,
             convert_audio_format,
@@ -0,0 +1,648 @@
+use md5::{Digest, Md5};
+
+pub const MAX_PARTITION_LENGTH: usize = 0xC00;
+pub const PARTITION_TABLE_SIZE: u32 = 0x1000;
+pub const DEFAULT_TABLE_OFFSET: u32 = 0x8000;
+
+const ENTRY_LEN: usize = 32;
+const ENTRY_MAGIC: [u8; 2] = [0xAA, 0x50];
+const MD5_MAGIC: [u8; 2] = [0xEB, 0xEB];
+const NAME_LEN: usize = 16;
+
+const APP_TYPE: u8 = 0x00;
+const DATA_TYPE: u8 = 0x01;
+const OTA_DATA_SUBTYPE: u8 = 0x00;
+const MIN_APP_OTA_SUBTYPE: u8 = 0x10;
+const NUM_APP_OTA_SUBTYPES: u8 = 16;
+
+const FLAG_ENCRYPTED: u32 = 1 << 0;
+const FLAG_READONLY: u32 = 1 << 1;
+
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct PartitionEntry {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ptype: u8,
+    pub subtype: u8,
+    pub offset: u32,
+    pub size: u32,
+    pub encrypted: bool,
+    pub readonly: bool,
+    /// 1-based CSV line the entry came from, 0 for binary input.
+    pub line: usize,
+}
+
+impl PartitionEntry {
+    fn describe(&self) -> String {
+        if self.line > 0 {
+            format!("line {}: partition '{}'", self.line, self.name)
+        } else {
+            format!("partition '{}'", self.name)
+        }
+    }
+}
+
+fn type_keyword(ptype: u8) -> Option<&'static str> {
+    match ptype {
+        APP_TYPE => Some("app"),
+        DATA_TYPE => Some("data"),
+        _ => None,
+    }
+}
+
+fn type_from_keyword(keyword: &str) -> Option<u8> {
+    match keyword {
+        "app" => Some(APP_TYPE),
+        "data" => Some(DATA_TYPE),
+        _ => None,
+    }
+}
+
+fn subtype_keyword(ptype: u8, subtype: u8) -> Option<String> {
+    let keyword = match (ptype, subtype) {
+        (APP_TYPE, 0x00) => "factory",
+        (APP_TYPE, 0x20) => "test",
+        (APP_TYPE, value)
+            if (MIN_APP_OTA_SUBTYPE..MIN_APP_OTA_SUBTYPE + NUM_APP_OTA_SUBTYPES)
+                .contains(&value) =>
+        {
+            return Some(format!("ota_{}", value - MIN_APP_OTA_SUBTYPE));
+        }
+        (DATA_TYPE, 0x00) => "ota",
+        (DATA_TYPE, 0x01) => "phy",
+        (DATA_TYPE, 0x02) => "nvs",
+        (DATA_TYPE, 0x03) => "coredump",
+        (DATA_TYPE, 0x04) => "nvs_keys",
+        (DATA_TYPE, 0x05) => "efuse",
+        (DATA_TYPE, 0x06) => "undefined",
+        (DATA_TYPE, 0x80) => "esphttpd",
+        (DATA_TYPE, 0x81) => "fat",
+        (DATA_TYPE, 0x82) => "spiffs",
+        (DATA_TYPE, 0x83) => "littlefs",
+        _ => return None,
+    };
+    Some(keyword.to_string())
+}
+
+fn subtype_from_keyword(ptype: u8, keyword: &str) -> Option<u8> {
+    if ptype == APP_TYPE {
+        if let Some(slot) = keyword.strip_prefix("ota_") {
+            return slot
+                .parse::<u8>()
+                .ok()
+                .filter(|slot| *slot < NUM_APP_OTA_SUBTYPES)
+                .map(|slot| MIN_APP_OTA_SUBTYPE + slot);
+        }
+    }
+    (0..=u8::MAX).find(|value| subtype_keyword(ptype, *value).as_deref() == Some(keyword))
+}
+
+/// Integer field parser matching gen_esp32part.py: `int(x, 0)` plus `K`/`M`
+/// suffixes, so `0x6000`, `24K` and `1M` are all accepted.
+fn parse_int(value: &str) -> Option<i64> {
+    let lower = value.trim().to_ascii_lowercase();
+    if let Some(base) = lower.strip_suffix('k') {
+        return parse_int(base).and_then(|v| v.checked_mul(1024));
+    }
+    if let Some(base) = lower.strip_suffix('m') {
+        return parse_int(base).and_then(|v| v.checked_mul(1024 * 1024));
+    }
+
+    let (negative, digits) = match lower.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, lower.as_str()),
+    };
+    let parsed = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(oct) = digits.strip_prefix("0o") {
+        i64::from_str_radix(oct, 8).ok()
+    } else if let Some(bin) = digits.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2).ok()
+    } else {
+        digits.parse::<i64>().ok()
+    }?;
+    Some(if negative { -parsed } else { parsed })
+}
+
+fn parse_keyword_or_int(value: &str, keyword: Option<u8>, field: &str) -> Result<u8, String> {
+    if let Some(value) = keyword {
+        return Ok(value);
+    }
+    parse_int(value)
+        .and_then(|v| u8::try_from(v).ok())
+        .ok_or_else(|| format!("{field} '{value}' is not valid"))
+}
+
+fn alignment_for_type(ptype: u8) -> u32 {
+    if ptype == APP_TYPE {
+        0x10000
+    } else {
+        0x1000
+    }
+}
+
+struct CsvRow {
+    entry: PartitionEntry,
+    offset: Option<u32>,
+    /// Negative sizes in the CSV mean "up to this absolute offset".
+    size: i64,
+}
+
+fn parse_csv_line(line: &str, line_no: usize) -> Result<CsvRow, String> {
+    let mut fields: Vec<&str> = line.split(',').map(str::trim).collect();
+    fields.resize(6.max(fields.len()), "");
+
+    let name = fields[0].to_string();
+    if name.is_empty() {
+        return Err("name field can't be empty".to_string());
+    }
+    if name.len() > NAME_LEN {
+        return Err(format!("name '{name}' is longer than {NAME_LEN} bytes"));
+    }
+
+    if fields[1].is_empty() {
+        return Err("field 'type' can't be left empty".to_string());
+    }
+    let type_name = fields[1].to_ascii_lowercase();
+    let ptype = parse_keyword_or_int(fields[1], type_from_keyword(&type_name), "type")?;
+
+    let subtype = if fields[2].is_empty() {
+        if ptype == APP_TYPE {
+            return Err("app partition cannot have an empty subtype".to_string());
+        }
+        0x06
+    } else {
+        let subtype_name = fields[2].to_ascii_lowercase();
+        parse_keyword_or_int(
+            fields[2],
+            subtype_from_keyword(ptype, &subtype_name),
+            "subtype",
+        )?
+    };
+
+    let offset = if fields[3].is_empty() {
+        None
+    } else {
+        let value = parse_int(fields[3])
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or_else(|| format!("offset '{}' is not valid", fields[3]))?;
+        Some(value)
+    };
+
+    if fields[4].is_empty() {
+        return Err("size field can't be empty".to_string());
+    }
+    let size = parse_int(fields[4])
+        .filter(|v| v.unsigned_abs() <= u64::from(u32::MAX))
+        .ok_or_else(|| format!("size '{}' is not valid", fields[4]))?;
+
+    let mut encrypted = false;
+    let mut readonly = false;
+    for flag in fields[5].split(':').map(str::trim).filter(|f| !f.is_empty()) {
+        match flag {
+            "encrypted" => encrypted = true,
+            "readonly" => readonly = true,
+            other => return Err(format!("flag column contains unknown flag '{other}'")),
+        }
+    }
+
+    Ok(CsvRow {
+        entry: PartitionEntry {
+            name,
+            ptype,
+            subtype,
+            offset: 0,
+            size: 0,
+            encrypted,
+            readonly,
+            line: line_no,
+        },
+        offset,
+        size,
+    })
+}
+
+/// Parses an ESP-IDF partition CSV, filling blank offsets with the next
+/// correctly aligned address after the previous partition.
+pub fn parse_csv(csv: &str, table_offset: u32) -> Result<Vec<PartitionEntry>, String> {
+    let mut rows = Vec::new();
+    for (index, raw_line) in csv.lines().enumerate() {
+        let line = raw_line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let row = parse_csv_line(line, index + 1).map_err(|e| format!("line {}: {e}", index + 1))?;
+        rows.push(row);
+    }
+
+    let mut last_end = u64::from(table_offset) + u64::from(PARTITION_TABLE_SIZE);
+    let mut entries = Vec::with_capacity(rows.len());
+    for (index, row) in rows.into_iter().enumerate() {
+        let line = row.entry.line;
+        let offset = match row.offset {
+            Some(offset) if u64::from(offset) < last_end => {
+                return Err(if index == 0 {
+                    format!(
+                        "line {line}: partitions overlap. Partition sets offset 0x{offset:x} but the partition table occupies 0x{table_offset:x}-0x{:x}",
+                        last_end - 1
+                    )
+                } else {
+                    format!(
+                        "line {line}: partitions overlap. Partition sets offset 0x{offset:x} but the previous partition ends at 0x{last_end:x}"
+                    )
+                });
+            }
+            Some(offset) => u64::from(offset),
+            None => {
+                let align = u64::from(alignment_for_type(row.entry.ptype));
+                last_end.div_ceil(align) * align
+            }
+        };
+
+        let size = if row.size < 0 {
+            (-row.size) as u64 - offset.min((-row.size) as u64)
+        } else {
+            row.size as u64
+        };
+        let end = offset + size;
+        if end > u64::from(u32::MAX) + 1 {
+            return Err(format!("line {line}: partition ends beyond the 4GB address space"));
+        }
+
+        let mut entry = row.entry;
+        entry.offset = offset as u32;
+        entry.size = size as u32;
+        entries.push(entry);
+        last_end = end;
+    }
+    Ok(entries)
+}
+
+/// Same checks gen_esp32part.py runs before emitting a table.
+pub fn verify(entries: &[PartitionEntry], table_offset: u32, flash_size: Option<u32>) -> Result<(), String> {
+    for entry in entries {
+        let align = alignment_for_type(entry.ptype);
+        if entry.offset % align != 0 {
+            return Err(format!(
+                "{} invalid: offset 0x{:x} is not aligned to 0x{align:x}",
+                entry.describe(),
+                entry.offset
+            ));
+        }
+    }
+
+    for (index, entry) in entries.iter().enumerate() {
+        if let Some(duplicate) = entries[index + 1..].iter().find(|e| e.name == entry.name) {
+            return Err(format!(
+                "{} has the same name as {}; partition names must be unique",
+                duplicate.describe(),
+                entry.describe()
+            ));
+        }
+    }
+
+    let table_end = u64::from(table_offset) + u64::from(PARTITION_TABLE_SIZE);
+    let mut sorted: Vec<_> = entries.iter().collect();
+    sorted.sort_by_key(|entry| entry.offset);
+    for (index, entry) in sorted.iter().enumerate() {
+        if u64::from(entry.offset) < table_end {
+            return Err(format!(
+                "{} offset 0x{:x} is below 0x{table_end:x}",
+                entry.describe(),
+                entry.offset
+            ));
+        }
+        if index > 0 {
+            let last = sorted[index - 1];
+            let last_end = u64::from(last.offset) + u64::from(last.size);
+            if u64::from(entry.offset) < last_end {
+                return Err(format!(
+                    "{} at 0x{:x} overlaps 0x{:x}-0x{:x}",
+                    entry.describe(),
+                    entry.offset,
+                    last.offset,
+                    last_end - 1
+                ));
+            }
+        }
+    }
+
+    let otadata: Vec<_> = entries
+        .iter()
+        .filter(|e| e.ptype == DATA_TYPE && e.subtype == OTA_DATA_SUBTYPE)
+        .collect();
+    if otadata.len() > 1 {
+        return Err(format!(
+            "{}: found multiple otadata partitions, only one data/ota partition is allowed",
+            otadata[1].describe()
+        ));
+    }
+    if let Some(entry) = otadata.first() {
+        if entry.size != 0x2000 {
+            return Err(format!("{}: otadata partition must have size = 0x2000", entry.describe()));
+        }
+    }
+
+    if let Some(flash_size) = flash_size {
+        let used = table_size_used(entries);
+        if u64::from(flash_size) < used {
+            return Err(format!(
+                "partition table occupies {:.1}MB of flash ({used} bytes) which does not fit in the configured flash size {}MB",
+                used as f64 / (1024.0 * 1024.0),
+                flash_size / (1024 * 1024)
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Offset the last partition ends at.
+pub fn table_size_used(entries: &[PartitionEntry]) -> u64 {
+    entries
+        .iter()
+        .map(|entry| u64::from(entry.offset) + u64::from(entry.size))
+        .max()
+        .unwrap_or(0)
+}
+
+pub fn parse_flash_size(flash_size: &str) -> Result<Option<u32>, String> {
+    let upper = flash_size.trim().to_ascii_uppercase();
+    if upper.is_empty() || upper == "NONE" {
+        return Ok(None);
+    }
+    upper
+        .strip_suffix("MB")
+        .and_then(|mb| mb.parse::<u32>().ok())
+        .filter(|mb| mb.is_power_of_two() && *mb <= 128)
+        .map(|mb| Some(mb * 1024 * 1024))
+        .ok_or_else(|| format!("unsupported flash size: {flash_size}"))
+}
+
+pub fn to_binary(entries: &[PartitionEntry]) -> Result<Vec<u8>, String> {
+    let mut result = Vec::with_capacity(MAX_PARTITION_LENGTH);
+    for entry in entries {
+        let mut name = [0u8; NAME_LEN];
+        let bytes = entry.name.as_bytes();
+        if bytes.len() > NAME_LEN {
+            return Err(format!("{} name is longer than {NAME_LEN} bytes", entry.describe()));
+        }
+        name[..bytes.len()].copy_from_slice(bytes);
+
+        let mut flags = 0u32;
+        if entry.encrypted {
+            flags |= FLAG_ENCRYPTED;
+        }
+        if entry.readonly {
+            flags |= FLAG_READONLY;
+        }
+
+        result.extend_from_slice(&ENTRY_MAGIC);
+        result.push(entry.ptype);
+        result.push(entry.subtype);
+        result.extend_from_slice(&entry.offset.to_le_bytes());
+        result.extend_from_slice(&entry.size.to_le_bytes());
+        result.extend_from_slice(&name);
+        result.extend_from_slice(&flags.to_le_bytes());
+    }
+
+    let digest = Md5::digest(&result);
+    result.extend_from_slice(&MD5_MAGIC);
+    result.extend_from_slice(&[0xFF; 14]);
+    result.extend_from_slice(&digest);
+
+    if result.len() >= MAX_PARTITION_LENGTH {
+        return Err(format!(
+            "binary partition table length ({}) longer than max",
+            result.len()
+        ));
+    }
+    result.resize(MAX_PARTITION_LENGTH, 0xFF);
+    Ok(result)
+}
+
+pub fn from_binary(data: &[u8]) -> Result<Vec<PartitionEntry>, String> {
+    let mut entries = Vec::new();
+    let mut md5 = Md5::new();
+
+    for (index, chunk) in data.chunks(ENTRY_LEN).enumerate() {
+        if chunk.len() != ENTRY_LEN {
+            return Err("partition table length must be a multiple of 32 bytes".to_string());
+        }
+        if chunk.iter().all(|byte| *byte == 0xFF) {
+            return Ok(entries);
+        }
+        if chunk[..2] == MD5_MAGIC {
+            let computed = md5.clone().finalize();
+            if chunk[16..] != computed[..] {
+                return Err(format!(
+                    "entry {index}: MD5 checksums don't match (computed {}, parsed {})",
+                    hex_string(&computed),
+                    hex_string(&chunk[16..])
+                ));
+            }
+            continue;
+        }
+        md5.update(chunk);
+
+        if chunk[..2] != ENTRY_MAGIC {
+            return Err(format!(
+                "entry {index}: invalid magic bytes {:02X} {:02X} for partition definition",
+                chunk[0], chunk[1]
+            ));
+        }
+        let name_bytes = &chunk[12..28];
+        let name_len = name_bytes.iter().position(|b| *b == 0).unwrap_or(NAME_LEN);
+        let flags = u32::from_le_bytes([chunk[28], chunk[29], chunk[30], chunk[31]]);
+
+        entries.push(PartitionEntry {
+            name: String::from_utf8_lossy(&name_bytes[..name_len]).to_string(),
+            ptype: chunk[2],
+            subtype: chunk[3],
+            offset: u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]),
+            size: u32::from_le_bytes([chunk[8], chunk[9], chunk[10], chunk[11]]),
+            encrypted: flags & FLAG_ENCRYPTED != 0,
+            readonly: flags & FLAG_READONLY != 0,
+            line: 0,
+        });
+    }
+    Err("partition table is missing an end-of-table marker".to_string())
+}
+
+fn hex_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn format_size(size: u32) -> String {
+    if size != 0 && size.is_multiple_of(0x100000) {
+        format!("{}M", size / 0x100000)
+    } else if size != 0 && size.is_multiple_of(0x400) {
+        format!("{}K", size / 0x400)
+    } else {
+        format!("0x{size:x}")
+    }
+}
+
+pub fn to_csv(entries: &[PartitionEntry]) -> String {
+    let mut rows = vec![
+        "# ESP-IDF Partition Table".to_string(),
+        "# Name, Type, SubType, Offset, Size, Flags".to_string(),
+    ];
+    for entry in entries {
+        let mut flags = Vec::new();
+        if entry.encrypted {
+            flags.push("encrypted");
+        }
+        if entry.readonly {
+            flags.push("readonly");
+        }
+        rows.push(
+            [
+                entry.name.clone(),
+                type_keyword(entry.ptype)
+                    .map(str::to_string)
+                    .unwrap_or_else(|| entry.ptype.to_string()),
+                subtype_keyword(entry.ptype, entry.subtype)
+                    .unwrap_or_else(|| entry.subtype.to_string()),
+                format!("0x{:x}", entry.offset),
+                format_size(entry.size),
+                flags.join(":"),
+            ]
+            .join(","),
+        );
+    }
+    rows.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SINGLE_APP_CSV: &str = "\
+# Name,   Type, SubType, Offset,  Size, Flags
+nvs,      data, nvs,     0x9000,  0x6000,
+phy_init, data, phy,     0xf000,  0x1000,
+factory,  app,  factory, 0x10000, 1M,
+";
+
+    /// First 0x80 bytes of `gen_esp32part.py partitions_singleapp.csv`.
+    const SINGLE_APP_BIN: &str = "\
+aa50010200900000006000006e76730000000000000000000000000000000000\
+aa50010100f00000001000007068795f696e6974000000000000000000000000\
+aa5000000000010000001000666163746f727900000000000000000000000000\
+ebebfffffffffffffffffffffffffffff4ad4f4538564b5d7435b62c75b69524";
+
+    fn unhex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn matches_gen_esp32part_output() {
+        let entries = parse_csv(SINGLE_APP_CSV, DEFAULT_TABLE_OFFSET).unwrap();
+        verify(&entries, DEFAULT_TABLE_OFFSET, parse_flash_size("4MB").unwrap()).unwrap();
+
+        let binary = to_binary(&entries).unwrap();
+        let expected = unhex(SINGLE_APP_BIN);
+        assert_eq!(binary.len(), MAX_PARTITION_LENGTH);
+        assert_eq!(binary[..expected.len()], expected[..]);
+        assert!(binary[expected.len()..].iter().all(|b| *b == 0xFF));
+
+        let decoded = from_binary(&binary).unwrap();
+        assert_eq!(to_csv(&decoded), to_csv(&entries));
+        assert_eq!(
+            to_csv(&decoded).lines().nth(4),
+            Some("factory,app,factory,0x10000,1M,")
+        );
+    }
+
+    #[test]
+    fn rejects_corrupted_md5_entry() {
+        let entries = parse_csv(SINGLE_APP_CSV, DEFAULT_TABLE_OFFSET).unwrap();
+        let mut binary = to_binary(&entries).unwrap();
+        binary[3 * ENTRY_LEN..3 * ENTRY_LEN + 2].copy_from_slice(&MD5_MAGIC);
+        binary[4 * ENTRY_LEN - 1] ^= 0x01;
+        let err = from_binary(&binary).unwrap_err();
+        assert!(err.contains("MD5 checksums don't match"), "{err}");
+
+        binary[3 * ENTRY_LEN] = 0x00;
+        let err = from_binary(&binary).unwrap_err();
+        assert!(err.contains("invalid magic bytes"), "{err}");
+    }
+
+    #[test]
+    fn places_blank_offsets_at_type_alignment() {
+        let csv = "\
+nvs,      data, nvs,     ,  0x4000,
+otadata,  data, ota,     ,  0x2000,
+phy_init, data, phy,     ,  0x1000,
+ota_0,    app,  ota_0,   ,  0x1000,
+ota_1,    app,  ota_1,   ,  0x1000,
+storage,  data, spiffs,  ,  -0x40000,
+";
+        let entries = parse_csv(csv, DEFAULT_TABLE_OFFSET).unwrap();
+        let placed: Vec<_> = entries.iter().map(|e| (e.offset, e.size)).collect();
+        assert_eq!(
+            placed,
+            [
+                (0x9000, 0x4000),
+                (0xD000, 0x2000),
+                (0xF000, 0x1000),
+                (0x10000, 0x1000),
+                (0x20000, 0x1000),
+                (0x21000, 0x1F000),
+            ]
+        );
+        verify(&entries, DEFAULT_TABLE_OFFSET, None).unwrap();
+
+        let moved = parse_csv("nvs,data,nvs,,0x1000\n", 0x10000).unwrap();
+        assert_eq!(moved[0].offset, 0x11000);
+    }
+
+    #[test]
+    fn reports_misaligned_and_overlapping_offsets() {
+        let entries = parse_csv("factory,app,factory,0x11000,1M\n", DEFAULT_TABLE_OFFSET).unwrap();
+        let err = verify(&entries, DEFAULT_TABLE_OFFSET, None).unwrap_err();
+        assert!(err.contains("not aligned to 0x10000"), "{err}");
+
+        let err = parse_csv("nvs,data,nvs,0x8000,0x1000\n", DEFAULT_TABLE_OFFSET).unwrap_err();
+        assert!(err.starts_with("line 1: partitions overlap"), "{err}");
+
+        let err = parse_csv(
+            "nvs,data,nvs,0x9000,24K\nphy,data,phy,0xc000,4K\n",
+            DEFAULT_TABLE_OFFSET,
+        )
+        .unwrap_err();
+        assert!(err.contains("previous partition ends at 0xf000"), "{err}");
+
+        let entries = parse_csv("factory,app,factory,0x10000,4M\n", DEFAULT_TABLE_OFFSET).unwrap();
+        let err = verify(&entries, DEFAULT_TABLE_OFFSET, Some(4 << 20)).unwrap_err();
+        assert!(err.contains("does not fit"), "{err}");
+    }
+
+    #[test]
+    fn parses_size_suffixes_and_flags() {
+        assert_eq!(parse_int("24K"), Some(24 * 1024));
+        assert_eq!(parse_int("1m"), Some(1 << 20));
+        assert_eq!(parse_int("0x10K"), Some(16 * 1024));
+        assert_eq!(parse_int("-0x40000"), Some(-0x40000));
+        assert_eq!(parse_int("4G"), None);
+
+        assert_eq!(format_size(0x6000), "24K");
+        assert_eq!(format_size(0x200000), "2M");
+        assert_eq!(format_size(0x1234), "0x1234");
+
+        let csv = "nvs_key,data,nvs_keys,0x9000,4K,encrypted:readonly\n";
+        let entries = parse_csv(csv, DEFAULT_TABLE_OFFSET).unwrap();
+        assert!(entries[0].encrypted && entries[0].readonly);
+        assert_eq!(entries[0].subtype, 0x04);
+        let binary = to_binary(&entries).unwrap();
+        assert_eq!(binary[28..32], [0x03, 0, 0, 0]);
+
+        let err = parse_csv("nvs,data,nvs,0x9000,4K,secure\n", DEFAULT_TABLE_OFFSET).unwrap_err();
+        assert!(err.contains("unknown flag 'secure'"), "{err}");
+        assert!(parse_flash_size("3MB").is_err());
+        assert_eq!(parse_flash_size("none").unwrap(), None);
+    }
+}
@@ -0,0 +1,2046 @@
+use crate::esp_backtrace::{self, BacktraceFrame, Symbolizer};
+use crate::esp_core_dump::{self, CoreDumpCollector};
+use crate::esp_log::{self, LogFilter, LogRecord};
+use crate::serial_capture::{CaptureWriter, Direction, RotationPolicy};
+use crate::serial_checksum::{ChecksumCheck, ChecksumSpec};
+use crate::serial_framing::{Frame, Framer, FramingMode, Stamp};
+use crate::serial_modbus::{self, ModbusFrame, ModbusPoll, ModbusPollResult};
+use crate::serial_network;
+use crate::serial_periodic::{PeriodicProgress, PeriodicSchedule, PeriodicSend};
+use crate::serial_ports::{self, SerialPortEntry};
+use crate::serial_rfc2217::{self, ComPort, ServerEvent};
+use crate::serial_script::{ScriptPort, ScriptProgress, ScriptRunner, ScriptStep};
+use crate::serial_sequence::{self, SequenceStep};
+use crate::serial_stats::{self, SessionStats, StatsSnapshot};
+use crate::serial_transfer::{Transfer, TransferJob, TransferPort, TransferProgress};
+use serialport::{DataBits, FlowControl, Parity, SerialPort, StopBits};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender, SyncSender, TryRecvError};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tauri::{Emitter, Manager};
+
+pub const EVENT_NAME: &str = "serial_assistant_event";
+
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+/// How often the reader samples CTS/DSR/RI/CD between reads.
+const MODEM_POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// How often a lost port is looked for while waiting to reconnect.
+const RECONNECT_INTERVAL: Duration = Duration::from_millis(250);
+
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(1);
+
+type SharedCapture = Arc<Mutex<Option<CaptureWriter>>>;
+
+pub struct SerialOpenOptions {
+    pub port: String,
+    pub baud_rate: u32,
+    pub data_bits: u8,
+    pub stop_bits: u8,
+    pub parity: String,
+    pub flow_control: String,
+    /// Where core dumps received on this port are saved.
+    pub core_dump_dir: PathBuf,
+}
+
+/// Parsed line settings, shared by opening and reconfiguring a port.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct LineSettings {
+    pub baud_rate: u32,
+    pub data_bits: DataBits,
+    pub stop_bits: StopBits,
+    pub parity: Parity,
+    pub flow_control: FlowControl,
+}
+
+impl LineSettings {
+    pub fn parse(
+        baud_rate: u32,
+        data_bits: u8,
+        stop_bits: u8,
+        parity: &str,
+        flow_control: &str,
+    ) -> Result<Self, String> {
+        if baud_rate == 0 {
+            return Err("baud_rate must be greater than 0".to_string());
+        }
+        Ok(Self {
+            baud_rate,
+            data_bits: parse_data_bits(data_bits)?,
+            stop_bits: parse_stop_bits(stop_bits)?,
+            parity: parse_parity(parity)?,
+            flow_control: parse_flow_control(flow_control)?,
+        })
+    }
+
+    fn apply(&self, port: &mut Box<dyn SerialPort>) -> Result<(), String> {
+        port.set_baud_rate(self.baud_rate)
+            .map_err(|e| format!("failed to set baud rate: {e}"))?;
+        port.set_data_bits(self.data_bits)
+            .map_err(|e| format!("failed to set data bits: {e}"))?;
+        port.set_stop_bits(self.stop_bits)
+            .map_err(|e| format!("failed to set stop bits: {e}"))?;
+        port.set_parity(self.parity)
+            .map_err(|e| format!("failed to set parity: {e}"))?;
+        port.set_flow_control(self.flow_control)
+            .map_err(|e| format!("failed to set flow control: {e}"))
+    }
+}
+
+/// Keeps a session alive across a device reset or USB re-enumeration: the
+/// port is reopened with the same settings once it shows up again.
+#[derive(Clone, Copy)]
+pub struct ReconnectPolicy {
+    /// Gives up after the port has been gone this long; `None` waits until
+    /// the session is closed.
+    pub timeout: Option<Duration>,
+}
+
+#[derive(serde::Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ReconnectState {
+    Waiting,
+    Reconnected,
+    Failed,
+}
+
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct ReconnectProgress {
+    pub state: ReconnectState,
+    /// The port being waited for, or the one the device came back on.
+    pub port: String,
+}
+
+/// Emits events for one session, tagging each payload with its id so a
+/// window holding several ports can route them.
+#[derive(Clone)]
+pub struct SessionEmitter {
+    window: tauri::Window,
+    session_id: String,
+    opened_at: Instant,
+}
+
+impl SessionEmitter {
+    /// Tags the event with the session id and its host time. Data events keep
+    /// the time their first byte was read; everything else is stamped now.
+    pub fn emit(&self, mut event: SerialAssistantEvent) {
+        let (monotonic, wall) = event.stamp.map_or_else(
+            || (Instant::now(), SystemTime::now()),
+            |stamp| (stamp.monotonic, stamp.wall),
+        );
+        event.session_id = self.session_id.clone();
+        event.monotonic_us = monotonic
+            .saturating_duration_since(self.opened_at)
+            .as_micros() as u64;
+        event.timestamp_ms = wall
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        let _ = self.window.emit(EVENT_NAME, event);
+    }
+}
+
+struct SerialSession {
+    port: String,
+    settings: LineSettings,
+    emitter: SessionEmitter,
+    capture: SharedCapture,
+    stats: Arc<SessionStats>,
+    command_tx: SyncSender<SerialCommand>,
+    reader_tx: Sender<ReaderCommand>,
+    closing: Arc<AtomicBool>,
+    reader_handle: Option<thread::JoinHandle<()>>,
+    writer_handle: Option<thread::JoinHandle<()>>,
+    script: Option<RunningTask>,
+    transfer: Option<RunningTask>,
+    modbus_poll: Option<RunningTask>,
+    rfc2217: Option<RunningTask>,
+    /// Whether the reader decodes Modbus RTU; its frame gap follows the baud.
+    modbus_decode: bool,
+    /// The reader's reconnect policy, kept here so the port watcher leaves
+    /// sessions that wait for their device alone.
+    reconnect: Option<ReconnectPolicy>,
+}
+
+/// A script, file transfer or server running alongside the session.
+struct RunningTask {
+    cancel: Arc<AtomicBool>,
+    handle: thread::JoinHandle<()>,
+}
+
+impl RunningTask {
+    fn is_running(task: &Option<RunningTask>) -> bool {
+        task.as_ref().is_some_and(|task| !task.handle.is_finished())
+    }
+}
+
+impl SerialSession {
+    /// Subscribes to the RX stream. The returned `ack` fires once the reader
+    /// has the tap in place; wait for it before sending anything that
+    /// expects a reply.
+    fn tap(&self) -> Result<(mpsc::Receiver<Vec<u8>>, mpsc::Receiver<()>), String> {
+        let (tx, rx) = mpsc::channel();
+        let (ack_tx, ack_rx) = mpsc::channel();
+        self.reader_tx
+            .send(ReaderCommand::Tap { tx, ack: ack_tx })
+            .map_err(|_| "serial reader is unavailable".to_string())?;
+        Ok((rx, ack_rx))
+    }
+}
+
+#[derive(serde::Serialize, Clone)]
+pub struct SerialSessionInfo {
+    pub session_id: String,
+    pub port: String,
+    pub baud_rate: u32,
+}
+
+#[derive(Default)]
+pub struct SerialAssistantState {
+    sessions: Mutex<HashMap<String, SerialSession>>,
+}
+
+/// Input modem status lines.
+#[derive(serde::Serialize, Clone, Copy, PartialEq, Eq)]
+pub struct ModemSignals {
+    pub cts: bool,
+    pub dsr: bool,
+    pub ri: bool,
+    pub cd: bool,
+}
+
+impl ModemSignals {
+    fn read(port: &mut Box<dyn SerialPort>) -> serialport::Result<Self> {
+        Ok(Self {
+            cts: port.read_clear_to_send()?,
+            dsr: port.read_data_set_ready()?,
+            ri: port.read_ring_indicator()?,
+            cd: port.read_carrier_detect()?,
+        })
+    }
+}
+
+#[derive(serde::Serialize, Clone)]
+pub struct SerialAssistantEvent {
+    session_id: String,
+    kind: String,
+    text: String,
+    hex: String,
+    /// Wall clock time, milliseconds since the Unix epoch.
+    timestamp_ms: u64,
+    /// Host monotonic time since the session was opened.
+    monotonic_us: u64,
+    /// Position of the first byte in the RX stream (data events only).
+    offset: u64,
+    /// Parsed ESP-IDF log fields (log events only).
+    log: Option<LogRecord>,
+    /// Symbolized frames, innermost first (backtrace events only).
+    frames: Option<Vec<BacktraceFrame>>,
+    /// Current modem status lines (signals events only).
+    signals: Option<ModemSignals>,
+    /// Session counters (stats events only).
+    stats: Option<StatsSnapshot>,
+    /// Script step or outcome (script events only).
+    script: Option<ScriptProgress>,
+    /// Schedule position after this send (periodic events only).
+    periodic: Option<PeriodicProgress>,
+    /// File transfer progress or outcome (transfer events only).
+    transfer: Option<TransferProgress>,
+    /// Decoded RTU frame (modbus events only).
+    modbus: Option<ModbusFrame>,
+    /// Latest poll response or failure (modbus_poll events only).
+    modbus_poll: Option<ModbusPollResult>,
+    /// Trailing checksum of a framed data event, when RX checking is on.
+    checksum: Option<ChecksumCheck>,
+    /// Where an automatic reconnect stands (reconnect events only).
+    reconnect: Option<ReconnectProgress>,
+    #[serde(skip)]
+    stamp: Option<Stamp>,
+}
+
+pub enum SerialCommand {
+    Send {
+        data: Vec<u8>,
+        response_tx: Sender<Result<usize, String>>,
+    },
+    SetSignals {
+        rts: bool,
+        dtr: bool,
+        response_tx: Sender<Result<(), String>>,
+    },
+    /// Holds TX in the break condition for the given time.
+    Break {
+        duration: Duration,
+        response_tx: Sender<Result<(), String>>,
+    },
+    /// Holds TX in the break condition until told otherwise.
+    SetBreak {
+        on: bool,
+        response_tx: Sender<Result<(), String>>,
+    },
+    ReadSignals {
+        response_tx: Sender<Result<ModemSignals, String>>,
+    },
+    /// Applies new line settings to the open port. The reader shares the
+    /// underlying handle, so it picks them up with its next read.
+    Reconfigure {
+        settings: LineSettings,
+        response_tx: Sender<Result<(), String>>,
+    },
+    /// Drives DTR/RTS through a reset or boot sequence with its delays.
+    RunSequence {
+        steps: Vec<SequenceStep>,
+        response_tx: Sender<Result<(), String>>,
+    },
+    /// Replaces any running periodic schedule with a new one.
+    StartPeriodic {
+        schedule: PeriodicSchedule,
+        response_tx: Sender<Result<(), String>>,
+    },
+    StopPeriodic {
+        response_tx: Sender<Result<(), String>>,
+    },
+    /// Runs a file transfer to completion, reading replies from `rx`.
+    Transfer {
+        job: TransferJob,
+        rx: mpsc::Receiver<Vec<u8>>,
+        cancel: Arc<AtomicBool>,
+        response_tx: Sender<Result<(), String>>,
+    },
+    /// Drops the lost port while the reader waits for the device, or takes
+    /// over the one it reopened.
+    SwapPort {
+        port: Option<Box<dyn SerialPort>>,
+        response_tx: Sender<Result<(), String>>,
+    },
+    Shutdown,
+}
+
+/// Control messages for the reader thread, which only polls between reads.
+enum ReaderCommand {
+    SetFraming(FramingMode),
+    SetLogFilter(Option<LogFilter>),
+    SetElf(Option<PathBuf>),
+    /// Enables Modbus RTU decoding with the given inter-frame gap.
+    SetModbus(Option<Duration>),
+    SetRxChecksum(Option<ChecksumSpec>),
+    SetReconnect(Option<ReconnectPolicy>),
+    /// Forwards every received chunk to `tx` until it hangs up. `ack` is
+    /// answered once the tap is in place, so no reply can slip past it.
+    Tap {
+        tx: Sender<Vec<u8>>,
+        ack: Sender<()>,
+    },
+    Stop,
+}
+
+impl SerialAssistantEvent {
+    fn new(kind: &str, text: String, hex: String) -> Self {
+        Self {
+            session_id: String::new(),
+            kind: kind.to_string(),
+            text,
+            hex,
+            timestamp_ms: 0,
+            monotonic_us: 0,
+            offset: 0,
+            log: None,
+            frames: None,
+            signals: None,
+            stats: None,
+            script: None,
+            periodic: None,
+            transfer: None,
+            modbus: None,
+            modbus_poll: None,
+            checksum: None,
+            reconnect: None,
+            stamp: None,
+        }
+    }
+
+    pub fn status(text: impl Into<String>) -> Self {
+        Self::new("status", text.into(), String::new())
+    }
+
+    pub fn error(text: impl Into<String>) -> Self {
+        Self::new("error", text.into(), String::new())
+    }
+
+    /// A problem that does not end the session, e.g. a capture or core dump
+    /// that could not be written.
+    pub fn warning(text: impl Into<String>) -> Self {
+        Self::new("warning", text.into(), String::new())
+    }
+
+    pub fn signals(signals: ModemSignals) -> Self {
+        let text = format!(
+            "CTS={} DSR={} RI={} CD={}",
+            u8::from(signals.cts),
+            u8::from(signals.dsr),
+            u8::from(signals.ri),
+            u8::from(signals.cd)
+        );
+        Self {
+            signals: Some(signals),
+            ..Self::new("signals", text, String::new())
+        }
+    }
+
+    pub fn stats(stats: StatsSnapshot) -> Self {
+        Self {
+            stats: Some(stats),
+            ..Self::new("stats", String::new(), String::new())
+        }
+    }
+
+    pub fn script(progress: ScriptProgress) -> Self {
+        Self {
+            script: Some(progress.clone()),
+            ..Self::new("script", progress.message, String::new())
+        }
+    }
+
+    pub fn transfer(progress: TransferProgress) -> Self {
+        Self {
+            transfer: Some(progress.clone()),
+            ..Self::new("transfer", progress.message, String::new())
+        }
+    }
+
+    pub fn modbus(frame: &Frame, decoded: ModbusFrame) -> Self {
+        Self {
+            kind: "modbus".to_string(),
+            text: decoded.summary.clone(),
+            modbus: Some(decoded),
+            ..Self::data(frame)
+        }
+    }
+
+    pub fn modbus_poll(result: ModbusPollResult) -> Self {
+        let text = match &result.error {
+            Some(err) => format!("modbus poll failed: {err}"),
+            None => String::new(),
+        };
+        Self {
+            modbus_poll: Some(result),
+            ..Self::new("modbus_poll", text, String::new())
+        }
+    }
+
+    pub fn port_removed(text: impl Into<String>) -> Self {
+        Self::new("port_removed", text.into(), String::new())
+    }
+
+    pub fn reconnect(progress: ReconnectProgress, text: impl Into<String>) -> Self {
+        Self {
+            reconnect: Some(progress),
+            ..Self::new("reconnect", text.into(), String::new())
+        }
+    }
+
+    /// A payload sent by the periodic schedule.
+    pub fn periodic(data: &[u8], progress: PeriodicProgress) -> Self {
+        Self {
+            periodic: Some(progress),
+            ..Self::new(
+                "periodic",
+                String::from_utf8_lossy(data).to_string(),
+                format_hex(data),
+            )
+        }
+    }
+
+    /// Data a remote RFC 2217 client wrote to the port.
+    pub fn remote_tx(data: &[u8]) -> Self {
+        Self::new(
+            "remote_tx",
+            String::from_utf8_lossy(data).to_string(),
+            format_hex(data),
+        )
+    }
+
+    pub fn data(frame: &Frame) -> Self {
+        let hex = format_hex(&frame.data);
+        let text = String::from_utf8_lossy(&frame.data).to_string();
+        Self {
+            offset: frame.stamp.offset,
+            stamp: Some(frame.stamp),
+            ..Self::new("data", text, hex)
+        }
+    }
+
+    pub fn log(frame: &Frame, record: LogRecord) -> Self {
+        Self {
+            kind: "log".to_string(),
+            log: Some(record),
+            ..Self::data(frame)
+        }
+    }
+
+    /// A core dump was received and saved; `text` is the file path.
+    pub fn core_dump(path: &Path) -> Self {
+        Self::new(
+            "core_dump",
+            path.to_string_lossy().to_string(),
+            String::new(),
+        )
+    }
+
+    pub fn backtrace(frame: &Frame, frames: Vec<BacktraceFrame>) -> Self {
+        let text = frames
+            .iter()
+            .map(BacktraceFrame::describe)
+            .collect::<Vec<_>>()
+            .join("\n");
+        Self {
+            offset: frame.stamp.offset,
+            stamp: Some(frame.stamp),
+            frames: Some(frames),
+            ..Self::new("backtrace", text, String::new())
+        }
+    }
+}
+
+fn format_hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{b:02X}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn parse_data_bits(bits: u8) -> Result<DataBits, String> {
+    match bits {
+        5 => Ok(DataBits::Five),
+        6 => Ok(DataBits::Six),
+        7 => Ok(DataBits::Seven),
+        8 => Ok(DataBits::Eight),
+        _ => Err("data_bits must be one of: 5, 6, 7, 8".to_string()),
+    }
+}
+
+fn parse_stop_bits(bits: u8) -> Result<StopBits, String> {
+    match bits {
+        1 => Ok(StopBits::One),
+        2 => Ok(StopBits::Two),
+        _ => Err("stop_bits must be one of: 1, 2".to_string()),
+    }
+}
+
+fn parse_parity(parity: &str) -> Result<Parity, String> {
+    match parity.to_ascii_lowercase().as_str() {
+        "none" => Ok(Parity::None),
+        "odd" => Ok(Parity::Odd),
+        "even" => Ok(Parity::Even),
+        _ => Err("parity must be one of: none, odd, even".to_string()),
+    }
+}
+
+fn parse_flow_control(flow_control: &str) -> Result<FlowControl, String> {
+    match flow_control.to_ascii_lowercase().as_str() {
+        "none" => Ok(FlowControl::None),
+        "software" => Ok(FlowControl::Software),
+        "hardware" => Ok(FlowControl::Hardware),
+        _ => Err("flow_control must be one of: none, software, hardware".to_string()),
+    }
+}
+
+impl SerialAssistantState {
+    fn lock(&self) -> Result<MutexGuard<'_, HashMap<String, SerialSession>>, String> {
+        self.sessions
+            .lock()
+            .map_err(|_| "failed to lock serial sessions".to_string())
+    }
+
+    pub fn contains(&self, session_id: &str) -> Result<bool, String> {
+        Ok(self.lock()?.contains_key(session_id))
+    }
+
+    pub fn sessions_for_window(&self, label: &str) -> Result<Vec<SerialSessionInfo>, String> {
+        let sessions = self.lock()?;
+        let mut list: Vec<_> = sessions
+            .iter()
+            .filter(|(_, session)| session.emitter.window.label() == label)
+            .map(|(session_id, session)| SerialSessionInfo {
+                session_id: session_id.clone(),
+                port: session.port.clone(),
+                baud_rate: session.settings.baud_rate,
+            })
+            .collect();
+        list.sort_by_key(|info| session_number(&info.session_id));
+        Ok(list)
+    }
+
+    /// Hands a command to the session's writer thread and waits for its reply.
+    /// Refused while a file transfer runs: the writer is busy with it until
+    /// it ends, and the caller would be stuck waiting just as long.
+    pub fn request<T>(
+        &self,
+        session_id: &str,
+        command: impl FnOnce(Sender<Result<T, String>>) -> SerialCommand,
+    ) -> Result<T, String> {
+        let command_tx = {
+            let sessions = self.lock()?;
+            let session = sessions
+                .get(session_id)
+                .ok_or_else(|| "serial is not connected".to_string())?;
+            if RunningTask::is_running(&session.transfer) {
+                return Err("a file transfer is in progress".to_string());
+            }
+            session.command_tx.clone()
+        };
+        request_writer(&command_tx, command)
+    }
+
+    /// Starts streaming the session to a new capture file pair in `dir` and
+    /// returns the text log path. A capture already running is closed first.
+    pub fn start_capture(
+        &self,
+        session_id: &str,
+        dir: &Path,
+        policy: RotationPolicy,
+    ) -> Result<String, String> {
+        let (port, capture, emitter) = {
+            let sessions = self.lock()?;
+            let session = sessions
+                .get(session_id)
+                .ok_or_else(|| "serial is not connected".to_string())?;
+            (
+                session.port.clone(),
+                Arc::clone(&session.capture),
+                session.emitter.clone(),
+            )
+        };
+
+        let writer = CaptureWriter::create(dir, &port, policy)?;
+        let path = writer.log_path().to_string_lossy().to_string();
+        let previous = capture
+            .lock()
+            .map_err(|_| "failed to lock serial capture".to_string())?
+            .replace(writer);
+        if let Some(previous) = previous {
+            let _ = previous.finish();
+        }
+        emitter.emit(SerialAssistantEvent::status(format!(
+            "capture started: {path}"
+        )));
+        Ok(path)
+    }
+
+    pub fn stop_capture(&self, session_id: &str) -> Result<(), String> {
+        let (capture, emitter) = {
+            let sessions = self.lock()?;
+            let session = sessions
+                .get(session_id)
+                .ok_or_else(|| "serial is not connected".to_string())?;
+            (Arc::clone(&session.capture), session.emitter.clone())
+        };
+
+        let writer = capture
+            .lock()
+            .map_err(|_| "failed to lock serial capture".to_string())?
+            .take();
+        if let Some(writer) = writer {
+            writer.finish()?;
+            emitter.emit(SerialAssistantEvent::status("capture stopped"));
+        }
+        Ok(())
+    }
+
+    fn send_to_reader(&self, session_id: &str, command: ReaderCommand) -> Result<(), String> {
+        let sessions = self.lock()?;
+        let session = sessions
+            .get(session_id)
+            .ok_or_else(|| "serial is not connected".to_string())?;
+        session
+            .reader_tx
+            .send(command)
+            .map_err(|_| "serial reader is unavailable".to_string())
+    }
+
+    pub fn set_framing(&self, session_id: &str, mode: FramingMode) -> Result<(), String> {
+        self.send_to_reader(session_id, ReaderCommand::SetFraming(mode))
+    }
+
+    /// `None` turns log parsing off and restores plain data events.
+    pub fn set_log_filter(
+        &self,
+        session_id: &str,
+        filter: Option<LogFilter>,
+    ) -> Result<(), String> {
+        self.send_to_reader(session_id, ReaderCommand::SetLogFilter(filter))
+    }
+
+    /// Checks the trailing checksum of every framed RX data event.
+    pub fn set_rx_checksum(
+        &self,
+        session_id: &str,
+        spec: Option<ChecksumSpec>,
+    ) -> Result<(), String> {
+        self.send_to_reader(session_id, ReaderCommand::SetRxChecksum(spec))
+    }
+
+    pub fn set_modbus_decode(&self, session_id: &str, enabled: bool) -> Result<(), String> {
+        let mut sessions = self.lock()?;
+        let session = sessions
+            .get_mut(session_id)
+            .ok_or_else(|| "serial is not connected".to_string())?;
+        let gap = enabled.then(|| serial_modbus::frame_gap(session.settings.baud_rate));
+        session
+            .reader_tx
+            .send(ReaderCommand::SetModbus(gap))
+            .map_err(|_| "serial reader is unavailable".to_string())?;
+        session.modbus_decode = enabled;
+        Ok(())
+    }
+
+    /// Turns automatic reconnect on or off. With a policy set, a read error
+    /// or a vanished port makes the reader wait for the device to come back
+    /// instead of ending the session.
+    pub fn set_reconnect(
+        &self,
+        session_id: &str,
+        policy: Option<ReconnectPolicy>,
+    ) -> Result<(), String> {
+        let mut sessions = self.lock()?;
+        let session = sessions
+            .get_mut(session_id)
+            .ok_or_else(|| "serial is not connected".to_string())?;
+        session
+            .reader_tx
+            .send(ReaderCommand::SetReconnect(policy))
+            .map_err(|_| "serial reader is unavailable".to_string())?;
+        session.reconnect = policy;
+        Ok(())
+    }
+
+    /// Hands the writer its side of a reconnect and records the name the
+    /// port came back under.
+    fn swap_port(
+        &self,
+        session_id: &str,
+        name: &str,
+        port: Option<Box<dyn SerialPort>>,
+    ) -> Result<(), String> {
+        // Not `request`: the port has to come back even under a transfer,
+        // which then fails on its own.
+        let command_tx = self
+            .lock()?
+            .get(session_id)
+            .map(|session| session.command_tx.clone())
+            .ok_or_else(|| "serial is not connected".to_string())?;
+        request_writer(&command_tx, |response_tx| SerialCommand::SwapPort {
+            port,
+            response_tx,
+        })?;
+        if let Some(session) = self.lock()?.get_mut(session_id) {
+            session.port = name.to_string();
+        }
+        Ok(())
+    }
+
+    /// Starts polling registers, replacing any poll already running on the
+    /// session. Each response or failure arrives as a modbus_poll event.
+    pub fn start_modbus_poll(&self, session_id: &str, poll: ModbusPoll) -> Result<(), String> {
+        let mut sessions = self.lock()?;
+        let session = sessions
+            .get_mut(session_id)
+            .ok_or_else(|| "serial is not connected".to_string())?;
+        if let Some(running) = &session.modbus_poll {
+            running.cancel.store(true, Ordering::SeqCst);
+        }
+
+        let (rx, ack_rx) = session.tap()?;
+        let cancel = Arc::new(AtomicBool::new(false));
+        let poll_cancel = Arc::clone(&cancel);
+        let command_tx = session.command_tx.clone();
+        let emitter = session.emitter.clone();
+        let handle = thread::spawn(move || {
+            if ack_rx.recv().is_err() {
+                return;
+            }
+            let send = |data: &[u8]| {
+                request_writer(&command_tx, |response_tx| SerialCommand::Send {
+                    data: data.to_vec(),
+                    response_tx,
+                })
+                .map(|_| ())
+            };
+            serial_modbus::run_poll(&poll, send, rx, &poll_cancel, |result| {
+                emitter.emit(SerialAssistantEvent::modbus_poll(result))
+            });
+        });
+        session.modbus_poll = Some(RunningTask { cancel, handle });
+        Ok(())
+    }
+
+    pub fn stop_modbus_poll(&self, session_id: &str) -> Result<(), String> {
+        let sessions = self.lock()?;
+        let session = sessions
+            .get(session_id)
+            .ok_or_else(|| "serial is not connected".to_string())?;
+        if let Some(poll) = &session.modbus_poll {
+            poll.cancel.store(true, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+
+    /// Sets the app ELF that panic backtraces are symbolized against. The
+    /// reader loads it and reports the result as a status or error event.
+    pub fn set_elf(&self, session_id: &str, path: Option<PathBuf>) -> Result<(), String> {
+        self.send_to_reader(session_id, ReaderCommand::SetElf(path))
+    }
+
+    /// Starts a script on the session's own thread. Progress and the outcome
+    /// arrive as script events; only one script runs per session.
+    pub fn run_script(&self, session_id: &str, steps: Vec<ScriptStep>) -> Result<(), String> {
+        let mut sessions = self.lock()?;
+        let session = sessions
+            .get_mut(session_id)
+            .ok_or_else(|| "serial is not connected".to_string())?;
+        if RunningTask::is_running(&session.script) {
+            return Err("a script is already running".to_string());
+        }
+
+        let (rx, ack_rx) = session.tap()?;
+        let cancel = Arc::new(AtomicBool::new(false));
+        let script_cancel = Arc::clone(&cancel);
+        let emitter = session.emitter.clone();
+        let port = WriterPort {
+            command_tx: session.command_tx.clone(),
+        };
+        let handle = thread::spawn(move || {
+            if ack_rx.recv().is_err() {
+                return;
+            }
+            let runner = ScriptRunner::new(port, rx, &script_cancel, |progress| {
+                emitter.emit(SerialAssistantEvent::script(progress))
+            });
+            runner.run(&steps);
+        });
+        session.script = Some(RunningTask { cancel, handle });
+        Ok(())
+    }
+
+    pub fn cancel_script(&self, session_id: &str) -> Result<(), String> {
+        let sessions = self.lock()?;
+        let session = sessions
+            .get(session_id)
+            .ok_or_else(|| "serial is not connected".to_string())?;
+        if let Some(script) = &session.script {
+            script.cancel.store(true, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+
+    /// Starts a file transfer. It runs on the writer thread, so nothing else
+    /// is sent until it ends and writer requests are refused meanwhile;
+    /// progress and the outcome arrive as transfer events.
+    pub fn start_transfer(&self, session_id: &str, job: TransferJob) -> Result<(), String> {
+        let mut sessions = self.lock()?;
+        let session = sessions
+            .get_mut(session_id)
+            .ok_or_else(|| "serial is not connected".to_string())?;
+        if RunningTask::is_running(&session.transfer) {
+            return Err("a file transfer is already running".to_string());
+        }
+
+        let (rx, ack_rx) = session.tap()?;
+        let cancel = Arc::new(AtomicBool::new(false));
+        let transfer_cancel = Arc::clone(&cancel);
+        let command_tx = session.command_tx.clone();
+        let emitter = session.emitter.clone();
+        let handle = thread::spawn(move || {
+            if ack_rx.recv().is_err() {
+                return;
+            }
+            let result = request_writer(&command_tx, |response_tx| SerialCommand::Transfer {
+                job,
+                rx,
+                cancel: transfer_cancel,
+                response_tx,
+            });
+            if let Err(err) = result {
+                emitter.emit(SerialAssistantEvent::warning(format!(
+                    "file transfer did not run: {err}"
+                )));
+            }
+        });
+        session.transfer = Some(RunningTask { cancel, handle });
+        Ok(())
+    }
+
+    pub fn cancel_transfer(&self, session_id: &str) -> Result<(), String> {
+        let sessions = self.lock()?;
+        let session = sessions
+            .get(session_id)
+            .ok_or_else(|| "serial is not connected".to_string())?;
+        if let Some(transfer) = &session.transfer {
+            transfer.cancel.store(true, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+
+    pub fn stats(&self, session_id: &str) -> Result<StatsSnapshot, String> {
+        self.lock()?
+            .get(session_id)
+            .map(|session| session.stats.snapshot())
+            .ok_or_else(|| "serial is not connected".to_string())
+    }
+
+    pub fn reconfigure(&self, session_id: &str, settings: LineSettings) -> Result<(), String> {
+        self.request(session_id, |response_tx| SerialCommand::Reconfigure {
+            settings,
+            response_tx,
+        })?;
+
+        let (port, emitter) = {
+            let mut sessions = self.lock()?;
+            let session = sessions
+                .get_mut(session_id)
+                .ok_or_else(|| "serial is not connected".to_string())?;
+            session.settings = settings;
+            if session.modbus_decode {
+                let gap = serial_modbus::frame_gap(settings.baud_rate);
+                let _ = session.reader_tx.send(ReaderCommand::SetModbus(Some(gap)));
+            }
+            (session.port.clone(), session.emitter.clone())
+        };
+        emitter.emit(SerialAssistantEvent::status(format!(
+            "serial reconfigured: {port} @ {}",
+            settings.baud_rate
+        )));
+        Ok(())
+    }
+
+    pub fn settings(&self, session_id: &str) -> Result<LineSettings, String> {
+        self.lock()?
+            .get(session_id)
+            .map(|session| session.settings)
+            .ok_or_else(|| "serial is not connected".to_string())
+    }
+
+    /// Publishes the session as an RFC 2217 server on `address` and returns
+    /// the address it is bound to. Remote clients share the port with the
+    /// local assistant, which keeps seeing everything they send and receive.
+    pub fn start_rfc2217(&self, session_id: &str, address: &str) -> Result<String, String> {
+        let mut sessions = self.lock()?;
+        let session = sessions
+            .get_mut(session_id)
+            .ok_or_else(|| "serial is not connected".to_string())?;
+        if RunningTask::is_running(&session.rfc2217) {
+            return Err("an RFC 2217 server is already running".to_string());
+        }
+
+        let listener = TcpListener::bind(address)
+            .map_err(|e| format!("failed to listen on {address}: {e}"))?;
+        let local_address = listener
+            .local_addr()
+            .map_err(|e| format!("failed to read listener address: {e}"))?
+            .to_string();
+        let (rx, ack_rx) = session.tap()?;
+        let cancel = Arc::new(AtomicBool::new(false));
+        let server_cancel = Arc::clone(&cancel);
+        let emitter = session.emitter.clone();
+        let mut port = RemotePort {
+            session_id: session_id.to_string(),
+            command_tx: session.command_tx.clone(),
+            emitter: emitter.clone(),
+        };
+        let handle = thread::spawn(move || {
+            if ack_rx.recv().is_err() {
+                return;
+            }
+            let result = serial_rfc2217::serve(listener, &mut port, rx, &server_cancel, |event| {
+                emitter.emit(match event {
+                    ServerEvent::Connected(peer) => {
+                        SerialAssistantEvent::status(format!("RFC 2217 client connected: {peer}"))
+                    }
+                    ServerEvent::Disconnected(peer) => SerialAssistantEvent::status(format!(
+                        "RFC 2217 client disconnected: {peer}"
+                    )),
+                    ServerEvent::Warning(err) => SerialAssistantEvent::warning(err),
+                })
+            });
+            if let Err(err) = result {
+                emitter.emit(SerialAssistantEvent::warning(err));
+            }
+            emitter.emit(SerialAssistantEvent::status("RFC 2217 server stopped"));
+        });
+        session.rfc2217 = Some(RunningTask { cancel, handle });
+        session.emitter.emit(SerialAssistantEvent::status(format!(
+            "RFC 2217 server listening on {local_address}"
+        )));
+        Ok(local_address)
+    }
+
+    pub fn stop_rfc2217(&self, session_id: &str) -> Result<(), String> {
+        let sessions = self.lock()?;
+        let session = sessions
+            .get(session_id)
+            .ok_or_else(|| "serial is not connected".to_string())?;
+        if let Some(server) = &session.rfc2217 {
+            server.cancel.store(true, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+
+    pub fn start_periodic(
+        &self,
+        session_id: &str,
+        schedule: PeriodicSchedule,
+    ) -> Result<(), String> {
+        self.request(session_id, |response_tx| SerialCommand::StartPeriodic {
+            schedule,
+            response_tx,
+        })
+    }
+
+    pub fn stop_periodic(&self, session_id: &str) -> Result<(), String> {
+        self.request(session_id, |response_tx| SerialCommand::StopPeriodic {
+            response_tx,
+        })
+    }
+
+    /// Removes the session and stops its threads. Returns `false` when the id
+    /// is unknown, e.g. the port watcher already closed it.
+    pub fn close(&self, session_id: &str, wait_for_threads: bool) -> Result<bool, String> {
+        let session = self.lock()?.remove(session_id);
+        let Some(session) = session else {
+            return Ok(false);
+        };
+        let emitter = session.emitter.clone();
+        shutdown_serial_session(session, wait_for_threads);
+        emitter.emit(SerialAssistantEvent::status("serial disconnected"));
+        Ok(true)
+    }
+
+    /// Called by a reader that stopped on a read error. The session is torn
+    /// down and its window gets `event`, unless it was closed meanwhile.
+    fn end_session(&self, session_id: &str, event: SerialAssistantEvent) {
+        let Some(session) = self
+            .lock()
+            .ok()
+            .and_then(|mut sessions| sessions.remove(session_id))
+        else {
+            return;
+        };
+        let emitter = session.emitter.clone();
+        shutdown_serial_session(session, false);
+        emitter.emit(event);
+    }
+
+    /// Called by the port watcher, for readers that stay blocked on a
+    /// vanished port instead of failing. Sessions on it are torn down and
+    /// their window is told why. Sessions with a reconnect policy are left
+    /// to their reader.
+    pub fn handle_port_removed(&self, entry: &SerialPortEntry) {
+        let removed: Vec<SerialSession> = match self.lock() {
+            Ok(mut sessions) => {
+                let ids: Vec<String> = sessions
+                    .iter()
+                    .filter(|(_, session)| {
+                        session.port == entry.name && session.reconnect.is_none()
+                    })
+                    .map(|(session_id, _)| session_id.clone())
+                    .collect();
+                ids.iter()
+                    .filter_map(|session_id| sessions.remove(session_id))
+                    .collect()
+            }
+            Err(_) => return,
+        };
+
+        for session in removed {
+            let emitter = session.emitter.clone();
+            shutdown_serial_session(session, false);
+            emitter.emit(SerialAssistantEvent::port_removed(format!(
+                "serial port removed: {}",
+                entry.name
+            )));
+        }
+    }
+}
+
+/// The counter in a `serial-<n>` id, so sessions list in the order they were
+/// opened rather than as strings ("serial-10" before "serial-2").
+fn session_number(session_id: &str) -> u64 {
+    session_id
+        .strip_prefix("serial-")
+        .and_then(|number| number.parse().ok())
+        .unwrap_or(u64::MAX)
+}
+
+/// Hands a command to a writer thread and waits for its reply.
+fn request_writer<T>(
+    command_tx: &SyncSender<SerialCommand>,
+    command: impl FnOnce(Sender<Result<T, String>>) -> SerialCommand,
+) -> Result<T, String> {
+    let (response_tx, response_rx) = mpsc::channel();
+    command_tx
+        .send(command(response_tx))
+        .map_err(|_| "serial writer is unavailable".to_string())?;
+
+    response_rx
+        .recv()
+        .map_err(|_| "serial writer did not respond".to_string())?
+}
+
+impl TransferPort for SessionWriter {
+    fn write(&mut self, data: &[u8]) -> Result<(), String> {
+        self.send(data).map(|_| ())
+    }
+}
+
+/// Lets a script write through the session's writer thread, so its output is
+/// captured and counted like any other send.
+struct WriterPort {
+    command_tx: SyncSender<SerialCommand>,
+}
+
+impl ScriptPort for WriterPort {
+    fn send(&mut self, data: &[u8]) -> Result<(), String> {
+        request_writer(&self.command_tx, |response_tx| SerialCommand::Send {
+            data: data.to_vec(),
+            response_tx,
+        })
+        .map(|_| ())
+    }
+
+    fn set_signals(&mut self, rts: bool, dtr: bool) -> Result<(), String> {
+        request_writer(&self.command_tx, |response_tx| SerialCommand::SetSignals {
+            rts,
+            dtr,
+            response_tx,
+        })
+    }
+}
+
+/// Applies what an RFC 2217 client asks for to the session it is attached
+/// to. Settings go through the session state so the local assistant sees
+/// them change too.
+struct RemotePort {
+    session_id: String,
+    command_tx: SyncSender<SerialCommand>,
+    emitter: SessionEmitter,
+}
+
+impl RemotePort {
+    fn state(&self) -> tauri::State<'_, SerialAssistantState> {
+        self.emitter.window.state::<SerialAssistantState>()
+    }
+
+    fn run_sequence(&self, step: SequenceStep) -> Result<(), String> {
+        request_writer(&self.command_tx, |response_tx| SerialCommand::RunSequence {
+            steps: vec![step],
+            response_tx,
+        })
+    }
+}
+
+impl ComPort for RemotePort {
+    fn write(&mut self, data: &[u8]) -> Result<(), String> {
+        request_writer(&self.command_tx, |response_tx| SerialCommand::Send {
+            data: data.to_vec(),
+            response_tx,
+        })?;
+        self.emitter.emit(SerialAssistantEvent::remote_tx(data));
+        Ok(())
+    }
+
+    fn settings(&mut self) -> Result<LineSettings, String> {
+        self.state().settings(&self.session_id)
+    }
+
+    fn apply(&mut self, settings: LineSettings) -> Result<(), String> {
+        self.state().reconfigure(&self.session_id, settings)
+    }
+
+    fn set_dtr(&mut self, level: bool) -> Result<(), String> {
+        self.run_sequence(SequenceStep::Dtr(level))
+    }
+
+    fn set_rts(&mut self, level: bool) -> Result<(), String> {
+        self.run_sequence(SequenceStep::Rts(level))
+    }
+
+    fn set_break(&mut self, on: bool) -> Result<(), String> {
+        request_writer(&self.command_tx, |response_tx| SerialCommand::SetBreak {
+            on,
+            response_tx,
+        })
+    }
+
+    fn modem(&mut self) -> Option<ModemSignals> {
+        request_writer(&self.command_tx, |response_tx| SerialCommand::ReadSignals {
+            response_tx,
+        })
+        .ok()
+    }
+}
+
+/// Appends a chunk to the session capture, if one is running. A capture that
+/// fails to write is dropped with an error event rather than retried per chunk.
+fn capture_chunk(
+    capture: &SharedCapture,
+    emitter: &SessionEmitter,
+    direction: Direction,
+    bytes: &[u8],
+) {
+    let Ok(mut guard) = capture.lock() else {
+        return;
+    };
+    let Some(writer) = guard.as_mut() else {
+        return;
+    };
+    if let Err(err) = writer.record(direction, bytes) {
+        *guard = None;
+        emitter.emit(SerialAssistantEvent::warning(format!(
+            "capture stopped: {err}"
+        )));
+    }
+}
+
+/// The event a session ends with after a read error it cannot recover from.
+/// A local port that is no longer listed was unplugged or reset; anything
+/// else is reported as the error itself.
+fn read_failure(
+    port: &str,
+    err: &std::io::Error,
+    port_exists: impl FnOnce(&str) -> bool,
+) -> SerialAssistantEvent {
+    if !serial_network::is_network_port(port) && !port_exists(port) {
+        SerialAssistantEvent::port_removed(format!("serial port removed: {port}"))
+    } else {
+        SerialAssistantEvent::error(format!("serial read failed ({port}): {err}"))
+    }
+}
+
+/// Everything the reader thread owns besides the port itself.
+struct SessionReader {
+    port: String,
+    emitter: SessionEmitter,
+    capture: SharedCapture,
+    framer: Framer,
+    line_framer: Framer,
+    log_filter: Option<LogFilter>,
+    /// Splits RX into lines for backtrace and core dump detection,
+    /// independently of the framing the user picked for display.
+    monitor: Framer,
+    symbolizer: Option<Symbolizer>,
+    core_dump: CoreDumpCollector,
+    core_dump_dir: PathBuf,
+    /// Last modem status reported, and when it was sampled. Polling stops
+    /// for good on ports that cannot report it.
+    modem: Option<ModemSignals>,
+    modem_polled_at: Option<Instant>,
+    modem_supported: bool,
+    stats: Arc<SessionStats>,
+    stats_emitted_at: Instant,
+    taps: Vec<Sender<Vec<u8>>>,
+    rx_offset: u64,
+    /// Splits RX at RTU frame gaps while Modbus decoding is on.
+    modbus: Option<Framer>,
+    rx_checksum: Option<ChecksumSpec>,
+    reconnect: Option<ReconnectPolicy>,
+    /// USB identity of the open port, used to find it again after a reset.
+    identity: Option<SerialPortEntry>,
+}
+
+impl SessionReader {
+    fn run(mut self, mut serial: Box<dyn SerialPort>, reader_rx: mpsc::Receiver<ReaderCommand>) {
+        let mut buffer = [0u8; 4096];
+        let mut lost = None;
+        loop {
+            match reader_rx.try_recv() {
+                Ok(ReaderCommand::Stop) | Err(TryRecvError::Disconnected) => break,
+                Ok(command) => {
+                    self.handle_command(command);
+                    self.apply_read_timeout(&mut serial);
+                }
+                Err(TryRecvError::Empty) => {}
+            }
+
+            self.poll_modem(&mut serial);
+
+            if self.stats_emitted_at.elapsed() >= serial_stats::STATS_INTERVAL {
+                self.emit_stats();
+            }
+
+            match serial.read(&mut buffer) {
+                Ok(size) if size > 0 => self.handle_chunk(&buffer[..size]),
+                Ok(_) => {}
+                Err(err) if err.kind() == std::io::ErrorKind::TimedOut => {
+                    let now = Instant::now();
+                    if let Some(frame) = self.active_framer().poll_idle(now) {
+                        self.handle_frame(frame);
+                    }
+                    if let Some(line) = self.monitor.poll_idle(now) {
+                        self.inspect_line(&line);
+                    }
+                    if let Some(frame) = self.modbus.as_mut().and_then(|m| m.poll_idle(now)) {
+                        self.emit_modbus(&frame);
+                    }
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {
+                    self.stats.record_read_error();
+                }
+                Err(err) if self.reconnect.is_some() => {
+                    self.stats.record_read_error();
+                    drop(serial);
+                    match self.wait_for_port(&reader_rx, &err) {
+                        Some(port) => serial = port,
+                        None => break,
+                    }
+                    self.apply_read_timeout(&mut serial);
+                }
+                Err(err) => {
+                    self.stats.record_read_error();
+                    lost = Some(read_failure(&self.port, &err, serial_ports::port_exists));
+                    break;
+                }
+            }
+        }
+
+        if let Some(frame) = self.active_framer().flush() {
+            self.handle_frame(frame);
+        }
+        // The port watcher polls too slowly to notice a device that drops
+        // and re-enumerates under the same name, so the reader ends its own
+        // session rather than leave it behind with nobody reading.
+        if let Some(event) = lost {
+            self.emitter
+                .window
+                .state::<SerialAssistantState>()
+                .end_session(&self.emitter.session_id, event);
+        }
+    }
+
+    fn handle_command(&mut self, command: ReaderCommand) {
+        match command {
+            ReaderCommand::SetFraming(mode) => {
+                if let Some(frame) = self.framer.set_mode(mode) {
+                    self.handle_frame(frame);
+                }
+            }
+            ReaderCommand::SetRxChecksum(spec) => self.rx_checksum = spec,
+            ReaderCommand::SetModbus(gap) => {
+                self.modbus = gap.map(|gap| Framer::new(FramingMode::IdleGap(gap)));
+            }
+            ReaderCommand::SetReconnect(policy) => self.reconnect = policy,
+            ReaderCommand::SetLogFilter(filter) => {
+                if let Some(frame) = self.active_framer().flush() {
+                    self.handle_frame(frame);
+                }
+                self.log_filter = filter;
+            }
+            ReaderCommand::SetElf(path) => self.load_elf(path),
+            ReaderCommand::Tap { tx, ack } => {
+                self.taps.push(tx);
+                let _ = ack.send(());
+            }
+            // Callers stop the reader before it gets here.
+            ReaderCommand::Stop => {}
+        }
+    }
+
+    /// Waits for a lost port to come back and returns the reopened handle.
+    /// Commands keep being served meanwhile; `None` means the session was
+    /// closed or the policy gave up.
+    fn wait_for_port(
+        &mut self,
+        reader_rx: &mpsc::Receiver<ReaderCommand>,
+        err: &std::io::Error,
+    ) -> Option<Box<dyn SerialPort>> {
+        let lost_at = Instant::now();
+        let state = self.emitter.window.state::<SerialAssistantState>();
+        // Release the writer's handle too, so the device can come back under
+        // its old name.
+        let _ = state.swap_port(&self.emitter.session_id, &self.port, None);
+        if let Some(frame) = self.active_framer().flush() {
+            self.handle_frame(frame);
+        }
+        self.emitter.emit(SerialAssistantEvent::reconnect(
+            ReconnectProgress {
+                state: ReconnectState::Waiting,
+                port: self.port.clone(),
+            },
+            format!("serial lost ({}): {err}; waiting to reconnect", self.port),
+        ));
+
+        loop {
+            match reader_rx.recv_timeout(RECONNECT_INTERVAL) {
+                Ok(ReaderCommand::Stop) | Err(RecvTimeoutError::Disconnected) => return None,
+                Ok(command) => self.handle_command(command),
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+            match self.try_reconnect(lost_at) {
+                Ok(Some(port)) => return Some(port),
+                Ok(None) => {}
+                Err(reason) => {
+                    self.emitter.emit(SerialAssistantEvent {
+                        reconnect: Some(ReconnectProgress {
+                            state: ReconnectState::Failed,
+                            port: self.port.clone(),
+                        }),
+                        ..SerialAssistantEvent::error(format!("serial reconnect failed: {reason}"))
+                    });
+                    return None;
+                }
+            }
+        }
+    }
+
+    /// One attempt to reopen the lost port. `Ok(None)` means try again later:
+    /// the device has not shown up yet, cannot be opened yet, or the session
+    /// is being closed and a stop is on its way.
+    fn try_reconnect(&mut self, lost_at: Instant) -> Result<Option<Box<dyn SerialPort>>, String> {
+        let Some(policy) = self.reconnect else {
+            return Err("auto reconnect was turned off".to_string());
+        };
+        if let Some(timeout) = policy
+            .timeout
+            .filter(|timeout| lost_at.elapsed() >= *timeout)
+        {
+            return Err(format!(
+                "{} did not come back within {} s",
+                self.port,
+                timeout.as_secs()
+            ));
+        }
+        let Some((name, identity)) = self.find_port() else {
+            return Ok(None);
+        };
+        let state = self.emitter.window.state::<SerialAssistantState>();
+        let Ok(settings) = state.settings(&self.emitter.session_id) else {
+            return Ok(None);
+        };
+        let Ok(serial) = open_port(&name, settings) else {
+            return Ok(None);
+        };
+        let reader_port = serial
+            .try_clone()
+            .map_err(|e| format!("failed to clone serial port: {e}"))?;
+        if state
+            .swap_port(&self.emitter.session_id, &name, Some(serial))
+            .is_err()
+        {
+            return Ok(None);
+        }
+
+        self.port = name;
+        self.identity = identity;
+        self.modem = None;
+        self.modem_polled_at = None;
+        self.modem_supported = true;
+        self.emitter.emit(SerialAssistantEvent::reconnect(
+            ReconnectProgress {
+                state: ReconnectState::Reconnected,
+                port: self.port.clone(),
+            },
+            format!("serial reconnected: {} @ {}", self.port, settings.baud_rate),
+        ));
+        Ok(Some(reader_port))
+    }
+
+    /// Looks for the lost port by USB identity, or by name when it has none.
+    /// Network endpoints are simply dialled again.
+    fn find_port(&self) -> Option<(String, Option<SerialPortEntry>)> {
+        if serial_network::is_network_port(&self.port) {
+            return Some((self.port.clone(), None));
+        }
+        let ports = serial_ports::list_ports().ok()?;
+        let entry = match &self.identity {
+            Some(identity) => serial_ports::find_reappeared(identity, &ports),
+            None => ports.iter().find(|entry| entry.name == self.port),
+        }?;
+        Some((entry.name.clone(), Some(entry.clone())))
+    }
+
+    /// Reads block no longer than the shortest gap a framer has to notice.
+    fn apply_read_timeout(&self, serial: &mut Box<dyn SerialPort>) {
+        let timeout = self
+            .modbus
+            .iter()
+            .chain([&self.framer])
+            .map(|framer| framer.mode().read_timeout(READ_TIMEOUT))
+            .min()
+            .unwrap_or(READ_TIMEOUT);
+        let _ = serial.set_timeout(timeout);
+    }
+
+    fn emit_modbus(&self, frame: &Frame) {
+        if let Some(decoded) = serial_modbus::decode(&frame.data) {
+            self.emitter
+                .emit(SerialAssistantEvent::modbus(frame, decoded));
+        }
+    }
+
+    fn emit_stats(&mut self) {
+        let now = Instant::now();
+        self.stats_emitted_at = now;
+        self.stats.sample_rates(now);
+        self.emitter
+            .emit(SerialAssistantEvent::stats(self.stats.snapshot()));
+    }
+
+    fn poll_modem(&mut self, serial: &mut Box<dyn SerialPort>) {
+        if !self.modem_supported
+            || self
+                .modem_polled_at
+                .is_some_and(|at| at.elapsed() < MODEM_POLL_INTERVAL)
+        {
+            return;
+        }
+        self.modem_polled_at = Some(Instant::now());
+        match ModemSignals::read(serial) {
+            Ok(signals) if self.modem != Some(signals) => {
+                self.modem = Some(signals);
+                self.emitter.emit(SerialAssistantEvent::signals(signals));
+            }
+            Ok(_) => {}
+            Err(_) => self.modem_supported = false,
+        }
+    }
+
+    /// Log parsing needs whole lines, so it takes over from the user framing
+    /// while a log filter is set.
+    fn active_framer(&mut self) -> &mut Framer {
+        if self.log_filter.is_some() {
+            &mut self.line_framer
+        } else {
+            &mut self.framer
+        }
+    }
+
+    fn handle_chunk(&mut self, bytes: &[u8]) {
+        let stamp = Stamp {
+            monotonic: Instant::now(),
+            wall: SystemTime::now(),
+            offset: self.rx_offset,
+        };
+        self.rx_offset += bytes.len() as u64;
+        capture_chunk(&self.capture, &self.emitter, Direction::Rx, bytes);
+        self.taps.retain(|tap| tap.send(bytes.to_vec()).is_ok());
+        let frames = self.active_framer().push(bytes, stamp);
+        self.stats.record_rx(bytes.len(), frames.is_empty());
+        for frame in frames {
+            self.handle_frame(frame);
+        }
+        for line in self.monitor.push(bytes, stamp) {
+            self.inspect_line(&line);
+        }
+        if let Some(modbus) = self.modbus.as_mut() {
+            for frame in modbus.push(bytes, stamp) {
+                self.emit_modbus(&frame);
+            }
+        }
+    }
+
+    fn load_elf(&mut self, path: Option<PathBuf>) {
+        self.monitor.flush();
+        self.symbolizer = None;
+        let Some(path) = path else {
+            return;
+        };
+        match Symbolizer::load(&path) {
+            Ok(symbolizer) => {
+                self.symbolizer = Some(symbolizer);
+                self.emitter.emit(SerialAssistantEvent::status(format!(
+                    "symbolizing backtraces with {}",
+                    path.display()
+                )));
+            }
+            Err(err) => self.emitter.emit(SerialAssistantEvent::warning(err)),
+        }
+    }
+
+    /// Collects core dumps and emits panic backtraces or register dumps
+    /// symbolized.
+    fn inspect_line(&mut self, line: &Frame) {
+        let text = esp_log::strip_ansi(&String::from_utf8_lossy(&line.data));
+        if let Some(result) = self.core_dump.push_line(&text) {
+            let saved =
+                result.and_then(|data| esp_core_dump::save(&self.core_dump_dir, &self.port, &data));
+            match saved {
+                Ok(path) => self.emitter.emit(SerialAssistantEvent::core_dump(&path)),
+                Err(err) => self.emitter.emit(SerialAssistantEvent::warning(err)),
+            }
+            return;
+        }
+
+        let Some(symbolizer) = &self.symbolizer else {
+            return;
+        };
+        let frames: Vec<BacktraceFrame> = esp_backtrace::find_code_addresses(&text)
+            .into_iter()
+            .flat_map(|address| symbolizer.symbolize(address))
+            .collect();
+        if frames.iter().any(|frame| frame.function.is_some()) {
+            self.emitter
+                .emit(SerialAssistantEvent::backtrace(line, frames));
+        }
+    }
+
+    fn handle_frame(&mut self, frame: Frame) {
+        if let Some(filter) = &self.log_filter {
+            if let Some(record) = esp_log::parse_line(&String::from_utf8_lossy(&frame.data)) {
+                if filter.accepts(&record) {
+                    self.emitter.emit(SerialAssistantEvent::log(&frame, record));
+                } else {
+                    self.stats.record_dropped_event();
+                }
+                return;
+            }
+        }
+        let mut event = SerialAssistantEvent::data(&frame);
+        // Raw chunks end wherever a read happened to, not at a checksum.
+        if frame.mode != FramingMode::Raw {
+            event.checksum = self.rx_checksum.and_then(|spec| spec.verify(&frame.data));
+        }
+        self.emitter.emit(event);
+    }
+}
+
+/// Everything the writer thread owns besides the port itself.
+struct SessionWriter {
+    /// `None` while the reader waits for a lost port to come back.
+    port: Option<Box<dyn SerialPort>>,
+    emitter: SessionEmitter,
+    capture: SharedCapture,
+    stats: Arc<SessionStats>,
+    closing: Arc<AtomicBool>,
+    periodic: Option<PeriodicSend>,
+}
+
+impl SessionWriter {
+    fn run(mut self, command_rx: mpsc::Receiver<SerialCommand>) {
+        loop {
+            // Commands are still served between periodic sends; the wait for
+            // the next one doubles as the schedule's timer.
+            let command = match self.periodic.as_ref().map(PeriodicSend::next_due) {
+                Some(due) => {
+                    match command_rx.recv_timeout(due.saturating_duration_since(Instant::now())) {
+                        Ok(command) => command,
+                        Err(RecvTimeoutError::Timeout) => {
+                            self.send_periodic();
+                            continue;
+                        }
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                }
+                None => match command_rx.recv() {
+                    Ok(command) => command,
+                    Err(_) => break,
+                },
+            };
+
+            match command {
+                SerialCommand::Send { data, response_tx } => {
+                    let _ = response_tx.send(self.send(&data));
+                }
+                SerialCommand::SetSignals {
+                    rts,
+                    dtr,
+                    response_tx,
+                } => {
+                    let result = attached(&mut self.port).and_then(|port| {
+                        port.write_request_to_send(rts)
+                            .map_err(|e| format!("failed to set RTS: {e}"))?;
+                        port.write_data_terminal_ready(dtr)
+                            .map_err(|e| format!("failed to set DTR: {e}"))
+                    });
+                    let _ = response_tx.send(result);
+                }
+                SerialCommand::Break {
+                    duration,
+                    response_tx,
+                } => {
+                    let closing = self.closing.as_ref();
+                    let result = attached(&mut self.port).and_then(|port| {
+                        port.set_break()
+                            .map_err(|e| format!("failed to set break: {e}"))?;
+                        let slept = serial_sequence::sleep_unless_closing(duration, closing);
+                        let cleared = port
+                            .clear_break()
+                            .map_err(|e| format!("failed to clear break: {e}"));
+                        slept.and(cleared)
+                    });
+                    let _ = response_tx.send(result);
+                }
+                SerialCommand::SetBreak { on, response_tx } => {
+                    let result = attached(&mut self.port).and_then(|port| {
+                        let result = if on {
+                            port.set_break()
+                        } else {
+                            port.clear_break()
+                        };
+                        result.map_err(|e| format!("failed to set break: {e}"))
+                    });
+                    let _ = response_tx.send(result);
+                }
+                SerialCommand::ReadSignals { response_tx } => {
+                    let result = attached(&mut self.port).and_then(|port| {
+                        ModemSignals::read(port)
+                            .map_err(|e| format!("failed to read modem lines: {e}"))
+                    });
+                    let _ = response_tx.send(result);
+                }
+                SerialCommand::Reconfigure {
+                    settings,
+                    response_tx,
+                } => {
+                    // A port lost meanwhile is reopened with the session
+                    // settings, which the caller updates on success.
+                    let result = match self.port.as_mut() {
+                        Some(port) => settings.apply(port),
+                        None => Ok(()),
+                    };
+                    let _ = response_tx.send(result);
+                }
+                SerialCommand::RunSequence { steps, response_tx } => {
+                    let closing = self.closing.as_ref();
+                    let result = attached(&mut self.port)
+                        .and_then(|port| serial_sequence::run(port, &steps, closing));
+                    let _ = response_tx.send(result);
+                }
+                SerialCommand::StartPeriodic {
+                    schedule,
+                    response_tx,
+                } => {
+                    if self.periodic.is_some() {
+                        self.emitter
+                            .emit(SerialAssistantEvent::status("periodic send restarted"));
+                    }
+                    self.periodic = Some(PeriodicSend::start(schedule, Instant::now()));
+                    let _ = response_tx.send(Ok(()));
+                }
+                SerialCommand::StopPeriodic { response_tx } => {
+                    if let Some(periodic) = self.periodic.take() {
+                        let progress = periodic.stopped();
+                        self.emitter.emit(SerialAssistantEvent {
+                            periodic: Some(progress),
+                            ..SerialAssistantEvent::status(format!(
+                                "periodic send stopped after {} sends",
+                                progress.sent
+                            ))
+                        });
+                    }
+                    let _ = response_tx.send(Ok(()));
+                }
+                SerialCommand::Transfer {
+                    job,
+                    rx,
+                    cancel,
+                    response_tx,
+                } => {
+                    let emitter = self.emitter.clone();
+                    Transfer::new(&mut self, rx, &cancel, |progress| {
+                        emitter.emit(SerialAssistantEvent::transfer(progress))
+                    })
+                    .run(&job);
+                    let _ = response_tx.send(Ok(()));
+                }
+                SerialCommand::SwapPort { port, response_tx } => {
+                    self.port = port;
+                    let _ = response_tx.send(Ok(()));
+                }
+                SerialCommand::Shutdown => break,
+            }
+        }
+    }
+
+    /// Writes and drains one payload, counting and capturing it.
+    fn send(&mut self, data: &[u8]) -> Result<usize, String> {
+        let started = Instant::now();
+        let closing = self.closing.as_ref();
+        let result = attached(&mut self.port)
+            .and_then(|port| {
+                port.write_all(data)
+                    .map_err(|e| format!("failed to write serial data: {e}"))?;
+                wait_for_output_drain(port, closing)
+            })
+            .map(|_| data.len());
+        self.stats.record_write(
+            result.as_ref().map(|len| *len).map_err(String::as_str),
+            started.elapsed(),
+        );
+        if result.is_ok() {
+            capture_chunk(&self.capture, &self.emitter, Direction::Tx, data);
+        }
+        result
+    }
+
+    /// Sends the payload that is due. A failed write ends the schedule
+    /// instead of repeating the same error every interval.
+    fn send_periodic(&mut self) {
+        let Some(periodic) = self.periodic.as_mut() else {
+            return;
+        };
+        let data = periodic.take_payload(Instant::now());
+        let progress = periodic.progress();
+        if let Err(err) = self.send(&data) {
+            self.periodic = None;
+            self.emitter.emit(SerialAssistantEvent {
+                periodic: Some(PeriodicProgress {
+                    done: true,
+                    ..progress
+                }),
+                ..SerialAssistantEvent::warning(format!("periodic send stopped: {err}"))
+            });
+            return;
+        }
+        if progress.done {
+            self.periodic = None;
+        }
+        self.emitter
+            .emit(SerialAssistantEvent::periodic(&data, progress));
+    }
+}
+
+/// The writer's port, unless it was dropped while the reader reconnects.
+fn attached(port: &mut Option<Box<dyn SerialPort>>) -> Result<&mut Box<dyn SerialPort>, String> {
+    port.as_mut()
+        .ok_or_else(|| "serial is reconnecting".to_string())
+}
+
+fn shutdown_serial_session(mut session: SerialSession, wait_for_threads: bool) {
+    session.closing.store(true, Ordering::SeqCst);
+    for task in [
+        &session.script,
+        &session.transfer,
+        &session.modbus_poll,
+        &session.rfc2217,
+    ]
+    .into_iter()
+    .flatten()
+    {
+        task.cancel.store(true, Ordering::SeqCst);
+    }
+    if let Some(writer) = session
+        .capture
+        .lock()
+        .ok()
+        .and_then(|mut guard| guard.take())
+    {
+        let _ = writer.finish();
+    }
+    let _ = session.command_tx.send(SerialCommand::Shutdown);
+    let _ = session.reader_tx.send(ReaderCommand::Stop);
+
+    let writer_handle = session.writer_handle.take();
+    let reader_handle = session.reader_handle.take();
+
+    if wait_for_threads {
+        if let Some(handle) = writer_handle {
+            let _ = handle.join();
+        }
+        if let Some(handle) = reader_handle {
+            let _ = handle.join();
+        }
+        return;
+    }
+
+    thread::spawn(move || {
+        if let Some(handle) = writer_handle {
+            let _ = handle.join();
+        }
+        if let Some(handle) = reader_handle {
+            let _ = handle.join();
+        }
+    });
+}
+
+fn wait_for_output_drain(
+    writer_port: &mut Box<dyn SerialPort>,
+    closing: &AtomicBool,
+) -> Result<(), String> {
+    let start = Instant::now();
+    let max_wait = Duration::from_millis(300);
+
+    loop {
+        if closing.load(Ordering::SeqCst) {
+            return Err("serial is closing".to_string());
+        }
+
+        match writer_port.bytes_to_write() {
+            Ok(0) => return Ok(()),
+            Ok(_) => {}
+            Err(_) => return Ok(()),
+        }
+
+        if start.elapsed() >= max_wait {
+            return Ok(());
+        }
+
+        thread::sleep(Duration::from_millis(2));
+    }
+}
+
+/// Opens a local device or a network endpoint with the given settings.
+fn open_port(port: &str, settings: LineSettings) -> Result<Box<dyn SerialPort>, String> {
+    if serial_network::is_network_port(port) {
+        return serial_network::open(port, settings, READ_TIMEOUT);
+    }
+    serialport::new(port, settings.baud_rate)
+        .data_bits(settings.data_bits)
+        .stop_bits(settings.stop_bits)
+        .parity(settings.parity)
+        .flow_control(settings.flow_control)
+        .timeout(READ_TIMEOUT)
+        .open()
+        .map_err(|e| format!("failed to open serial port: {e}"))
+}
+
+/// Opens the port, starts the reader and writer threads and returns the new
+/// session id.
+pub fn open_session(
+    window: &tauri::Window,
+    state: &SerialAssistantState,
+    options: SerialOpenOptions,
+) -> Result<String, String> {
+    if options.port.is_empty() {
+        return Err("port is required".to_string());
+    }
+
+    let settings = LineSettings::parse(
+        options.baud_rate,
+        options.data_bits,
+        options.stop_bits,
+        &options.parity,
+        &options.flow_control,
+    )?;
+
+    let port = options.port;
+    let baud_rate = settings.baud_rate;
+    let serial = open_port(&port, settings)?;
+    let identity = if serial_network::is_network_port(&port) {
+        None
+    } else {
+        serial_ports::list_ports()
+            .ok()
+            .and_then(|ports| ports.into_iter().find(|entry| entry.name == port))
+    };
+
+    let reader_port = serial
+        .try_clone()
+        .map_err(|e| format!("failed to clone serial port: {e}"))?;
+    let writer_port = serial;
+    let session_id = format!("serial-{}", NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed));
+    let emitter = SessionEmitter {
+        window: window.clone(),
+        session_id: session_id.clone(),
+        opened_at: Instant::now(),
+    };
+    let (command_tx, command_rx) = mpsc::sync_channel::<SerialCommand>(1);
+    let (reader_tx, reader_rx) = mpsc::channel();
+    let closing = Arc::new(AtomicBool::new(false));
+    let capture: SharedCapture = Arc::new(Mutex::new(None));
+    let stats = Arc::new(SessionStats::default());
+    let reader = SessionReader {
+        port: port.clone(),
+        emitter: emitter.clone(),
+        capture: Arc::clone(&capture),
+        framer: Framer::new(FramingMode::Raw),
+        line_framer: Framer::new(FramingMode::Newline),
+        log_filter: None,
+        monitor: Framer::new(FramingMode::Newline),
+        symbolizer: None,
+        core_dump: CoreDumpCollector::default(),
+        core_dump_dir: options.core_dump_dir,
+        modem: None,
+        modem_polled_at: None,
+        modem_supported: true,
+        stats: Arc::clone(&stats),
+        stats_emitted_at: Instant::now(),
+        taps: Vec::new(),
+        rx_offset: 0,
+        modbus: None,
+        rx_checksum: None,
+        reconnect: None,
+        identity,
+    };
+
+    // Held until the session is listed and announced, so a reader that fails
+    // straight away still finds its session to end, after "connected".
+    let mut sessions = state.lock()?;
+    let reader_handle = thread::spawn(move || reader.run(reader_port, reader_rx));
+
+    let writer = SessionWriter {
+        port: Some(writer_port),
+        emitter: emitter.clone(),
+        capture: Arc::clone(&capture),
+        stats: Arc::clone(&stats),
+        closing: Arc::clone(&closing),
+        periodic: None,
+    };
+    let writer_handle = thread::spawn(move || writer.run(command_rx));
+
+    let status = SerialAssistantEvent::status(format!("serial connected: {port} @ {baud_rate}"));
+    sessions.insert(
+        session_id.clone(),
+        SerialSession {
+            port,
+            settings,
+            emitter: emitter.clone(),
+            capture,
+            stats,
+            command_tx,
+            reader_tx,
+            closing,
+            reader_handle: Some(reader_handle),
+            writer_handle: Some(writer_handle),
+            script: None,
+            transfer: None,
+            modbus_poll: None,
+            rfc2217: None,
+            modbus_decode: false,
+            reconnect: None,
+        },
+    );
+    emitter.emit(status);
+    drop(sessions);
+    Ok(session_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Error, ErrorKind};
+
+    #[test]
+    fn ends_sessions_on_ports_that_reenumerate_between_watcher_polls() {
+        let err = Error::new(ErrorKind::BrokenPipe, "device disconnected");
+
+        // The reader sees the port missing even if the watcher never does.
+        let event = read_failure("/dev/ttyACM0", &err, |_| false);
+        assert_eq!(event.kind, "port_removed");
+        assert_eq!(event.text, "serial port removed: /dev/ttyACM0");
+
+        // Already back under the same name: still fatal for this handle.
+        let event = read_failure("/dev/ttyACM0", &err, |_| true);
+        assert_eq!(event.kind, "error");
+        assert_eq!(
+            event.text,
+            "serial read failed (/dev/ttyACM0): device disconnected"
+        );
+
+        // Network ports are never looked up in the local port list.
+        let event = read_failure("tcp://127.0.0.1:4000", &err, |_| {
+            panic!("network ports are not listed")
+        });
+        assert_eq!(event.kind, "error");
+    }
+
+    #[test]
+    fn orders_sessions_by_opening() {
+        let mut ids = ["serial-10", "serial-2", "serial-1", "serial-11"];
+        ids.sort_by_key(|id| session_number(id));
+        assert_eq!(ids, ["serial-1", "serial-2", "serial-10", "serial-11"]);
+    }
+}
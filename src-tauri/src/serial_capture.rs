@@ -0,0 +1,373 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub const CAPTURE_DIR: &str = "captures";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Rx,
+    Tx,
+}
+
+impl Direction {
+    fn label(self) -> &'static str {
+        match self {
+            Direction::Rx => "RX",
+            Direction::Tx => "TX",
+        }
+    }
+
+    fn index(self) -> usize {
+        match self {
+            Direction::Rx => 0,
+            Direction::Tx => 1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct RotationPolicy {
+    pub max_bytes: Option<u64>,
+    pub max_duration: Option<Duration>,
+}
+
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct CaptureFileInfo {
+    pub name: String,
+    pub path: String,
+    pub size: u64,
+    pub modified: u64,
+}
+
+/// Streams a session to `<name>.rx.bin` and `<name>.log`, starting a new pair
+/// of files whenever the rotation policy trips. The raw file holds only the
+/// received bytes, exactly as they arrived, so it can be replayed as device
+/// output; the text log has both directions, one timestamped line per device
+/// line.
+pub struct CaptureWriter {
+    dir: PathBuf,
+    prefix: String,
+    policy: RotationPolicy,
+    raw: BufWriter<File>,
+    log: BufWriter<File>,
+    log_path: PathBuf,
+    opened_at: Instant,
+    written: u64,
+    /// Partial lines per direction, so an interleaved TX does not split an
+    /// RX line in the text log.
+    pending: [Vec<u8>; 2],
+}
+
+impl CaptureWriter {
+    pub fn create(dir: &Path, port: &str, policy: RotationPolicy) -> Result<Self, String> {
+        fs::create_dir_all(dir).map_err(|e| format!("failed to create capture directory: {e}"))?;
+        let prefix = sanitize_port_name(port);
+        let (raw, log, log_path) = open_pair(dir, &prefix)?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            prefix,
+            policy,
+            raw,
+            log,
+            log_path,
+            opened_at: Instant::now(),
+            written: 0,
+            pending: [Vec::new(), Vec::new()],
+        })
+    }
+
+    pub fn log_path(&self) -> &Path {
+        &self.log_path
+    }
+
+    pub fn record(&mut self, direction: Direction, bytes: &[u8]) -> Result<(), String> {
+        if self.should_rotate() {
+            self.rotate()?;
+        }
+
+        // TX only goes to the text log; the raw file stays a pure RX stream.
+        if direction == Direction::Rx {
+            self.raw
+                .write_all(bytes)
+                .map_err(|e| format!("failed to write capture: {e}"))?;
+        }
+        self.written += bytes.len() as u64;
+
+        for &byte in bytes {
+            match byte {
+                b'\n' => self.flush_pending_line(direction)?,
+                b'\r' => {}
+                _ => self.pending[direction.index()].push(byte),
+            }
+        }
+
+        self.raw
+            .flush()
+            .and_then(|_| self.log.flush())
+            .map_err(|e| format!("failed to flush capture: {e}"))
+    }
+
+    pub fn finish(mut self) -> Result<(), String> {
+        self.flush_pending_line(Direction::Rx)?;
+        self.flush_pending_line(Direction::Tx)?;
+        self.raw
+            .flush()
+            .and_then(|_| self.log.flush())
+            .map_err(|e| format!("failed to flush capture: {e}"))
+    }
+
+    fn should_rotate(&self) -> bool {
+        self.policy.max_bytes.is_some_and(|max| self.written >= max)
+            || self
+                .policy
+                .max_duration
+                .is_some_and(|max| self.opened_at.elapsed() >= max)
+    }
+
+    fn rotate(&mut self) -> Result<(), String> {
+        let _ = self.raw.flush();
+        let _ = self.log.flush();
+        let (raw, log, log_path) = open_pair(&self.dir, &self.prefix)?;
+        self.raw = raw;
+        self.log = log;
+        self.log_path = log_path;
+        self.opened_at = Instant::now();
+        self.written = 0;
+        Ok(())
+    }
+
+    fn flush_pending_line(&mut self, direction: Direction) -> Result<(), String> {
+        let pending = &mut self.pending[direction.index()];
+        if pending.is_empty() {
+            return Ok(());
+        }
+        let line = String::from_utf8_lossy(pending).to_string();
+        pending.clear();
+        writeln!(
+            self.log,
+            "[{}] {}: {}",
+            format_timestamp(SystemTime::now()),
+            direction.label(),
+            line
+        )
+        .map_err(|e| format!("failed to write capture log: {e}"))
+    }
+}
+
+fn open_pair(
+    dir: &Path,
+    prefix: &str,
+) -> Result<(BufWriter<File>, BufWriter<File>, PathBuf), String> {
+    let stamp = format_file_stamp(SystemTime::now());
+    let mut base = format!("{prefix}-{stamp}");
+    let mut suffix = 1;
+    while dir.join(format!("{base}.rx.bin")).exists() {
+        suffix += 1;
+        base = format!("{prefix}-{stamp}-{suffix}");
+    }
+
+    let raw_path = dir.join(format!("{base}.rx.bin"));
+    let log_path = dir.join(format!("{base}.log"));
+    let raw = File::create(&raw_path)
+        .map_err(|e| format!("failed to create {}: {e}", raw_path.display()))?;
+    let log = File::create(&log_path)
+        .map_err(|e| format!("failed to create {}: {e}", log_path.display()))?;
+    Ok((BufWriter::new(raw), BufWriter::new(log), log_path))
+}
+
+pub fn sanitize_port_name(port: &str) -> String {
+    let name = port.rsplit(['/', '\\']).next().unwrap_or(port);
+    let cleaned: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if cleaned.is_empty() {
+        "serial".to_string()
+    } else {
+        cleaned
+    }
+}
+
+/// UTC calendar fields for a Unix timestamp (days-from-civil inverse).
+fn civil_from_unix(secs: u64) -> (i64, u32, u32, u32, u32, u32) {
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (
+        year,
+        month,
+        day,
+        (rem / 3600) as u32,
+        (rem % 3600 / 60) as u32,
+        (rem % 60) as u32,
+    )
+}
+
+pub fn format_timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let (year, month, day, hour, minute, second) = civil_from_unix(since_epoch.as_secs());
+    format!(
+        "{year:04}-{month:02}-{day:02} {hour:02}:{minute:02}:{second:02}.{:03}Z",
+        since_epoch.subsec_millis()
+    )
+}
+
+pub fn format_file_stamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (year, month, day, hour, minute, second) = civil_from_unix(secs);
+    format!("{year:04}{month:02}{day:02}T{hour:02}{minute:02}{second:02}Z")
+}
+
+pub fn list_captures(dir: &Path) -> Result<Vec<CaptureFileInfo>, String> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("failed to read capture directory: {e}")),
+    };
+
+    let mut captures: Vec<CaptureFileInfo> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            if !metadata.is_file() {
+                return None;
+            }
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |d| d.as_millis() as u64);
+            Some(CaptureFileInfo {
+                name: entry.file_name().to_string_lossy().to_string(),
+                path: entry.path().to_string_lossy().to_string(),
+                size: metadata.len(),
+                modified,
+            })
+        })
+        .collect();
+    captures.sort_by(|a, b| {
+        b.modified
+            .cmp(&a.modified)
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    Ok(captures)
+}
+
+/// Resolves a capture file name from `list_captures`, refusing anything that
+/// would escape the capture directory.
+pub fn capture_path(dir: &Path, name: &str) -> Result<PathBuf, String> {
+    if name.is_empty() || name.contains(['/', '\\']) || name == "." || name == ".." {
+        return Err(format!("invalid capture name: {name}"));
+    }
+    let path = dir.join(name);
+    if !path.is_file() {
+        return Err(format!("capture not found: {name}"));
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("serial-capture-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn strip_stamps(log: &str) -> Vec<&str> {
+        log.lines()
+            .map(|line| line.split_once("] ").map_or(line, |(_, rest)| rest))
+            .collect()
+    }
+
+    #[test]
+    fn formats_utc_timestamps() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_700_000_000_250);
+        assert_eq!(format_timestamp(time), "2023-11-14 22:13:20.250Z");
+        assert_eq!(format_file_stamp(time), "20231114T221320Z");
+        let leap_day = UNIX_EPOCH + Duration::from_secs(951_782_400);
+        assert_eq!(format_timestamp(leap_day), "2000-02-29 00:00:00.000Z");
+    }
+
+    #[test]
+    fn raw_file_is_rx_only_and_log_has_both_directions() {
+        let dir = scratch_dir("directions");
+        let mut writer =
+            CaptureWriter::create(&dir, "/dev/ttyUSB0", RotationPolicy::default()).unwrap();
+        let log_path = writer.log_path().to_path_buf();
+        writer.record(Direction::Rx, b"hello\r\nwor").unwrap();
+        writer.record(Direction::Tx, b"AT\r\n").unwrap();
+        writer.record(Direction::Rx, b"ld\n").unwrap();
+        writer.finish().unwrap();
+
+        let name = log_path.file_name().unwrap().to_string_lossy().to_string();
+        assert!(name.starts_with("ttyUSB0-"), "{name}");
+        let raw = fs::read(log_path.with_extension("rx.bin")).unwrap();
+        assert_eq!(raw, b"hello\r\nworld\n");
+        let log = fs::read_to_string(&log_path).unwrap();
+        assert_eq!(strip_stamps(&log), ["RX: hello", "TX: AT", "RX: world"]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn rotates_by_size_and_lists_newest_first() {
+        let dir = scratch_dir("rotation");
+        let policy = RotationPolicy {
+            max_bytes: Some(4),
+            max_duration: None,
+        };
+        let mut writer = CaptureWriter::create(&dir, "COM3", policy).unwrap();
+        writer.record(Direction::Rx, b"first\n").unwrap();
+        writer.record(Direction::Rx, b"second\n").unwrap();
+        writer.finish().unwrap();
+
+        let captures = list_captures(&dir).unwrap();
+        assert_eq!(captures.len(), 4);
+        let raws: Vec<_> = captures
+            .iter()
+            .filter(|capture| capture.name.ends_with(".rx.bin"))
+            .map(|capture| fs::read(&capture.path).unwrap())
+            .collect();
+        assert!(raws.contains(&b"first\n".to_vec()) && raws.contains(&b"second\n".to_vec()));
+        assert!(capture_path(&dir, &captures[0].name).is_ok());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn rejects_names_outside_the_capture_dir() {
+        let dir = scratch_dir("names");
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["", ".", "..", "../secret", "a/b", "a\\b", "missing.log"] {
+            assert!(capture_path(&dir, name).is_err(), "{name}");
+        }
+        assert_eq!(
+            sanitize_port_name("/dev/tty.usbserial-110"),
+            "tty_usbserial-110"
+        );
+        assert_eq!(sanitize_port_name("\\\\.\\COM12"), "COM12");
+        assert!(list_captures(&dir.join("absent")).unwrap().is_empty());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,256 @@
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    Sum8,
+    Xor8,
+    /// CRC-8 with polynomial 0x07 and no reflection.
+    Crc8,
+    Crc16Modbus,
+    /// CRC-16/CCITT-FALSE: polynomial 0x1021, initial value 0xFFFF.
+    Crc16Ccitt,
+    /// CRC-16/XMODEM: polynomial 0x1021, initial value 0.
+    Crc16Xmodem,
+    /// The IEEE CRC-32 used by zlib and Ethernet.
+    Crc32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChecksumSpec {
+    pub algorithm: ChecksumAlgorithm,
+    pub little_endian: bool,
+}
+
+/// Result of checking the trailing checksum of a received frame.
+#[derive(serde::Serialize, Clone, Copy, Debug)]
+pub struct ChecksumCheck {
+    pub algorithm: &'static str,
+    pub valid: bool,
+    pub expected: u32,
+    pub received: u32,
+}
+
+impl ChecksumAlgorithm {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Sum8 => "sum8",
+            Self::Xor8 => "xor8",
+            Self::Crc8 => "crc8",
+            Self::Crc16Modbus => "crc16-modbus",
+            Self::Crc16Ccitt => "crc16-ccitt",
+            Self::Crc16Xmodem => "crc16-xmodem",
+            Self::Crc32 => "crc32",
+        }
+    }
+
+    fn width(self) -> usize {
+        match self {
+            Self::Sum8 | Self::Xor8 | Self::Crc8 => 1,
+            Self::Crc16Modbus | Self::Crc16Ccitt | Self::Crc16Xmodem => 2,
+            Self::Crc32 => 4,
+        }
+    }
+
+    fn compute(self, data: &[u8]) -> u32 {
+        match self {
+            Self::Sum8 => u32::from(data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))),
+            Self::Xor8 => u32::from(data.iter().fold(0u8, |sum, b| sum ^ b)),
+            Self::Crc8 => u32::from(crc8(data)),
+            Self::Crc16Modbus => u32::from(crc16_modbus(data)),
+            Self::Crc16Ccitt => u32::from(crc16_ccitt(data, 0xffff)),
+            Self::Crc16Xmodem => u32::from(crc16_xmodem(data)),
+            Self::Crc32 => crc32(data),
+        }
+    }
+}
+
+impl ChecksumSpec {
+    /// Parses an algorithm name such as `crc16-modbus`. Modbus sends its CRC
+    /// low byte first, so it defaults to little endian; everything else
+    /// defaults to big endian.
+    pub fn parse(algorithm: &str, endian: Option<&str>) -> Result<Self, String> {
+        let algorithm = match algorithm.to_ascii_lowercase().replace('_', "-").as_str() {
+            "sum8" | "sum" => ChecksumAlgorithm::Sum8,
+            "xor8" | "xor" => ChecksumAlgorithm::Xor8,
+            "crc8" => ChecksumAlgorithm::Crc8,
+            "crc16-modbus" | "modbus" => ChecksumAlgorithm::Crc16Modbus,
+            "crc16-ccitt" | "ccitt" => ChecksumAlgorithm::Crc16Ccitt,
+            "crc16-xmodem" | "xmodem" => ChecksumAlgorithm::Crc16Xmodem,
+            "crc32" => ChecksumAlgorithm::Crc32,
+            _ => {
+                return Err(format!(
+                    "checksum must be one of: sum8, xor8, crc8, crc16-modbus, crc16-ccitt, crc16-xmodem, crc32 (got {algorithm})"
+                ))
+            }
+        };
+        let little_endian = match endian.map(str::to_ascii_lowercase).as_deref() {
+            None | Some("") => algorithm == ChecksumAlgorithm::Crc16Modbus,
+            Some("little" | "le") => true,
+            Some("big" | "be") => false,
+            Some(other) => return Err(format!("endianness must be big or little (got {other})")),
+        };
+        Ok(Self {
+            algorithm,
+            little_endian,
+        })
+    }
+
+    /// The checksum of `data` as it goes on the wire.
+    pub fn encode(&self, data: &[u8]) -> Vec<u8> {
+        let width = self.algorithm.width();
+        let value = self.algorithm.compute(data);
+        if self.little_endian {
+            value.to_le_bytes()[..width].to_vec()
+        } else {
+            value.to_be_bytes()[4 - width..].to_vec()
+        }
+    }
+
+    pub fn append(&self, data: &mut Vec<u8>) {
+        let checksum = self.encode(data);
+        data.extend_from_slice(&checksum);
+    }
+
+    /// Checks the checksum at the end of `frame`. Returns `None` when the
+    /// frame is not longer than the checksum itself.
+    pub fn verify(&self, frame: &[u8]) -> Option<ChecksumCheck> {
+        let width = self.algorithm.width();
+        let body_len = frame.len().checked_sub(width).filter(|len| *len > 0)?;
+        let (body, trailer) = frame.split_at(body_len);
+        let mut bytes = [0u8; 4];
+        let received = if self.little_endian {
+            bytes[..width].copy_from_slice(trailer);
+            u32::from_le_bytes(bytes)
+        } else {
+            bytes[4 - width..].copy_from_slice(trailer);
+            u32::from_be_bytes(bytes)
+        };
+        let expected = self.algorithm.compute(body);
+        Some(ChecksumCheck {
+            algorithm: self.algorithm.name(),
+            valid: expected == received,
+            expected,
+            received,
+        })
+    }
+}
+
+pub fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |mut crc, byte| {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+/// CRC-16/MODBUS, sent low byte first.
+pub fn crc16_modbus(data: &[u8]) -> u16 {
+    data.iter().fold(0xffff, |mut crc, byte| {
+        crc ^= u16::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xa001
+            } else {
+                crc >> 1
+            };
+        }
+        crc
+    })
+}
+
+fn crc16_ccitt(data: &[u8], init: u16) -> u16 {
+    data.iter().fold(init, |mut crc, byte| {
+        crc ^= u16::from(*byte) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+pub fn crc16_xmodem(data: &[u8]) -> u16 {
+    crc16_ccitt(data, 0)
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(0xffff_ffffu32, |mut crc, byte| {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+        crc
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The standard CRC catalogue check input.
+    const CHECK: &[u8] = b"123456789";
+
+    fn spec(algorithm: &str, endian: Option<&str>) -> ChecksumSpec {
+        ChecksumSpec::parse(algorithm, endian).unwrap()
+    }
+
+    #[test]
+    fn matches_catalogue_check_values() {
+        assert_eq!(crc8(CHECK), 0xF4);
+        assert_eq!(crc16_modbus(CHECK), 0x4B37);
+        assert_eq!(crc16_ccitt(CHECK, 0xFFFF), 0x29B1);
+        assert_eq!(crc16_xmodem(CHECK), 0x31C3);
+        assert_eq!(crc32(CHECK), 0xCBF4_3926);
+        assert_eq!(spec("sum8", None).encode(CHECK), [0xDD]);
+        assert_eq!(spec("xor8", None).encode(CHECK), [0x31]);
+    }
+
+    #[test]
+    fn encodes_in_wire_order() {
+        assert_eq!(spec("crc16-modbus", None).encode(CHECK), [0x37, 0x4B]);
+        assert_eq!(spec("modbus", Some("big")).encode(CHECK), [0x4B, 0x37]);
+        assert_eq!(spec("CRC16_XMODEM", None).encode(CHECK), [0x31, 0xC3]);
+        assert_eq!(spec("crc32", None).encode(CHECK), [0xCB, 0xF4, 0x39, 0x26]);
+        assert_eq!(
+            spec("crc32", Some("le")).encode(CHECK),
+            [0x26, 0x39, 0xF4, 0xCB]
+        );
+
+        // Modbus "read holding registers" request from the spec examples.
+        let mut request = vec![0x01, 0x03, 0x00, 0x00, 0x00, 0x0A];
+        spec("crc16-modbus", None).append(&mut request);
+        assert_eq!(request[6..], [0xC5, 0xCD]);
+    }
+
+    #[test]
+    fn verifies_trailing_checksums() {
+        let crc = spec("crc16-xmodem", None);
+        let mut frame = CHECK.to_vec();
+        crc.append(&mut frame);
+        let check = crc.verify(&frame).unwrap();
+        assert!(check.valid);
+        assert_eq!((check.expected, check.received), (0x31C3, 0x31C3));
+        assert_eq!(check.algorithm, "crc16-xmodem");
+
+        frame[0] ^= 0x01;
+        assert!(!crc.verify(&frame).unwrap().valid);
+        assert!(crc.verify(&[0x31, 0xC3]).is_none());
+    }
+
+    #[test]
+    fn rejects_unknown_names() {
+        assert!(ChecksumSpec::parse("md5", None).is_err());
+        assert!(ChecksumSpec::parse("crc8", Some("middle")).is_err());
+    }
+}
@@ -0,0 +1,290 @@
+use std::time::{Duration, Instant, SystemTime};
+
+/// Frames that never see their terminator are emitted once they reach this
+/// size, so a device streaming binary cannot grow the buffer without bound.
+const MAX_FRAME_LEN: usize = 64 * 1024;
+/// Newline and delimiter modes flush a partial frame after this much silence,
+/// otherwise prompts without a trailing newline would never show up.
+const PARTIAL_FRAME_TIMEOUT: Duration = Duration::from_millis(250);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FramingMode {
+    Raw,
+    Newline,
+    IdleGap(Duration),
+    FixedLength(usize),
+    Delimiter(u8),
+}
+
+impl FramingMode {
+    pub fn parse(mode: &str, value: Option<u64>) -> Result<Self, String> {
+        let value_for = |name: &str| value.ok_or_else(|| format!("{name} framing needs a value"));
+        match mode.to_ascii_lowercase().as_str() {
+            "raw" | "none" => Ok(FramingMode::Raw),
+            "newline" | "line" => Ok(FramingMode::Newline),
+            "idle" | "idle_gap" => {
+                let gap = value_for("idle gap")?;
+                if gap == 0 {
+                    return Err("idle gap must be at least 1 ms".to_string());
+                }
+                Ok(FramingMode::IdleGap(Duration::from_millis(gap)))
+            }
+            "fixed" | "fixed_length" => {
+                let len = value_for("fixed length")?;
+                if len == 0 || len as usize > MAX_FRAME_LEN {
+                    return Err(format!("fixed length must be 1..={MAX_FRAME_LEN}"));
+                }
+                Ok(FramingMode::FixedLength(len as usize))
+            }
+            "delimiter" => {
+                let byte = value_for("delimiter")?;
+                let byte =
+                    u8::try_from(byte).map_err(|_| "delimiter must be a byte".to_string())?;
+                Ok(FramingMode::Delimiter(byte))
+            }
+            _ => Err("framing must be one of: raw, newline, idle, fixed, delimiter".to_string()),
+        }
+    }
+
+    /// How long the reader may block in `read()` without delaying a flush.
+    pub fn read_timeout(self, default: Duration) -> Duration {
+        match self {
+            FramingMode::IdleGap(gap) => gap.clamp(Duration::from_millis(1), default),
+            _ => default,
+        }
+    }
+}
+
+/// Host-side receive time of the first byte of a frame.
+#[derive(Clone, Copy)]
+pub struct Stamp {
+    pub monotonic: Instant,
+    pub wall: SystemTime,
+    /// Position of the first byte in the session's RX stream.
+    pub offset: u64,
+}
+
+pub struct Frame {
+    pub data: Vec<u8>,
+    pub stamp: Stamp,
+    /// Mode of the framer that cut this frame; a mode switch can hand back a
+    /// frame buffered under the previous one.
+    pub mode: FramingMode,
+}
+
+pub struct Framer {
+    mode: FramingMode,
+    buffer: Vec<u8>,
+    started: Option<Stamp>,
+    last_byte_at: Option<Instant>,
+}
+
+impl Framer {
+    pub fn new(mode: FramingMode) -> Self {
+        Self {
+            mode,
+            buffer: Vec::new(),
+            started: None,
+            last_byte_at: None,
+        }
+    }
+
+    pub fn mode(&self) -> FramingMode {
+        self.mode
+    }
+
+    /// Switches mode, handing back whatever was buffered under the old one.
+    pub fn set_mode(&mut self, mode: FramingMode) -> Option<Frame> {
+        let pending = self.flush();
+        self.mode = mode;
+        pending
+    }
+
+    /// Feeds one `read()` chunk. `stamp` is when the chunk arrived and
+    /// where it starts in the stream.
+    pub fn push(&mut self, bytes: &[u8], stamp: Stamp) -> Vec<Frame> {
+        let mut frames = Vec::new();
+        if let (FramingMode::IdleGap(gap), Some(last)) = (self.mode, self.last_byte_at) {
+            if stamp.monotonic.duration_since(last) >= gap {
+                frames.extend(self.flush());
+            }
+        }
+        self.last_byte_at = Some(stamp.monotonic);
+
+        if self.mode == FramingMode::Raw {
+            frames.push(Frame {
+                data: bytes.to_vec(),
+                stamp,
+                mode: self.mode,
+            });
+            return frames;
+        }
+
+        for (index, &byte) in bytes.iter().enumerate() {
+            if self.buffer.is_empty() {
+                self.started = Some(Stamp {
+                    offset: stamp.offset + index as u64,
+                    ..stamp
+                });
+            }
+            self.buffer.push(byte);
+
+            let complete = match self.mode {
+                FramingMode::Newline => byte == b'\n',
+                FramingMode::Delimiter(delimiter) => byte == delimiter,
+                FramingMode::FixedLength(len) => self.buffer.len() >= len,
+                FramingMode::IdleGap(_) | FramingMode::Raw => false,
+            };
+            if complete || self.buffer.len() >= MAX_FRAME_LEN {
+                frames.extend(self.flush());
+            }
+        }
+        frames
+    }
+
+    /// Called when `read()` times out. Emits the buffered frame when the
+    /// line has been quiet long enough for the current mode.
+    pub fn poll_idle(&mut self, now: Instant) -> Option<Frame> {
+        let last = self.last_byte_at?;
+        let timeout = match self.mode {
+            FramingMode::IdleGap(gap) => gap,
+            FramingMode::Newline | FramingMode::Delimiter(_) => PARTIAL_FRAME_TIMEOUT,
+            FramingMode::Raw | FramingMode::FixedLength(_) => return None,
+        };
+        if now.duration_since(last) >= timeout {
+            self.flush()
+        } else {
+            None
+        }
+    }
+
+    pub fn flush(&mut self) -> Option<Frame> {
+        let stamp = self.started.take()?;
+        if self.buffer.is_empty() {
+            return None;
+        }
+        Some(Frame {
+            data: std::mem::take(&mut self.buffer),
+            stamp,
+            mode: self.mode,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stamp(at: Instant, offset: u64) -> Stamp {
+        Stamp {
+            monotonic: at,
+            wall: SystemTime::now(),
+            offset,
+        }
+    }
+
+    fn data(frames: &[Frame]) -> Vec<&[u8]> {
+        frames.iter().map(|frame| frame.data.as_slice()).collect()
+    }
+
+    #[test]
+    fn splits_lines_across_reads() {
+        let t0 = Instant::now();
+        let mut framer = Framer::new(FramingMode::Newline);
+        let frames = framer.push(b"ab\ncd", stamp(t0, 0));
+        assert_eq!(data(&frames), [b"ab\n"]);
+        assert_eq!(frames[0].stamp.offset, 0);
+
+        let frames = framer.push(b"e\r\nf", stamp(t0, 5));
+        assert_eq!(data(&frames), [b"cde\r\n"]);
+        assert_eq!(frames[0].stamp.offset, 3);
+
+        assert!(framer.poll_idle(t0 + Duration::from_millis(100)).is_none());
+        let prompt = framer.poll_idle(t0 + PARTIAL_FRAME_TIMEOUT).unwrap();
+        assert_eq!(prompt.data, b"f");
+        assert_eq!(prompt.stamp.offset, 8);
+    }
+
+    #[test]
+    fn cuts_fixed_length_and_delimited_frames() {
+        let t0 = Instant::now();
+        let mut fixed = Framer::new(FramingMode::FixedLength(3));
+        let frames = fixed.push(b"1234567", stamp(t0, 0));
+        assert_eq!(data(&frames), [b"123", b"456"]);
+        assert_eq!(frames[1].stamp.offset, 3);
+        assert!(fixed.poll_idle(t0 + Duration::from_secs(1)).is_none());
+        assert_eq!(fixed.flush().unwrap().data, b"7");
+
+        let mut slip = Framer::new(FramingMode::Delimiter(0xC0));
+        let frames = slip.push(&[0x01, 0xC0, 0x02, 0x03, 0xC0], stamp(t0, 0));
+        assert_eq!(data(&frames), [&[0x01, 0xC0][..], &[0x02, 0x03, 0xC0]]);
+    }
+
+    #[test]
+    fn splits_on_idle_gaps() {
+        let t0 = Instant::now();
+        let gap = Duration::from_millis(20);
+        let mut framer = Framer::new(FramingMode::IdleGap(gap));
+        assert!(framer.push(b"xy", stamp(t0, 0)).is_empty());
+        assert!(framer.push(b"z", stamp(t0 + gap / 2, 2)).is_empty());
+        let frames = framer.push(b"w", stamp(t0 + gap * 3, 3));
+        assert_eq!(data(&frames), [b"xyz"]);
+        assert!(framer.poll_idle(t0 + gap * 3 + gap / 2).is_none());
+        assert_eq!(framer.poll_idle(t0 + gap * 4).unwrap().data, b"w");
+    }
+
+    #[test]
+    fn raw_passes_reads_through_and_caps_unterminated_frames() {
+        let t0 = Instant::now();
+        let mut raw = Framer::new(FramingMode::Raw);
+        let frames = raw.push(b"no newline", stamp(t0, 0));
+        assert_eq!(data(&frames), [b"no newline"]);
+        assert_eq!(frames[0].mode, FramingMode::Raw);
+
+        let mut lines = Framer::new(FramingMode::Newline);
+        let frames = lines.push(&vec![b'x'; MAX_FRAME_LEN + 1], stamp(t0, 0));
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].data.len(), MAX_FRAME_LEN);
+    }
+
+    #[test]
+    fn mode_switch_returns_the_old_frame() {
+        let t0 = Instant::now();
+        let mut framer = Framer::new(FramingMode::Newline);
+        assert!(framer.push(b"partial", stamp(t0, 0)).is_empty());
+        let pending = framer.set_mode(FramingMode::Raw).unwrap();
+        assert_eq!(pending.data, b"partial");
+        assert_eq!(pending.mode, FramingMode::Newline);
+        assert_eq!(framer.mode(), FramingMode::Raw);
+    }
+
+    #[test]
+    fn parses_modes() {
+        assert_eq!(
+            FramingMode::parse("line", None).unwrap(),
+            FramingMode::Newline
+        );
+        assert_eq!(
+            FramingMode::parse("delimiter", Some(0x7E)).unwrap(),
+            FramingMode::Delimiter(0x7E)
+        );
+        assert_eq!(
+            FramingMode::parse("idle", Some(5)).unwrap(),
+            FramingMode::IdleGap(Duration::from_millis(5))
+        );
+        assert!(FramingMode::parse("idle", None).is_err());
+        assert!(FramingMode::parse("fixed", Some(0)).is_err());
+        assert!(FramingMode::parse("delimiter", Some(256)).is_err());
+        assert!(FramingMode::parse("cobs", None).is_err());
+
+        let default = Duration::from_millis(50);
+        assert_eq!(
+            FramingMode::IdleGap(Duration::ZERO).read_timeout(default),
+            Duration::from_millis(1)
+        );
+        assert_eq!(
+            FramingMode::IdleGap(Duration::from_secs(1)).read_timeout(default),
+            default
+        );
+    }
+}
@@ -0,0 +1,565 @@
+use crate::serial_checksum::crc16_modbus;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+/// Modbus RTU characters are always 11 bits: start, 8 data, parity or a
+/// second stop bit, and stop.
+const BITS_PER_CHAR: f64 = 11.0;
+/// Above 19200 baud the spec fixes the 3.5 character gap at 1.75 ms.
+const FIXED_GAP_BAUD: u32 = 19200;
+const FIXED_GAP: Duration = Duration::from_micros(1750);
+/// Most registers a single read may ask for.
+const MAX_READ_QUANTITY: u16 = 125;
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(1);
+/// How often waits wake up to check for cancellation.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// The silence that separates two RTU frames at `baud_rate`.
+pub fn frame_gap(baud_rate: u32) -> Duration {
+    if baud_rate == 0 || baud_rate > FIXED_GAP_BAUD {
+        return FIXED_GAP;
+    }
+    Duration::from_secs_f64(3.5 * BITS_PER_CHAR / f64::from(baud_rate))
+}
+
+fn crc_valid(frame: &[u8]) -> bool {
+    let Some(body_len) = frame.len().checked_sub(2) else {
+        return false;
+    };
+    crc16_modbus(&frame[..body_len]).to_le_bytes() == frame[body_len..]
+}
+
+fn with_crc(mut frame: Vec<u8>) -> Vec<u8> {
+    let crc = crc16_modbus(&frame);
+    frame.extend_from_slice(&crc.to_le_bytes());
+    frame
+}
+
+fn word(bytes: &[u8], index: usize) -> u16 {
+    u16::from_be_bytes([bytes[index], bytes[index + 1]])
+}
+
+fn function_name(function: u8) -> &'static str {
+    match function & 0x7f {
+        0x01 => "read coils",
+        0x02 => "read discrete inputs",
+        0x03 => "read holding registers",
+        0x04 => "read input registers",
+        0x05 => "write single coil",
+        0x06 => "write single register",
+        0x0f => "write multiple coils",
+        0x10 => "write multiple registers",
+        _ => "function",
+    }
+}
+
+fn exception_name(code: u8) -> &'static str {
+    match code {
+        0x01 => "illegal function",
+        0x02 => "illegal data address",
+        0x03 => "illegal data value",
+        0x04 => "server device failure",
+        0x05 => "acknowledge",
+        0x06 => "server device busy",
+        0x08 => "memory parity error",
+        0x0a => "gateway path unavailable",
+        0x0b => "gateway target failed to respond",
+        _ => "unknown exception",
+    }
+}
+
+#[derive(serde::Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ModbusValue {
+    /// Register or coil address, when the frame says which one.
+    pub address: Option<u16>,
+    pub value: u16,
+}
+
+/// One decoded RTU frame. RTU does not mark requests and responses, so
+/// frames are told apart by their length.
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct ModbusFrame {
+    pub unit: u8,
+    pub function: u8,
+    pub crc_valid: bool,
+    pub response: bool,
+    pub exception: Option<u8>,
+    pub address: Option<u16>,
+    pub quantity: Option<u16>,
+    pub values: Vec<ModbusValue>,
+    pub summary: String,
+}
+
+/// Decodes a frame split off the bus. Returns `None` for fragments too
+/// short to be RTU frames.
+pub fn decode(frame: &[u8]) -> Option<ModbusFrame> {
+    if frame.len() < 4 {
+        return None;
+    }
+    let (unit, function) = (frame[0], frame[1]);
+    let mut decoded = ModbusFrame {
+        unit,
+        function,
+        crc_valid: crc_valid(frame),
+        response: false,
+        exception: None,
+        address: None,
+        quantity: None,
+        values: Vec::new(),
+        summary: String::new(),
+    };
+    let name = function_name(function);
+    if !decoded.crc_valid {
+        decoded.summary = format!("unit {unit} {name}: CRC mismatch");
+        return Some(decoded);
+    }
+
+    let body = &frame[2..frame.len() - 2];
+    let addressed = |address: u16, value: u16| ModbusValue {
+        address: Some(address),
+        value,
+    };
+    decoded.summary = match function {
+        _ if function & 0x80 != 0 && body.len() == 1 => {
+            decoded.response = true;
+            decoded.exception = Some(body[0]);
+            format!(
+                "unit {unit} {name}: exception {} ({})",
+                body[0],
+                exception_name(body[0])
+            )
+        }
+        // A coil response with byte count 3 (17-24 coils) has the length of
+        // a read request. Only RX is decoded, which is mostly responses to
+        // our own polls, so the response reading wins.
+        0x01 | 0x02 if !body.is_empty() && body.len() == 1 + usize::from(body[0]) => {
+            decoded.response = true;
+            decoded.values = body[1..]
+                .iter()
+                .flat_map(|byte| (0..8).map(move |bit| u16::from(byte >> bit & 1)))
+                .map(|value| ModbusValue {
+                    address: None,
+                    value,
+                })
+                .collect();
+            format!("unit {unit} {name} response: {} bytes", body[0])
+        }
+        0x01..=0x04 if body.len() == 4 => {
+            decoded.address = Some(word(body, 0));
+            decoded.quantity = Some(word(body, 2));
+            format!(
+                "unit {unit} {name} request: {} x{}",
+                word(body, 0),
+                word(body, 2)
+            )
+        }
+        0x03 | 0x04
+            if !body.is_empty()
+                && body.len() == 1 + usize::from(body[0])
+                && body[0].is_multiple_of(2) =>
+        {
+            decoded.response = true;
+            decoded.values = body[1..]
+                .chunks(2)
+                .map(|pair| ModbusValue {
+                    address: None,
+                    value: word(pair, 0),
+                })
+                .collect();
+            let values = decoded
+                .values
+                .iter()
+                .map(|value| value.value.to_string())
+                .collect::<Vec<_>>()
+                .join(" ");
+            format!("unit {unit} {name} response: {values}")
+        }
+        0x05 | 0x06 if body.len() == 4 => {
+            decoded.address = Some(word(body, 0));
+            decoded.values = vec![addressed(word(body, 0), word(body, 2))];
+            format!("unit {unit} {name}: {} = {}", word(body, 0), word(body, 2))
+        }
+        0x0f | 0x10 if body.len() == 4 => {
+            decoded.response = true;
+            decoded.address = Some(word(body, 0));
+            decoded.quantity = Some(word(body, 2));
+            format!(
+                "unit {unit} {name} response: {} x{}",
+                word(body, 0),
+                word(body, 2)
+            )
+        }
+        0x10 if body.len() >= 5 && body.len() == 5 + usize::from(body[4]) => {
+            let address = word(body, 0);
+            decoded.address = Some(address);
+            decoded.quantity = Some(word(body, 2));
+            decoded.values = body[5..]
+                .chunks_exact(2)
+                .zip(address..)
+                .map(|(pair, address)| addressed(address, word(pair, 0)))
+                .collect();
+            format!("unit {unit} {name} request: {} x{}", address, word(body, 2))
+        }
+        _ => format!(
+            "unit {unit} {name} 0x{function:02X}: {} data bytes",
+            body.len()
+        ),
+    };
+    Some(decoded)
+}
+
+/// A register read repeated on an interval.
+#[derive(Clone, Debug)]
+pub struct ModbusPoll {
+    unit: u8,
+    function: u8,
+    address: u16,
+    quantity: u16,
+    interval: Duration,
+}
+
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct ModbusPollResult {
+    pub unit: u8,
+    pub function: u8,
+    pub polls: u64,
+    pub failures: u64,
+    /// Time from the end of the request to a complete response.
+    pub latency_ms: Option<u64>,
+    pub values: Vec<ModbusValue>,
+    pub error: Option<String>,
+}
+
+impl ModbusPoll {
+    pub fn new(
+        unit: u8,
+        function: u8,
+        address: u16,
+        quantity: u16,
+        interval: Duration,
+    ) -> Result<Self, String> {
+        if !(1..=247).contains(&unit) {
+            return Err("unit id must be 1..=247".to_string());
+        }
+        if function != 0x03 && function != 0x04 {
+            return Err("only functions 3 and 4 can be polled".to_string());
+        }
+        if quantity == 0 || quantity > MAX_READ_QUANTITY {
+            return Err(format!("quantity must be 1..={MAX_READ_QUANTITY}"));
+        }
+        if u32::from(address) + u32::from(quantity) > 0x1_0000 {
+            return Err("register range ends past 65535".to_string());
+        }
+        if interval < POLL_INTERVAL {
+            return Err(format!(
+                "poll interval must be at least {} ms",
+                POLL_INTERVAL.as_millis()
+            ));
+        }
+        Ok(Self {
+            unit,
+            function,
+            address,
+            quantity,
+            interval,
+        })
+    }
+
+    pub fn request(&self) -> Vec<u8> {
+        let mut frame = vec![self.unit, self.function];
+        frame.extend_from_slice(&self.address.to_be_bytes());
+        frame.extend_from_slice(&self.quantity.to_be_bytes());
+        with_crc(frame)
+    }
+
+    /// Checks the bytes received so far. `Ok(None)` means the response is
+    /// not complete yet.
+    fn parse_response(&self, bytes: &[u8]) -> Result<Option<Vec<ModbusValue>>, String> {
+        if bytes.len() < 2 {
+            return Ok(None);
+        }
+        if bytes[0] != self.unit {
+            return Err(format!(
+                "response from unit {} instead of {}",
+                bytes[0], self.unit
+            ));
+        }
+        if bytes[1] == self.function | 0x80 {
+            if bytes.len() < 5 {
+                return Ok(None);
+            }
+            if !crc_valid(&bytes[..5]) {
+                return Err("CRC mismatch".to_string());
+            }
+            return Err(format!(
+                "exception {} ({})",
+                bytes[2],
+                exception_name(bytes[2])
+            ));
+        }
+        if bytes[1] != self.function {
+            return Err(format!("unexpected function 0x{:02X}", bytes[1]));
+        }
+        let len = 5 + 2 * usize::from(self.quantity);
+        if bytes.len() < len {
+            return Ok(None);
+        }
+        let frame = &bytes[..len];
+        if !crc_valid(frame) || usize::from(frame[2]) != len - 5 {
+            return Err("CRC mismatch".to_string());
+        }
+        Ok(Some(
+            frame[3..len - 2]
+                .chunks(2)
+                .zip(self.address..)
+                .map(|(pair, address)| ModbusValue {
+                    address: Some(address),
+                    value: word(pair, 0),
+                })
+                .collect(),
+        ))
+    }
+}
+
+/// Sends the poll's request every interval until cancelled, reporting each
+/// response or failure. Received bytes arrive on `rx`; the loop ends early
+/// when a request cannot be sent.
+pub fn run_poll(
+    poll: &ModbusPoll,
+    mut send: impl FnMut(&[u8]) -> Result<(), String>,
+    rx: Receiver<Vec<u8>>,
+    cancel: &AtomicBool,
+    mut on_result: impl FnMut(ModbusPollResult),
+) {
+    let request = poll.request();
+    let mut result = ModbusPollResult {
+        unit: poll.unit,
+        function: poll.function,
+        polls: 0,
+        failures: 0,
+        latency_ms: None,
+        values: Vec::new(),
+        error: None,
+    };
+    let mut next_due = Instant::now();
+    while !cancel.load(Ordering::SeqCst) {
+        let now = Instant::now();
+        if now < next_due {
+            std::thread::sleep((next_due - now).min(POLL_INTERVAL));
+            continue;
+        }
+        next_due += poll.interval;
+        if next_due < now {
+            next_due = now + poll.interval;
+        }
+
+        // Anything left over belongs to an earlier, failed poll.
+        while rx.try_recv().is_ok() {}
+        result.polls += 1;
+        if let Err(err) = send(&request) {
+            result.failures += 1;
+            result.error = Some(err);
+            on_result(result);
+            return;
+        }
+
+        let sent_at = Instant::now();
+        let outcome = receive_response(poll, &rx, cancel);
+        result.latency_ms = None;
+        result.error = None;
+        match outcome {
+            Ok(Some(values)) => {
+                result.latency_ms = Some(sent_at.elapsed().as_millis() as u64);
+                result.values = values;
+            }
+            Ok(None) => return,
+            Err(err) => {
+                result.failures += 1;
+                result.error = Some(err);
+            }
+        }
+        on_result(result.clone());
+    }
+}
+
+/// Waits for one response. `Ok(None)` means the poll was cancelled.
+fn receive_response(
+    poll: &ModbusPoll,
+    rx: &Receiver<Vec<u8>>,
+    cancel: &AtomicBool,
+) -> Result<Option<Vec<ModbusValue>>, String> {
+    let deadline = Instant::now() + RESPONSE_TIMEOUT;
+    let mut bytes = Vec::new();
+    loop {
+        if cancel.load(Ordering::SeqCst) {
+            return Ok(None);
+        }
+        if let Some(values) = poll.parse_response(&bytes)? {
+            return Ok(Some(values));
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(if bytes.is_empty() {
+                "no response".to_string()
+            } else {
+                format!("incomplete response ({} bytes)", bytes.len())
+            });
+        }
+        match rx.recv_timeout((deadline - now).min(POLL_INTERVAL)) {
+            Ok(chunk) => bytes.extend(chunk),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return Err("serial reader stopped".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    /// "Read holding registers" example from the Modbus application
+    /// protocol spec: unit 17 asks for 3 registers from 107.
+    const SPEC_REQUEST: [u8; 8] = [0x11, 0x03, 0x00, 0x6B, 0x00, 0x03, 0x76, 0x87];
+    const SPEC_RESPONSE: [u8; 11] = [
+        0x11, 0x03, 0x06, 0x02, 0x2B, 0x00, 0x00, 0x00, 0x64, 0xC8, 0xBA,
+    ];
+
+    fn spec_poll() -> ModbusPoll {
+        ModbusPoll::new(0x11, 0x03, 0x6B, 3, Duration::from_millis(50)).unwrap()
+    }
+
+    fn values(values: &[ModbusValue]) -> Vec<u16> {
+        values.iter().map(|value| value.value).collect()
+    }
+
+    #[test]
+    fn decodes_the_spec_example() {
+        let request = decode(&SPEC_REQUEST).unwrap();
+        assert!(request.crc_valid && !request.response);
+        assert_eq!((request.address, request.quantity), (Some(107), Some(3)));
+        assert_eq!(
+            request.summary,
+            "unit 17 read holding registers request: 107 x3"
+        );
+
+        let response = decode(&SPEC_RESPONSE).unwrap();
+        assert!(response.crc_valid && response.response);
+        assert_eq!(values(&response.values), [555, 0, 100]);
+        assert_eq!(
+            response.summary,
+            "unit 17 read holding registers response: 555 0 100"
+        );
+
+        let mut corrupted = SPEC_RESPONSE;
+        corrupted[4] ^= 0x01;
+        let corrupted = decode(&corrupted).unwrap();
+        assert!(!corrupted.crc_valid);
+        assert_eq!(
+            corrupted.summary,
+            "unit 17 read holding registers: CRC mismatch"
+        );
+        assert!(decode(&SPEC_REQUEST[..3]).is_none());
+    }
+
+    #[test]
+    fn decodes_writes_coils_and_exceptions() {
+        let write = decode(&[0x11, 0x06, 0x00, 0x01, 0x00, 0x03, 0x9A, 0x9B]).unwrap();
+        assert_eq!(
+            write.values,
+            [ModbusValue {
+                address: Some(1),
+                value: 3
+            }]
+        );
+
+        let coils = decode(&with_crc(vec![0x01, 0x01, 0x01, 0b0000_0101])).unwrap();
+        assert!(coils.response);
+        assert_eq!(values(&coils.values), [1, 0, 1, 0, 0, 0, 0, 0]);
+
+        // Three bytes of coils is as long as a read request.
+        let coils = decode(&with_crc(vec![0x01, 0x01, 0x03, 0xFF, 0x00, 0x81])).unwrap();
+        assert!(coils.response);
+        assert_eq!(coils.values.len(), 24);
+        assert_eq!(coils.summary, "unit 1 read coils response: 3 bytes");
+        let request = decode(&with_crc(vec![0x01, 0x02, 0x00, 0x13, 0x00, 0x18])).unwrap();
+        assert!(!request.response);
+        assert_eq!((request.address, request.quantity), (Some(19), Some(24)));
+
+        let exception = decode(&[0x01, 0x83, 0x02, 0xC0, 0xF1]).unwrap();
+        assert_eq!(exception.exception, Some(2));
+        assert_eq!(
+            exception.summary,
+            "unit 1 read holding registers: exception 2 (illegal data address)"
+        );
+    }
+
+    #[test]
+    fn builds_and_checks_poll_frames() {
+        let poll = spec_poll();
+        assert_eq!(poll.request(), SPEC_REQUEST);
+        assert_eq!(poll.parse_response(&SPEC_RESPONSE[..6]).unwrap(), None);
+        let read = poll.parse_response(&SPEC_RESPONSE).unwrap().unwrap();
+        assert_eq!(
+            read[2],
+            ModbusValue {
+                address: Some(109),
+                value: 100
+            }
+        );
+
+        let err = poll
+            .parse_response(&with_crc(vec![0x11, 0x83, 0x02]))
+            .unwrap_err();
+        assert_eq!(err, "exception 2 (illegal data address)");
+        let err = poll.parse_response(&[0x12, 0x03]).unwrap_err();
+        assert_eq!(err, "response from unit 18 instead of 17");
+
+        assert!(ModbusPoll::new(0, 3, 0, 1, Duration::from_secs(1)).is_err());
+        assert!(ModbusPoll::new(1, 6, 0, 1, Duration::from_secs(1)).is_err());
+        assert!(ModbusPoll::new(1, 3, 0, 126, Duration::from_secs(1)).is_err());
+        assert!(ModbusPoll::new(1, 3, 0xFFFF, 2, Duration::from_secs(1)).is_err());
+    }
+
+    #[test]
+    fn computes_inter_frame_gaps() {
+        assert_eq!(frame_gap(9600).as_micros(), 4010);
+        assert_eq!(frame_gap(19200).as_micros(), 2005);
+        assert_eq!(frame_gap(115_200), FIXED_GAP);
+    }
+
+    #[test]
+    fn polls_until_a_send_fails() {
+        let poll = spec_poll();
+        let (tx, rx) = mpsc::channel();
+        let cancel = AtomicBool::new(false);
+        let mut results = Vec::new();
+        let mut sends = 0;
+        let send = |data: &[u8]| {
+            assert_eq!(data, SPEC_REQUEST);
+            sends += 1;
+            match sends {
+                // The response arrives split across two reads.
+                1 => {
+                    tx.send(SPEC_RESPONSE[..4].to_vec()).unwrap();
+                    tx.send(SPEC_RESPONSE[4..].to_vec()).unwrap();
+                    Ok(())
+                }
+                2 => Ok(()),
+                _ => Err("serial writer is unavailable".to_string()),
+            }
+        };
+        run_poll(&poll, send, rx, &cancel, |result| results.push(result));
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(values(&results[0].values), [555, 0, 100]);
+        assert!(results[0].latency_ms.is_some());
+        assert_eq!(results[1].error.as_deref(), Some("no response"));
+        assert_eq!(
+            results[2].error.as_deref(),
+            Some("serial writer is unavailable")
+        );
+        assert_eq!((results[2].polls, results[2].failures), (3, 2));
+    }
+}
@@ -0,0 +1,740 @@
+use crate::serial_assistant::{LineSettings, ModemSignals};
+use crate::serial_rfc2217::{self as rfc2217, TelnetItem, TelnetOptions, TelnetParser};
+use crate::serial_virtual::{DeviceScript, Loopback};
+use serialport::{ClearBuffer, DataBits, FlowControl, Parity, SerialPort, StopBits};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tungstenite::{Message, WebSocket};
+use url::Url;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long an RFC 2217 server gets to agree to COM-PORT-OPTION.
+const NEGOTIATION_TIMEOUT: Duration = Duration::from_secs(2);
+/// WebSocket reads hold the socket lock, so they wait in short slices to
+/// let sends through.
+const WEBSOCKET_READ_SLICE: Duration = Duration::from_millis(10);
+
+/// Whether `port` names a network endpoint such as `tcp://host:port`, or a
+/// `sim://` virtual device, rather than a local device.
+pub fn is_network_port(port: &str) -> bool {
+    port.contains("://")
+}
+
+/// Connects to `rfc2217://`, `tcp://` or `ws://`, or starts a `sim://`
+/// device, and returns it as a serial port, so sessions run the same reader
+/// and writer threads over it.
+pub fn open(
+    port: &str,
+    settings: LineSettings,
+    timeout: Duration,
+) -> Result<Box<dyn SerialPort>, String> {
+    let url = Url::parse(port).map_err(|e| format!("invalid port address {port}: {e}"))?;
+    let transport: Arc<dyn Transport> = match url.scheme() {
+        "tcp" => Arc::new(TcpTransport::connect(&url)?),
+        "rfc2217" => Arc::new(Rfc2217Transport::connect(&url, &settings)?),
+        "ws" => Arc::new(WebSocketTransport::connect(&url)?),
+        "sim" => Arc::new(Loopback::start(DeviceScript::parse(
+            port,
+            settings.baud_rate,
+        )?)),
+        "wss" => return Err("wss:// is not supported, use ws://".to_string()),
+        scheme => {
+            return Err(format!(
+                "port address must start with rfc2217://, tcp://, ws:// or sim:// (got {scheme}://)"
+            ))
+        }
+    };
+    Ok(Box::new(NetworkPort {
+        name: port.to_string(),
+        transport,
+        settings: Arc::new(Mutex::new(settings)),
+        timeout,
+    }))
+}
+
+fn connect_stream(url: &Url) -> Result<TcpStream, String> {
+    let default_port = (url.scheme() == "ws").then_some(80);
+    if url.port().or(default_port).is_none() {
+        return Err(format!("{url} needs a port"));
+    }
+    let addresses: Vec<SocketAddr> = url
+        .socket_addrs(|| default_port)
+        .map_err(|e| format!("failed to resolve {url}: {e}"))?;
+    let mut last_error = None;
+    for address in addresses {
+        match TcpStream::connect_timeout(&address, CONNECT_TIMEOUT) {
+            Ok(stream) => {
+                let _ = stream.set_nodelay(true);
+                return Ok(stream);
+            }
+            Err(err) => last_error = Some(err),
+        }
+    }
+    Err(match last_error {
+        Some(err) => format!("failed to connect to {url}: {err}"),
+        None => format!("failed to resolve {url}"),
+    })
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> io::Result<MutexGuard<'_, T>> {
+    mutex
+        .lock()
+        .map_err(|_| io::Error::other("network port lock poisoned"))
+}
+
+fn closed() -> io::Error {
+    io::Error::new(ErrorKind::ConnectionAborted, "connection closed by remote")
+}
+
+fn unsupported(what: &str) -> serialport::Error {
+    serialport::Error::new(
+        serialport::ErrorKind::Io(ErrorKind::Unsupported),
+        format!("{what} is not available on this connection"),
+    )
+}
+
+/// A line setting or control line change requested through `SerialPort`.
+pub enum Control {
+    BaudRate(u32),
+    DataBits(DataBits),
+    Parity(Parity),
+    StopBits(StopBits),
+    FlowControl(FlowControl),
+    Dtr(bool),
+    Rts(bool),
+    Break(bool),
+}
+
+/// One connection, shared by the reader and writer clones of a port.
+pub trait Transport: Send + Sync {
+    /// Reads at least one byte, or fails with `TimedOut` after `timeout`.
+    fn read(&self, buffer: &mut [u8], timeout: Duration) -> io::Result<usize>;
+    fn write(&self, data: &[u8]) -> io::Result<()>;
+    /// Raw streams carry bytes only: line settings are accepted and
+    /// ignored, and control lines are unavailable.
+    fn control(&self, control: Control) -> serialport::Result<()> {
+        match control {
+            Control::Dtr(_) => Err(unsupported("DTR")),
+            Control::Rts(_) => Err(unsupported("RTS")),
+            Control::Break(_) => Err(unsupported("break")),
+            _ => Ok(()),
+        }
+    }
+    fn modem(&self) -> serialport::Result<ModemSignals> {
+        Err(unsupported("modem status"))
+    }
+}
+
+/// Copies what fits of `pending` into `buffer`.
+pub fn take_pending(pending: &mut Vec<u8>, buffer: &mut [u8]) -> usize {
+    let size = pending.len().min(buffer.len());
+    buffer[..size].copy_from_slice(&pending[..size]);
+    pending.drain(..size);
+    size
+}
+
+fn is_timeout(err: &io::Error) -> bool {
+    matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+struct TcpTransport {
+    reader: Mutex<TcpStream>,
+    writer: Mutex<TcpStream>,
+}
+
+impl TcpTransport {
+    fn connect(url: &Url) -> Result<Self, String> {
+        let stream = connect_stream(url)?;
+        let writer = stream
+            .try_clone()
+            .map_err(|e| format!("failed to clone connection: {e}"))?;
+        Ok(Self {
+            reader: Mutex::new(stream),
+            writer: Mutex::new(writer),
+        })
+    }
+}
+
+impl Transport for TcpTransport {
+    fn read(&self, buffer: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        let mut stream = lock(&self.reader)?;
+        stream.set_read_timeout(Some(timeout.max(Duration::from_millis(1))))?;
+        match stream.read(buffer) {
+            Ok(0) => Err(closed()),
+            Ok(size) => Ok(size),
+            Err(err) if is_timeout(&err) => Err(ErrorKind::TimedOut.into()),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn write(&self, data: &[u8]) -> io::Result<()> {
+        lock(&self.writer)?.write_all(data)
+    }
+}
+
+/// The reader's half of an RFC 2217 connection.
+struct Rfc2217Reader {
+    stream: TcpStream,
+    parser: TelnetParser,
+    pending: Vec<u8>,
+}
+
+/// A Telnet COM-PORT-OPTION client, as served by ser2net or
+/// `esp_rfc2217_server`. Settings and control lines are forwarded to the
+/// server without waiting for it to confirm them.
+struct Rfc2217Transport {
+    reader: Mutex<Rfc2217Reader>,
+    writer: Mutex<TcpStream>,
+    options: Mutex<TelnetOptions>,
+    modem: Mutex<ModemSignals>,
+}
+
+impl Rfc2217Transport {
+    fn connect(url: &Url, settings: &LineSettings) -> Result<Self, String> {
+        let stream = connect_stream(url)?;
+        let writer = stream
+            .try_clone()
+            .map_err(|e| format!("failed to clone connection: {e}"))?;
+        let transport = Self {
+            reader: Mutex::new(Rfc2217Reader {
+                stream,
+                parser: TelnetParser::default(),
+                pending: Vec::new(),
+            }),
+            writer: Mutex::new(writer),
+            options: Mutex::new(TelnetOptions::default()),
+            modem: Mutex::new(ModemSignals {
+                cts: false,
+                dsr: false,
+                ri: false,
+                cd: false,
+            }),
+        };
+        transport
+            .negotiate()
+            .map_err(|e| format!("RFC 2217 negotiation with {url} failed: {e}"))?;
+
+        for control in [
+            Control::BaudRate(settings.baud_rate),
+            Control::DataBits(settings.data_bits),
+            Control::Parity(settings.parity),
+            Control::StopBits(settings.stop_bits),
+            Control::FlowControl(settings.flow_control),
+        ] {
+            transport
+                .control(control)
+                .map_err(|e| format!("failed to configure {url}: {e}"))?;
+        }
+        transport
+            .send_raw(&rfc2217::com_port_command(rfc2217::NOTIFY_MODEMSTATE, &[]))
+            .map_err(|e| format!("failed to configure {url}: {e}"))?;
+        Ok(transport)
+    }
+
+    /// Asks for binary mode and COM-PORT-OPTION and waits for the server to
+    /// agree. Data that arrives meanwhile is kept for the first read.
+    fn negotiate(&self) -> io::Result<()> {
+        let mut request = Vec::new();
+        {
+            let mut options = lock(&self.options)?;
+            for (command, option) in [
+                (rfc2217::WILL, rfc2217::BINARY),
+                (rfc2217::DO, rfc2217::BINARY),
+                (rfc2217::DO, rfc2217::SGA),
+                (rfc2217::WILL, rfc2217::COM_PORT_OPTION),
+            ] {
+                request.extend(options.request(command, option));
+            }
+        }
+        self.send_raw(&request)?;
+
+        let deadline = Instant::now() + NEGOTIATION_TIMEOUT;
+        let mut reader = lock(&self.reader)?;
+        while !lock(&self.options)?.remote_enabled(rfc2217::COM_PORT_OPTION) {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(io::Error::new(
+                    ErrorKind::TimedOut,
+                    "server did not enable COM-PORT-OPTION",
+                ));
+            }
+            self.receive(&mut reader, remaining)?;
+        }
+        Ok(())
+    }
+
+    fn send_raw(&self, bytes: &[u8]) -> io::Result<()> {
+        lock(&self.writer)?.write_all(bytes)
+    }
+
+    /// Reads one chunk and handles the Telnet commands in it; its data is
+    /// appended to `pending`.
+    fn receive(&self, reader: &mut Rfc2217Reader, timeout: Duration) -> io::Result<()> {
+        let mut buffer = [0u8; 4096];
+        reader
+            .stream
+            .set_read_timeout(Some(timeout.max(Duration::from_millis(1))))?;
+        let size = match reader.stream.read(&mut buffer) {
+            Ok(0) => return Err(closed()),
+            Ok(size) => size,
+            Err(err) if is_timeout(&err) => return Err(ErrorKind::TimedOut.into()),
+            Err(err) => return Err(err),
+        };
+        for item in reader.parser.push(&buffer[..size]) {
+            match item {
+                TelnetItem::Data(data) => reader.pending.extend(data),
+                TelnetItem::Negotiate { command, option } => {
+                    let reply = lock(&self.options)?.negotiate(command, option);
+                    if let Some(reply) = reply {
+                        self.send_raw(&reply)?;
+                    }
+                }
+                TelnetItem::Subnegotiate { option, data } => {
+                    if option != rfc2217::COM_PORT_OPTION {
+                        continue;
+                    }
+                    // Acknowledgements of our own requests need no action.
+                    if let [command, state, ..] = data[..] {
+                        if command == rfc2217::NOTIFY_MODEMSTATE + rfc2217::SERVER_OFFSET {
+                            *lock(&self.modem)? = rfc2217::decode_modem_state(state);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Transport for Rfc2217Transport {
+    fn read(&self, buffer: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        let deadline = Instant::now() + timeout;
+        let mut reader = lock(&self.reader)?;
+        while reader.pending.is_empty() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(ErrorKind::TimedOut.into());
+            }
+            self.receive(&mut reader, remaining)?;
+        }
+        Ok(take_pending(&mut reader.pending, buffer))
+    }
+
+    fn write(&self, data: &[u8]) -> io::Result<()> {
+        self.send_raw(&rfc2217::escape(data))
+    }
+
+    fn control(&self, control: Control) -> serialport::Result<()> {
+        let (command, value) = match control {
+            Control::BaudRate(baud_rate) => {
+                (rfc2217::SET_BAUDRATE, baud_rate.to_be_bytes().to_vec())
+            }
+            Control::DataBits(bits) => {
+                (rfc2217::SET_DATASIZE, vec![rfc2217::encode_data_bits(bits)])
+            }
+            Control::Parity(parity) => (rfc2217::SET_PARITY, vec![rfc2217::encode_parity(parity)]),
+            Control::StopBits(bits) => {
+                (rfc2217::SET_STOPSIZE, vec![rfc2217::encode_stop_bits(bits)])
+            }
+            Control::FlowControl(flow_control) => (
+                rfc2217::SET_CONTROL,
+                vec![rfc2217::encode_flow_control(flow_control)],
+            ),
+            Control::Dtr(level) => (
+                rfc2217::SET_CONTROL,
+                vec![if level {
+                    rfc2217::CONTROL_DTR_ON
+                } else {
+                    rfc2217::CONTROL_DTR_OFF
+                }],
+            ),
+            Control::Rts(level) => (
+                rfc2217::SET_CONTROL,
+                vec![if level {
+                    rfc2217::CONTROL_RTS_ON
+                } else {
+                    rfc2217::CONTROL_RTS_OFF
+                }],
+            ),
+            Control::Break(on) => (
+                rfc2217::SET_CONTROL,
+                vec![if on {
+                    rfc2217::CONTROL_BREAK_ON
+                } else {
+                    rfc2217::CONTROL_BREAK_OFF
+                }],
+            ),
+        };
+        self.send_raw(&rfc2217::com_port_command(command, &value))?;
+        Ok(())
+    }
+
+    /// The last state the server notified; all lines read low until then.
+    fn modem(&self) -> serialport::Result<ModemSignals> {
+        Ok(*lock(&self.modem)?)
+    }
+}
+
+/// Binary WebSocket messages carry the stream in both directions, as ESP
+/// WiFi-UART bridges do. Text messages are accepted as raw bytes too.
+struct WebSocketTransport {
+    socket: Mutex<WebSocket<TcpStream>>,
+    pending: Mutex<Vec<u8>>,
+}
+
+impl WebSocketTransport {
+    fn connect(url: &Url) -> Result<Self, String> {
+        let stream = connect_stream(url)?;
+        let (socket, _) = tungstenite::client(url.as_str(), stream)
+            .map_err(|e| format!("WebSocket handshake with {url} failed: {e}"))?;
+        socket
+            .get_ref()
+            .set_read_timeout(Some(WEBSOCKET_READ_SLICE))
+            .map_err(|e| format!("failed to configure connection: {e}"))?;
+        Ok(Self {
+            socket: Mutex::new(socket),
+            pending: Mutex::new(Vec::new()),
+        })
+    }
+}
+
+fn websocket_error(err: tungstenite::Error) -> io::Error {
+    match err {
+        tungstenite::Error::Io(err) => err,
+        tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed => closed(),
+        err => io::Error::other(err.to_string()),
+    }
+}
+
+impl Transport for WebSocketTransport {
+    fn read(&self, buffer: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        let deadline = Instant::now() + timeout;
+        let mut pending = lock(&self.pending)?;
+        while pending.is_empty() {
+            if Instant::now() >= deadline {
+                return Err(ErrorKind::TimedOut.into());
+            }
+            let message = lock(&self.socket)?.read_message();
+            match message {
+                Ok(Message::Binary(data)) => pending.extend(data),
+                Ok(Message::Text(text)) => pending.extend(text.into_bytes()),
+                Ok(Message::Close(_)) => return Err(closed()),
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(err)) if is_timeout(&err) => {}
+                Err(err) => return Err(websocket_error(err)),
+            }
+        }
+        Ok(take_pending(&mut pending, buffer))
+    }
+
+    fn write(&self, data: &[u8]) -> io::Result<()> {
+        lock(&self.socket)?
+            .write_message(Message::Binary(data.to_vec()))
+            .map_err(websocket_error)
+    }
+}
+
+/// A network connection presented as a serial port. Each clone shares the
+/// connection but keeps its own read timeout.
+struct NetworkPort {
+    name: String,
+    transport: Arc<dyn Transport>,
+    settings: Arc<Mutex<LineSettings>>,
+    timeout: Duration,
+}
+
+impl NetworkPort {
+    fn settings(&self) -> serialport::Result<LineSettings> {
+        Ok(*lock(&self.settings)?)
+    }
+
+    fn update(
+        &self,
+        control: Control,
+        apply: impl FnOnce(&mut LineSettings),
+    ) -> serialport::Result<()> {
+        self.transport.control(control)?;
+        apply(&mut *lock(&self.settings)?);
+        Ok(())
+    }
+}
+
+impl Read for NetworkPort {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        self.transport.read(buffer, self.timeout)
+    }
+}
+
+impl Write for NetworkPort {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.transport.write(data)?;
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SerialPort for NetworkPort {
+    fn name(&self) -> Option<String> {
+        Some(self.name.clone())
+    }
+
+    fn baud_rate(&self) -> serialport::Result<u32> {
+        Ok(self.settings()?.baud_rate)
+    }
+
+    fn data_bits(&self) -> serialport::Result<DataBits> {
+        Ok(self.settings()?.data_bits)
+    }
+
+    fn flow_control(&self) -> serialport::Result<FlowControl> {
+        Ok(self.settings()?.flow_control)
+    }
+
+    fn parity(&self) -> serialport::Result<Parity> {
+        Ok(self.settings()?.parity)
+    }
+
+    fn stop_bits(&self) -> serialport::Result<StopBits> {
+        Ok(self.settings()?.stop_bits)
+    }
+
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> serialport::Result<()> {
+        self.update(Control::BaudRate(baud_rate), |settings| {
+            settings.baud_rate = baud_rate
+        })
+    }
+
+    fn set_data_bits(&mut self, data_bits: DataBits) -> serialport::Result<()> {
+        self.update(Control::DataBits(data_bits), |settings| {
+            settings.data_bits = data_bits
+        })
+    }
+
+    fn set_flow_control(&mut self, flow_control: FlowControl) -> serialport::Result<()> {
+        self.update(Control::FlowControl(flow_control), |settings| {
+            settings.flow_control = flow_control
+        })
+    }
+
+    fn set_parity(&mut self, parity: Parity) -> serialport::Result<()> {
+        self.update(Control::Parity(parity), |settings| settings.parity = parity)
+    }
+
+    fn set_stop_bits(&mut self, stop_bits: StopBits) -> serialport::Result<()> {
+        self.update(Control::StopBits(stop_bits), |settings| {
+            settings.stop_bits = stop_bits
+        })
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> serialport::Result<()> {
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    fn write_request_to_send(&mut self, level: bool) -> serialport::Result<()> {
+        self.transport.control(Control::Rts(level))
+    }
+
+    fn write_data_terminal_ready(&mut self, level: bool) -> serialport::Result<()> {
+        self.transport.control(Control::Dtr(level))
+    }
+
+    fn read_clear_to_send(&mut self) -> serialport::Result<bool> {
+        Ok(self.transport.modem()?.cts)
+    }
+
+    fn read_data_set_ready(&mut self) -> serialport::Result<bool> {
+        Ok(self.transport.modem()?.dsr)
+    }
+
+    fn read_ring_indicator(&mut self) -> serialport::Result<bool> {
+        Ok(self.transport.modem()?.ri)
+    }
+
+    fn read_carrier_detect(&mut self) -> serialport::Result<bool> {
+        Ok(self.transport.modem()?.cd)
+    }
+
+    fn bytes_to_read(&self) -> serialport::Result<u32> {
+        Ok(0)
+    }
+
+    /// Writes are handed to the socket whole, so nothing waits here.
+    fn bytes_to_write(&self) -> serialport::Result<u32> {
+        Ok(0)
+    }
+
+    fn clear(&self, _buffer_to_clear: ClearBuffer) -> serialport::Result<()> {
+        Ok(())
+    }
+
+    fn try_clone(&self) -> serialport::Result<Box<dyn SerialPort>> {
+        Ok(Box::new(NetworkPort {
+            name: self.name.clone(),
+            transport: Arc::clone(&self.transport),
+            settings: Arc::clone(&self.settings),
+            timeout: self.timeout,
+        }))
+    }
+
+    fn set_break(&self) -> serialport::Result<()> {
+        self.transport.control(Control::Break(true))
+    }
+
+    fn clear_break(&self) -> serialport::Result<()> {
+        self.transport.control(Control::Break(false))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serial_rfc2217::tests::RecordingPort;
+    use std::net::TcpListener;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::mpsc;
+    use std::thread;
+
+    const TIMEOUT: Duration = Duration::from_millis(50);
+
+    fn settings() -> LineSettings {
+        LineSettings::parse(115200, 8, 1, "none", "none").unwrap()
+    }
+
+    fn read_until(port: &mut Box<dyn SerialPort>, count: usize) -> Vec<u8> {
+        let mut received = Vec::new();
+        let mut buffer = [0u8; 64];
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while received.len() < count && Instant::now() < deadline {
+            match port.read(&mut buffer) {
+                Ok(read) => received.extend_from_slice(&buffer[..read]),
+                Err(err) if is_timeout(&err) => {}
+                Err(err) => panic!("read failed: {err}"),
+            }
+        }
+        received
+    }
+
+    #[test]
+    fn tcp_passes_bytes_through_unchanged() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buffer = [0u8; 16];
+            let read = stream.read(&mut buffer).unwrap();
+            stream.write_all(&buffer[..read]).unwrap();
+        });
+
+        let mut port = open(&format!("tcp://{address}"), settings(), TIMEOUT).unwrap();
+        let mut reader = port.try_clone().unwrap();
+        assert!(port.write_data_terminal_ready(true).is_err());
+        port.set_baud_rate(9600).unwrap();
+        assert_eq!(reader.baud_rate().unwrap(), 9600);
+        port.write_all(b"ping\xff").unwrap();
+        assert_eq!(read_until(&mut reader, 5), b"ping\xff");
+
+        server.join().unwrap();
+        let err = reader.read(&mut [0u8; 4]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ConnectionAborted);
+    }
+
+    #[test]
+    fn rejects_bad_addresses() {
+        let err = open("tcp://127.0.0.1", settings(), TIMEOUT).unwrap_err();
+        assert!(err.contains("needs a port"), "{err}");
+        assert!(open("wss://127.0.0.1:1", settings(), TIMEOUT).is_err());
+        assert!(open("foo://127.0.0.1:1", settings(), TIMEOUT).is_err());
+    }
+
+    #[test]
+    fn websocket_carries_text_and_binary_messages() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut socket = tungstenite::accept(stream).unwrap();
+            socket.write_message(Message::Text("hello".into())).unwrap();
+            loop {
+                match socket.read_message() {
+                    Ok(Message::Binary(data)) => {
+                        socket.write_message(Message::Binary(data)).unwrap()
+                    }
+                    Ok(Message::Close(_)) | Err(_) => break,
+                    Ok(_) => {}
+                }
+            }
+        });
+
+        let mut port = open(&format!("ws://{address}/uart"), settings(), TIMEOUT).unwrap();
+        let mut reader = port.try_clone().unwrap();
+        assert_eq!(read_until(&mut reader, 5), b"hello");
+        port.write_all(&[0, 1, 2, 0xff]).unwrap();
+        assert_eq!(read_until(&mut reader, 4), vec![0, 1, 2, 0xff]);
+        assert!(reader.read_carrier_detect().is_err());
+
+        drop(port);
+        drop(reader);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn rfc2217_client_drives_the_remote_port() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let signals = ModemSignals {
+            cts: true,
+            dsr: true,
+            ri: false,
+            cd: false,
+        };
+        let mut remote = RecordingPort::new(9600, signals);
+        let log = remote.log.clone();
+        let (rx_tx, rx) = mpsc::channel();
+        let server = thread::spawn(move || {
+            let cancel = AtomicBool::new(false);
+            rfc2217::serve(listener, &mut remote, rx, &cancel, |event| {
+                if let rfc2217::ServerEvent::Warning(warning) = event {
+                    panic!("server warning: {warning}");
+                }
+            })
+        });
+
+        let mut port = open(&format!("rfc2217://{address}"), settings(), TIMEOUT).unwrap();
+        let mut reader = port.try_clone().unwrap();
+        port.write_request_to_send(false).unwrap();
+        port.write_data_terminal_ready(true).unwrap();
+        port.set_baud_rate(460800).unwrap();
+        port.write_all(&[1, 0xff, 2]).unwrap();
+        thread::sleep(Duration::from_millis(200));
+        rx_tx.send(vec![0xff, 0xff, 7]).unwrap();
+        assert_eq!(read_until(&mut reader, 3), vec![0xff, 0xff, 7]);
+        assert!(reader.read_clear_to_send().unwrap());
+        assert!(reader.read_data_set_ready().unwrap());
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                "baud 115200",
+                "rts false",
+                "dtr true",
+                "baud 460800",
+                "write [1, 255, 2]"
+            ]
+        );
+
+        drop(port);
+        drop(reader);
+        thread::sleep(Duration::from_millis(100));
+        drop(rx_tx);
+        server.join().unwrap().unwrap();
+    }
+}
@@ -0,0 +1,195 @@
+use std::time::{Duration, Instant};
+
+const MAX_PAYLOADS: usize = 256;
+
+/// What to send and how often. Payloads are sent in turn, one per interval.
+#[derive(Clone, Debug)]
+pub struct PeriodicSchedule {
+    payloads: Vec<Vec<u8>>,
+    interval: Duration,
+    /// Total sends before stopping; `None` runs until stopped.
+    count: Option<u64>,
+    /// Byte overwritten with a counter that increments on every send.
+    counter_offset: Option<usize>,
+}
+
+impl PeriodicSchedule {
+    pub fn new(
+        payloads: Vec<Vec<u8>>,
+        interval: Duration,
+        count: Option<u64>,
+        counter_offset: Option<usize>,
+    ) -> Result<Self, String> {
+        if payloads.is_empty() || payloads.iter().any(Vec::is_empty) {
+            return Err("periodic send needs at least one non-empty payload".to_string());
+        }
+        if payloads.len() > MAX_PAYLOADS {
+            return Err(format!(
+                "periodic send takes at most {MAX_PAYLOADS} payloads"
+            ));
+        }
+        if interval.is_zero() {
+            return Err("periodic interval must be at least 1 ms".to_string());
+        }
+        if count == Some(0) {
+            return Err("periodic count must be at least 1".to_string());
+        }
+        if let Some(offset) = counter_offset {
+            if payloads.iter().any(|payload| offset >= payload.len()) {
+                return Err(format!("counter offset {offset} is outside a payload"));
+            }
+        }
+        Ok(Self {
+            payloads,
+            interval,
+            count,
+            counter_offset,
+        })
+    }
+}
+
+#[derive(serde::Serialize, Clone, Copy, Debug)]
+pub struct PeriodicProgress {
+    pub sent: u64,
+    pub remaining: Option<u64>,
+    /// Set on the last event of a schedule, however it ended.
+    pub done: bool,
+}
+
+/// A running schedule. Due times advance by whole intervals from the start,
+/// so jitter in one send does not shift the ones after it.
+pub struct PeriodicSend {
+    schedule: PeriodicSchedule,
+    next_due: Instant,
+    sent: u64,
+}
+
+impl PeriodicSend {
+    pub fn start(schedule: PeriodicSchedule, now: Instant) -> Self {
+        Self {
+            schedule,
+            next_due: now,
+            sent: 0,
+        }
+    }
+
+    pub fn next_due(&self) -> Instant {
+        self.next_due
+    }
+
+    /// Returns the payload due now and moves on to the next slot. When the
+    /// sender has fallen more than an interval behind, missed slots are
+    /// skipped rather than sent in a burst.
+    pub fn take_payload(&mut self, now: Instant) -> Vec<u8> {
+        let index = (self.sent % self.schedule.payloads.len() as u64) as usize;
+        let mut payload = self.schedule.payloads[index].clone();
+        if let Some(offset) = self.schedule.counter_offset {
+            payload[offset] = self.sent as u8;
+        }
+        self.sent += 1;
+
+        self.next_due += self.schedule.interval;
+        if now.saturating_duration_since(self.next_due) > self.schedule.interval {
+            self.next_due = now + self.schedule.interval;
+        }
+        payload
+    }
+
+    /// Progress for a schedule that ended early, by request or on an error.
+    pub fn stopped(&self) -> PeriodicProgress {
+        PeriodicProgress {
+            done: true,
+            ..self.progress()
+        }
+    }
+
+    pub fn progress(&self) -> PeriodicProgress {
+        let remaining = self
+            .schedule
+            .count
+            .map(|count| count.saturating_sub(self.sent));
+        PeriodicProgress {
+            sent: self.sent,
+            remaining,
+            done: remaining == Some(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVAL: Duration = Duration::from_millis(10);
+
+    fn schedule(
+        payloads: Vec<Vec<u8>>,
+        count: Option<u64>,
+        counter: Option<usize>,
+    ) -> PeriodicSend {
+        let schedule = PeriodicSchedule::new(payloads, INTERVAL, count, counter).unwrap();
+        PeriodicSend::start(schedule, Instant::now())
+    }
+
+    #[test]
+    fn rotates_payloads_and_wraps_the_counter() {
+        let mut send = schedule(vec![vec![0xA0, 0, 0xA1], vec![0xB0, 0]], None, Some(1));
+        let now = send.next_due();
+        assert_eq!(send.take_payload(now), [0xA0, 0, 0xA1]);
+        assert_eq!(send.take_payload(now), [0xB0, 1]);
+        assert_eq!(send.take_payload(now), [0xA0, 2, 0xA1]);
+        for _ in 3..256 {
+            send.take_payload(now);
+        }
+        assert_eq!(send.progress().sent, 256);
+        // The counter byte wraps after 255.
+        assert_eq!(send.take_payload(now), [0xA0, 0, 0xA1]);
+        assert_eq!(send.take_payload(now), [0xB0, 1]);
+    }
+
+    #[test]
+    fn keeps_the_grid_and_skips_missed_slots() {
+        let mut send = schedule(vec![vec![1]], None, None);
+        let start = send.next_due();
+
+        // A send up to one interval late keeps the original grid.
+        send.take_payload(start + Duration::from_millis(9));
+        assert_eq!(send.next_due(), start + INTERVAL);
+        send.take_payload(start + Duration::from_millis(20));
+        assert_eq!(send.next_due(), start + 2 * INTERVAL);
+
+        // Falling further behind moves on from now instead of bursting
+        // through every missed slot.
+        let late = start + Duration::from_millis(500);
+        send.take_payload(late);
+        assert_eq!(send.next_due(), late + INTERVAL);
+        assert_eq!(send.progress().sent, 3);
+    }
+
+    #[test]
+    fn counts_down_to_done() {
+        let mut send = schedule(vec![vec![1]], Some(2), None);
+        let now = send.next_due();
+        send.take_payload(now);
+        let progress = send.progress();
+        assert_eq!(
+            (progress.sent, progress.remaining, progress.done),
+            (1, Some(1), false)
+        );
+        assert!(send.stopped().done);
+        send.take_payload(now);
+        let progress = send.progress();
+        assert_eq!((progress.remaining, progress.done), (Some(0), true));
+    }
+
+    #[test]
+    fn rejects_bad_schedules() {
+        let new = PeriodicSchedule::new;
+        assert!(new(vec![], INTERVAL, None, None).is_err());
+        assert!(new(vec![vec![]], INTERVAL, None, None).is_err());
+        assert!(new(vec![vec![1]; 257], INTERVAL, None, None).is_err());
+        assert!(new(vec![vec![1]], Duration::ZERO, None, None).is_err());
+        assert!(new(vec![vec![1]], INTERVAL, Some(0), None).is_err());
+        assert!(new(vec![vec![1, 2], vec![1]], INTERVAL, None, Some(1)).is_err());
+    }
+}
@@ -0,0 +1,306 @@
+use serialport::{available_ports, SerialPortInfo, SerialPortType};
+use std::collections::HashMap;
+use std::thread;
+use std::time::Duration;
+use tauri::Emitter;
+
+const WATCH_INTERVAL: Duration = Duration::from_millis(1000);
+
+const VID_SILABS: u16 = 0x10C4;
+const VID_WCH: u16 = 0x1A86;
+const VID_FTDI: u16 = 0x0403;
+const VID_ESPRESSIF: u16 = 0x303A;
+
+const PID_USB_JTAG_SERIAL: u16 = 0x1001;
+const PID_ESP32S2_CDC: u16 = 0x0002;
+const PID_ESP32S3_CDC: u16 = 0x0009;
+
+#[derive(serde::Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct SerialPortEntry {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub port_type: String,
+    pub vid: Option<u16>,
+    pub pid: Option<u16>,
+    pub serial_number: Option<String>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub interface: Option<u8>,
+    pub bridge: Option<String>,
+    /// The chip's own USB peripheral rather than an external UART bridge.
+    /// These ports re-enumerate on reset and use a different reset sequence.
+    pub native_usb: bool,
+}
+
+/// Maps well known USB-UART bridges and Espressif native USB devices to a
+/// readable label.
+pub fn bridge_label(vid: u16, pid: u16) -> Option<&'static str> {
+    let label = match (vid, pid) {
+        (VID_SILABS, 0xEA60) => "CP2102",
+        (VID_SILABS, 0xEA70) => "CP2105",
+        (VID_SILABS, 0xEA71) => "CP2108",
+        (VID_SILABS, _) => "CP210x",
+        (VID_WCH, 0x7523) => "CH340",
+        (VID_WCH, 0x7522) => "CH340K",
+        (VID_WCH, 0x5523) => "CH341",
+        (VID_WCH, 0x55D3) => "CH343",
+        (VID_WCH, 0x55D4) => "CH9102",
+        (VID_WCH, _) => "CH34x",
+        (VID_FTDI, 0x6001) => "FTDI FT232R",
+        (VID_FTDI, 0x6010) => "FTDI FT2232",
+        (VID_FTDI, 0x6011) => "FTDI FT4232",
+        (VID_FTDI, 0x6014) => "FTDI FT232H",
+        (VID_FTDI, 0x6015) => "FTDI FT231X",
+        (VID_FTDI, _) => "FTDI",
+        (VID_ESPRESSIF, PID_USB_JTAG_SERIAL) => "Espressif USB-JTAG-Serial",
+        (VID_ESPRESSIF, PID_ESP32S2_CDC) => "ESP32-S2 USB CDC",
+        (VID_ESPRESSIF, PID_ESP32S3_CDC) => "ESP32-S3 USB CDC",
+        (VID_ESPRESSIF, _) => "Espressif USB CDC",
+        _ => return None,
+    };
+    Some(label)
+}
+
+impl From<&SerialPortInfo> for SerialPortEntry {
+    fn from(info: &SerialPortInfo) -> Self {
+        let mut entry = SerialPortEntry {
+            name: info.port_name.clone(),
+            port_type: "unknown".to_string(),
+            vid: None,
+            pid: None,
+            serial_number: None,
+            manufacturer: None,
+            product: None,
+            interface: None,
+            bridge: None,
+            native_usb: false,
+        };
+
+        match &info.port_type {
+            SerialPortType::UsbPort(usb) => {
+                entry.port_type = "usb".to_string();
+                entry.vid = Some(usb.vid);
+                entry.pid = Some(usb.pid);
+                entry.serial_number = usb.serial_number.clone();
+                entry.manufacturer = usb.manufacturer.clone();
+                entry.product = usb.product.clone();
+                entry.interface = usb.interface;
+                entry.bridge = bridge_label(usb.vid, usb.pid).map(str::to_string);
+                entry.native_usb = usb.vid == VID_ESPRESSIF;
+            }
+            SerialPortType::PciPort => entry.port_type = "pci".to_string(),
+            SerialPortType::BluetoothPort => entry.port_type = "bluetooth".to_string(),
+            SerialPortType::Unknown => {}
+        }
+        entry
+    }
+}
+
+pub fn list_ports() -> Result<Vec<SerialPortEntry>, String> {
+    let ports = available_ports().map_err(|e| format!("failed to list serial ports: {e}"))?;
+    Ok(ports.iter().map(SerialPortEntry::from).collect())
+}
+
+pub fn port_exists(name: &str) -> bool {
+    available_ports()
+        .map(|ports| ports.iter().any(|port| port.port_name == name))
+        .unwrap_or(true)
+}
+
+/// Picks the port a device came back on after a reset or re-enumeration.
+/// A USB serial number identifies the board even when it returns under a new
+/// name or PID (ROM download mode vs. the app's CDC). Without one, the old
+/// name with the same VID:PID is taken, or failing that the only port with
+/// that VID:PID; with two identical bridges there is no telling which board
+/// is which, so none is picked.
+pub fn find_reappeared<'a>(
+    original: &SerialPortEntry,
+    ports: &'a [SerialPortEntry],
+) -> Option<&'a SerialPortEntry> {
+    if original.serial_number.is_some() {
+        return ports.iter().find(|port| {
+            port.vid == original.vid
+                && port.serial_number == original.serial_number
+                && port.interface == original.interface
+        });
+    }
+    let same_device = |port: &&SerialPortEntry| {
+        port.vid == original.vid && port.pid == original.pid && port.interface == original.interface
+    };
+    let by_name = ports
+        .iter()
+        .filter(same_device)
+        .find(|port| port.name == original.name);
+    if by_name.is_some() || original.vid.is_none() {
+        return by_name;
+    }
+    let mut candidates = ports.iter().filter(same_device);
+    match (candidates.next(), candidates.next()) {
+        (Some(port), None) => Some(port),
+        _ => None,
+    }
+}
+
+/// Polls the port list in the background and emits `serial_port_added` /
+/// `serial_port_removed` with the port record. A port that keeps its name
+/// but changes identity (another board on the same COM number) is reported
+/// as removed and added again. `on_removed` runs for ports gone since the
+/// last poll; a device that drops and re-enumerates under the same name
+/// within one poll is never seen missing, which is why session readers end
+/// their own sessions on read errors.
+pub fn spawn_watcher<F>(app: tauri::AppHandle, on_removed: F)
+where
+    F: Fn(&SerialPortEntry) + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut known = snapshot().unwrap_or_default();
+        loop {
+            thread::sleep(WATCH_INTERVAL);
+            let Ok(current) = snapshot() else {
+                continue;
+            };
+
+            for (name, entry) in &known {
+                // A changed entry under the same name is reported as removed
+                // and re-added so the lists refresh, but the port itself is
+                // still there and open sessions on it stay up.
+                if !current.contains_key(name) {
+                    on_removed(entry);
+                }
+                if current.get(name) != Some(entry) {
+                    let _ = app.emit("serial_port_removed", entry.clone());
+                }
+            }
+            for (name, entry) in &current {
+                if known.get(name) != Some(entry) {
+                    let _ = app.emit("serial_port_added", entry.clone());
+                }
+            }
+            known = current;
+        }
+    });
+}
+
+fn snapshot() -> Result<HashMap<String, SerialPortEntry>, String> {
+    Ok(list_ports()?
+        .into_iter()
+        .map(|entry| (entry.name.clone(), entry))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_known_bridges() {
+        let cases = [
+            (0x10C4, 0xEA60, Some("CP2102")),
+            (0x10C4, 0xEA63, Some("CP210x")),
+            (0x1A86, 0x7523, Some("CH340")),
+            (0x1A86, 0x55D4, Some("CH9102")),
+            (0x1A86, 0x1234, Some("CH34x")),
+            (0x0403, 0x6001, Some("FTDI FT232R")),
+            (0x0403, 0x6014, Some("FTDI FT232H")),
+            (0x0403, 0x0000, Some("FTDI")),
+            (0x303A, 0x1001, Some("Espressif USB-JTAG-Serial")),
+            (0x303A, 0x0009, Some("ESP32-S3 USB CDC")),
+            (0x303A, 0x4001, Some("Espressif USB CDC")),
+            (0x2341, 0x0043, None),
+        ];
+        for (vid, pid, label) in cases {
+            assert_eq!(bridge_label(vid, pid), label, "{vid:04X}:{pid:04X}");
+        }
+    }
+
+    fn usb(name: &str, vid: u16, pid: u16, serial: Option<&str>) -> SerialPortEntry {
+        SerialPortEntry {
+            name: name.to_string(),
+            port_type: "usb".to_string(),
+            vid: Some(vid),
+            pid: Some(pid),
+            serial_number: serial.map(str::to_string),
+            manufacturer: None,
+            product: None,
+            interface: None,
+            bridge: bridge_label(vid, pid).map(str::to_string),
+            native_usb: vid == VID_ESPRESSIF,
+        }
+    }
+
+    #[test]
+    fn finds_reappeared_ports() {
+        let s3 = usb("/dev/ttyACM0", 0x303A, 0x1001, Some("F4:12:FA:00:11:22"));
+        let ch340 = usb("/dev/ttyUSB0", 0x1A86, 0x7523, None);
+        let other_s3 = usb("/dev/ttyACM0", 0x303A, 0x1001, Some("F4:12:FA:33:44:55"));
+        let cases = [
+            (
+                "same name",
+                &ch340,
+                vec![ch340.clone()],
+                Some("/dev/ttyUSB0"),
+            ),
+            (
+                "renamed, matched by serial number across a PID change",
+                &s3,
+                vec![
+                    other_s3.clone(),
+                    usb("/dev/ttyACM1", 0x303A, 0x0009, Some("F4:12:FA:00:11:22")),
+                ],
+                Some("/dev/ttyACM1"),
+            ),
+            (
+                "another board took the name",
+                &s3,
+                vec![other_s3.clone()],
+                None,
+            ),
+            (
+                "old name preferred over an identical bridge",
+                &ch340,
+                vec![usb("/dev/ttyUSB1", 0x1A86, 0x7523, None), ch340.clone()],
+                Some("/dev/ttyUSB0"),
+            ),
+            (
+                "renamed, the only bridge of its kind",
+                &ch340,
+                vec![
+                    usb("/dev/ttyUSB1", 0x1A86, 0x7523, None),
+                    usb("/dev/ttyUSB2", 0x10C4, 0xEA60, None),
+                ],
+                Some("/dev/ttyUSB1"),
+            ),
+            (
+                "renamed, two identical bridges",
+                &ch340,
+                vec![
+                    usb("/dev/ttyUSB1", 0x1A86, 0x7523, None),
+                    usb("/dev/ttyUSB2", 0x1A86, 0x7523, None),
+                ],
+                None,
+            ),
+            ("gone", &ch340, vec![], None),
+        ];
+        for (case, original, ports, expected) in cases {
+            let found = find_reappeared(original, &ports).map(|port| port.name.as_str());
+            assert_eq!(found, expected, "{case}");
+        }
+    }
+
+    #[test]
+    fn finds_non_usb_ports_by_name_only() {
+        let uart = SerialPortEntry {
+            port_type: "unknown".to_string(),
+            vid: None,
+            pid: None,
+            ..usb("/dev/ttyS0", 0, 0, None)
+        };
+        let renamed = SerialPortEntry {
+            name: "/dev/ttyS1".to_string(),
+            ..uart.clone()
+        };
+        assert!(find_reappeared(&uart, std::slice::from_ref(&renamed)).is_none());
+        let ports = [renamed, uart.clone()];
+        assert_eq!(find_reappeared(&uart, &ports).unwrap().name, "/dev/ttyS0");
+    }
+}
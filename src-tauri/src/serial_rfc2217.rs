@@ -0,0 +1,855 @@
+use crate::serial_assistant::{LineSettings, ModemSignals};
+use serialport::{DataBits, FlowControl, Parity, StopBits};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const IAC: u8 = 255;
+pub const DONT: u8 = 254;
+pub const DO: u8 = 253;
+pub const WONT: u8 = 252;
+pub const WILL: u8 = 251;
+pub const SB: u8 = 250;
+pub const SE: u8 = 240;
+
+pub const BINARY: u8 = 0;
+pub const SGA: u8 = 3;
+pub const COM_PORT_OPTION: u8 = 44;
+
+// COM-PORT-OPTION commands as sent by the client. The server answers with
+// the same command plus SERVER_OFFSET.
+pub const SIGNATURE: u8 = 0;
+pub const SET_BAUDRATE: u8 = 1;
+pub const SET_DATASIZE: u8 = 2;
+pub const SET_PARITY: u8 = 3;
+pub const SET_STOPSIZE: u8 = 4;
+pub const SET_CONTROL: u8 = 5;
+pub const NOTIFY_LINESTATE: u8 = 6;
+pub const NOTIFY_MODEMSTATE: u8 = 7;
+pub const FLOWCONTROL_SUSPEND: u8 = 8;
+pub const FLOWCONTROL_RESUME: u8 = 9;
+pub const SET_LINESTATE_MASK: u8 = 10;
+pub const SET_MODEMSTATE_MASK: u8 = 11;
+pub const PURGE_DATA: u8 = 12;
+pub const SERVER_OFFSET: u8 = 100;
+
+// SET-CONTROL values.
+pub const CONTROL_FLOW_QUERY: u8 = 0;
+pub const CONTROL_FLOW_NONE: u8 = 1;
+pub const CONTROL_FLOW_XONXOFF: u8 = 2;
+pub const CONTROL_FLOW_HARDWARE: u8 = 3;
+pub const CONTROL_BREAK_QUERY: u8 = 4;
+pub const CONTROL_BREAK_ON: u8 = 5;
+pub const CONTROL_BREAK_OFF: u8 = 6;
+pub const CONTROL_DTR_QUERY: u8 = 7;
+pub const CONTROL_DTR_ON: u8 = 8;
+pub const CONTROL_DTR_OFF: u8 = 9;
+pub const CONTROL_RTS_QUERY: u8 = 10;
+pub const CONTROL_RTS_ON: u8 = 11;
+pub const CONTROL_RTS_OFF: u8 = 12;
+
+const PURGE_RECEIVE: u8 = 1;
+const PURGE_BOTH: u8 = 3;
+
+const SIGNATURE_TEXT: &str = "wheat-esp-tools";
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(50);
+/// How often modem lines are sampled for a client that asked to be told.
+const MODEM_NOTIFY_INTERVAL: Duration = Duration::from_millis(200);
+
+/// One piece of a Telnet stream, in the order it arrived.
+#[derive(Debug, PartialEq, Eq)]
+pub enum TelnetItem {
+    Data(Vec<u8>),
+    Negotiate { command: u8, option: u8 },
+    Subnegotiate { option: u8, data: Vec<u8> },
+}
+
+#[derive(Clone, Copy, Default)]
+enum ParserState {
+    #[default]
+    Data,
+    Iac,
+    Negotiate(u8),
+    Sub,
+    SubIac,
+}
+
+/// Splits a Telnet stream into data and option commands. Commands may be
+/// split across reads; doubled IAC bytes are unescaped.
+#[derive(Default)]
+pub struct TelnetParser {
+    state: ParserState,
+    data: Vec<u8>,
+    sub: Vec<u8>,
+}
+
+impl TelnetParser {
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<TelnetItem> {
+        let mut items = Vec::new();
+        for &byte in bytes {
+            self.state = match (self.state, byte) {
+                (ParserState::Data, IAC) => ParserState::Iac,
+                (ParserState::Data, _) => {
+                    self.data.push(byte);
+                    ParserState::Data
+                }
+                (ParserState::Iac, IAC) => {
+                    self.data.push(IAC);
+                    ParserState::Data
+                }
+                (ParserState::Iac, WILL | WONT | DO | DONT) => ParserState::Negotiate(byte),
+                (ParserState::Iac, SB) => {
+                    self.sub.clear();
+                    ParserState::Sub
+                }
+                // NOP, GA and the other bare commands carry nothing we use.
+                (ParserState::Iac, _) => ParserState::Data,
+                (ParserState::Negotiate(command), option) => {
+                    self.flush_data(&mut items);
+                    items.push(TelnetItem::Negotiate { command, option });
+                    ParserState::Data
+                }
+                (ParserState::Sub, IAC) => ParserState::SubIac,
+                (ParserState::Sub, _) => {
+                    self.sub.push(byte);
+                    ParserState::Sub
+                }
+                (ParserState::SubIac, IAC) => {
+                    self.sub.push(IAC);
+                    ParserState::Sub
+                }
+                (ParserState::SubIac, SE) => {
+                    self.flush_data(&mut items);
+                    if let Some((&option, data)) = self.sub.split_first() {
+                        items.push(TelnetItem::Subnegotiate {
+                            option,
+                            data: data.to_vec(),
+                        });
+                    }
+                    ParserState::Data
+                }
+                (ParserState::SubIac, _) => ParserState::Data,
+            };
+        }
+        self.flush_data(&mut items);
+        items
+    }
+
+    fn flush_data(&mut self, items: &mut Vec<TelnetItem>) {
+        if !self.data.is_empty() {
+            items.push(TelnetItem::Data(std::mem::take(&mut self.data)));
+        }
+    }
+}
+
+/// Doubles IAC bytes so `data` passes through Telnet unchanged.
+pub fn escape(data: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(data.len());
+    for &byte in data {
+        escaped.push(byte);
+        if byte == IAC {
+            escaped.push(IAC);
+        }
+    }
+    escaped
+}
+
+/// Options enabled on each side of a connection. Binary mode,
+/// suppress-go-ahead and COM-PORT-OPTION are agreed to in both directions and
+/// everything else is refused. Only state changes are answered, so the two
+/// sides cannot loop.
+#[derive(Default)]
+pub struct TelnetOptions {
+    /// Bit masks of options we WILL and they WILL.
+    local: u64,
+    remote: u64,
+}
+
+impl TelnetOptions {
+    /// Records that we asked for `command` (WILL or DO) ourselves, and
+    /// returns the bytes to send.
+    pub fn request(&mut self, command: u8, option: u8) -> [u8; 3] {
+        let bit = 1u64 << option;
+        if command == WILL {
+            self.local |= bit;
+        } else {
+            self.remote |= bit;
+        }
+        [IAC, command, option]
+    }
+
+    /// Whether the other side agreed to `option`.
+    pub fn remote_enabled(&self, option: u8) -> bool {
+        option < 64 && self.remote & (1u64 << option) != 0
+    }
+
+    /// Handles a WILL/WONT/DO/DONT from the other side and returns the
+    /// reply to send, if any.
+    pub fn negotiate(&mut self, command: u8, option: u8) -> Option<[u8; 3]> {
+        let supported = matches!(option, BINARY | SGA | COM_PORT_OPTION);
+        let bit = if supported { 1u64 << option } else { 0 };
+        let reply = match command {
+            WILL if !supported => Some(DONT),
+            DO if !supported => Some(WONT),
+            WILL => (self.remote & bit == 0).then_some(DO),
+            DO => (self.local & bit == 0).then_some(WILL),
+            WONT => (self.remote & bit != 0).then_some(DONT),
+            DONT => (self.local & bit != 0).then_some(WONT),
+            _ => None,
+        };
+        match command {
+            WILL => self.remote |= bit,
+            DO => self.local |= bit,
+            WONT => self.remote &= !bit,
+            DONT => self.local &= !bit,
+            _ => {}
+        }
+        reply.map(|reply| [IAC, reply, option])
+    }
+}
+
+/// A COM-PORT-OPTION subnegotiation carrying `command` and `value`.
+pub fn com_port_command(command: u8, value: &[u8]) -> Vec<u8> {
+    let mut message = vec![IAC, SB, COM_PORT_OPTION, command];
+    message.extend(escape(value));
+    message.extend([IAC, SE]);
+    message
+}
+
+pub fn encode_data_bits(bits: DataBits) -> u8 {
+    match bits {
+        DataBits::Five => 5,
+        DataBits::Six => 6,
+        DataBits::Seven => 7,
+        DataBits::Eight => 8,
+    }
+}
+
+pub fn decode_data_bits(value: u8) -> Option<DataBits> {
+    match value {
+        5 => Some(DataBits::Five),
+        6 => Some(DataBits::Six),
+        7 => Some(DataBits::Seven),
+        8 => Some(DataBits::Eight),
+        _ => None,
+    }
+}
+
+/// Mark (4) and space (5) parity exist in RFC 2217 but not in `serialport`.
+pub fn encode_parity(parity: Parity) -> u8 {
+    match parity {
+        Parity::None => 1,
+        Parity::Odd => 2,
+        Parity::Even => 3,
+    }
+}
+
+pub fn decode_parity(value: u8) -> Option<Parity> {
+    match value {
+        1 => Some(Parity::None),
+        2 => Some(Parity::Odd),
+        3 => Some(Parity::Even),
+        _ => None,
+    }
+}
+
+/// 1.5 stop bits (3) is not supported by `serialport`.
+pub fn encode_stop_bits(bits: StopBits) -> u8 {
+    match bits {
+        StopBits::One => 1,
+        StopBits::Two => 2,
+    }
+}
+
+pub fn decode_stop_bits(value: u8) -> Option<StopBits> {
+    match value {
+        1 => Some(StopBits::One),
+        2 => Some(StopBits::Two),
+        _ => None,
+    }
+}
+
+pub fn encode_flow_control(flow_control: FlowControl) -> u8 {
+    match flow_control {
+        FlowControl::None => CONTROL_FLOW_NONE,
+        FlowControl::Software => CONTROL_FLOW_XONXOFF,
+        FlowControl::Hardware => CONTROL_FLOW_HARDWARE,
+    }
+}
+
+pub fn decode_flow_control(value: u8) -> Option<FlowControl> {
+    match value {
+        CONTROL_FLOW_NONE => Some(FlowControl::None),
+        CONTROL_FLOW_XONXOFF => Some(FlowControl::Software),
+        CONTROL_FLOW_HARDWARE => Some(FlowControl::Hardware),
+        _ => None,
+    }
+}
+
+/// The NOTIFY-MODEMSTATE byte: line states in the high nibble, changes since
+/// `previous` in the low one.
+pub fn encode_modem_state(signals: ModemSignals, previous: Option<ModemSignals>) -> u8 {
+    let previous = previous.unwrap_or(signals);
+    let mut state = 0;
+    for (level, was, line_bit, delta_bit) in [
+        (signals.cts, previous.cts, 0x10, 0x01),
+        (signals.dsr, previous.dsr, 0x20, 0x02),
+        (signals.ri, previous.ri, 0x40, 0x04),
+        (signals.cd, previous.cd, 0x80, 0x08),
+    ] {
+        if level {
+            state |= line_bit;
+        }
+        if level != was {
+            state |= delta_bit;
+        }
+    }
+    state
+}
+
+pub fn decode_modem_state(state: u8) -> ModemSignals {
+    ModemSignals {
+        cts: state & 0x10 != 0,
+        dsr: state & 0x20 != 0,
+        ri: state & 0x40 != 0,
+        cd: state & 0x80 != 0,
+    }
+}
+
+/// The serial side of the server: whatever a remote client asks for is
+/// applied through this.
+pub trait ComPort {
+    fn write(&mut self, data: &[u8]) -> Result<(), String>;
+    fn settings(&mut self) -> Result<LineSettings, String>;
+    fn apply(&mut self, settings: LineSettings) -> Result<(), String>;
+    fn set_dtr(&mut self, level: bool) -> Result<(), String>;
+    fn set_rts(&mut self, level: bool) -> Result<(), String>;
+    fn set_break(&mut self, on: bool) -> Result<(), String>;
+    /// `None` when the port cannot report its input lines.
+    fn modem(&mut self) -> Option<ModemSignals>;
+}
+
+pub enum ServerEvent {
+    Connected(SocketAddr),
+    Disconnected(SocketAddr),
+    /// A request that could not be applied, or a connection that failed.
+    /// The client is told the value still in effect.
+    Warning(String),
+}
+
+/// Serves one client at a time until `cancel` is set or `rx`, the session's
+/// RX stream, hangs up. Further connections wait in the listen backlog.
+pub fn serve(
+    listener: TcpListener,
+    port: &mut impl ComPort,
+    rx: mpsc::Receiver<Vec<u8>>,
+    cancel: &AtomicBool,
+    mut on_event: impl FnMut(ServerEvent),
+) -> Result<(), String> {
+    listener
+        .set_nonblocking(true)
+        .map_err(|e| format!("failed to configure RFC 2217 listener: {e}"))?;
+
+    while !cancel.load(Ordering::SeqCst) {
+        let (stream, peer) = match listener.accept() {
+            Ok(accepted) => accepted,
+            Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                // Nobody is listening, so RX received meanwhile is dropped.
+                loop {
+                    match rx.try_recv() {
+                        Ok(_) => {}
+                        Err(TryRecvError::Empty) => break,
+                        Err(TryRecvError::Disconnected) => return Ok(()),
+                    }
+                }
+                thread::sleep(ACCEPT_INTERVAL);
+                continue;
+            }
+            Err(err) => return Err(format!("failed to accept RFC 2217 client: {err}")),
+        };
+
+        on_event(ServerEvent::Connected(peer));
+        let result = Session::new(stream, port, &rx, cancel, &mut on_event)
+            .and_then(|mut session| session.run());
+        on_event(ServerEvent::Disconnected(peer));
+        if let Err(err) = result {
+            on_event(ServerEvent::Warning(err));
+        }
+        if matches!(rx.try_recv(), Err(TryRecvError::Disconnected)) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// State of one client connection.
+struct Session<'a, P: ComPort, F: FnMut(ServerEvent)> {
+    stream: TcpStream,
+    port: &'a mut P,
+    rx: &'a mpsc::Receiver<Vec<u8>>,
+    cancel: &'a AtomicBool,
+    on_event: &'a mut F,
+    parser: TelnetParser,
+    options: TelnetOptions,
+    dtr: bool,
+    rts: bool,
+    breaking: bool,
+    modem_mask: u8,
+    modem_sent: Option<ModemSignals>,
+    modem_checked_at: Option<Instant>,
+    /// Set once the client asks for COM-PORT-OPTION, which is when modem
+    /// notifications become meaningful to it.
+    com_port: bool,
+}
+
+impl<'a, P: ComPort, F: FnMut(ServerEvent)> Session<'a, P, F> {
+    fn new(
+        stream: TcpStream,
+        port: &'a mut P,
+        rx: &'a mpsc::Receiver<Vec<u8>>,
+        cancel: &'a AtomicBool,
+        on_event: &'a mut F,
+    ) -> Result<Self, String> {
+        stream
+            .set_nonblocking(false)
+            .and_then(|_| stream.set_read_timeout(Some(POLL_INTERVAL)))
+            .and_then(|_| stream.set_nodelay(true))
+            .map_err(|e| format!("failed to configure RFC 2217 client socket: {e}"))?;
+        let mut session = Self {
+            stream,
+            port,
+            rx,
+            cancel,
+            on_event,
+            parser: TelnetParser::default(),
+            options: TelnetOptions::default(),
+            dtr: false,
+            rts: false,
+            breaking: false,
+            modem_mask: 0xff,
+            modem_sent: None,
+            modem_checked_at: None,
+            com_port: false,
+        };
+        for (command, option) in [
+            (WILL, BINARY),
+            (DO, BINARY),
+            (WILL, SGA),
+            (WILL, COM_PORT_OPTION),
+        ] {
+            let request = session.options.request(command, option);
+            session.send(&request)?;
+        }
+        Ok(session)
+    }
+
+    fn run(&mut self) -> Result<(), String> {
+        let mut buffer = [0u8; 4096];
+        while !self.cancel.load(Ordering::SeqCst) {
+            loop {
+                match self.rx.try_recv() {
+                    Ok(data) => self.send(&escape(&data))?,
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                }
+            }
+
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Ok(()),
+                Ok(size) => {
+                    for item in self.parser.push(&buffer[..size]) {
+                        self.handle(item)?;
+                    }
+                }
+                Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(format!("RFC 2217 client read failed: {err}")),
+            }
+
+            self.notify_modem(false)?;
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, bytes: &[u8]) -> Result<(), String> {
+        self.stream
+            .write_all(bytes)
+            .map_err(|e| format!("RFC 2217 client write failed: {e}"))
+    }
+
+    fn reply(&mut self, command: u8, value: &[u8]) -> Result<(), String> {
+        self.send(&com_port_command(command + SERVER_OFFSET, value))
+    }
+
+    fn reject(&mut self, message: String) {
+        (self.on_event)(ServerEvent::Warning(message));
+    }
+
+    fn handle(&mut self, item: TelnetItem) -> Result<(), String> {
+        match item {
+            TelnetItem::Data(data) => self.port.write(&data),
+            TelnetItem::Negotiate { command, option } => self.negotiate(command, option),
+            TelnetItem::Subnegotiate { option, data } if option == COM_PORT_OPTION => {
+                match data.split_first() {
+                    Some((&command, value)) => self.com_port_command(command, value),
+                    None => Ok(()),
+                }
+            }
+            TelnetItem::Subnegotiate { .. } => Ok(()),
+        }
+    }
+
+    fn negotiate(&mut self, command: u8, option: u8) -> Result<(), String> {
+        if option == COM_PORT_OPTION && matches!(command, WILL | DO) {
+            self.com_port = true;
+        }
+        match self.options.negotiate(command, option) {
+            Some(reply) => self.send(&reply),
+            None => Ok(()),
+        }
+    }
+
+    fn com_port_command(&mut self, command: u8, value: &[u8]) -> Result<(), String> {
+        match command {
+            SIGNATURE => self.reply(SIGNATURE, SIGNATURE_TEXT.as_bytes()),
+            SET_BAUDRATE => {
+                let requested = value.get(..4).map_or(0, |bytes| {
+                    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+                });
+                let settings = self.update_settings(requested != 0, |settings| {
+                    settings.baud_rate = requested;
+                    Ok(())
+                })?;
+                self.reply(SET_BAUDRATE, &settings.baud_rate.to_be_bytes())
+            }
+            SET_DATASIZE => {
+                let requested = value.first().copied().unwrap_or(0);
+                let settings = self.update_settings(requested != 0, |settings| {
+                    settings.data_bits = decode_data_bits(requested)
+                        .ok_or_else(|| format!("unsupported data size {requested}"))?;
+                    Ok(())
+                })?;
+                self.reply(SET_DATASIZE, &[encode_data_bits(settings.data_bits)])
+            }
+            SET_PARITY => {
+                let requested = value.first().copied().unwrap_or(0);
+                let settings = self.update_settings(requested != 0, |settings| {
+                    settings.parity = decode_parity(requested)
+                        .ok_or_else(|| format!("unsupported parity {requested}"))?;
+                    Ok(())
+                })?;
+                self.reply(SET_PARITY, &[encode_parity(settings.parity)])
+            }
+            SET_STOPSIZE => {
+                let requested = value.first().copied().unwrap_or(0);
+                let settings = self.update_settings(requested != 0, |settings| {
+                    settings.stop_bits = decode_stop_bits(requested)
+                        .ok_or_else(|| format!("unsupported stop size {requested}"))?;
+                    Ok(())
+                })?;
+                self.reply(SET_STOPSIZE, &[encode_stop_bits(settings.stop_bits)])
+            }
+            SET_CONTROL => {
+                let answer = self.set_control(value.first().copied().unwrap_or(0))?;
+                self.reply(SET_CONTROL, &[answer])
+            }
+            NOTIFY_MODEMSTATE => self.notify_modem(true),
+            SET_LINESTATE_MASK => self.reply(SET_LINESTATE_MASK, value),
+            SET_MODEMSTATE_MASK => {
+                self.modem_mask = value.first().copied().unwrap_or(0xff);
+                self.reply(SET_MODEMSTATE_MASK, &[self.modem_mask])
+            }
+            PURGE_DATA => {
+                let which = value.first().copied().unwrap_or(PURGE_BOTH);
+                if matches!(which, PURGE_RECEIVE | PURGE_BOTH) {
+                    while self.rx.try_recv().is_ok() {}
+                }
+                self.reply(PURGE_DATA, &[which])
+            }
+            // Writes to the port block until drained, so there is nothing
+            // buffered here to suspend.
+            FLOWCONTROL_SUSPEND | FLOWCONTROL_RESUME | NOTIFY_LINESTATE => Ok(()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Applies `change` to the current settings when `apply` is set and
+    /// returns whatever is in effect afterwards.
+    fn update_settings(
+        &mut self,
+        apply: bool,
+        change: impl FnOnce(&mut LineSettings) -> Result<(), String>,
+    ) -> Result<LineSettings, String> {
+        let current = self.port.settings()?;
+        if !apply {
+            return Ok(current);
+        }
+        let mut settings = current;
+        let result = change(&mut settings).and_then(|_| {
+            if settings == current {
+                Ok(())
+            } else {
+                self.port.apply(settings)
+            }
+        });
+        match result {
+            Ok(()) => Ok(settings),
+            Err(err) => {
+                self.reject(err);
+                Ok(current)
+            }
+        }
+    }
+
+    /// Handles a SET-CONTROL value and returns the one to answer with.
+    fn set_control(&mut self, value: u8) -> Result<u8, String> {
+        let result = match value {
+            CONTROL_FLOW_QUERY => {
+                return Ok(encode_flow_control(self.port.settings()?.flow_control));
+            }
+            CONTROL_FLOW_NONE | CONTROL_FLOW_XONXOFF | CONTROL_FLOW_HARDWARE => {
+                let settings = self.update_settings(true, |settings| {
+                    settings.flow_control = decode_flow_control(value).unwrap_or(FlowControl::None);
+                    Ok(())
+                })?;
+                return Ok(encode_flow_control(settings.flow_control));
+            }
+            CONTROL_BREAK_ON | CONTROL_BREAK_OFF => {
+                let on = value == CONTROL_BREAK_ON;
+                self.port.set_break(on).map(|_| self.breaking = on)
+            }
+            CONTROL_DTR_ON | CONTROL_DTR_OFF => {
+                let level = value == CONTROL_DTR_ON;
+                self.port.set_dtr(level).map(|_| self.dtr = level)
+            }
+            CONTROL_RTS_ON | CONTROL_RTS_OFF => {
+                let level = value == CONTROL_RTS_ON;
+                self.port.set_rts(level).map(|_| self.rts = level)
+            }
+            _ => Ok(()),
+        };
+        if let Err(err) = result {
+            self.reject(err);
+        }
+        Ok(match value {
+            CONTROL_BREAK_QUERY..=CONTROL_BREAK_OFF if self.breaking => CONTROL_BREAK_ON,
+            CONTROL_BREAK_QUERY..=CONTROL_BREAK_OFF => CONTROL_BREAK_OFF,
+            CONTROL_DTR_QUERY..=CONTROL_DTR_OFF if self.dtr => CONTROL_DTR_ON,
+            CONTROL_DTR_QUERY..=CONTROL_DTR_OFF => CONTROL_DTR_OFF,
+            CONTROL_RTS_QUERY..=CONTROL_RTS_OFF if self.rts => CONTROL_RTS_ON,
+            CONTROL_RTS_QUERY..=CONTROL_RTS_OFF => CONTROL_RTS_OFF,
+            _ => value,
+        })
+    }
+
+    /// Sends NOTIFY-MODEMSTATE when the lines changed, or right away when
+    /// the client asked for it.
+    fn notify_modem(&mut self, requested: bool) -> Result<(), String> {
+        if !requested
+            && (!self.com_port
+                || self
+                    .modem_checked_at
+                    .is_some_and(|at| at.elapsed() < MODEM_NOTIFY_INTERVAL))
+        {
+            return Ok(());
+        }
+        self.modem_checked_at = Some(Instant::now());
+        let Some(signals) = self.port.modem() else {
+            return if requested {
+                self.reply(NOTIFY_MODEMSTATE, &[0])
+            } else {
+                Ok(())
+            };
+        };
+        if !requested && self.modem_sent == Some(signals) {
+            return Ok(());
+        }
+        let state = encode_modem_state(signals, self.modem_sent) & self.modem_mask;
+        self.modem_sent = Some(signals);
+        self.reply(NOTIFY_MODEMSTATE, &[state])
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// A port that records what the server asks of it.
+    pub(crate) struct RecordingPort {
+        pub log: Arc<Mutex<Vec<String>>>,
+        pub settings: LineSettings,
+        pub modem: ModemSignals,
+    }
+
+    impl RecordingPort {
+        pub fn new(baud_rate: u32, modem: ModemSignals) -> Self {
+            Self {
+                log: Arc::default(),
+                settings: LineSettings::parse(baud_rate, 8, 1, "none", "none").unwrap(),
+                modem,
+            }
+        }
+
+        fn record(&self, entry: String) {
+            self.log.lock().unwrap().push(entry);
+        }
+    }
+
+    impl ComPort for RecordingPort {
+        fn write(&mut self, data: &[u8]) -> Result<(), String> {
+            self.record(format!("write {data:?}"));
+            Ok(())
+        }
+
+        fn settings(&mut self) -> Result<LineSettings, String> {
+            Ok(self.settings)
+        }
+
+        fn apply(&mut self, settings: LineSettings) -> Result<(), String> {
+            self.record(format!("baud {}", settings.baud_rate));
+            self.settings = settings;
+            Ok(())
+        }
+
+        fn set_dtr(&mut self, level: bool) -> Result<(), String> {
+            self.record(format!("dtr {level}"));
+            Ok(())
+        }
+
+        fn set_rts(&mut self, level: bool) -> Result<(), String> {
+            self.record(format!("rts {level}"));
+            Ok(())
+        }
+
+        fn set_break(&mut self, _on: bool) -> Result<(), String> {
+            Ok(())
+        }
+
+        fn modem(&mut self) -> Option<ModemSignals> {
+            Some(self.modem)
+        }
+    }
+
+    #[test]
+    fn parses_telnet_commands_split_across_reads() {
+        let mut parser = TelnetParser::default();
+        let mut items = parser.push(&[1, 2, IAC]);
+        items.extend(parser.push(&[IAC, 3, IAC, WILL, COM_PORT_OPTION, IAC, SB, 44, 1, 0, IAC]));
+        items.extend(parser.push(&[IAC, 0, 0, IAC, SE, 9]));
+        assert_eq!(
+            items,
+            vec![
+                TelnetItem::Data(vec![1, 2]),
+                TelnetItem::Data(vec![IAC, 3]),
+                TelnetItem::Negotiate {
+                    command: WILL,
+                    option: COM_PORT_OPTION
+                },
+                TelnetItem::Subnegotiate {
+                    option: COM_PORT_OPTION,
+                    data: vec![1, 0, IAC, 0, 0]
+                },
+                TelnetItem::Data(vec![9]),
+            ]
+        );
+        assert_eq!(escape(&[1, IAC]), vec![1, IAC, IAC]);
+    }
+
+    #[test]
+    fn negotiates_com_port_option_and_applies_requests() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let signals = ModemSignals {
+            cts: true,
+            dsr: false,
+            ri: false,
+            cd: true,
+        };
+        let mut port = RecordingPort::new(9600, signals);
+        let log = port.log.clone();
+        let (rx_tx, rx) = mpsc::channel();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let server_events = events.clone();
+        let server = thread::spawn(move || {
+            let cancel = AtomicBool::new(false);
+            serve(listener, &mut port, rx, &cancel, |event| {
+                server_events.lock().unwrap().push(match event {
+                    ServerEvent::Connected(_) => "connected".to_string(),
+                    ServerEvent::Disconnected(_) => "disconnected".to_string(),
+                    ServerEvent::Warning(warning) => warning,
+                })
+            })
+        });
+
+        let mut client = TcpStream::connect(address).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        let mut request = vec![IAC, WILL, COM_PORT_OPTION, IAC, DO, COM_PORT_OPTION];
+        request.extend(com_port_command(SET_BAUDRATE, &115200u32.to_be_bytes()));
+        request.extend(com_port_command(SET_CONTROL, &[CONTROL_DTR_ON]));
+        request.extend(com_port_command(SET_PARITY, &[4]));
+        request.extend(com_port_command(NOTIFY_MODEMSTATE, &[]));
+        request.extend([b'h', IAC, IAC]);
+        client.write_all(&request).unwrap();
+        thread::sleep(Duration::from_millis(150));
+        rx_tx.send(vec![IAC, b'x']).unwrap();
+
+        let mut parser = TelnetParser::default();
+        let mut items = Vec::new();
+        let mut buffer = [0u8; 1024];
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while Instant::now() < deadline {
+            if let Ok(count) = client.read(&mut buffer) {
+                items.extend(parser.push(&buffer[..count]));
+            }
+        }
+        let replies: Vec<Vec<u8>> = items
+            .iter()
+            .filter_map(|item| match item {
+                TelnetItem::Subnegotiate { data, .. } => Some(data.clone()),
+                _ => None,
+            })
+            .collect();
+        let mut baud_reply = vec![SERVER_OFFSET + SET_BAUDRATE];
+        baud_reply.extend(115200u32.to_be_bytes());
+        assert!(replies.contains(&baud_reply), "{replies:?}");
+        assert!(replies.contains(&vec![SERVER_OFFSET + SET_CONTROL, CONTROL_DTR_ON]));
+        // Mark parity is refused; the reply carries the parity still in use.
+        assert!(replies.contains(&vec![SERVER_OFFSET + SET_PARITY, 1]));
+        assert!(replies.contains(&vec![SERVER_OFFSET + NOTIFY_MODEMSTATE, 0x90]));
+        assert!(items.contains(&TelnetItem::Data(vec![IAC, b'x'])));
+        // The server offered WILL up front, so the client's DO is not acked again.
+        let will = TelnetItem::Negotiate {
+            command: WILL,
+            option: COM_PORT_OPTION,
+        };
+        assert_eq!(items.iter().filter(|item| **item == will).count(), 1);
+        assert!(items.contains(&TelnetItem::Negotiate {
+            command: DO,
+            option: COM_PORT_OPTION
+        }));
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                "baud 115200".to_string(),
+                "dtr true".to_string(),
+                format!("write {:?}", [b'h', IAC]),
+            ]
+        );
+
+        drop(client);
+        thread::sleep(Duration::from_millis(100));
+        drop(rx_tx);
+        server.join().unwrap().unwrap();
+        let events = events.lock().unwrap();
+        assert_eq!(events[0], "connected");
+        assert!(events[1].contains("unsupported parity"), "{events:?}");
+        assert_eq!(events[2], "disconnected");
+    }
+}
@@ -0,0 +1,678 @@
+use regex::bytes::Regex;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+/// Received text kept for `expect`; older bytes are dropped first.
+const MAX_BUFFER_LEN: usize = 64 * 1024;
+/// How often waits wake up to check for cancellation.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+const MAX_LOOP_COUNT: u32 = 100_000;
+
+/// One script statement. `line` is the 1-based source line, used in
+/// progress events and errors.
+#[derive(Clone, Debug)]
+pub struct ScriptStep {
+    pub line: usize,
+    pub action: ScriptAction,
+}
+
+#[derive(Clone, Debug)]
+pub enum ScriptAction {
+    Send(String),
+    SendHex(String),
+    Expect {
+        pattern: String,
+        timeout: Duration,
+        variable: Option<String>,
+    },
+    Delay(Duration),
+    SetSignals {
+        rts: bool,
+        dtr: bool,
+    },
+    Loop {
+        count: u32,
+        steps: Vec<ScriptStep>,
+    },
+    Pass(Option<String>),
+    Fail(String),
+}
+
+#[derive(serde::Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ScriptState {
+    Running,
+    Passed,
+    Failed,
+    Cancelled,
+}
+
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct ScriptProgress {
+    pub state: ScriptState,
+    pub line: Option<usize>,
+    /// Loop iteration of the innermost loop, starting at 1.
+    pub iteration: Option<u32>,
+    pub message: String,
+    pub variables: BTreeMap<String, String>,
+}
+
+/// Output side of the session a script drives.
+pub trait ScriptPort {
+    fn send(&mut self, data: &[u8]) -> Result<(), String>;
+    fn set_signals(&mut self, rts: bool, dtr: bool) -> Result<(), String>;
+}
+
+enum Stop {
+    Passed(String),
+    Failed(String),
+    Cancelled,
+}
+
+fn parse_number<T: std::str::FromStr>(line: usize, what: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("line {line}: invalid {what}: {value}"))
+}
+
+fn parse_level(line: usize, value: &str) -> Result<bool, String> {
+    match value {
+        "0" => Ok(false),
+        "1" => Ok(true),
+        _ => Err(format!("line {line}: signal level must be 0 or 1")),
+    }
+}
+
+/// Parses the line-based script language:
+///
+/// ```text
+/// # send text with \r \n \t \\ and \xHH escapes
+/// send AT\r\n
+/// sendhex 01 03 00 00
+/// # timeout in ms, then a regex
+/// expect 2000 ^OK
+/// # like expect, storing group 1 (or the whole match) in `ip`
+/// capture ip 5000 IP: (\S+)
+/// delay 500
+/// # RTS and DTR levels
+/// signals 0 1
+/// loop 10
+///   send ping ${ip}\n
+/// end
+/// pass done
+/// fail no answer
+/// ```
+///
+/// `${name}` in send text and patterns expands to a captured variable.
+/// Comments are whole lines starting with `#`.
+pub fn parse(script: &str) -> Result<Vec<ScriptStep>, String> {
+    let mut stack: Vec<(usize, u32, Vec<ScriptStep>)> = Vec::new();
+    let mut steps = Vec::new();
+
+    for (index, raw) in script.lines().enumerate() {
+        let line = index + 1;
+        let trimmed = raw.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let (keyword, rest) = trimmed
+            .split_once(char::is_whitespace)
+            .unwrap_or((trimmed, ""));
+        let rest = rest.trim_start();
+        let words: Vec<&str> = rest.split_whitespace().collect();
+
+        let action = match keyword.to_ascii_lowercase().as_str() {
+            "send" => {
+                check_literal(line, rest, unescape)?;
+                ScriptAction::Send(rest.to_string())
+            }
+            "sendhex" => {
+                check_literal(line, rest, parse_hex)?;
+                ScriptAction::SendHex(rest.to_string())
+            }
+            "expect" | "capture" => {
+                let (variable, rest) = if keyword.eq_ignore_ascii_case("capture") {
+                    let (name, rest) = rest
+                        .split_once(char::is_whitespace)
+                        .ok_or_else(|| format!("line {line}: capture needs a variable"))?;
+                    (Some(name.to_string()), rest.trim_start())
+                } else {
+                    (None, rest)
+                };
+                let (timeout, pattern) = rest.split_once(char::is_whitespace).ok_or_else(|| {
+                    format!("line {line}: {keyword} needs a timeout and a pattern")
+                })?;
+                let timeout: u64 = parse_number(line, "timeout", timeout)?;
+                ScriptAction::Expect {
+                    pattern: pattern.trim().to_string(),
+                    timeout: Duration::from_millis(timeout),
+                    variable,
+                }
+            }
+            "delay" => {
+                let ms: u64 = parse_number(line, "delay", words.first().copied().unwrap_or(""))?;
+                ScriptAction::Delay(Duration::from_millis(ms))
+            }
+            "signals" => {
+                let [rts, dtr] = words[..] else {
+                    return Err(format!("line {line}: signals needs RTS and DTR levels"));
+                };
+                ScriptAction::SetSignals {
+                    rts: parse_level(line, rts)?,
+                    dtr: parse_level(line, dtr)?,
+                }
+            }
+            "loop" => {
+                let count: u32 =
+                    parse_number(line, "loop count", words.first().copied().unwrap_or(""))?;
+                if count == 0 || count > MAX_LOOP_COUNT {
+                    return Err(format!(
+                        "line {line}: loop count must be 1..={MAX_LOOP_COUNT}"
+                    ));
+                }
+                stack.push((line, count, std::mem::take(&mut steps)));
+                continue;
+            }
+            "end" => {
+                let (loop_line, count, outer) = stack
+                    .pop()
+                    .ok_or_else(|| format!("line {line}: end without loop"))?;
+                let body = std::mem::replace(&mut steps, outer);
+                steps.push(ScriptStep {
+                    line: loop_line,
+                    action: ScriptAction::Loop { count, steps: body },
+                });
+                continue;
+            }
+            "pass" => ScriptAction::Pass(Some(rest.to_string()).filter(|m| !m.is_empty())),
+            "fail" => ScriptAction::Fail(if rest.is_empty() {
+                "failed".to_string()
+            } else {
+                rest.to_string()
+            }),
+            _ => return Err(format!("line {line}: unknown command: {keyword}")),
+        };
+        steps.push(ScriptStep { line, action });
+    }
+
+    if let Some((line, _, _)) = stack.last() {
+        return Err(format!("line {line}: loop without end"));
+    }
+    if steps.is_empty() {
+        return Err("script is empty".to_string());
+    }
+    Ok(steps)
+}
+
+/// Decodes send data without `${name}` references up front, so a typo fails
+/// the script before it starts driving the board. Text with references is
+/// only complete once they expand at run time.
+fn check_literal(
+    line: usize,
+    text: &str,
+    decode: fn(&str) -> Result<Vec<u8>, String>,
+) -> Result<(), String> {
+    if !text.contains("${") {
+        decode(text).map_err(|e| format!("line {line}: {e}"))?;
+    }
+    Ok(())
+}
+
+/// Expands `\r`, `\n`, `\t`, `\\` and `\xHH` in send text.
+fn unescape(text: &str) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0u8; 4];
+            out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('r') => out.push(b'\r'),
+            Some('n') => out.push(b'\n'),
+            Some('t') => out.push(b'\t'),
+            Some('\\') => out.push(b'\\'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                if hex.len() != 2 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    return Err(format!("invalid escape: \\x{hex}"));
+                }
+                out.push(u8::from_str_radix(&hex, 16).expect("two hex digits"));
+            }
+            Some(other) => return Err(format!("invalid escape: \\{other}")),
+            None => return Err("send text ends with a backslash".to_string()),
+        }
+    }
+    Ok(out)
+}
+
+fn parse_hex(text: &str) -> Result<Vec<u8>, String> {
+    let digits: Vec<u8> = text
+        .bytes()
+        .filter(|b| !b.is_ascii_whitespace() && *b != b',')
+        .collect();
+    let digits = digits.strip_prefix(b"0x").unwrap_or(&digits);
+    if !digits.len().is_multiple_of(2) {
+        return Err(format!("odd number of hex digits: {text}"));
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let high = char::from(pair[0]).to_digit(16);
+            let low = char::from(pair[1]).to_digit(16);
+            match (high, low) {
+                (Some(high), Some(low)) => Ok((high << 4 | low) as u8),
+                _ => Err(format!("invalid hex: {text}")),
+            }
+        })
+        .collect()
+}
+
+/// Runs a parsed script against a session, reading received bytes from `rx`.
+pub struct ScriptRunner<'a, P: ScriptPort> {
+    port: P,
+    rx: Receiver<Vec<u8>>,
+    cancel: &'a AtomicBool,
+    buffer: Vec<u8>,
+    variables: BTreeMap<String, String>,
+    iteration: Option<u32>,
+    on_progress: Box<dyn FnMut(ScriptProgress) + 'a>,
+}
+
+impl<'a, P: ScriptPort> ScriptRunner<'a, P> {
+    pub fn new(
+        port: P,
+        rx: Receiver<Vec<u8>>,
+        cancel: &'a AtomicBool,
+        on_progress: impl FnMut(ScriptProgress) + 'a,
+    ) -> Self {
+        Self {
+            port,
+            rx,
+            cancel,
+            buffer: Vec::new(),
+            variables: BTreeMap::new(),
+            iteration: None,
+            on_progress: Box::new(on_progress),
+        }
+    }
+
+    /// Runs every step and reports the outcome as the final progress event.
+    /// A script that runs off its end passes.
+    pub fn run(mut self, steps: &[ScriptStep]) -> ScriptState {
+        let (state, line, message) = match self.run_steps(steps) {
+            Ok(()) => (ScriptState::Passed, None, "script passed".to_string()),
+            Err((line, Stop::Passed(message))) => (ScriptState::Passed, Some(line), message),
+            Err((line, Stop::Failed(message))) => (ScriptState::Failed, Some(line), message),
+            Err((line, Stop::Cancelled)) => (
+                ScriptState::Cancelled,
+                Some(line),
+                "script cancelled".to_string(),
+            ),
+        };
+        self.report(state, line, message);
+        state
+    }
+
+    fn report(&mut self, state: ScriptState, line: Option<usize>, message: String) {
+        let progress = ScriptProgress {
+            state,
+            line,
+            iteration: self.iteration,
+            message,
+            variables: self.variables.clone(),
+        };
+        (self.on_progress)(progress);
+    }
+
+    fn run_steps(&mut self, steps: &[ScriptStep]) -> Result<(), (usize, Stop)> {
+        for step in steps {
+            if self.cancel.load(Ordering::SeqCst) {
+                return Err((step.line, Stop::Cancelled));
+            }
+            match &step.action {
+                ScriptAction::Loop { count, steps } => self.run_loop(step.line, *count, steps)?,
+                _ => self.run_step(step).map_err(|stop| (step.line, stop))?,
+            }
+        }
+        Ok(())
+    }
+
+    fn run_loop(
+        &mut self,
+        line: usize,
+        count: u32,
+        steps: &[ScriptStep],
+    ) -> Result<(), (usize, Stop)> {
+        let outer = self.iteration;
+        for iteration in 1..=count {
+            self.iteration = Some(iteration);
+            self.report(
+                ScriptState::Running,
+                Some(line),
+                format!("loop {iteration}/{count}"),
+            );
+            self.run_steps(steps)?;
+        }
+        self.iteration = outer;
+        Ok(())
+    }
+
+    fn run_step(&mut self, step: &ScriptStep) -> Result<(), Stop> {
+        match &step.action {
+            ScriptAction::Send(text) => {
+                self.report(
+                    ScriptState::Running,
+                    Some(step.line),
+                    format!("send {text}"),
+                );
+                let data = unescape(&self.expand(text, false)).map_err(Stop::Failed)?;
+                self.port.send(&data).map_err(Stop::Failed)
+            }
+            ScriptAction::SendHex(hex) => {
+                self.report(
+                    ScriptState::Running,
+                    Some(step.line),
+                    format!("sendhex {hex}"),
+                );
+                let data = parse_hex(&self.expand(hex, false)).map_err(Stop::Failed)?;
+                self.port.send(&data).map_err(Stop::Failed)
+            }
+            ScriptAction::Expect {
+                pattern,
+                timeout,
+                variable,
+            } => {
+                self.report(
+                    ScriptState::Running,
+                    Some(step.line),
+                    format!("expect {pattern}"),
+                );
+                self.expect(pattern, *timeout, variable.as_deref())
+            }
+            ScriptAction::Delay(duration) => {
+                self.report(
+                    ScriptState::Running,
+                    Some(step.line),
+                    format!("delay {} ms", duration.as_millis()),
+                );
+                self.wait(*duration)
+            }
+            ScriptAction::SetSignals { rts, dtr } => {
+                self.report(
+                    ScriptState::Running,
+                    Some(step.line),
+                    format!("signals RTS={} DTR={}", u8::from(*rts), u8::from(*dtr)),
+                );
+                self.port.set_signals(*rts, *dtr).map_err(Stop::Failed)
+            }
+            ScriptAction::Loop { .. } => unreachable!("loops are run by run_steps"),
+            ScriptAction::Pass(message) => Err(Stop::Passed(
+                message
+                    .as_deref()
+                    .map(|message| self.expand(message, false))
+                    .unwrap_or_else(|| "script passed".to_string()),
+            )),
+            ScriptAction::Fail(message) => Err(Stop::Failed(self.expand(message, false))),
+        }
+    }
+
+    /// Replaces `${name}` with captured variables; values are regex-escaped
+    /// when expanding a pattern.
+    fn expand(&self, text: &str, pattern: bool) -> String {
+        let mut out = text.to_string();
+        for (name, value) in &self.variables {
+            let value = if pattern {
+                regex::escape(value)
+            } else {
+                value.clone()
+            };
+            out = out.replace(&format!("${{{name}}}"), &value);
+        }
+        out
+    }
+
+    fn expect(
+        &mut self,
+        pattern: &str,
+        timeout: Duration,
+        variable: Option<&str>,
+    ) -> Result<(), Stop> {
+        let regex = Regex::new(&self.expand(pattern, true))
+            .map_err(|e| Stop::Failed(format!("invalid pattern {pattern}: {e}")))?;
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(captures) = regex.captures(&self.buffer) {
+                let whole = captures.get(0).expect("match has group 0");
+                if let Some(name) = variable {
+                    let value = captures.get(1).unwrap_or(whole);
+                    self.variables.insert(
+                        name.to_string(),
+                        String::from_utf8_lossy(value.as_bytes()).to_string(),
+                    );
+                }
+                let end = whole.end();
+                self.buffer.drain(..end);
+                return Ok(());
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(Stop::Failed(format!(
+                    "timed out after {} ms waiting for {pattern}",
+                    timeout.as_millis()
+                )));
+            }
+            self.receive(deadline - now)?;
+        }
+    }
+
+    fn wait(&mut self, duration: Duration) -> Result<(), Stop> {
+        let deadline = Instant::now() + duration;
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(());
+            }
+            self.receive(deadline - now)?;
+        }
+    }
+
+    /// Buffers received data for up to `max_wait`, waking early to notice
+    /// cancellation.
+    fn receive(&mut self, max_wait: Duration) -> Result<(), Stop> {
+        if self.cancel.load(Ordering::SeqCst) {
+            return Err(Stop::Cancelled);
+        }
+        match self.rx.recv_timeout(max_wait.min(POLL_INTERVAL)) {
+            Ok(chunk) => {
+                self.buffer.extend_from_slice(&chunk);
+                if self.buffer.len() > MAX_BUFFER_LEN {
+                    let excess = self.buffer.len() - MAX_BUFFER_LEN;
+                    self.buffer.drain(..excess);
+                }
+                Ok(())
+            }
+            Err(RecvTimeoutError::Timeout) => Ok(()),
+            Err(RecvTimeoutError::Disconnected) => {
+                Err(Stop::Failed("serial session closed".to_string()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::{self, Sender};
+
+    /// An AT modem: answers `AT+CIFSR` with its address and everything else
+    /// with `OK`, and records what the script sent.
+    struct Modem {
+        rx_tx: Sender<Vec<u8>>,
+        sent: Sender<Vec<u8>>,
+    }
+
+    impl ScriptPort for Modem {
+        fn send(&mut self, data: &[u8]) -> Result<(), String> {
+            let _ = self.sent.send(data.to_vec());
+            let reply: &[u8] = if data.starts_with(b"AT+CIFSR") {
+                b"AT+CIFSR\r\n+CIFSR:STAIP,\"192.168.4.2\"\r\n\r\nOK\r\n"
+            } else {
+                b"OK\r\n"
+            };
+            let _ = self.rx_tx.send(reply.to_vec());
+            Ok(())
+        }
+
+        fn set_signals(&mut self, rts: bool, dtr: bool) -> Result<(), String> {
+            let _ = self
+                .sent
+                .send(format!("signals {} {}", u8::from(rts), u8::from(dtr)).into_bytes());
+            Ok(())
+        }
+    }
+
+    struct Outcome {
+        state: ScriptState,
+        events: Vec<ScriptProgress>,
+        sent: Vec<Vec<u8>>,
+    }
+
+    fn run(script: &str) -> Outcome {
+        let steps = parse(script).unwrap();
+        let (rx_tx, rx) = mpsc::channel();
+        let (sent_tx, sent_rx) = mpsc::channel();
+        let cancel = AtomicBool::new(false);
+        let mut events = Vec::new();
+        let modem = Modem {
+            rx_tx,
+            sent: sent_tx,
+        };
+        let state =
+            ScriptRunner::new(modem, rx, &cancel, |progress| events.push(progress)).run(&steps);
+        Outcome {
+            state,
+            events,
+            sent: sent_rx.try_iter().collect(),
+        }
+    }
+
+    #[test]
+    fn captures_variables_and_loops() {
+        let outcome = run("\
+# ask for the address, then ping it twice
+send AT+CIFSR\\r\\n
+capture ip 500 STAIP,\"([0-9.]+)\"
+expect 500 OK
+signals 0 1
+loop 2
+  send ping ${ip}\\x0a
+  expect 500 OK
+end
+");
+        assert_eq!(outcome.state, ScriptState::Passed);
+        assert_eq!(
+            outcome.sent,
+            [
+                b"AT+CIFSR\r\n".to_vec(),
+                b"signals 0 1".to_vec(),
+                b"ping 192.168.4.2\n".to_vec(),
+                b"ping 192.168.4.2\n".to_vec(),
+            ]
+        );
+        let last = outcome.events.last().unwrap();
+        assert_eq!(last.variables["ip"], "192.168.4.2");
+        assert_eq!(last.message, "script passed");
+        assert!(outcome
+            .events
+            .iter()
+            .any(|event| event.message == "loop 2/2" && event.iteration == Some(2)));
+    }
+
+    #[test]
+    fn reports_failures_with_line_and_iteration() {
+        let outcome = run("send AT\nexpect 50 ERROR\n");
+        assert_eq!(outcome.state, ScriptState::Failed);
+        let last = outcome.events.last().unwrap();
+        assert_eq!(last.line, Some(2));
+        assert_eq!(last.message, "timed out after 50 ms waiting for ERROR");
+
+        let outcome = run("loop 3\n  send x\n  fail bad ${x}\nend\n");
+        assert_eq!(outcome.state, ScriptState::Failed);
+        let last = outcome.events.last().unwrap();
+        assert_eq!((last.line, last.iteration), (Some(3), Some(1)));
+
+        assert_eq!(run("pass early\nfail never\n").state, ScriptState::Passed);
+        assert_eq!(run("sendhex 01 0A,ff\n").sent, [vec![0x01, 0x0A, 0xFF]]);
+    }
+
+    #[test]
+    fn stops_when_cancelled() {
+        let steps = parse("delay 5000\n").unwrap();
+        let (_rx_tx, rx) = mpsc::channel();
+        let (sent, _) = mpsc::channel();
+        let cancel = AtomicBool::new(true);
+        let modem = Modem {
+            rx_tx: mpsc::channel().0,
+            sent,
+        };
+        let state = ScriptRunner::new(modem, rx, &cancel, |_| {}).run(&steps);
+        assert_eq!(state, ScriptState::Cancelled);
+    }
+
+    #[test]
+    fn rejects_malformed_scripts() {
+        assert_eq!(
+            parse("loop 2\nsend x").unwrap_err(),
+            "line 1: loop without end"
+        );
+        assert_eq!(parse("end").unwrap_err(), "line 1: end without loop");
+        assert_eq!(
+            parse("bogus").unwrap_err(),
+            "line 1: unknown command: bogus"
+        );
+        assert!(parse("loop 0\nend").is_err());
+        assert!(parse("signals 1").is_err());
+        assert!(parse("signals 1 2").is_err());
+        assert!(parse("expect 100").is_err());
+        assert!(parse("capture 100 x").is_err());
+        assert_eq!(
+            parse("\n# only a comment\n").unwrap_err(),
+            "script is empty"
+        );
+    }
+
+    #[test]
+    fn unescapes_send_text() {
+        assert_eq!(unescape("a\\r\\n\\t\\\\\\x41").unwrap(), b"a\r\n\t\\A");
+        assert!(unescape("a\\q").is_err());
+        assert!(unescape("a\\").is_err());
+        assert!(unescape("\\xZZ").is_err());
+        assert!(unescape("\\x4").is_err());
+        assert!(unescape("\\x+1").is_err());
+        assert_eq!(parse_hex("0x0102").unwrap(), [1, 2]);
+        assert!(parse_hex("123").is_err());
+        assert!(parse_hex("+1").is_err());
+        // Non-ASCII input is rejected, not sliced through.
+        assert!(parse_hex("aé1").is_err());
+        assert!(parse_hex("é").is_err());
+    }
+
+    #[test]
+    fn rejects_bad_send_data_before_running() {
+        assert_eq!(
+            parse("send AT\\r\\n\nsendhex 01 aé1\n").unwrap_err(),
+            "line 2: invalid hex: 01 aé1"
+        );
+        assert_eq!(
+            parse("send AT\\q").unwrap_err(),
+            "line 1: invalid escape: \\q"
+        );
+        // Data with variables is only checked once they expand.
+        assert!(parse("capture v 100 (.*)\nsendhex ${v}\nsend \\x${v}\n").is_ok());
+    }
+}
@@ -0,0 +1,201 @@
+use serialport::SerialPort;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Longest single wait (or BREAK) the writer thread may be asked for; it is
+/// blocked for the whole time.
+pub const MAX_WAIT: Duration = Duration::from_secs(10);
+
+/// Built-in sequences, written in the same notation users can type.
+/// `D`/`R` set DTR/RTS (1 = asserted), `W` waits for milliseconds.
+const BUILTIN_SEQUENCES: &[(&str, &str)] = &[
+    // esptool ClassicReset: EN low with IO0 low, then release EN.
+    ("classic", "D0|R1|W100|D1|R0|W50|D0"),
+    // esptool USBJTAGSerialReset for the built-in USB-JTAG-Serial bridge.
+    (
+        "usb_jtag_serial",
+        "R0|D0|W100|D1|R0|W100|R1|D0|R1|W100|D0|R0",
+    ),
+    // Pulse EN only, so the chip boots the app.
+    ("hard_reset", "D0|R1|W100|R0"),
+    // Boards whose transistors invert both lines.
+    ("classic_inverted", "D1|R0|W100|D0|R1|W50|D1"),
+    ("hard_reset_inverted", "D1|R0|W100|R1"),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SequenceStep {
+    Dtr(bool),
+    Rts(bool),
+    Wait(Duration),
+}
+
+/// Parses a sequence such as `D0|R1|W100|D1|R0|W50`.
+pub fn parse(spec: &str) -> Result<Vec<SequenceStep>, String> {
+    let steps = spec
+        .split('|')
+        .map(str::trim)
+        .filter(|step| !step.is_empty())
+        .map(|step| {
+            let mut chars = step.chars();
+            let kind = chars.next().unwrap_or_default().to_ascii_uppercase();
+            let value = chars.as_str();
+            let level = || match value {
+                "0" => Ok(false),
+                "1" => Ok(true),
+                _ => Err(format!("invalid signal level in sequence step: {step}")),
+            };
+            match kind {
+                'D' => Ok(SequenceStep::Dtr(level()?)),
+                'R' => Ok(SequenceStep::Rts(level()?)),
+                'W' => {
+                    let ms: u64 = value
+                        .parse()
+                        .map_err(|_| format!("invalid wait in sequence step: {step}"))?;
+                    let wait = Duration::from_millis(ms);
+                    if wait > MAX_WAIT {
+                        return Err(format!(
+                            "sequence waits are limited to {} ms",
+                            MAX_WAIT.as_millis()
+                        ));
+                    }
+                    Ok(SequenceStep::Wait(wait))
+                }
+                _ => Err(format!("unknown sequence step: {step}")),
+            }
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    if steps.is_empty() {
+        return Err("sequence is empty".to_string());
+    }
+    Ok(steps)
+}
+
+/// Resolves a built-in sequence name or parses a user-defined sequence.
+pub fn resolve(sequence: &str) -> Result<Vec<SequenceStep>, String> {
+    let sequence = sequence.trim();
+    match BUILTIN_SEQUENCES
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(sequence))
+    {
+        Some((_, spec)) => parse(spec),
+        None => parse(sequence),
+    }
+}
+
+/// Runs the steps on the port, sleeping between them on the calling thread
+/// and giving up early when the session starts closing.
+pub fn run(
+    port: &mut Box<dyn SerialPort>,
+    steps: &[SequenceStep],
+    closing: &AtomicBool,
+) -> Result<(), String> {
+    for step in steps {
+        match *step {
+            SequenceStep::Dtr(level) => port
+                .write_data_terminal_ready(level)
+                .map_err(|e| format!("failed to set DTR: {e}"))?,
+            SequenceStep::Rts(level) => port
+                .write_request_to_send(level)
+                .map_err(|e| format!("failed to set RTS: {e}"))?,
+            SequenceStep::Wait(wait) => sleep_unless_closing(wait, closing)?,
+        }
+    }
+    Ok(())
+}
+
+/// Sleeps for `duration`, returning early with an error once the session
+/// starts closing.
+pub fn sleep_unless_closing(duration: Duration, closing: &AtomicBool) -> Result<(), String> {
+    let deadline = Instant::now() + duration;
+    loop {
+        if closing.load(Ordering::SeqCst) {
+            return Err("serial is closing".to_string());
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(());
+        }
+        thread::sleep((deadline - now).min(Duration::from_millis(10)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use SequenceStep::{Dtr, Rts, Wait};
+
+    fn ms(millis: u64) -> SequenceStep {
+        Wait(Duration::from_millis(millis))
+    }
+
+    #[test]
+    fn builtin_sequences_match_esptool() {
+        // esptool ClassicReset with the default 50 ms reset delay.
+        let classic = vec![
+            Dtr(false),
+            Rts(true),
+            ms(100),
+            Dtr(true),
+            Rts(false),
+            ms(50),
+            Dtr(false),
+        ];
+        assert_eq!(resolve("classic").unwrap(), classic);
+        // esptool USBJTAGSerialReset, passing through (1,1) rather than (0,0).
+        assert_eq!(
+            resolve("usb_jtag_serial").unwrap(),
+            [
+                Rts(false),
+                Dtr(false),
+                ms(100),
+                Dtr(true),
+                Rts(false),
+                ms(100),
+                Rts(true),
+                Dtr(false),
+                Rts(true),
+                ms(100),
+                Dtr(false),
+                Rts(false),
+            ]
+        );
+        assert_eq!(
+            resolve(" Hard_Reset ").unwrap(),
+            [Dtr(false), Rts(true), ms(100), Rts(false)]
+        );
+
+        let inverted: Vec<_> = classic
+            .iter()
+            .map(|step| match *step {
+                Dtr(level) => Dtr(!level),
+                Rts(level) => Rts(!level),
+                wait => wait,
+            })
+            .collect();
+        assert_eq!(resolve("classic_inverted").unwrap(), inverted);
+    }
+
+    #[test]
+    fn parses_custom_sequences() {
+        assert_eq!(
+            resolve("d1| r0 |W0|").unwrap(),
+            [Dtr(true), Rts(false), ms(0)]
+        );
+        for spec in ["", "||", "D2", "R", "X1", "W", "W-1", "W1.5", "é1", "D0|Q"] {
+            assert!(resolve(spec).is_err(), "{spec:?}");
+        }
+        assert_eq!(parse("D0|Q1").unwrap_err(), "unknown sequence step: Q1");
+    }
+
+    #[test]
+    fn caps_waits_at_ten_seconds() {
+        assert_eq!(parse("W10000").unwrap(), [Wait(MAX_WAIT)]);
+        assert_eq!(
+            parse("D0|W10001").unwrap_err(),
+            "sequence waits are limited to 10000 ms"
+        );
+    }
+}
@@ -0,0 +1,228 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How often the reader refreshes throughput and emits a stats event.
+pub const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Default)]
+struct Rates {
+    sampled_at: Option<Instant>,
+    rx_bytes: u64,
+    tx_bytes: u64,
+    rx_rate: u64,
+    tx_rate: u64,
+    peak_rx_rate: u64,
+    peak_tx_rate: u64,
+}
+
+/// Counters shared by a session's reader and writer threads.
+pub struct SessionStats {
+    opened_at: Instant,
+    rx_bytes: AtomicU64,
+    tx_bytes: AtomicU64,
+    rx_chunks: AtomicU64,
+    coalesced_chunks: AtomicU64,
+    dropped_events: AtomicU64,
+    read_errors: AtomicU64,
+    writes: AtomicU64,
+    write_errors: AtomicU64,
+    write_time_us: AtomicU64,
+    last_write_us: AtomicU64,
+    max_write_us: AtomicU64,
+    rates: Mutex<Rates>,
+}
+
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct StatsSnapshot {
+    pub uptime_ms: u64,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    /// Bytes per second over the last stats interval.
+    pub rx_rate: u64,
+    pub tx_rate: u64,
+    pub peak_rx_rate: u64,
+    pub peak_tx_rate: u64,
+    pub rx_chunks: u64,
+    /// Reads that produced no event of their own because the framer held
+    /// them back to merge with later bytes.
+    pub coalesced_chunks: u64,
+    /// Frames that were read but never emitted, e.g. filtered log lines.
+    pub dropped_events: u64,
+    pub read_errors: u64,
+    pub writes: u64,
+    pub write_errors: u64,
+    /// Time from the start of a write until the output buffer drained.
+    pub last_write_us: u64,
+    pub avg_write_us: u64,
+    pub max_write_us: u64,
+}
+
+impl Default for SessionStats {
+    fn default() -> Self {
+        Self {
+            opened_at: Instant::now(),
+            rx_bytes: AtomicU64::new(0),
+            tx_bytes: AtomicU64::new(0),
+            rx_chunks: AtomicU64::new(0),
+            coalesced_chunks: AtomicU64::new(0),
+            dropped_events: AtomicU64::new(0),
+            read_errors: AtomicU64::new(0),
+            writes: AtomicU64::new(0),
+            write_errors: AtomicU64::new(0),
+            write_time_us: AtomicU64::new(0),
+            last_write_us: AtomicU64::new(0),
+            max_write_us: AtomicU64::new(0),
+            rates: Mutex::new(Rates::default()),
+        }
+    }
+}
+
+impl SessionStats {
+    pub fn record_rx(&self, bytes: usize, coalesced: bool) {
+        self.rx_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.rx_chunks.fetch_add(1, Ordering::Relaxed);
+        if coalesced {
+            self.coalesced_chunks.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_dropped_event(&self) {
+        self.dropped_events.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_read_error(&self) {
+        self.read_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_write(&self, result: Result<usize, &str>, elapsed: Duration) {
+        let bytes = match result {
+            Ok(bytes) => bytes,
+            Err(_) => {
+                self.write_errors.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+        };
+        let elapsed_us = elapsed.as_micros() as u64;
+        self.tx_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        self.write_time_us.fetch_add(elapsed_us, Ordering::Relaxed);
+        self.last_write_us.store(elapsed_us, Ordering::Relaxed);
+        self.max_write_us.fetch_max(elapsed_us, Ordering::Relaxed);
+    }
+
+    /// Recomputes throughput from the byte counters since the last call.
+    pub fn sample_rates(&self, now: Instant) {
+        let Ok(mut rates) = self.rates.lock() else {
+            return;
+        };
+        let rx_bytes = self.rx_bytes.load(Ordering::Relaxed);
+        let tx_bytes = self.tx_bytes.load(Ordering::Relaxed);
+        if let Some(sampled_at) = rates.sampled_at {
+            let elapsed = now.saturating_duration_since(sampled_at).as_secs_f64();
+            if elapsed > 0.0 {
+                rates.rx_rate = ((rx_bytes - rates.rx_bytes) as f64 / elapsed) as u64;
+                rates.tx_rate = ((tx_bytes - rates.tx_bytes) as f64 / elapsed) as u64;
+                rates.peak_rx_rate = rates.peak_rx_rate.max(rates.rx_rate);
+                rates.peak_tx_rate = rates.peak_tx_rate.max(rates.tx_rate);
+            }
+        }
+        rates.sampled_at = Some(now);
+        rates.rx_bytes = rx_bytes;
+        rates.tx_bytes = tx_bytes;
+    }
+
+    pub fn snapshot(&self) -> StatsSnapshot {
+        let (rx_rate, tx_rate, peak_rx_rate, peak_tx_rate) = self
+            .rates
+            .lock()
+            .map(|rates| {
+                (
+                    rates.rx_rate,
+                    rates.tx_rate,
+                    rates.peak_rx_rate,
+                    rates.peak_tx_rate,
+                )
+            })
+            .unwrap_or_default();
+        let writes = self.writes.load(Ordering::Relaxed);
+        StatsSnapshot {
+            uptime_ms: self.opened_at.elapsed().as_millis() as u64,
+            rx_bytes: self.rx_bytes.load(Ordering::Relaxed),
+            tx_bytes: self.tx_bytes.load(Ordering::Relaxed),
+            rx_rate,
+            tx_rate,
+            peak_rx_rate,
+            peak_tx_rate,
+            rx_chunks: self.rx_chunks.load(Ordering::Relaxed),
+            coalesced_chunks: self.coalesced_chunks.load(Ordering::Relaxed),
+            dropped_events: self.dropped_events.load(Ordering::Relaxed),
+            read_errors: self.read_errors.load(Ordering::Relaxed),
+            writes,
+            write_errors: self.write_errors.load(Ordering::Relaxed),
+            last_write_us: self.last_write_us.load(Ordering::Relaxed),
+            avg_write_us: self
+                .write_time_us
+                .load(Ordering::Relaxed)
+                .checked_div(writes)
+                .unwrap_or(0),
+            max_write_us: self.max_write_us.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rates_follow_the_sampling_interval() {
+        let stats = SessionStats::default();
+        let start = Instant::now();
+        stats.record_rx(1000, false);
+        // The first sample only sets the baseline.
+        stats.sample_rates(start);
+        assert_eq!(stats.snapshot().rx_rate, 0);
+
+        stats.record_rx(3000, false);
+        stats.record_write(Ok(500), Duration::from_micros(200));
+        stats.sample_rates(start + Duration::from_millis(500));
+        let snapshot = stats.snapshot();
+        assert_eq!((snapshot.rx_rate, snapshot.tx_rate), (6000, 1000));
+
+        stats.record_rx(100, false);
+        stats.sample_rates(start + Duration::from_millis(1500));
+        // A repeated timestamp leaves the last rates alone.
+        stats.sample_rates(start + Duration::from_millis(1500));
+        let snapshot = stats.snapshot();
+        assert_eq!((snapshot.rx_rate, snapshot.tx_rate), (100, 0));
+        assert_eq!((snapshot.peak_rx_rate, snapshot.peak_tx_rate), (6000, 1000));
+        assert_eq!((snapshot.rx_bytes, snapshot.tx_bytes), (4100, 500));
+    }
+
+    #[test]
+    fn counts_chunks_errors_and_write_times() {
+        let stats = SessionStats::default();
+        stats.record_rx(10, false);
+        stats.record_rx(10, true);
+        stats.record_rx(10, true);
+        stats.record_dropped_event();
+        stats.record_read_error();
+        stats.record_read_error();
+        stats.record_write(Ok(4), Duration::from_micros(300));
+        stats.record_write(Ok(4), Duration::from_micros(100));
+        stats.record_write(Err("timed out"), Duration::from_micros(5000));
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.rx_chunks, 3);
+        assert_eq!(snapshot.coalesced_chunks, 2);
+        assert_eq!(snapshot.dropped_events, 1);
+        assert_eq!(snapshot.read_errors, 2);
+        assert_eq!((snapshot.writes, snapshot.write_errors), (2, 1));
+        assert_eq!(snapshot.tx_bytes, 8);
+        // Failed writes do not count toward the write times.
+        assert_eq!(snapshot.last_write_us, 100);
+        assert_eq!(snapshot.avg_write_us, 200);
+        assert_eq!(snapshot.max_write_us, 300);
+    }
+}
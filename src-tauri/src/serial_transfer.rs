@@ -0,0 +1,946 @@
+use crate::serial_checksum::crc16_xmodem;
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+const SUB: u8 = 0x1a;
+const CRC_REQUEST: u8 = b'C';
+
+/// How long a sender waits for the receiver to ask for the first block.
+const START_TIMEOUT: Duration = Duration::from_secs(60);
+/// How often a receiver repeats its start request, and how many times.
+const START_INTERVAL: Duration = Duration::from_secs(3);
+const START_ATTEMPTS: u32 = 20;
+/// Receivers fall back from CRC to checksum after this many unanswered `C`s.
+const CRC_ATTEMPTS: u32 = 5;
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+const BYTE_TIMEOUT: Duration = Duration::from_secs(1);
+const MAX_RETRIES: u32 = 10;
+/// A raw receive ends once the line has been quiet this long after data.
+const RAW_IDLE_TIMEOUT: Duration = Duration::from_secs(3);
+const MAX_RAW_CHUNK: usize = 64 * 1024;
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+/// How often waits wake up to check for cancellation.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransferProtocol {
+    /// Plain bytes, sent in chunks with a pause after each.
+    Raw {
+        chunk_size: usize,
+        chunk_delay: Duration,
+    },
+    /// XMODEM with 128 byte blocks and CRC-16.
+    Xmodem,
+    /// XMODEM with 1024 byte blocks and CRC-16.
+    Xmodem1k,
+    /// YMODEM batch: 1024 byte blocks, file name and size in block 0.
+    Ymodem,
+}
+
+impl TransferProtocol {
+    pub fn parse(
+        protocol: &str,
+        chunk_size: Option<usize>,
+        chunk_delay: Option<Duration>,
+    ) -> Result<Self, String> {
+        match protocol.to_ascii_lowercase().as_str() {
+            "raw" => {
+                let chunk_size = chunk_size.unwrap_or(1024);
+                if chunk_size == 0 || chunk_size > MAX_RAW_CHUNK {
+                    return Err(format!("raw chunk size must be 1..={MAX_RAW_CHUNK} bytes"));
+                }
+                Ok(Self::Raw {
+                    chunk_size,
+                    chunk_delay: chunk_delay.unwrap_or_default(),
+                })
+            }
+            "xmodem" | "xmodem-crc" => Ok(Self::Xmodem),
+            "xmodem-1k" | "xmodem1k" => Ok(Self::Xmodem1k),
+            "ymodem" => Ok(Self::Ymodem),
+            _ => Err(format!("unsupported transfer protocol: {protocol}")),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Raw { .. } => "raw",
+            Self::Xmodem => "xmodem",
+            Self::Xmodem1k => "xmodem-1k",
+            Self::Ymodem => "ymodem",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum TransferDirection {
+    Send(Vec<PathBuf>),
+    /// A file for raw and XMODEM, a directory for YMODEM, which carries
+    /// file names itself.
+    Receive(PathBuf),
+}
+
+#[derive(Clone, Debug)]
+pub struct TransferJob {
+    pub protocol: TransferProtocol,
+    pub direction: TransferDirection,
+}
+
+impl TransferJob {
+    /// Checks the paths up front, so mistakes are reported by the command
+    /// instead of after the peer has been waiting for a transfer.
+    pub fn new(protocol: TransferProtocol, direction: TransferDirection) -> Result<Self, String> {
+        match &direction {
+            TransferDirection::Send(files) => {
+                if files.is_empty() {
+                    return Err("no file to send".to_string());
+                }
+                if files.len() > 1 && protocol != TransferProtocol::Ymodem {
+                    return Err(format!(
+                        "{} sends one file at a time; use YMODEM for batches",
+                        protocol.name()
+                    ));
+                }
+                for file in files {
+                    if !file.is_file() {
+                        return Err(format!("file not found: {}", file.display()));
+                    }
+                }
+            }
+            TransferDirection::Receive(dest) => {
+                if protocol == TransferProtocol::Ymodem {
+                    fs::create_dir_all(dest)
+                        .map_err(|e| format!("failed to create receive directory: {e}"))?;
+                } else if dest.is_dir() {
+                    return Err(format!("{} is a directory", dest.display()));
+                }
+            }
+        }
+        Ok(Self {
+            protocol,
+            direction,
+        })
+    }
+}
+
+#[derive(serde::Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferState {
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct TransferProgress {
+    pub state: TransferState,
+    pub protocol: &'static str,
+    pub sending: bool,
+    /// File currently being transferred, if known.
+    pub file: Option<String>,
+    pub bytes: u64,
+    pub total: Option<u64>,
+    pub message: String,
+}
+
+/// Output side of the session a transfer runs on.
+pub trait TransferPort {
+    fn write(&mut self, data: &[u8]) -> Result<(), String>;
+}
+
+enum Abort {
+    Cancelled,
+    Failed(String),
+}
+
+impl From<String> for Abort {
+    fn from(err: String) -> Self {
+        Self::Failed(err)
+    }
+}
+
+/// One XMODEM/YMODEM packet as seen by a receiver.
+enum Packet {
+    Block {
+        number: u8,
+        data: Vec<u8>,
+    },
+    Eot,
+    /// Timed out or failed its check; the sender should repeat it.
+    Bad,
+}
+
+/// Runs one transfer, reporting progress throughout and a final
+/// completed, failed or cancelled update. Received bytes arrive on `rx`.
+pub struct Transfer<'a, P: TransferPort, F: FnMut(TransferProgress)> {
+    port: &'a mut P,
+    rx: Receiver<Vec<u8>>,
+    pending: VecDeque<u8>,
+    cancel: &'a AtomicBool,
+    on_progress: F,
+    protocol: TransferProtocol,
+    sending: bool,
+    file: Option<String>,
+    bytes: u64,
+    total: Option<u64>,
+    reported_at: Option<Instant>,
+}
+
+impl<'a, P: TransferPort, F: FnMut(TransferProgress)> Transfer<'a, P, F> {
+    pub fn new(
+        port: &'a mut P,
+        rx: Receiver<Vec<u8>>,
+        cancel: &'a AtomicBool,
+        on_progress: F,
+    ) -> Self {
+        Self {
+            port,
+            rx,
+            pending: VecDeque::new(),
+            cancel,
+            on_progress,
+            protocol: TransferProtocol::Xmodem,
+            sending: true,
+            file: None,
+            bytes: 0,
+            total: None,
+            reported_at: None,
+        }
+    }
+
+    pub fn run(mut self, job: &TransferJob) -> TransferState {
+        self.protocol = job.protocol;
+        let result = match &job.direction {
+            TransferDirection::Send(files) => {
+                self.sending = true;
+                self.send_files(files)
+            }
+            TransferDirection::Receive(dest) => {
+                self.sending = false;
+                self.receive(dest)
+            }
+        };
+        let (state, message) = match result {
+            Ok(message) => (TransferState::Completed, message),
+            Err(Abort::Cancelled) => {
+                self.send_cancel();
+                (TransferState::Cancelled, "transfer cancelled".to_string())
+            }
+            Err(Abort::Failed(err)) => {
+                self.send_cancel();
+                (TransferState::Failed, err)
+            }
+        };
+        self.report(state, message);
+        state
+    }
+
+    fn report(&mut self, state: TransferState, message: String) {
+        self.reported_at = Some(Instant::now());
+        (self.on_progress)(TransferProgress {
+            state,
+            protocol: self.protocol.name(),
+            sending: self.sending,
+            file: self.file.clone(),
+            bytes: self.bytes,
+            total: self.total,
+            message,
+        });
+    }
+
+    /// Reports progress at most every `PROGRESS_INTERVAL`.
+    fn advance(&mut self, bytes: usize) {
+        self.bytes += bytes as u64;
+        if self
+            .reported_at
+            .is_some_and(|at| at.elapsed() < PROGRESS_INTERVAL)
+        {
+            return;
+        }
+        self.report(TransferState::Running, String::new());
+    }
+
+    fn start_file(&mut self, name: &str, total: Option<u64>) {
+        self.file = Some(name.to_string());
+        self.bytes = 0;
+        self.total = total;
+        let direction = if self.sending { "sending" } else { "receiving" };
+        self.report(TransferState::Running, format!("{direction} {name}"));
+    }
+
+    fn check_cancel(&self) -> Result<(), Abort> {
+        if self.cancel.load(Ordering::SeqCst) {
+            Err(Abort::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Tells an XMODEM/YMODEM peer to give up, so it does not keep retrying.
+    fn send_cancel(&mut self) {
+        if !matches!(self.protocol, TransferProtocol::Raw { .. }) {
+            let _ = self.port.write(&[CAN; 8]);
+        }
+    }
+
+    fn read_byte(&mut self, timeout: Duration) -> Result<Option<u8>, Abort> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(byte) = self.pending.pop_front() {
+                return Ok(Some(byte));
+            }
+            self.check_cancel()?;
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            match self.rx.recv_timeout((deadline - now).min(POLL_INTERVAL)) {
+                Ok(chunk) => self.pending.extend(chunk),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(Abort::Failed("serial reader stopped".to_string()))
+                }
+            }
+        }
+    }
+
+    /// Drops whatever the peer sent so far, so a stale byte is not taken
+    /// as the reply to the next packet.
+    fn purge(&mut self) {
+        self.pending.clear();
+        while self.rx.try_recv().is_ok() {}
+    }
+
+    /// A CAN only counts when a second one follows, as line noise can
+    /// produce a single one.
+    fn is_cancel_pair(&mut self) -> Result<bool, Abort> {
+        Ok(self.read_byte(BYTE_TIMEOUT)? == Some(CAN))
+    }
+
+    fn send_files(&mut self, files: &[PathBuf]) -> Result<String, Abort> {
+        let mut total = 0;
+        for file in files {
+            let data =
+                fs::read(file).map_err(|e| format!("failed to read {}: {e}", file.display()))?;
+            let name = file_name(file);
+            self.start_file(&name, Some(data.len() as u64));
+            match self.protocol {
+                TransferProtocol::Raw {
+                    chunk_size,
+                    chunk_delay,
+                } => self.send_raw(&data, chunk_size, chunk_delay)?,
+                TransferProtocol::Xmodem => self.send_xmodem(&data, 128)?,
+                TransferProtocol::Xmodem1k => self.send_xmodem(&data, 1024)?,
+                TransferProtocol::Ymodem => self.send_ymodem_file(file, &name, &data)?,
+            }
+            total += data.len();
+        }
+        if self.protocol == TransferProtocol::Ymodem {
+            // An empty block 0 ends the batch.
+            let crc = self.wait_for_start()?;
+            self.send_block(encode_block(0, &[], 128, 0, crc))?;
+        }
+        Ok(format!("sent {} file(s), {total} bytes", files.len()))
+    }
+
+    fn send_raw(
+        &mut self,
+        data: &[u8],
+        chunk_size: usize,
+        chunk_delay: Duration,
+    ) -> Result<(), Abort> {
+        for chunk in data.chunks(chunk_size) {
+            self.check_cancel()?;
+            self.port.write(chunk)?;
+            self.advance(chunk.len());
+            if !chunk_delay.is_zero() {
+                self.sleep(chunk_delay)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn sleep(&self, duration: Duration) -> Result<(), Abort> {
+        let deadline = Instant::now() + duration;
+        loop {
+            self.check_cancel()?;
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(());
+            }
+            thread::sleep((deadline - now).min(POLL_INTERVAL));
+        }
+    }
+
+    /// Waits for the receiver to ask for data. Returns whether it asked for
+    /// CRC-16 (`C`) rather than the original checksum (NAK).
+    fn wait_for_start(&mut self) -> Result<bool, Abort> {
+        let deadline = Instant::now() + START_TIMEOUT;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.read_byte(remaining)? {
+                Some(CRC_REQUEST) => return Ok(true),
+                Some(NAK) => return Ok(false),
+                Some(CAN) if self.is_cancel_pair()? => {
+                    return Err(Abort::Failed("receiver cancelled the transfer".to_string()))
+                }
+                Some(_) => {}
+                None => {
+                    return Err(Abort::Failed(
+                        "receiver did not start the transfer".to_string(),
+                    ))
+                }
+            }
+        }
+    }
+
+    fn send_xmodem(&mut self, data: &[u8], block_size: usize) -> Result<(), Abort> {
+        let crc = self.wait_for_start()?;
+        self.send_blocks(data, block_size, crc)?;
+        self.send_eot()
+    }
+
+    fn send_ymodem_file(&mut self, path: &Path, name: &str, data: &[u8]) -> Result<(), Abort> {
+        let crc = self.wait_for_start()?;
+        let modified = fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|time| time.as_secs())
+            .unwrap_or(0);
+        let mut header = name.as_bytes().to_vec();
+        header.push(0);
+        header.extend(format!("{} {modified:o} 0", data.len()).bytes());
+        header.push(0);
+        let header_size = if header.len() > 128 { 1024 } else { 128 };
+        if header.len() > header_size {
+            return Err(Abort::Failed(format!("file name is too long: {name}")));
+        }
+        self.send_block(encode_block(0, &header, header_size, 0, crc))?;
+
+        // After block 0 the receiver asks again before the data blocks.
+        let crc = self.wait_for_start()?;
+        self.send_blocks(data, 1024, crc)?;
+        self.send_eot()
+    }
+
+    /// Sends `data` from block 1 on. With 1K blocks, a tail of 128 bytes or
+    /// less goes in a short block to save padding.
+    fn send_blocks(&mut self, data: &[u8], block_size: usize, crc: bool) -> Result<(), Abort> {
+        let mut number = 1u8;
+        let mut offset = 0;
+        while offset < data.len() {
+            let remaining = data.len() - offset;
+            let size = if block_size > 128 && remaining <= 128 {
+                128
+            } else {
+                block_size
+            };
+            let chunk = &data[offset..offset + remaining.min(size)];
+            self.send_block(encode_block(number, chunk, size, SUB, crc))?;
+            self.advance(chunk.len());
+            offset += chunk.len();
+            number = number.wrapping_add(1);
+        }
+        Ok(())
+    }
+
+    fn send_block(&mut self, packet: Vec<u8>) -> Result<(), Abort> {
+        for _ in 0..MAX_RETRIES {
+            self.purge();
+            self.port.write(&packet)?;
+            if self.wait_for_ack()? {
+                return Ok(());
+            }
+        }
+        Err(Abort::Failed(format!(
+            "block {} was rejected {MAX_RETRIES} times",
+            packet[1]
+        )))
+    }
+
+    /// Returns `true` on ACK and `false` when the packet should be repeated.
+    fn wait_for_ack(&mut self) -> Result<bool, Abort> {
+        let deadline = Instant::now() + RESPONSE_TIMEOUT;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.read_byte(remaining)? {
+                Some(ACK) => return Ok(true),
+                Some(NAK) | None => return Ok(false),
+                Some(CAN) if self.is_cancel_pair()? => {
+                    return Err(Abort::Failed("receiver cancelled the transfer".to_string()))
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    /// YMODEM receivers NAK the first EOT to make sure it was not noise, so
+    /// a NAK just means "send it again".
+    fn send_eot(&mut self) -> Result<(), Abort> {
+        for _ in 0..MAX_RETRIES {
+            self.purge();
+            self.port.write(&[EOT])?;
+            if self.wait_for_ack()? {
+                return Ok(());
+            }
+        }
+        Err(Abort::Failed(
+            "receiver did not acknowledge EOT".to_string(),
+        ))
+    }
+
+    fn receive(&mut self, dest: &Path) -> Result<String, Abort> {
+        match self.protocol {
+            TransferProtocol::Raw { .. } => {
+                let mut file = create_file(dest)?;
+                self.start_file(&file_name(dest), None);
+                self.receive_raw(&mut file)?;
+                Ok(format!("received {} bytes", self.bytes))
+            }
+            TransferProtocol::Xmodem | TransferProtocol::Xmodem1k => {
+                let mut file = create_file(dest)?;
+                self.start_file(&file_name(dest), None);
+                let crc = self.request_start()?;
+                self.receive_blocks(&mut file, crc, None)?;
+                Ok(format!("received {} bytes", self.bytes))
+            }
+            TransferProtocol::Ymodem => self.receive_ymodem(dest),
+        }
+    }
+
+    fn receive_raw(&mut self, file: &mut File) -> Result<(), Abort> {
+        let mut last_data = None::<Instant>;
+        loop {
+            self.check_cancel()?;
+            if last_data.is_some_and(|at| at.elapsed() >= RAW_IDLE_TIMEOUT) {
+                return Ok(());
+            }
+            match self.rx.recv_timeout(POLL_INTERVAL) {
+                Ok(chunk) => {
+                    file.write_all(&chunk)
+                        .map_err(|e| format!("failed to write received data: {e}"))?;
+                    last_data = Some(Instant::now());
+                    self.advance(chunk.len());
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(Abort::Failed("serial reader stopped".to_string()))
+                }
+            }
+        }
+    }
+
+    /// Asks the sender to start, trying CRC mode first and falling back to
+    /// checksums for senders that only know the original XMODEM. The first
+    /// packet header is left in the input for `read_packet`.
+    fn request_start(&mut self) -> Result<bool, Abort> {
+        for attempt in 0..START_ATTEMPTS {
+            let crc = attempt < CRC_ATTEMPTS;
+            self.port.write(&[if crc { CRC_REQUEST } else { NAK }])?;
+            let deadline = Instant::now() + START_INTERVAL;
+            loop {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                match self.read_byte(remaining)? {
+                    Some(byte @ (SOH | STX | EOT)) => {
+                        self.pending.push_front(byte);
+                        return Ok(crc);
+                    }
+                    Some(CAN) if self.is_cancel_pair()? => {
+                        return Err(Abort::Failed("sender cancelled the transfer".to_string()))
+                    }
+                    Some(_) => {}
+                    None => break,
+                }
+            }
+        }
+        Err(Abort::Failed(
+            "sender did not start the transfer".to_string(),
+        ))
+    }
+
+    fn read_packet(&mut self, crc: bool) -> Result<Packet, Abort> {
+        let size = loop {
+            match self.read_byte(RESPONSE_TIMEOUT)? {
+                Some(SOH) => break 128,
+                Some(STX) => break 1024,
+                Some(EOT) => return Ok(Packet::Eot),
+                Some(CAN) if self.is_cancel_pair()? => {
+                    return Err(Abort::Failed("sender cancelled the transfer".to_string()))
+                }
+                Some(_) => {}
+                None => return Ok(Packet::Bad),
+            }
+        };
+        let trailer = if crc { 2 } else { 1 };
+        let mut packet = Vec::with_capacity(size + 2 + trailer);
+        while packet.len() < size + 2 + trailer {
+            match self.read_byte(BYTE_TIMEOUT)? {
+                Some(byte) => packet.push(byte),
+                None => return Ok(Packet::Bad),
+            }
+        }
+        let (number, complement) = (packet[0], packet[1]);
+        let data = &packet[2..2 + size];
+        let check = &packet[2 + size..];
+        let valid = if crc {
+            check == crc16_xmodem(data).to_be_bytes()
+        } else {
+            check[0] == checksum(data)
+        };
+        if number != !complement || !valid {
+            self.purge();
+            return Ok(Packet::Bad);
+        }
+        Ok(Packet::Block {
+            number,
+            data: data.to_vec(),
+        })
+    }
+
+    /// Receives data blocks from block 1 until EOT. `limit` is the file size
+    /// from a YMODEM header; without one, the padding of the last block is
+    /// kept, as XMODEM has no way to tell it from data.
+    fn receive_blocks(
+        &mut self,
+        file: &mut File,
+        crc: bool,
+        limit: Option<u64>,
+    ) -> Result<(), Abort> {
+        let mut expected = 1u8;
+        let mut errors = 0;
+        let mut eot_seen = false;
+        loop {
+            match self.read_packet(crc)? {
+                Packet::Block { number, data } if number == expected => {
+                    let keep = match limit {
+                        Some(limit) => (limit.saturating_sub(self.bytes) as usize).min(data.len()),
+                        None => data.len(),
+                    };
+                    file.write_all(&data[..keep])
+                        .map_err(|e| format!("failed to write received data: {e}"))?;
+                    self.advance(keep);
+                    expected = expected.wrapping_add(1);
+                    errors = 0;
+                    self.port.write(&[ACK])?;
+                }
+                // Our ACK got lost and the sender repeated the block.
+                Packet::Block { number, .. } if number == expected.wrapping_sub(1) => {
+                    self.port.write(&[ACK])?;
+                }
+                Packet::Block { number, .. } => {
+                    return Err(Abort::Failed(format!(
+                        "expected block {expected}, got {number}"
+                    )))
+                }
+                Packet::Eot if self.protocol == TransferProtocol::Ymodem && !eot_seen => {
+                    eot_seen = true;
+                    self.port.write(&[NAK])?;
+                }
+                Packet::Eot => {
+                    self.port.write(&[ACK])?;
+                    return Ok(());
+                }
+                Packet::Bad => {
+                    errors += 1;
+                    if errors >= MAX_RETRIES {
+                        return Err(Abort::Failed(format!(
+                            "block {expected} failed {MAX_RETRIES} times"
+                        )));
+                    }
+                    self.port.write(&[NAK])?;
+                }
+            }
+        }
+    }
+
+    fn receive_ymodem(&mut self, dir: &Path) -> Result<String, Abort> {
+        let mut files = 0;
+        let mut total = 0;
+        loop {
+            let crc = self.request_start()?;
+            let header = self.read_header(crc)?;
+            // An empty file name ends the batch.
+            let Some((name, size)) = parse_ymodem_header(&header) else {
+                self.port.write(&[ACK])?;
+                return Ok(format!("received {files} file(s), {total} bytes"));
+            };
+            let mut file = create_file(&dir.join(&name))?;
+            self.start_file(&name, size);
+            self.port.write(&[ACK])?;
+            self.port.write(&[if crc { CRC_REQUEST } else { NAK }])?;
+            self.receive_blocks(&mut file, crc, size)?;
+            files += 1;
+            total += self.bytes;
+        }
+    }
+
+    fn read_header(&mut self, crc: bool) -> Result<Vec<u8>, Abort> {
+        for _ in 0..MAX_RETRIES {
+            match self.read_packet(crc)? {
+                Packet::Block { number: 0, data } => return Ok(data),
+                _ => self.port.write(&[NAK])?,
+            }
+        }
+        Err(Abort::Failed("no valid YMODEM header".to_string()))
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+fn create_file(path: &Path) -> Result<File, String> {
+    File::create(path).map_err(|e| format!("failed to create {}: {e}", path.display()))
+}
+
+/// Parses `name\0size mtime mode...` from block 0. The name is reduced to
+/// its last component so a sender cannot write outside the directory.
+fn parse_ymodem_header(block: &[u8]) -> Option<(String, Option<u64>)> {
+    let end = block.iter().position(|byte| *byte == 0)?;
+    let name = String::from_utf8_lossy(&block[..end]).to_string();
+    let name = Path::new(&name).file_name()?.to_string_lossy().to_string();
+    let rest = &block[end + 1..];
+    let rest = &rest[..rest
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(rest.len())];
+    let size = String::from_utf8_lossy(rest)
+        .split_whitespace()
+        .next()
+        .and_then(|size| size.parse().ok());
+    Some((name, size))
+}
+
+/// Builds a packet, padding `data` to `size` with `pad`: SUB for data
+/// blocks, zeros for YMODEM block 0.
+fn encode_block(number: u8, data: &[u8], size: usize, pad: u8, crc: bool) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(size + 5);
+    packet.push(if size == 1024 { STX } else { SOH });
+    packet.push(number);
+    packet.push(!number);
+    let body = packet.len();
+    packet.extend_from_slice(data);
+    packet.resize(body + size, pad);
+    if crc {
+        packet.extend_from_slice(&crc16_xmodem(&packet[body..]).to_be_bytes());
+    } else {
+        packet.push(checksum(&packet[body..]));
+    }
+    packet
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::{self, Sender};
+
+    /// One direction of a null-modem cable. With `corrupt_first_block` set
+    /// the first data block is garbled once, so the receiver has to NAK it.
+    struct Wire {
+        tx: Sender<Vec<u8>>,
+        corrupt_first_block: bool,
+    }
+
+    impl TransferPort for Wire {
+        fn write(&mut self, data: &[u8]) -> Result<(), String> {
+            let mut data = data.to_vec();
+            if self.corrupt_first_block && data.len() > 128 && data[1] == 1 {
+                self.corrupt_first_block = false;
+                data[10] ^= 0xFF;
+            }
+            let _ = self.tx.send(data);
+            Ok(())
+        }
+    }
+
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 + 3) as u8).collect()
+    }
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("serial-transfer-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Sends `files` from one `Transfer` to another over a pair of channels
+    /// and returns both outcomes.
+    fn transfer(
+        protocol: TransferProtocol,
+        dir: &Path,
+        files: &[(&str, Vec<u8>)],
+        dest: PathBuf,
+        corrupt_first_block: bool,
+    ) -> (TransferState, TransferState) {
+        let paths = files
+            .iter()
+            .map(|(name, data)| {
+                let path = dir.join(name);
+                fs::write(&path, data).unwrap();
+                path
+            })
+            .collect();
+        let send = TransferJob::new(protocol, TransferDirection::Send(paths)).unwrap();
+        let receive = TransferJob::new(protocol, TransferDirection::Receive(dest)).unwrap();
+        let (to_receiver, receiver_rx) = mpsc::channel();
+        let (to_sender, sender_rx) = mpsc::channel();
+        let sender = thread::spawn(move || {
+            let cancel = AtomicBool::new(false);
+            let mut wire = Wire {
+                tx: to_receiver,
+                corrupt_first_block,
+            };
+            Transfer::new(&mut wire, sender_rx, &cancel, |_| {}).run(&send)
+        });
+        let cancel = AtomicBool::new(false);
+        let mut wire = Wire {
+            tx: to_sender,
+            corrupt_first_block: false,
+        };
+        let received = Transfer::new(&mut wire, receiver_rx, &cancel, |_| {}).run(&receive);
+        (sender.join().unwrap(), received)
+    }
+
+    #[test]
+    fn encodes_blocks() {
+        let packet = encode_block(1, b"123456789", 128, SUB, true);
+        assert_eq!(packet.len(), 133);
+        assert_eq!(packet[..3], [SOH, 0x01, 0xFE]);
+        assert_eq!(packet[3..12], *b"123456789");
+        assert!(packet[12..131].iter().all(|byte| *byte == SUB));
+        assert_eq!(packet[131..], [0xE4, 0x47]);
+
+        let packet = encode_block(1, b"123456789", 128, SUB, false);
+        assert_eq!(packet.len(), 132);
+        assert_eq!(packet[131], 0xF3);
+
+        let packet = encode_block(0xFF, &[], 1024, 0, true);
+        assert_eq!(packet[..3], [STX, 0xFF, 0x00]);
+        assert_eq!(packet.len(), 1029);
+    }
+
+    #[test]
+    fn parses_ymodem_headers() {
+        let packet = encode_block(0, b"hello.txt\x005 0\x00", 128, 0, true);
+        assert_eq!(packet[131..], [0x92, 0x23]);
+        assert_eq!(
+            parse_ymodem_header(&packet[3..131]),
+            Some(("hello.txt".to_string(), Some(5)))
+        );
+        assert_eq!(
+            parse_ymodem_header(b"../../etc/passwd\x00123 14537472350 0\x00"),
+            Some(("passwd".to_string(), Some(123)))
+        );
+        assert_eq!(
+            parse_ymodem_header(b"no-size.bin\x00\x00"),
+            Some(("no-size.bin".to_string(), None))
+        );
+        // An empty name marks the end of a batch.
+        assert_eq!(parse_ymodem_header(&[0u8; 128]), None);
+    }
+
+    #[test]
+    fn xmodem_recovers_from_a_corrupted_block() {
+        let dir = scratch_dir("xmodem");
+        let states = transfer(
+            TransferProtocol::Xmodem,
+            &dir,
+            &[("app.bin", pattern(300))],
+            dir.join("out.bin"),
+            true,
+        );
+        assert_eq!(states, (TransferState::Completed, TransferState::Completed));
+        // XMODEM has no length field: the last block arrives padded.
+        let received = fs::read(dir.join("out.bin")).unwrap();
+        assert_eq!(received.len(), 384);
+        assert_eq!(received[..300], pattern(300)[..]);
+        assert!(received[300..].iter().all(|byte| *byte == SUB));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn xmodem_1k_sends_a_short_tail_block() {
+        let dir = scratch_dir("xmodem-1k");
+        let states = transfer(
+            TransferProtocol::Xmodem1k,
+            &dir,
+            &[("app.bin", pattern(2100))],
+            dir.join("out.bin"),
+            false,
+        );
+        assert_eq!(states, (TransferState::Completed, TransferState::Completed));
+        let received = fs::read(dir.join("out.bin")).unwrap();
+        assert_eq!(received.len(), 2048 + 128);
+        assert_eq!(received[..2100], pattern(2100)[..]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn ymodem_sends_a_batch_with_names_and_sizes() {
+        let dir = scratch_dir("ymodem");
+        let files = [
+            ("firmware.bin", pattern(70_000)),
+            ("notes.txt", pattern(5)),
+            ("empty", Vec::new()),
+        ];
+        let states = transfer(
+            TransferProtocol::Ymodem,
+            &dir,
+            &files,
+            dir.join("out"),
+            true,
+        );
+        assert_eq!(states, (TransferState::Completed, TransferState::Completed));
+        for (name, data) in &files {
+            assert_eq!(
+                &fs::read(dir.join("out").join(name)).unwrap(),
+                data,
+                "{name}"
+            );
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn validates_jobs_up_front() {
+        let dir = scratch_dir("jobs");
+        let file = dir.join("a.bin");
+        fs::write(&file, b"a").unwrap();
+        let batch = TransferDirection::Send(vec![file.clone(), file.clone()]);
+        assert!(TransferJob::new(TransferProtocol::Xmodem, batch.clone()).is_err());
+        assert!(TransferJob::new(TransferProtocol::Ymodem, batch).is_ok());
+        let missing = TransferDirection::Send(vec![dir.join("missing.bin")]);
+        assert!(TransferJob::new(TransferProtocol::Xmodem, missing).is_err());
+        let into_dir = TransferDirection::Receive(dir.clone());
+        assert!(TransferJob::new(TransferProtocol::Xmodem, into_dir).is_err());
+
+        assert!(TransferProtocol::parse("raw", Some(0), None).is_err());
+        assert_eq!(
+            TransferProtocol::parse("XMODEM-1K", None, None).unwrap(),
+            TransferProtocol::Xmodem1k
+        );
+        assert!(TransferProtocol::parse("zmodem", None, None).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,615 @@
+use crate::serial_assistant::ModemSignals;
+use crate::serial_network::{self, Control, Transport};
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// Raw captures are replayed in chunks this size, paced at the line rate.
+const REPLAY_CHUNK: usize = 64;
+const MAX_ECHO_DELAY: Duration = Duration::from_secs(60);
+/// How often an idle device checks whether it should stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReplayStep {
+    Wait(Duration),
+    Send(Vec<u8>),
+    /// Holds the replay until the host sends something, where the capture
+    /// recorded a TX line.
+    AwaitInput,
+}
+
+/// What a simulated device does with the port.
+#[derive(Debug)]
+pub enum DeviceScript {
+    /// Sends back whatever it receives after `delay`.
+    Echo { delay: Duration },
+    /// Plays recorded output; `repeat` starts over at the end.
+    Replay {
+        steps: Vec<ReplayStep>,
+        repeat: bool,
+    },
+}
+
+impl DeviceScript {
+    /// Parses `sim://echo?delay=<ms>` or
+    /// `sim://replay?file=<path>&speed=<factor>&loop=<bool>`. Raw `.bin`
+    /// captures are paced at `baud_rate`; `.log` captures keep their
+    /// recorded timing.
+    pub fn parse(device: &str, baud_rate: u32) -> Result<Self, String> {
+        let url = Url::parse(device).map_err(|e| format!("invalid device {device}: {e}"))?;
+        if url.scheme() != "sim" {
+            return Err(format!(
+                "virtual device must start with sim:// (got {device})"
+            ));
+        }
+        let query: HashMap<String, String> = url.query_pairs().into_owned().collect();
+        match url.host_str().unwrap_or_default() {
+            "echo" => {
+                let delay = match query.get("delay") {
+                    Some(delay) => Duration::from_millis(
+                        delay
+                            .parse()
+                            .map_err(|_| format!("invalid echo delay: {delay}"))?,
+                    ),
+                    None => Duration::ZERO,
+                };
+                if delay > MAX_ECHO_DELAY {
+                    return Err("echo delay must be at most 60000 ms".to_string());
+                }
+                Ok(Self::Echo { delay })
+            }
+            "replay" => {
+                let file = query
+                    .get("file")
+                    .filter(|file| !file.is_empty())
+                    .ok_or_else(|| "replay needs a capture file".to_string())?;
+                let speed = match query.get("speed") {
+                    Some(speed) => speed
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|speed| speed.is_finite() && *speed > 0.0)
+                        .ok_or_else(|| format!("invalid replay speed: {speed}"))?,
+                    None => 1.0,
+                };
+                let repeat = matches!(
+                    query.get("loop").map(String::as_str),
+                    Some("1" | "true" | "yes")
+                );
+                Ok(Self::Replay {
+                    steps: load_replay(Path::new(file), baud_rate, speed)?,
+                    repeat,
+                })
+            }
+            other => Err(format!(
+                "virtual device must be sim://echo or sim://replay (got sim://{other})"
+            )),
+        }
+    }
+}
+
+fn load_replay(path: &Path, baud_rate: u32, speed: f64) -> Result<Vec<ReplayStep>, String> {
+    let data =
+        std::fs::read(path).map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+    let is_log = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("log"));
+    let steps = if is_log {
+        parse_capture_log(&String::from_utf8_lossy(&data), speed)
+    } else {
+        pace_raw(&data, baud_rate, speed)
+    };
+    if !steps.iter().any(|step| matches!(step, ReplayStep::Send(_))) {
+        return Err(format!("{} has no received data to replay", path.display()));
+    }
+    Ok(steps)
+}
+
+/// Splits raw RX bytes into chunks sent at the rate a UART at `baud_rate`
+/// (10 bits per byte) would deliver them.
+fn pace_raw(data: &[u8], baud_rate: u32, speed: f64) -> Vec<ReplayStep> {
+    let byte_time = 10.0 / f64::from(baud_rate.max(1)) / speed;
+    data.chunks(REPLAY_CHUNK)
+        .flat_map(|chunk| {
+            [
+                ReplayStep::Send(chunk.to_vec()),
+                ReplayStep::Wait(Duration::from_secs_f64(byte_time * chunk.len() as f64)),
+            ]
+        })
+        .collect()
+}
+
+/// Turns `[2024-01-02 03:04:05.678Z] RX: text` lines, as written by
+/// `CaptureWriter`, into timed steps. TX lines become points where the
+/// replay waits for the host.
+fn parse_capture_log(text: &str, speed: f64) -> Vec<ReplayStep> {
+    let mut steps = Vec::new();
+    let mut previous: Option<u64> = None;
+    for line in text.lines() {
+        let Some((time_ms, direction, payload)) = parse_log_line(line) else {
+            continue;
+        };
+        if let Some(previous) = previous {
+            // Lines are stamped with the time of day only, so a capture
+            // running past midnight wraps.
+            let delta = (time_ms + 86_400_000 - previous) % 86_400_000;
+            if delta > 0 {
+                steps.push(ReplayStep::Wait(Duration::from_secs_f64(
+                    delta as f64 / 1000.0 / speed,
+                )));
+            }
+        }
+        previous = Some(time_ms);
+        match direction {
+            "RX" => {
+                let mut data = payload.as_bytes().to_vec();
+                data.extend_from_slice(b"\r\n");
+                steps.push(ReplayStep::Send(data));
+            }
+            _ => steps.push(ReplayStep::AwaitInput),
+        }
+    }
+    steps
+}
+
+fn parse_log_line(line: &str) -> Option<(u64, &str, &str)> {
+    let rest = line.strip_prefix('[')?;
+    let (stamp, rest) = rest.split_once("] ")?;
+    let (direction, payload) = rest.split_once(": ")?;
+    if direction != "RX" && direction != "TX" {
+        return None;
+    }
+    let time = stamp.split_once(' ')?.1.trim_end_matches('Z');
+    let (clock, millis) = time.split_once('.')?;
+    let mut fields = clock.split(':').map(|field| field.parse::<u64>().ok());
+    let (hour, minute, second) = (fields.next()??, fields.next()??, fields.next()??);
+    let millis: u64 = millis.parse().ok()?;
+    Some((
+        ((hour * 60 + minute) * 60 + second) * 1000 + millis,
+        direction,
+        payload,
+    ))
+}
+
+/// Waits until `deadline`, or for host input when there is none. Returns
+/// `false` once the host hung up or the device was stopped.
+fn idle(input: &Receiver<Vec<u8>>, deadline: Option<Instant>, stop: &AtomicBool) -> bool {
+    loop {
+        if stop.load(Ordering::SeqCst) {
+            return false;
+        }
+        let slice = match deadline {
+            Some(deadline) => {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    return true;
+                }
+                left.min(POLL_INTERVAL)
+            }
+            None => POLL_INTERVAL,
+        };
+        match input.recv_timeout(slice) {
+            Ok(_) if deadline.is_none() => return true,
+            Ok(_) | Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return false,
+        }
+    }
+}
+
+/// Runs `script` against host writes arriving on `input` until the host
+/// hangs up or `stop` is set. A finished replay leaves the device silent
+/// rather than disconnecting it.
+pub fn run_device(
+    script: &DeviceScript,
+    input: &Receiver<Vec<u8>>,
+    mut output: impl FnMut(&[u8]) -> Result<(), String>,
+    stop: &AtomicBool,
+) -> Result<(), String> {
+    match script {
+        DeviceScript::Echo { delay } => {
+            let mut queue: VecDeque<(Instant, Vec<u8>)> = VecDeque::new();
+            while !stop.load(Ordering::SeqCst) {
+                let wait = queue.front().map_or(POLL_INTERVAL, |(due, _)| {
+                    due.saturating_duration_since(Instant::now())
+                        .min(POLL_INTERVAL)
+                });
+                match input.recv_timeout(wait) {
+                    Ok(data) => queue.push_back((Instant::now() + *delay, data)),
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => return Ok(()),
+                }
+                let now = Instant::now();
+                while queue.front().is_some_and(|(due, _)| *due <= now) {
+                    if let Some((_, data)) = queue.pop_front() {
+                        output(&data)?;
+                    }
+                }
+            }
+            Ok(())
+        }
+        DeviceScript::Replay { steps, repeat } => {
+            loop {
+                for step in steps {
+                    let running = match step {
+                        ReplayStep::Wait(wait) => idle(input, Some(Instant::now() + *wait), stop),
+                        ReplayStep::Send(data) => {
+                            output(data)?;
+                            true
+                        }
+                        ReplayStep::AwaitInput => idle(input, None, stop),
+                    };
+                    if !running {
+                        return Ok(());
+                    }
+                }
+                if !repeat {
+                    break;
+                }
+            }
+            while idle(input, None, stop) {}
+            Ok(())
+        }
+    }
+}
+
+/// An in-process device behind a `sim://` port. Control lines are wired
+/// like a null modem: CTS follows RTS, DSR and CD follow DTR.
+pub struct Loopback {
+    input: Mutex<Sender<Vec<u8>>>,
+    output: Mutex<Receiver<Vec<u8>>>,
+    pending: Mutex<Vec<u8>>,
+    stop: Arc<AtomicBool>,
+    rts: AtomicBool,
+    dtr: AtomicBool,
+}
+
+impl Loopback {
+    pub fn start(script: DeviceScript) -> Self {
+        let (input_tx, input_rx) = mpsc::channel::<Vec<u8>>();
+        let (output_tx, output_rx) = mpsc::channel::<Vec<u8>>();
+        let stop = Arc::new(AtomicBool::new(false));
+        let device_stop = Arc::clone(&stop);
+        thread::spawn(move || {
+            let _ = run_device(
+                &script,
+                &input_rx,
+                |data| {
+                    output_tx
+                        .send(data.to_vec())
+                        .map_err(|_| "virtual port closed".to_string())
+                },
+                &device_stop,
+            );
+        });
+        Self {
+            input: Mutex::new(input_tx),
+            output: Mutex::new(output_rx),
+            pending: Mutex::new(Vec::new()),
+            stop,
+            rts: AtomicBool::new(false),
+            dtr: AtomicBool::new(false),
+        }
+    }
+}
+
+impl Drop for Loopback {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> io::Result<std::sync::MutexGuard<'_, T>> {
+    mutex
+        .lock()
+        .map_err(|_| io::Error::other("virtual port lock poisoned"))
+}
+
+impl Transport for Loopback {
+    fn read(&self, buffer: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        let mut pending = lock(&self.pending)?;
+        if pending.is_empty() {
+            match lock(&self.output)?.recv_timeout(timeout) {
+                Ok(data) => pending.extend(data),
+                Err(RecvTimeoutError::Timeout) => return Err(io::ErrorKind::TimedOut.into()),
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::ConnectionAborted,
+                        "virtual device stopped",
+                    ))
+                }
+            }
+        }
+        Ok(serial_network::take_pending(&mut pending, buffer))
+    }
+
+    fn write(&self, data: &[u8]) -> io::Result<()> {
+        lock(&self.input)?
+            .send(data.to_vec())
+            .map_err(|_| io::Error::new(io::ErrorKind::ConnectionAborted, "virtual device stopped"))
+    }
+
+    fn control(&self, control: Control) -> serialport::Result<()> {
+        match control {
+            Control::Rts(level) => self.rts.store(level, Ordering::SeqCst),
+            Control::Dtr(level) => self.dtr.store(level, Ordering::SeqCst),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn modem(&self) -> serialport::Result<ModemSignals> {
+        let dtr = self.dtr.load(Ordering::SeqCst);
+        Ok(ModemSignals {
+            cts: self.rts.load(Ordering::SeqCst),
+            dsr: dtr,
+            ri: false,
+            cd: dtr,
+        })
+    }
+}
+
+/// A simulator attached to a pseudo-terminal, for tools outside the app.
+struct VirtualPty {
+    stop: Arc<AtomicBool>,
+    handle: thread::JoinHandle<()>,
+}
+
+/// Simulators published on pseudo-terminals, keyed by the path clients open.
+#[derive(Default)]
+pub struct VirtualDeviceState {
+    ptys: Mutex<HashMap<String, VirtualPty>>,
+}
+
+impl VirtualDeviceState {
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, HashMap<String, VirtualPty>>, String> {
+        self.ptys
+            .lock()
+            .map_err(|_| "failed to lock virtual devices".to_string())
+    }
+
+    /// Lists the pseudo-terminal paths that have a simulator running.
+    pub fn list(&self) -> Result<Vec<String>, String> {
+        let mut ptys = self.lock()?;
+        ptys.retain(|_, pty| !pty.handle.is_finished());
+        let mut paths: Vec<String> = ptys.keys().cloned().collect();
+        paths.sort();
+        Ok(paths)
+    }
+
+    /// Creates a pseudo-terminal pair, runs `script` on the master side and
+    /// returns the slave path, which opens like any serial device.
+    #[cfg(unix)]
+    pub fn start_pty(&self, script: DeviceScript) -> Result<String, String> {
+        use serialport::{SerialPort, TTYPort};
+        use std::io::{Read, Write};
+
+        let (mut master, slave) =
+            TTYPort::pair().map_err(|e| format!("failed to create pseudo-terminal: {e}"))?;
+        let path = slave
+            .name()
+            .ok_or_else(|| "pseudo-terminal has no name".to_string())?;
+        let mut reader = master
+            .try_clone_native()
+            .map_err(|e| format!("failed to clone pseudo-terminal: {e}"))?;
+        reader
+            .set_timeout(POLL_INTERVAL)
+            .map_err(|e| format!("failed to configure pseudo-terminal: {e}"))?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let (input_tx, input_rx) = mpsc::channel::<Vec<u8>>();
+        let reader_stop = Arc::clone(&stop);
+        thread::spawn(move || {
+            let mut buffer = [0u8; 4096];
+            while !reader_stop.load(Ordering::SeqCst) {
+                match reader.read(&mut buffer) {
+                    Ok(size) if size > 0 => {
+                        if input_tx.send(buffer[..size].to_vec()).is_err() {
+                            break;
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(err) if err.kind() == io::ErrorKind::TimedOut => {}
+                    Err(_) => break,
+                }
+            }
+        });
+
+        let device_stop = Arc::clone(&stop);
+        let handle = thread::spawn(move || {
+            // Holding the slave open keeps the master readable while no
+            // client has the device open.
+            let _slave = slave;
+            let _ = run_device(
+                &script,
+                &input_rx,
+                |data| {
+                    master
+                        .write_all(data)
+                        .map_err(|e| format!("failed to write pseudo-terminal: {e}"))
+                },
+                &device_stop,
+            );
+            device_stop.store(true, Ordering::SeqCst);
+        });
+        self.lock()?
+            .insert(path.clone(), VirtualPty { stop, handle });
+        Ok(path)
+    }
+
+    #[cfg(not(unix))]
+    pub fn start_pty(&self, _script: DeviceScript) -> Result<String, String> {
+        Err(
+            "pseudo-terminals are only available on Linux and macOS; open a sim:// port instead"
+                .to_string(),
+        )
+    }
+
+    pub fn stop_pty(&self, path: &str) -> Result<(), String> {
+        if let Some(pty) = self.lock()?.remove(path) {
+            pty.stop.store(true, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serial_assistant::LineSettings;
+    use crate::serial_capture::{CaptureWriter, Direction, RotationPolicy};
+    use serialport::SerialPort;
+    use std::io::{Read, Write};
+    use std::path::PathBuf;
+
+    const READ_TIMEOUT: Duration = Duration::from_millis(20);
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("serial-virtual-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Opens `device` the way a session does.
+    fn open(device: &str) -> Box<dyn SerialPort> {
+        let settings = LineSettings::parse(115200, 8, 1, "none", "none").unwrap();
+        serial_network::open(device, settings, READ_TIMEOUT).unwrap()
+    }
+
+    fn read_for(port: &mut dyn Read, count: usize, limit: Duration) -> Vec<u8> {
+        let mut received = Vec::new();
+        let mut buffer = [0u8; 256];
+        let deadline = Instant::now() + limit;
+        while received.len() < count && Instant::now() < deadline {
+            match port.read(&mut buffer) {
+                Ok(read) => received.extend_from_slice(&buffer[..read]),
+                Err(err) if err.kind() == io::ErrorKind::TimedOut => {}
+                Err(err) => panic!("read failed: {err}"),
+            }
+        }
+        received
+    }
+
+    #[test]
+    fn parses_capture_log_timing() {
+        let text = "[2024-01-02 23:59:59.900Z] RX: boot\n\
+                    [2024-01-03 00:00:00.100Z] TX: AT\n\
+                    not a capture line\n\
+                    [2024-01-03 00:00:00.150Z] RX: OK\n";
+        assert_eq!(
+            parse_capture_log(text, 2.0),
+            vec![
+                ReplayStep::Send(b"boot\r\n".to_vec()),
+                ReplayStep::Wait(Duration::from_millis(100)),
+                ReplayStep::AwaitInput,
+                ReplayStep::Wait(Duration::from_millis(25)),
+                ReplayStep::Send(b"OK\r\n".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_bad_devices() {
+        assert!(DeviceScript::parse("sim://nope", 9600).is_err());
+        assert!(DeviceScript::parse("sim://echo?delay=x", 9600).is_err());
+        assert!(DeviceScript::parse("sim://echo?delay=60001", 9600).is_err());
+        let err = DeviceScript::parse("sim://replay", 9600).unwrap_err();
+        assert!(err.contains("capture file"), "{err}");
+    }
+
+    #[test]
+    fn echo_returns_writes_after_the_delay() {
+        let mut port = open("sim://echo?delay=100");
+        let mut reader = port.try_clone().unwrap();
+        let started = Instant::now();
+        port.write_all(b"abc").unwrap();
+        port.write_all(b"def").unwrap();
+        assert!(read_for(&mut reader, 1, Duration::from_millis(60)).is_empty());
+        assert_eq!(read_for(&mut reader, 6, Duration::from_secs(2)), b"abcdef");
+        assert!(started.elapsed() >= Duration::from_millis(100));
+
+        // RTS is looped back to CTS.
+        port.write_request_to_send(true).unwrap();
+        assert!(reader.read_clear_to_send().unwrap());
+        assert!(!reader.read_data_set_ready().unwrap());
+    }
+
+    #[test]
+    fn replays_a_recorded_capture() {
+        let dir = scratch_dir("replay");
+        let mut writer = CaptureWriter::create(&dir, "COM3", RotationPolicy::default()).unwrap();
+        let log_path = writer.log_path().to_path_buf();
+        writer.record(Direction::Rx, b"hello\n").unwrap();
+        thread::sleep(Duration::from_millis(50));
+        writer.record(Direction::Tx, b"go\n").unwrap();
+        writer.record(Direction::Rx, b"done\n").unwrap();
+        writer.finish().unwrap();
+
+        let mut port = open(&format!("sim://replay?file={}", log_path.display()));
+        assert_eq!(read_for(&mut port, 7, Duration::from_secs(1)), b"hello\r\n");
+        // The capture shows the host speaking next, so nothing more arrives
+        // until it does.
+        assert!(read_for(&mut port, 1, Duration::from_millis(200)).is_empty());
+        port.write_all(b"go\n").unwrap();
+        assert_eq!(read_for(&mut port, 6, Duration::from_secs(1)), b"done\r\n");
+
+        let raw_path = log_path.with_extension("rx.bin");
+        let mut port = open(&format!("sim://replay?file={}", raw_path.display()));
+        assert_eq!(
+            read_for(&mut port, 11, Duration::from_secs(1)),
+            b"hello\ndone\n"
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn serves_devices_on_pseudo_terminals() {
+        let dir = scratch_dir("pty");
+        let raw_path = dir.join("boot.rx.bin");
+        std::fs::write(&raw_path, [7u8; 200]).unwrap();
+        let state = VirtualDeviceState::default();
+        let replay = state
+            .start_pty(
+                DeviceScript::parse(
+                    &format!("sim://replay?file={}&loop=1", raw_path.display()),
+                    115200,
+                )
+                .unwrap(),
+            )
+            .unwrap();
+        let echo = state
+            .start_pty(DeviceScript::parse("sim://echo", 115200).unwrap())
+            .unwrap();
+        let mut expected = vec![replay.clone(), echo.clone()];
+        expected.sort();
+        assert_eq!(state.list().unwrap(), expected);
+
+        let mut tty = serialport::new(&replay, 115200)
+            .timeout(READ_TIMEOUT)
+            .open()
+            .unwrap();
+        let received = read_for(&mut tty, 400, Duration::from_secs(2));
+        assert!(received.len() >= 400, "looped {} bytes", received.len());
+        assert!(received.iter().all(|byte| *byte == 7));
+
+        let mut tty = serialport::new(&echo, 115200)
+            .timeout(READ_TIMEOUT)
+            .open()
+            .unwrap();
+        tty.write_all(b"xyz").unwrap();
+        assert_eq!(read_for(&mut tty, 3, Duration::from_secs(2)), b"xyz");
+
+        state.stop_pty(&replay).unwrap();
+        state.stop_pty(&echo).unwrap();
+        assert!(state.list().unwrap().is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -40,6 +40,7 @@ const messages = {
       flash: "烧录",
       open: "打开",
       remove: "删除",
+      inspect: "信息",
       eraseFlash: "擦除固件",
       eraseFlashInfo: "烧录前先擦除Flash",
       path: "路径",
@@ -68,6 +69,8 @@ const messages = {
       table: "表格",
       text: "文本",
       partitionTableSize: "分区表大小",
+      exportBinary: "导出BIN",
+      exported: "分区表已导出到 {path}",
     },
     firmware: {
       path: "历史路径",
@@ -125,11 +128,79 @@ const messages = {
       sendHex: "HEX发送",
       sendNewline: "发送换行",
       periodicSend: "周期发送",
+      periodicCount: "次数",
+      periodicCountHint: "0 表示一直发送直到停止",
+      periodicCounter: "计数字节",
       ms: "毫秒",
       displayText: "文本显示",
       displayHex: "HEX显示",
       displayTime: "显示时间",
       displayTxRx: "显示收发",
+      captureToDisk: "保存到磁盘",
+      captureFiles: "已保存的记录",
+      captureOpen: "打开",
+      captureReplay: "回放",
+      framing: "分帧",
+      framingRaw: "原始",
+      framingNewline: "按行",
+      framingIdle: "空闲间隔(ms)",
+      framingFixed: "定长",
+      framingDelimiter: "分隔字节",
+      logFilter: "日志过滤",
+      logFilterOff: "关闭",
+      logDenyTags: "屏蔽TAG，逗号分隔",
+      coreDumpSaved: "核心转储已保存",
+      runSequence: "复位",
+      sequenceHardReset: "复位运行",
+      sequenceBootloader: "进入下载模式",
+      sequenceUsbJtag: "USB-JTAG 下载模式",
+      sequenceHardResetInverted: "复位运行（反相）",
+      sequenceBootloaderInverted: "下载模式（反相）",
+      sequenceCustom: "自定义序列",
+      scriptPanel: "脚本",
+      scriptPlaceholder: "send AT\\r\\n\nexpect 1000 OK",
+      scriptRun: "运行",
+      scriptCancel: "停止",
+      transferPanel: "文件传输",
+      transferChunk: "分块",
+      transferDelay: "间隔",
+      transferSend: "发送文件",
+      transferReceive: "接收文件",
+      transferCancel: "取消",
+      modbusPanel: "Modbus RTU",
+      modbusDecode: "解析 RTU 帧",
+      modbusUnit: "从站",
+      modbusAddress: "地址",
+      modbusQuantity: "数量",
+      modbusHolding: "保持寄存器",
+      modbusInput: "输入寄存器",
+      modbusPoll: "轮询",
+      modbusStop: "停止",
+      checksum: "校验",
+      rxChecksum: "接收校验",
+      checksumNone: "无",
+      checksumDefaultEndian: "默认",
+      rfc2217Panel: "远程访问 (RFC 2217)",
+      rfc2217AddressHint: "监听地址，127.0.0.1 仅本机，0.0.0.0 允许局域网访问",
+      rfc2217Start: "启动",
+      rfc2217Stop: "停止",
+      rfc2217Listening: "监听中：rfc2217://{address}",
+      portUrlHint: "选择串口，或输入 rfc2217://、tcp://、ws://、sim:// 地址",
+      virtualPanel: "虚拟设备",
+      virtualEcho: "回显",
+      virtualReplay: "回放",
+      virtualDelay: "延时",
+      virtualSpeed: "倍速",
+      virtualLoop: "循环",
+      virtualFile: "选择抓包",
+      virtualFileHint: "未选择文件（.log 按时间回放，其他按原始字节）",
+      virtualUse: "作为串口",
+      virtualPty: "创建虚拟串口",
+      virtualPtyStop: "关闭",
+      virtualPtyStarted: "虚拟串口已创建：{path}",
+      autoReconnect: "自动重连",
+      reconnectTimeoutHint: "设备断开后等待重新出现的最长时间，0 表示一直等待",
+      seconds: "秒",
       autoScroll: "自动滚动",
       sendTextPlaceholder: "输入要发送的文本",
       sendHexPlaceholder: "输入HEX数据，例如：48 65 6C 6C 6F",
@@ -247,6 +318,7 @@ const messages = {
       flash: "Flash",
       open: "Open",
       remove: "Remove",
+      inspect: "Info",
       eraseFlash: "Erase Flash",
       eraseFlashInfo: "Erase before burning",
       path: "Path",
@@ -275,6 +347,8 @@ const messages = {
       table: "Table",
       text: "Text",
       partitionTableSize: "Partition table size",
+      exportBinary: "Export BIN",
+      exported: "Partition table exported to {path}",
     },
     firmware: {
       path: "History Path",
@@ -332,11 +406,79 @@ const messages = {
       sendHex: "Send HEX",
       sendNewline: "Send Newline",
       periodicSend: "Periodic Send",
+      periodicCount: "Count",
+      periodicCountHint: "0 sends until stopped",
+      periodicCounter: "Counter byte",
       ms: "ms",
       displayText: "Display Text",
       displayHex: "Display HEX",
       displayTime: "Show Time",
       displayTxRx: "Show TX/RX",
+      captureToDisk: "Capture to disk",
+      captureFiles: "Saved captures",
+      captureOpen: "Open",
+      captureReplay: "Replay",
+      framing: "Framing",
+      framingRaw: "Raw",
+      framingNewline: "Line",
+      framingIdle: "Idle gap (ms)",
+      framingFixed: "Fixed length",
+      framingDelimiter: "Delimiter byte",
+      logFilter: "Log filter",
+      logFilterOff: "Off",
+      logDenyTags: "Muted tags, comma separated",
+      coreDumpSaved: "Core dump saved",
+      runSequence: "Reset",
+      sequenceHardReset: "Reset to app",
+      sequenceBootloader: "Download mode",
+      sequenceUsbJtag: "USB-JTAG download mode",
+      sequenceHardResetInverted: "Reset to app (inverted)",
+      sequenceBootloaderInverted: "Download mode (inverted)",
+      sequenceCustom: "Custom sequence",
+      scriptPanel: "Script",
+      scriptPlaceholder: "send AT\\r\\n\nexpect 1000 OK",
+      scriptRun: "Run",
+      scriptCancel: "Stop",
+      transferPanel: "File Transfer",
+      transferChunk: "Chunk",
+      transferDelay: "Delay",
+      transferSend: "Send File",
+      transferReceive: "Receive File",
+      transferCancel: "Cancel",
+      modbusPanel: "Modbus RTU",
+      modbusDecode: "Decode RTU frames",
+      modbusUnit: "Unit",
+      modbusAddress: "Address",
+      modbusQuantity: "Count",
+      modbusHolding: "Holding regs",
+      modbusInput: "Input regs",
+      modbusPoll: "Poll",
+      modbusStop: "Stop",
+      checksum: "Checksum",
+      rxChecksum: "RX checksum",
+      checksumNone: "None",
+      checksumDefaultEndian: "Default",
+      rfc2217Panel: "Remote Access (RFC 2217)",
+      rfc2217AddressHint: "Listen address: 127.0.0.1 for this machine only, 0.0.0.0 for the LAN",
+      rfc2217Start: "Start",
+      rfc2217Stop: "Stop",
+      rfc2217Listening: "Listening: rfc2217://{address}",
+      portUrlHint: "Pick a port or type an rfc2217://, tcp://, ws:// or sim:// address",
+      virtualPanel: "Virtual device",
+      virtualEcho: "Echo",
+      virtualReplay: "Replay",
+      virtualDelay: "Delay",
+      virtualSpeed: "Speed",
+      virtualLoop: "Loop",
+      virtualFile: "Pick capture",
+      virtualFileHint: "No file (.log replays with timing, others as raw bytes)",
+      virtualUse: "Use as port",
+      virtualPty: "Create pty",
+      virtualPtyStop: "Close",
+      virtualPtyStarted: "Virtual port created: {path}",
+      autoReconnect: "Auto reconnect",
+      reconnectTimeoutHint: "How long to wait for the device to come back; 0 waits until you disconnect",
+      seconds: "s",
       autoScroll: "Auto Scroll",
       sendTextPlaceholder: "Enter text to send",
       sendHexPlaceholder: "Enter HEX bytes, for example: 48 65 6C 6C 6F",
@@ -6,6 +6,19 @@ export interface FileInfo {
   createTime: number;
 }
 
+export interface SerialPortInfo {
+  name: string;
+  type: "usb" | "pci" | "bluetooth" | "unknown";
+  vid?: number;
+  pid?: number;
+  serialNumber?: string;
+  manufacturer?: string;
+  product?: string;
+  interface?: number;
+  bridge?: string;
+  nativeUsb: boolean;
+}
+
 export interface Firmware {
   size?: string;
   check: boolean;
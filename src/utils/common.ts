@@ -1,4 +1,5 @@
 import { invoke } from "@tauri-apps/api/core";
+import { listen } from "@tauri-apps/api/event";
 import {
   readTextFile,
   writeTextFile,
@@ -7,7 +8,7 @@ import {
   FileEntry,
 } from "@tauri-apps/plugin-fs";
 import { save } from "@tauri-apps/plugin-dialog";
-import { FileInfo, Firmware } from "@/model/model";
+import { FileInfo, Firmware, SerialPortInfo } from "@/model/model";
 import prettyBytes from "pretty-bytes";
 
 export async function saveFileDialog() {
@@ -37,8 +38,44 @@ export async function saveTextFileDialog(defaultPath?: string) {
   return filePath;
 }
 
+export function normalizeSerialPortInfo(info: any) {
+  return {
+    name: info.name,
+    type: info.type,
+    vid: info.vid ?? undefined,
+    pid: info.pid ?? undefined,
+    serialNumber: info.serial_number ?? undefined,
+    manufacturer: info.manufacturer ?? undefined,
+    product: info.product ?? undefined,
+    interface: info.interface ?? undefined,
+    bridge: info.bridge ?? undefined,
+    nativeUsb: info.native_usb,
+  } as SerialPortInfo;
+}
+
 export async function getSerialPortList() {
-  return (await invoke("get_serial_port_list")) as string[];
+  const list = (await invoke("get_serial_port_list")) as any[];
+  return list.map(normalizeSerialPortInfo);
+}
+
+export async function listenSerialPortChanges(
+  handler: (change: "added" | "removed", port: SerialPortInfo) => void,
+) {
+  const unlistenAdded = await listen("serial_port_added", (event) =>
+    handler("added", normalizeSerialPortInfo(event.payload)),
+  );
+  const unlistenRemoved = await listen("serial_port_removed", (event) =>
+    handler("removed", normalizeSerialPortInfo(event.payload)),
+  );
+  return () => {
+    unlistenAdded();
+    unlistenRemoved();
+  };
+}
+
+export function serialPortLabel(port: SerialPortInfo) {
+  const detail = port.bridge ?? port.product;
+  return detail ? `${port.name} (${detail})` : port.name;
 }
 
 export async function getCurrentDir() {
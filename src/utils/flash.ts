@@ -0,0 +1,187 @@
+import { invoke } from "@tauri-apps/api/core";
+import { listen } from "@tauri-apps/api/event";
+import { writeln } from "@/bus/terminal";
+
+export interface FlashFile {
+  address: string;
+  path: string;
+}
+
+export interface FlashWriteEventPayload {
+  kind: "status" | "progress" | "error";
+  text: string;
+  index: number;
+  address: number;
+  written: number;
+  total: number;
+}
+
+const formatAddress = (address: number) =>
+  `0x${address.toString(16).padStart(8, "0")}`;
+
+async function withFlashLog<T>(task: () => Promise<T>) {
+  let lastPercent = -1;
+  const unlisten = await listen<FlashWriteEventPayload>(
+    "flash_write_event",
+    (event) => {
+      const payload = event.payload;
+      if (payload.kind !== "progress") {
+        lastPercent = -1;
+        writeln(payload.text);
+        return;
+      }
+
+      const percent = Math.floor((payload.written * 100) / payload.total);
+      if (percent !== lastPercent && (percent % 10 === 0 || percent === 100)) {
+        lastPercent = percent;
+        writeln(
+          `Writing at ${formatAddress(payload.address + payload.written)}... (${percent} %)`,
+        );
+      }
+    },
+  );
+
+  try {
+    return await task();
+  } finally {
+    unlisten();
+  }
+}
+
+export async function flashWrite(
+  port: string,
+  baudRate: number,
+  flashMode: string,
+  files: FlashFile[],
+) {
+  return withFlashLog(() =>
+    invoke("flash_write", {
+      port,
+      baudRate,
+      flashMode,
+      files: files.map((item) => ({ address: item.address, path: item.path })),
+    }),
+  );
+}
+
+export async function ramLoad(port: string, baudRate: number, path: string) {
+  return withFlashLog(() => invoke("ram_load", { port, baudRate, path }));
+}
+
+export interface MergeRegion {
+  kind: "image" | "padding";
+  address: number;
+  size: number;
+  path: string;
+  patched: boolean;
+}
+
+export interface MergeFirmwareResult {
+  path: string;
+  size: number;
+  regions: MergeRegion[];
+}
+
+export interface MergeFlashParams {
+  flashMode?: string;
+  flashFreq?: string;
+  flashSize?: string;
+}
+
+export async function mergeFirmware(
+  chip: string,
+  files: FlashFile[],
+  outputName: string,
+  params: MergeFlashParams = {},
+) {
+  return (await invoke("merge_firmware", {
+    chip,
+    files: files.map((item) => ({ address: item.address, path: item.path })),
+    outputName,
+    flashMode: params.flashMode,
+    flashFreq: params.flashFreq,
+    flashSize: params.flashSize,
+  })) as MergeFirmwareResult;
+}
+
+export function formatMergeRegion(region: MergeRegion) {
+  const end = formatAddress(region.address + region.size - 1);
+  const source = region.kind === "padding" ? "(0xFF padding)" : region.path;
+  const patched = region.patched ? " [header patched]" : "";
+  return `${formatAddress(region.address)}-${end} ${source}${patched}`;
+}
+
+export interface FirmwareSegmentInfo {
+  address: number;
+  length: number;
+  file_offset: number;
+}
+
+export interface FirmwareAppDescription {
+  secure_version: number;
+  version: string;
+  project_name: string;
+  compile_time: string;
+  compile_date: string;
+  idf_version: string;
+  elf_sha256: string;
+}
+
+export interface FirmwareImageInfo {
+  size: number;
+  chip: string;
+  chip_id: number | null;
+  segment_count: number;
+  flash_mode: string;
+  flash_freq: string;
+  flash_size: string;
+  entry: number;
+  wp_pin: number | null;
+  min_chip_rev: string | null;
+  max_chip_rev: string | null;
+  segments: FirmwareSegmentInfo[];
+  checksum_stored: number;
+  checksum_calculated: number;
+  checksum_valid: boolean;
+  sha256_stored: string | null;
+  sha256_calculated: string | null;
+  sha256_valid: boolean | null;
+  app_desc: FirmwareAppDescription | null;
+}
+
+export async function inspectFirmwareImage(path: string) {
+  return (await invoke("inspect_firmware_image", { path })) as FirmwareImageInfo;
+}
+
+const validity = (valid: boolean) => (valid ? "valid" : "invalid");
+
+export function formatFirmwareImageInfo(info: FirmwareImageInfo) {
+  const lines = [
+    `Chip: ${info.chip}, entry ${formatAddress(info.entry)}`,
+    `Flash: ${info.flash_mode}, ${info.flash_freq}, ${info.flash_size}`,
+  ];
+  if (info.min_chip_rev) {
+    lines.push(`Chip revision: ${info.min_chip_rev} - ${info.max_chip_rev}`);
+  }
+  info.segments.forEach((segment, index) => {
+    lines.push(
+      `Segment ${index}: ${formatAddress(segment.address)} len 0x${segment.length.toString(16)} @ file 0x${segment.file_offset.toString(16)}`,
+    );
+  });
+  lines.push(
+    `Checksum: 0x${info.checksum_stored.toString(16).padStart(2, "0")} (${validity(info.checksum_valid)})`,
+  );
+  if (info.sha256_valid !== null) {
+    lines.push(
+      `SHA-256: ${info.sha256_stored ?? "missing"} (${validity(info.sha256_valid)})`,
+    );
+  }
+  if (info.app_desc) {
+    const desc = info.app_desc;
+    lines.push(`Project: ${desc.project_name} ${desc.version}`);
+    lines.push(`ESP-IDF: ${desc.idf_version}`);
+    lines.push(`Compiled: ${desc.compile_date} ${desc.compile_time}`);
+    lines.push(`ELF SHA-256: ${desc.elf_sha256}`);
+  }
+  return lines;
+}
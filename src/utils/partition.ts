@@ -0,0 +1,58 @@
+import { invoke } from "@tauri-apps/api/core";
+
+export interface PartitionEntry {
+  name: string;
+  type: number;
+  subtype: number;
+  offset: number;
+  size: number;
+  encrypted: boolean;
+  readonly: boolean;
+  line: number;
+}
+
+export interface PartitionTableResult {
+  csv: string;
+  entries: PartitionEntry[];
+  usedSize: number;
+}
+
+function normalizePartitionTableResult(payload: any): PartitionTableResult {
+  return {
+    csv: payload.csv,
+    entries: payload.entries ?? [],
+    usedSize: payload.used_size,
+  };
+}
+
+const normalizeFlashSize = (flashSize?: string) =>
+  flashSize && flashSize != "NONE" ? flashSize : undefined;
+
+export async function partitionTableFromCsv(csv: string, flashSize?: string) {
+  const payload = await invoke("partition_table_from_csv", {
+    csv,
+    flashSize: normalizeFlashSize(flashSize),
+  });
+  return normalizePartitionTableResult(payload);
+}
+
+export async function partitionTableFromFile(path: string, flashSize?: string) {
+  const payload = await invoke("partition_table_from_file", {
+    path,
+    flashSize: normalizeFlashSize(flashSize),
+  });
+  return normalizePartitionTableResult(payload);
+}
+
+export async function partitionTableToBinary(
+  csv: string,
+  outputPath: string,
+  flashSize?: string,
+) {
+  const payload = await invoke("partition_table_to_binary", {
+    csv,
+    outputPath,
+    flashSize: normalizeFlashSize(flashSize),
+  });
+  return normalizePartitionTableResult(payload);
+}
@@ -3,7 +3,13 @@ import { invoke } from "@tauri-apps/api/core";
 export type SerialParity = "none" | "odd" | "even";
 export type SerialFlowControl = "none" | "software" | "hardware";
 
+/** Network endpoints (`rfc2217://`, `tcp://`, `ws://`) and `sim://` devices open like local ports. */
+export function isNetworkPort(port: string) {
+  return port.includes("://");
+}
+
 export interface SerialOpenOptions {
+  /** A local device name or a network URL such as `rfc2217://host:port`. */
   port: string;
   baudRate: number;
   dataBits: number;
@@ -13,34 +19,532 @@ export interface SerialOpenOptions {
 }
 
 export interface SerialAssistantEventPayload {
-  kind: "status" | "error" | "data";
+  session_id: string;
+  kind:
+    | "status"
+    | "error"
+    | "warning"
+    | "data"
+    | "log"
+    | "backtrace"
+    | "core_dump"
+    | "signals"
+    | "stats"
+    | "script"
+    | "periodic"
+    | "remote_tx"
+    | "transfer"
+    | "modbus"
+    | "modbus_poll"
+    | "reconnect"
+    | "port_removed";
   text: string;
   hex: string;
+  timestamp_ms: number;
+  monotonic_us: number;
+  offset: number;
+  log: SerialLogRecord | null;
+  frames: SerialBacktraceFrame[] | null;
+  signals: SerialModemSignals | null;
+  stats: SerialStats | null;
+  script: SerialScriptProgress | null;
+  periodic: SerialPeriodicProgress | null;
+  transfer: SerialTransferProgress | null;
+  modbus: ModbusFrame | null;
+  modbus_poll: ModbusPollResult | null;
+  checksum: SerialChecksumCheck | null;
+  reconnect: SerialReconnectProgress | null;
+}
+
+export interface SerialReconnectProgress {
+  state: "waiting" | "reconnected" | "failed";
+  /** The port being waited for, or the one the device came back on. */
+  port: string;
+}
+
+export type SerialChecksumAlgorithm =
+  | "sum8"
+  | "xor8"
+  | "crc8"
+  | "crc16-modbus"
+  | "crc16-ccitt"
+  | "crc16-xmodem"
+  | "crc32";
+
+/** `endian` defaults to little for CRC16-Modbus and big for everything else. */
+export interface SerialChecksumSpec {
+  algorithm: SerialChecksumAlgorithm;
+  endian?: "big" | "little";
+}
+
+export interface SerialChecksumCheck {
+  algorithm: SerialChecksumAlgorithm;
+  valid: boolean;
+  expected: number;
+  received: number;
+}
+
+export interface ModbusValue {
+  address: number | null;
+  value: number;
+}
+
+export interface ModbusFrame {
+  unit: number;
+  function: number;
+  crc_valid: boolean;
+  response: boolean;
+  exception: number | null;
+  address: number | null;
+  quantity: number | null;
+  values: ModbusValue[];
+  summary: string;
+}
+
+export interface ModbusPollResult {
+  unit: number;
+  function: number;
+  polls: number;
+  failures: number;
+  latency_ms: number | null;
+  values: ModbusValue[];
+  error: string | null;
+}
+
+export interface ModbusPollOptions {
+  unit: number;
+  /** 3 reads holding registers, 4 reads input registers. */
+  function: 3 | 4;
+  address: number;
+  quantity: number;
+  intervalMs: number;
+}
+
+export type SerialTransferProtocol = "raw" | "xmodem" | "xmodem-1k" | "ymodem";
+
+export interface SerialTransferProgress {
+  state: "running" | "completed" | "failed" | "cancelled";
+  protocol: SerialTransferProtocol;
+  sending: boolean;
+  file: string | null;
+  bytes: number;
+  total: number | null;
+  message: string;
+}
+
+export interface SerialPeriodicProgress {
+  sent: number;
+  /** `null` when the schedule runs until stopped. */
+  remaining: number | null;
+  done: boolean;
+}
+
+export interface SerialScriptProgress {
+  state: "running" | "passed" | "failed" | "cancelled";
+  line: number | null;
+  iteration: number | null;
+  message: string;
+  variables: Record<string, string>;
+}
+
+export interface SerialStats {
+  uptime_ms: number;
+  rx_bytes: number;
+  tx_bytes: number;
+  rx_rate: number;
+  tx_rate: number;
+  peak_rx_rate: number;
+  peak_tx_rate: number;
+  rx_chunks: number;
+  coalesced_chunks: number;
+  dropped_events: number;
+  read_errors: number;
+  writes: number;
+  write_errors: number;
+  last_write_us: number;
+  avg_write_us: number;
+  max_write_us: number;
+}
+
+export interface SerialModemSignals {
+  cts: boolean;
+  dsr: boolean;
+  ri: boolean;
+  cd: boolean;
+}
+
+export interface SerialBacktraceFrame {
+  address: number;
+  function: string | null;
+  file: string | null;
+  line: number | null;
+  inlined: boolean;
+}
+
+export type SerialLogLevel = "error" | "warn" | "info" | "debug" | "verbose";
+
+export interface SerialLogRecord {
+  level: SerialLogLevel;
+  time: string;
+  tick: number | null;
+  tag: string;
+  message: string;
+}
+
+export interface SerialLogFilter {
+  enabled: boolean;
+  minLevel?: SerialLogLevel;
+  allowTags?: string[];
+  denyTags?: string[];
+}
+
+export type SerialFramingMode = "raw" | "newline" | "idle" | "fixed" | "delimiter";
+
+export interface SerialSessionInfo {
+  sessionId: string;
+  port: string;
+  baudRate: number;
 }
 
 export async function serialAssistantOpen(options: SerialOpenOptions) {
-  return invoke("serial_assistant_open", {
+  return (await invoke("serial_assistant_open", {
     port: options.port,
     baudRate: options.baudRate,
     dataBits: options.dataBits,
     stopBits: options.stopBits,
     parity: options.parity,
     flowControl: options.flowControl,
+  })) as string;
+}
+
+export async function serialAssistantReconfigure(
+  sessionId: string,
+  options: Omit<SerialOpenOptions, "port">,
+) {
+  return invoke("serial_assistant_reconfigure", {
+    sessionId,
+    baudRate: options.baudRate,
+    dataBits: options.dataBits,
+    stopBits: options.stopBits,
+    parity: options.parity,
+    flowControl: options.flowControl,
+  });
+}
+
+/** With a checksum spec, the backend appends the checksum to `data` before sending. */
+export async function serialAssistantSend(sessionId: string, data: number[], checksum?: SerialChecksumSpec) {
+  return (await invoke("serial_assistant_send", {
+    sessionId,
+    data,
+    checksum: checksum?.algorithm,
+    checksumEndian: checksum?.endian,
+  })) as number;
+}
+
+/** The checksum bytes for `data`, in wire order. */
+export async function serialChecksum(data: number[], spec: SerialChecksumSpec) {
+  return (await invoke("serial_checksum", { data, algorithm: spec.algorithm, endian: spec.endian })) as number[];
+}
+
+export async function serialAssistantSetRxChecksum(sessionId: string, spec: SerialChecksumSpec | null) {
+  return invoke("serial_assistant_set_rx_checksum", {
+    sessionId,
+    algorithm: spec?.algorithm,
+    endian: spec?.endian,
+  });
+}
+
+export interface SerialPeriodicSchedule {
+  payloads: number[][];
+  intervalMs: number;
+  /** Total sends; omit to run until stopped. */
+  count?: number;
+  /** Byte replaced by a counter that increments on every send. */
+  counterOffset?: number;
+}
+
+export async function serialAssistantStartPeriodic(sessionId: string, schedule: SerialPeriodicSchedule) {
+  return invoke("serial_assistant_start_periodic", {
+    sessionId,
+    payloads: schedule.payloads,
+    intervalMs: schedule.intervalMs,
+    count: schedule.count,
+    counterOffset: schedule.counterOffset,
+  });
+}
+
+export async function serialAssistantStopPeriodic(sessionId: string) {
+  return invoke("serial_assistant_stop_periodic", { sessionId });
+}
+
+/** Sends files; only YMODEM takes more than one. Chunking applies to raw sends. */
+export async function serialAssistantSendFile(
+  sessionId: string,
+  protocol: SerialTransferProtocol,
+  paths: string[],
+  chunkSize?: number,
+  chunkDelayMs?: number,
+) {
+  return invoke("serial_assistant_send_file", { sessionId, protocol, paths, chunkSize, chunkDelayMs });
+}
+
+/** `destination` is a file, or a directory for YMODEM, which names the files itself. */
+export async function serialAssistantReceiveFile(
+  sessionId: string,
+  protocol: SerialTransferProtocol,
+  destination: string,
+) {
+  return invoke("serial_assistant_receive_file", { sessionId, protocol, destination });
+}
+
+export async function serialAssistantCancelTransfer(sessionId: string) {
+  return invoke("serial_assistant_cancel_transfer", { sessionId });
+}
+
+export async function serialAssistantClose(sessionId: string) {
+  return invoke("serial_assistant_close", { sessionId });
+}
+
+export async function serialAssistantIsOpen(sessionId: string) {
+  return (await invoke("serial_assistant_is_open", { sessionId })) as boolean;
+}
+
+export async function serialAssistantRunScript(sessionId: string, script: string) {
+  return invoke("serial_assistant_run_script", { sessionId, script });
+}
+
+export async function serialAssistantCancelScript(sessionId: string) {
+  return invoke("serial_assistant_cancel_script", { sessionId });
+}
+
+export async function serialAssistantStats(sessionId: string) {
+  return (await invoke("serial_assistant_stats", { sessionId })) as SerialStats;
+}
+
+export async function serialAssistantSessions() {
+  const list = (await invoke("serial_assistant_sessions")) as any[];
+  return list.map(
+    (item) =>
+      ({
+        sessionId: item.session_id,
+        port: item.port,
+        baudRate: item.baud_rate,
+      }) as SerialSessionInfo,
+  );
+}
+
+export async function serialAssistantSetSignals(
+  sessionId: string,
+  rts: boolean,
+  dtr: boolean,
+) {
+  return invoke("serial_assistant_set_signals", { sessionId, rts, dtr });
+}
+
+/** Built-in reset sequences; anything else is sent as a custom `D0|R1|W100` sequence. */
+export type SerialResetSequence =
+  | "classic"
+  | "usb_jtag_serial"
+  | "hard_reset"
+  | "classic_inverted"
+  | "hard_reset_inverted";
+
+export async function serialAssistantSendBreak(sessionId: string, durationMs: number) {
+  return invoke("serial_assistant_send_break", { sessionId, durationMs });
+}
+
+export async function serialAssistantRunSequence(sessionId: string, sequence: string) {
+  return invoke("serial_assistant_run_sequence", { sessionId, sequence });
+}
+
+export async function serialAssistantSetFraming(
+  sessionId: string,
+  mode: SerialFramingMode,
+  value?: number,
+) {
+  return invoke("serial_assistant_set_framing", { sessionId, mode, value });
+}
+
+export async function serialAssistantSetLogFilter(
+  sessionId: string,
+  filter: SerialLogFilter,
+) {
+  return invoke("serial_assistant_set_log_filter", {
+    sessionId,
+    enabled: filter.enabled,
+    minLevel: filter.minLevel,
+    allowTags: filter.allowTags ?? [],
+    denyTags: filter.denyTags ?? [],
+  });
+}
+
+/** Decodes RX as Modbus RTU frames, split at the 3.5 character gap of the current baud. */
+/** Keeps the session across device resets; `timeoutMs` of 0 waits until closed. */
+export async function serialAssistantSetReconnect(sessionId: string, enabled: boolean, timeoutMs = 0) {
+  return invoke("serial_assistant_set_reconnect", { sessionId, enabled, timeoutMs });
+}
+
+export async function serialAssistantSetModbusDecode(sessionId: string, enabled: boolean) {
+  return invoke("serial_assistant_set_modbus_decode", { sessionId, enabled });
+}
+
+export async function serialAssistantStartModbusPoll(sessionId: string, options: ModbusPollOptions) {
+  return invoke("serial_assistant_start_modbus_poll", { sessionId, ...options });
+}
+
+export async function serialAssistantStopModbusPoll(sessionId: string) {
+  return invoke("serial_assistant_stop_modbus_poll", { sessionId });
+}
+
+/** Publishes the session as an RFC 2217 server; resolves to the bound address. */
+export async function serialAssistantStartRfc2217(sessionId: string, address: string) {
+  return (await invoke("serial_assistant_start_rfc2217", { sessionId, address })) as string;
+}
+
+export async function serialAssistantStopRfc2217(sessionId: string) {
+  return invoke("serial_assistant_stop_rfc2217", { sessionId });
+}
+
+export interface SerialVirtualDevice {
+  mode: "echo" | "replay";
+  /** Echo delay in milliseconds. */
+  delay?: number;
+  /** Capture log (`.log`) or raw file to replay. */
+  file?: string;
+  speed?: number;
+  loop?: boolean;
+}
+
+/** Builds the `sim://` URL that opens a simulated device as a serial port. */
+export function serialVirtualDeviceUrl(device: SerialVirtualDevice) {
+  const params = new URLSearchParams();
+  if (device.mode === "echo") {
+    if (device.delay) {
+      params.set("delay", String(device.delay));
+    }
+  } else {
+    params.set("file", device.file ?? "");
+    if (device.speed && device.speed !== 1) {
+      params.set("speed", String(device.speed));
+    }
+    if (device.loop) {
+      params.set("loop", "1");
+    }
+  }
+  const query = params.toString();
+  return `sim://${device.mode}${query ? `?${query}` : ""}`;
+}
+
+/** Publishes a simulated device on a pseudo-terminal; resolves to its path (Linux/macOS only). */
+export async function serialVirtualStartPty(device: string, baudRate?: number) {
+  return (await invoke("serial_virtual_start_pty", { device, baudRate })) as string;
+}
+
+export async function serialVirtualStopPty(path: string) {
+  return invoke("serial_virtual_stop_pty", { path });
+}
+
+export async function serialVirtualListPtys() {
+  return (await invoke("serial_virtual_list_ptys")) as string[];
+}
+
+export async function serialAssistantSetElf(sessionId: string, path: string | null) {
+  return invoke("serial_assistant_set_elf", { sessionId, path });
+}
+
+export async function findProjectElf(flasherArgsPath: string) {
+  return (await invoke("find_project_elf", { flasherArgsPath })) as string;
+}
+
+export interface CoreDumpTask {
+  tcb: number;
+  name: string | null;
+  crashed: boolean;
+  registers: { name: string; value: number }[];
+  stack_start: number | null;
+  stack_size: number | null;
+  backtrace: SerialBacktraceFrame[];
+}
+
+export interface CoreDumpReport {
+  version: string;
+  chip: string | null;
+  arch: string;
+  checksum_valid: boolean;
+  app_elf_sha256: string | null;
+  panic_reason: string | null;
+  crashed_task: number | null;
+  symbolized: boolean;
+  tasks: CoreDumpTask[];
+}
+
+export async function decodeCoreDump(path: string, elfPath?: string) {
+  return (await invoke("decode_core_dump", { path, elfPath })) as CoreDumpReport;
+}
+
+const hex32 = (value: number) => `0x${value.toString(16).padStart(8, "0")}`;
+
+export function formatCoreDumpReport(report: CoreDumpReport) {
+  const lines = [
+    `Core dump ${report.version} (${report.chip ?? report.arch}), checksum ${
+      report.checksum_valid ? "OK" : "invalid"
+    }`,
+  ];
+  if (report.panic_reason) {
+    lines.push(`Panic reason: ${report.panic_reason}`);
+  }
+  if (report.app_elf_sha256) {
+    lines.push(`App ELF SHA256: ${report.app_elf_sha256}`);
+  }
+  const tasks = [...report.tasks].sort((a, b) => Number(b.crashed) - Number(a.crashed));
+  tasks.forEach((task) => {
+    const stack =
+      task.stack_start === null
+        ? ""
+        : ` stack ${hex32(task.stack_start)} (${task.stack_size} bytes)`;
+    lines.push(
+      `Task ${task.name ?? "?"} @ ${hex32(task.tcb)}${task.crashed ? " [crashed]" : ""}${stack}`,
+    );
+    task.backtrace.forEach((frame) => {
+      const location = frame.file ? ` at ${frame.file}${frame.line ? `:${frame.line}` : ""}` : "";
+      lines.push(`  ${hex32(frame.address)}: ${frame.function ?? "??"}${location}`);
+    });
   });
+  return lines;
+}
+
+export interface SerialCaptureOptions {
+  maxSizeKb?: number;
+  maxMinutes?: number;
+}
+
+export interface SerialCaptureFile {
+  name: string;
+  path: string;
+  size: number;
+  modified: number;
 }
 
-export async function serialAssistantSend(data: number[]) {
-  return (await invoke("serial_assistant_send", { data })) as number;
+export async function serialAssistantStartCapture(
+  sessionId: string,
+  options: SerialCaptureOptions = {},
+) {
+  return (await invoke("serial_assistant_start_capture", {
+    sessionId,
+    maxSizeKb: options.maxSizeKb,
+    maxMinutes: options.maxMinutes,
+  })) as string;
 }
 
-export async function serialAssistantClose() {
-  return invoke("serial_assistant_close");
+export async function serialAssistantStopCapture(sessionId: string) {
+  return invoke("serial_assistant_stop_capture", { sessionId });
 }
 
-export async function serialAssistantIsOpen() {
-  return (await invoke("serial_assistant_is_open")) as boolean;
+export async function serialCaptureList() {
+  return (await invoke("serial_capture_list")) as SerialCaptureFile[];
 }
 
-export async function serialAssistantSetSignals(rts: boolean, dtr: boolean) {
-  return invoke("serial_assistant_set_signals", { rts, dtr });
+/** Opens a capture file in the system's default application. */
+export async function serialCaptureOpen(name: string) {
+  return invoke("serial_capture_open", { name });
 }